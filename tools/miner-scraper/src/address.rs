@@ -0,0 +1,190 @@
+//! Miner addresses and cached name resolution.
+//!
+//! A target is an IPv4 or IPv6 literal or a DNS name, optionally with a
+//! port: `10.0.0.1`, `10.0.0.1:4029`, `fe80::1`, `[fe80::1]:4029`,
+//! `miner-7.lan:4029`. Names are resolved by the scrape loop and the result
+//! is reused for a TTL, so a fleet of named miners does not hit the resolver
+//! on every command, and the IP that was actually scraped can be exported.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use crate::cgminer::DEFAULT_PORT;
+
+#[cfg(test)]
+#[path = "tests/address.rs"]
+mod tests;
+
+/// Host part of an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Ip(IpAddr),
+    Name(String),
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Host::Ip(ip) => write!(f, "{ip}"),
+            Host::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+impl FromStr for Host {
+    type Err = anyhow::Error;
+
+    /// Parse an IP literal, optionally in brackets, or a DNS name.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let unbracketed = s
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .unwrap_or(s);
+        if let Ok(ip) = unbracketed.parse() {
+            return Ok(Host::Ip(ip));
+        }
+        let valid_name = !s.is_empty()
+            && s.len() <= 253
+            && s.split('.').all(|label| {
+                !label.is_empty()
+                    && label.len() <= 63
+                    && !label.starts_with('-')
+                    && !label.ends_with('-')
+                    && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            })
+            // A numeric last label is a mistyped IPv4 address, not a name.
+            && !s
+                .rsplit('.')
+                .next()
+                .is_some_and(|label| label.chars().all(|c| c.is_ascii_digit()));
+        if !valid_name {
+            bail!("invalid host {s:?}");
+        }
+        Ok(Host::Name(s.to_ascii_lowercase()))
+    }
+}
+
+/// Host and cgminer API port of a miner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub host: Host,
+    pub port: u16,
+}
+
+impl Address {
+    pub fn new(host: Host, port: u16) -> Self {
+        Self { host, port }
+    }
+
+    /// Value of the `host` label: the host, plus the port if it is not 4028.
+    ///
+    /// Several miners behind one address with forwarded ports stay apart.
+    pub fn name(&self) -> String {
+        if self.port == DEFAULT_PORT {
+            self.host.to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host {
+            Host::Ip(IpAddr::V6(ip)) => write!(f, "[{ip}]:{}", self.port),
+            _ => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = anyhow::Error;
+
+    /// Parse `host`, `host:port`, `[v6]:port` or a bare IPv6 literal.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .with_context(|| format!("invalid address {s:?}: missing ]"))?;
+            match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None if rest.is_empty() => (host, None),
+                None => bail!("invalid address {s:?}"),
+            }
+        } else if s.matches(':').count() == 1 {
+            let (host, port) = s.split_once(':').unwrap_or((s, ""));
+            (host, Some(port))
+        } else {
+            // No port, or an IPv6 literal without brackets.
+            (s, None)
+        };
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .with_context(|| format!("invalid port in {s:?}"))?,
+            None => DEFAULT_PORT,
+        };
+        Ok(Self::new(host.parse()?, port))
+    }
+}
+
+/// Resolves the host of an address, caching the result of a name lookup.
+///
+/// The system resolver does not expose record TTLs, so a configured one is
+/// used. A failed lookup falls back to the last resolved IP, if any, so a
+/// flaky DNS server does not take miners offline.
+pub struct Resolver {
+    address: Address,
+    cached: Option<(IpAddr, Instant)>,
+}
+
+impl Resolver {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            cached: None,
+        }
+    }
+
+    /// Return the IP to connect to, resolving the name if the cached IP is
+    /// older than `ttl`.
+    pub async fn resolve(&mut self, ttl: Duration) -> Result<IpAddr> {
+        let name = match &self.address.host {
+            Host::Ip(ip) => return Ok(*ip),
+            Host::Name(name) => name,
+        };
+        if let Some((ip, at)) = self.cached {
+            if at.elapsed() < ttl {
+                return Ok(ip);
+            }
+        }
+
+        let lookup = tokio::net::lookup_host((name.as_str(), self.address.port)).await;
+        let resolved = lookup.map(|mut addrs| addrs.next().map(|a| a.ip()));
+        match (resolved, self.cached) {
+            (Ok(Some(ip)), cached) => {
+                if cached.is_none_or(|(old, _)| old != ip) {
+                    log::info!("{name} resolved to {ip}");
+                }
+                self.cached = Some((ip, Instant::now()));
+                Ok(ip)
+            }
+            (Ok(None), Some((ip, _))) => {
+                log::warn!("{name} resolved to no address, keeping {ip}");
+                Ok(ip)
+            }
+            (Err(e), Some((ip, _))) => {
+                log::warn!("failed to resolve {name}: {e}, keeping {ip}");
+                Ok(ip)
+            }
+            (Ok(None), None) => bail!("{name} resolved to no address"),
+            (Err(e), None) => Err(e).with_context(|| format!("failed to resolve {name}")),
+        }
+    }
+}
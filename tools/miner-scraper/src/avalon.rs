@@ -0,0 +1,117 @@
+//! Canaan Avalon `estats` strings.
+//!
+//! Avalon miners report almost all of their telemetry in one string per
+//! control board, `MM ID0`, made of `Key[value]` pairs such as
+//! `Elapsed[5866] Fan1[3760] MGHS[50102.33 49871.20 50211.87]`. Values are
+//! a number, a space-separated list with one number per hashboard or chip,
+//! or free text. This module turns each string into an object in an `MM`
+//! array, so the generic parser in `cgminer` exports it like any nested
+//! structure, labelled `mm`.
+
+use serde_json::{Map, Number, Value};
+
+#[cfg(test)]
+#[path = "tests/avalon.rs"]
+mod tests;
+
+/// Parse a `Key[value] Key[value] ...` string into an object.
+///
+/// Brackets may nest, in which case the value is parsed as an object of
+/// its own. Text outside of any `Key[...]` pair is ignored.
+pub fn parse_mm(s: &str) -> Map<String, Value> {
+    let mut fields = Map::new();
+    let mut rest = s;
+    while let Some(open) = rest.find('[') {
+        let key = rest[..open]
+            .rsplit(char::is_whitespace)
+            .next()
+            .unwrap_or_default();
+        let Some(len) = closing_bracket(&rest[open + 1..]) else {
+            break;
+        };
+        let raw = &rest[open + 1..open + 1 + len];
+        rest = &rest[open + 2 + len..];
+        if key.is_empty() {
+            continue;
+        }
+        let value = if raw.contains('[') {
+            Value::Object(parse_mm(raw))
+        } else {
+            parse_value(raw)
+        };
+        fields.insert(key.to_owned(), value);
+    }
+    fields
+}
+
+/// Length of the value before the `]` closing an already opened bracket.
+fn closing_bracket(s: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 0 => return Some(i),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A number, an array of numbers, or else the trimmed text. Percentages
+/// like `53%` count as numbers.
+fn parse_value(raw: &str) -> Value {
+    let numbers: Option<Vec<Value>> = raw
+        .split_whitespace()
+        .map(|token| {
+            let n: f64 = token.strip_suffix('%').unwrap_or(token).parse().ok()?;
+            Number::from_f64(n).map(Value::Number)
+        })
+        .collect();
+    match numbers {
+        Some(mut numbers) if numbers.len() == 1 => numbers.swap_remove(0),
+        Some(numbers) if !numbers.is_empty() => Value::Array(numbers),
+        _ => Value::String(raw.trim().to_owned()),
+    }
+}
+
+/// Replace the `MM ID<n>` strings of every `STATS` entry with an `MM`
+/// array of parsed objects, each with its `MMIndex`.
+///
+/// The `POOL<n>` entries are dropped: they repeat fields like `Elapsed`
+/// without an index to tell them apart, and `pools` has their data anyway.
+pub fn expand(response: &mut Value) {
+    let Some(entries) = response.get_mut("STATS").and_then(Value::as_array_mut) else {
+        return;
+    };
+    entries.retain(|entry| {
+        !entry
+            .get("ID")
+            .and_then(Value::as_str)
+            .is_some_and(|id| id.starts_with("POOL"))
+    });
+    for entry in entries.iter_mut().filter_map(Value::as_object_mut) {
+        let keys: Vec<String> = entry
+            .keys()
+            .filter(|key| key.starts_with("MM ID"))
+            .cloned()
+            .collect();
+        let mut mms = Vec::new();
+        for key in keys {
+            let Some(index) = key["MM ID".len()..].parse::<u32>().ok() else {
+                continue;
+            };
+            let Some(Value::String(s)) = entry.remove(&key) else {
+                continue;
+            };
+            let mut mm = parse_mm(&s);
+            mm.insert("MMIndex".to_owned(), Value::from(index));
+            mms.push((index, Value::Object(mm)));
+        }
+        if !mms.is_empty() {
+            mms.sort_by_key(|(index, _)| *index);
+            let mms = mms.into_iter().map(|(_, mm)| mm).collect();
+            entry.insert("MM".to_owned(), Value::Array(mms));
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Bitaxe and other `AxeOS` boards.
+//!
+//! `AxeOS` has no cgminer API. `GET /api/system/info` returns one flat
+//! object with the hashrate in GH/s, the ASIC and regulator temperatures,
+//! fan, power and share counts, and identity fields such as `deviceModel`,
+//! `version` and `macAddr`. Its fields are exported as `system_<field>`.
+
+use std::time::Duration;
+
+use futures_util::future::{self, BoxFuture};
+use futures_util::FutureExt;
+use serde_json::Value;
+
+use crate::backend::{self, Backend};
+use crate::cgminer::{CommandResult, Endpoint, Firmware};
+use crate::info::MinerInfo;
+use crate::schema::Schema;
+use crate::web;
+
+#[cfg(test)]
+#[path = "tests/axeos.rs"]
+mod tests;
+
+/// The one document with both metrics and identity.
+const SYSTEM_INFO: &str = "/api/system/info";
+
+/// The `AxeOS` web API.
+pub struct AxeOS;
+
+impl Backend for AxeOS {
+    fn detect<'a>(&'a self, _endpoint: Endpoint<'a>) -> BoxFuture<'a, Firmware> {
+        future::ready(Firmware::AxeOS).boxed()
+    }
+
+    fn scrape<'a>(
+        &'a self,
+        endpoint: Endpoint<'a>,
+        fw: Firmware,
+        timeout: Duration,
+        schema: Schema,
+    ) -> BoxFuture<'a, Vec<CommandResult>> {
+        async move {
+            let document = web::get(endpoint.host, endpoint.port, SYSTEM_INFO, None);
+            vec![backend::scrape_json(endpoint, fw, "system", document, timeout, schema).await]
+        }
+        .boxed()
+    }
+
+    fn info<'a>(&'a self, endpoint: Endpoint<'a>, _fw: Firmware) -> BoxFuture<'a, MinerInfo> {
+        async move {
+            match web::get(endpoint.host, endpoint.port, SYSTEM_INFO, None).await {
+                Ok(system) => parse_info(&system),
+                Err(err) => {
+                    log::debug!("{SYSTEM_INFO} on {} failed: {err:#}", endpoint.name);
+                    MinerInfo::default()
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Identity fields of a `/api/system/info` reply.
+///
+/// Older `AxeOS` versions have no `deviceModel`; the ASIC model is the
+/// closest they report.
+fn parse_info(system: &Value) -> MinerInfo {
+    let field = |key: &str| {
+        system
+            .get(key)
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .trim()
+            .to_owned()
+    };
+    let model = match field("deviceModel") {
+        model if model.is_empty() => field("ASICModel"),
+        model => format!("Bitaxe {model}"),
+    };
+    let version = field("version");
+    MinerInfo {
+        model,
+        api_version: String::new(),
+        miner_version: version.clone(),
+        firmware_version: version,
+        mac: field("macAddr").to_lowercase(),
+    }
+}
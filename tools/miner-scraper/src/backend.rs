@@ -0,0 +1,130 @@
+//! Ways of reading a miner.
+//!
+//! Most miners are scraped over the cgminer API, but some devices only
+//! have an HTTP JSON API, and some firmwares report more over their web API
+//! than over cgminer. The `backend` of a `[[target]]` table picks one. Every
+//! backend yields per-section results and a `MinerInfo`, so the scrape loop,
+//! the store and the health series do not depend on how a miner was read.
+
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cgminer::{self, CommandResult, Endpoint, Firmware};
+use crate::info::{self, MinerInfo};
+use crate::schema::{self, Schema};
+use crate::{axeos, vnish};
+
+/// A way of reading metrics and identity from a miner.
+///
+/// Backends may keep state across scrapes, such as a web API session, so
+/// each scrape loop owns one.
+pub trait Backend: Send + Sync {
+    /// Firmware of a miner whose target does not configure one.
+    fn detect<'a>(&'a self, endpoint: Endpoint<'a>) -> BoxFuture<'a, Firmware>;
+
+    /// Read all sections of one scrape, each with its own result and
+    /// `timeout`.
+    fn scrape<'a>(
+        &'a self,
+        endpoint: Endpoint<'a>,
+        fw: Firmware,
+        timeout: Duration,
+        schema: Schema,
+    ) -> BoxFuture<'a, Vec<CommandResult>>;
+
+    /// Identity of the miner. Fields that cannot be read are left empty.
+    fn info<'a>(&'a self, endpoint: Endpoint<'a>, fw: Firmware) -> BoxFuture<'a, MinerInfo>;
+}
+
+/// The `backend` of a target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// The cgminer API, with the firmware detected or configured.
+    #[default]
+    Cgminer,
+    /// The `AxeOS` web API of Bitaxe boards.
+    AxeOS,
+    /// The Vnish web API, which needs the web password.
+    Vnish,
+}
+
+impl Kind {
+    /// Whether the backend talks to the web API rather than cgminer.
+    pub fn is_web(self) -> bool {
+        self != Kind::Cgminer
+    }
+
+    /// Create the backend for one target.
+    pub fn build(self, password: Option<&str>) -> Box<dyn Backend> {
+        match self {
+            Kind::Cgminer => Box::new(Cgminer),
+            Kind::AxeOS => Box::new(axeos::AxeOS),
+            Kind::Vnish => Box::new(vnish::Vnish::new(password.unwrap_or_default())),
+        }
+    }
+}
+
+/// The cgminer API, see `cgminer::scrape`.
+pub struct Cgminer;
+
+impl Backend for Cgminer {
+    fn detect<'a>(&'a self, endpoint: Endpoint<'a>) -> BoxFuture<'a, Firmware> {
+        Firmware::detect(endpoint.host, endpoint.port).boxed()
+    }
+
+    fn scrape<'a>(
+        &'a self,
+        endpoint: Endpoint<'a>,
+        fw: Firmware,
+        timeout: Duration,
+        schema: Schema,
+    ) -> BoxFuture<'a, Vec<CommandResult>> {
+        cgminer::scrape(endpoint, fw, timeout, schema).boxed()
+    }
+
+    fn info<'a>(&'a self, endpoint: Endpoint<'a>, fw: Firmware) -> BoxFuture<'a, MinerInfo> {
+        info::scrape(endpoint.host, endpoint.port, fw).boxed()
+    }
+}
+
+/// `GET` one web API document and format it like a cgminer `section`, so
+/// `section_field` names and the schema rules apply as for cgminer replies.
+pub async fn scrape_json(
+    endpoint: Endpoint<'_>,
+    fw: Firmware,
+    section: &'static str,
+    document: impl std::future::Future<Output = anyhow::Result<Value>>,
+    timeout: Duration,
+    schema: Schema,
+) -> CommandResult {
+    let lines = match tokio::time::timeout(timeout, document).await {
+        Ok(Ok(value)) => Ok(render_json(endpoint.name, fw, section, value, schema)),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(anyhow::anyhow!("timeout")),
+    };
+    CommandResult {
+        command: section,
+        lines,
+    }
+}
+
+/// Metric lines of a web API document, named after `section` and its fields.
+pub fn render_json(
+    host: &str,
+    fw: Firmware,
+    section: &str,
+    value: Value,
+    schema: Schema,
+) -> Vec<String> {
+    let mut response = Value::Object(serde_json::Map::from_iter([(
+        section.to_uppercase(),
+        Value::Array(vec![value]),
+    )]));
+    let samples = cgminer::parse_samples(&mut response);
+    schema::render(host, fw, &samples, 1.0, schema)
+}
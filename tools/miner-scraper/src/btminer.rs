@@ -0,0 +1,197 @@
+//! Privileged commands of the Whatsminer btminer API.
+//!
+//! Whatsminers run btminer, which listens on the cgminer port and answers
+//! read-only commands like `summary`, `devs` and `pools` in plain cgminer
+//! JSON, so Whatsminers are scraped by `cgminer::scrape` like any other
+//! firmware.
+//! Commands that change the miner, e.g. `reboot` or `adjust_power_limit`,
+//! need a token instead: `get_token` returns a salt, a new salt and a time,
+//! from which the admin password derives an AES-256 key and a signature.
+//! The command and its signature are then sent AES-ECB encrypted and
+//! base64 encoded, and the reply comes back encrypted the same way.
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes256;
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use md5::{Digest, Md5};
+use serde_json::{json, Value};
+use sha2::Sha256;
+
+use crate::cgminer;
+
+#[cfg(test)]
+#[path = "tests/btminer.rs"]
+mod tests;
+
+/// Admin password of a Whatsminer as shipped.
+pub const DEFAULT_PASSWORD: &str = "admin";
+
+/// Alphabet of the crypt(3) base64 encoding.
+const CRYPT_ALPHABET: &[u8; 64] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The hash part of MD5-crypt (`$1$salt$hash`) of `password`.
+///
+/// btminer derives both its key and its signature with the system crypt(3),
+/// so this is the FreeBSD algorithm as glibc implements it, salt truncated
+/// to 8 bytes.
+fn md5_crypt(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+
+    let alternate = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+    let mut ctx = Md5::new()
+        .chain_update(password)
+        .chain_update(b"$1$")
+        .chain_update(salt);
+    for chunk in password.chunks(16) {
+        ctx.update(&alternate[..chunk.len()]);
+    }
+    let mut n = password.len();
+    while n > 0 {
+        if n & 1 == 1 {
+            ctx.update([0]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        n >>= 1;
+    }
+    let mut digest = ctx.finalize();
+
+    for round in 0..1000 {
+        let mut ctx = Md5::new();
+        if round % 2 == 1 {
+            ctx.update(password);
+        } else {
+            ctx.update(digest);
+        }
+        if round % 3 != 0 {
+            ctx.update(salt);
+        }
+        if round % 7 != 0 {
+            ctx.update(password);
+        }
+        if round % 2 == 1 {
+            ctx.update(digest);
+        } else {
+            ctx.update(password);
+        }
+        digest = ctx.finalize();
+    }
+
+    let mut hash = String::with_capacity(22);
+    let mut push = |value: u32, chars: usize| {
+        let mut value = value;
+        for _ in 0..chars {
+            hash.push(char::from(CRYPT_ALPHABET[(value & 0x3f) as usize]));
+            value >>= 6;
+        }
+    };
+    let d = |i: usize| u32::from(digest[i]);
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push(d(a) << 16 | d(b) << 8 | d(c), 4);
+    }
+    push(d(11), 2);
+    hash
+}
+
+/// Key and signature of one privileged command, from a `get_token` reply.
+pub struct Token {
+    cipher: Aes256,
+    sign: String,
+}
+
+impl Token {
+    /// Derive the token from the admin password and the `Msg` of a
+    /// `get_token` reply, which has `time`, `salt` and `newsalt`.
+    pub fn new(password: &str, msg: &Value) -> Result<Self> {
+        let field = |name: &str| {
+            msg.get(name)
+                .and_then(Value::as_str)
+                .with_context(|| format!("get_token reply without {name}"))
+        };
+        let key = md5_crypt(password.as_bytes(), field("salt")?.as_bytes());
+        let sign = md5_crypt(
+            format!("{key}{}", field("time")?).as_bytes(),
+            field("newsalt")?.as_bytes(),
+        );
+        let aes_key = Sha256::digest(key.as_bytes());
+        Ok(Self {
+            cipher: Aes256::new(&aes_key),
+            sign,
+        })
+    }
+
+    /// Encrypt a request: NUL padded to the block size, AES-ECB, base64.
+    fn encrypt(&self, plain: &str) -> String {
+        let mut data = plain.as_bytes().to_vec();
+        data.resize(data.len().div_ceil(16) * 16, 0);
+        for block in data.chunks_exact_mut(16) {
+            self.cipher
+                .encrypt_block(GenericArray::from_mut_slice(block));
+        }
+        BASE64.encode(data)
+    }
+
+    /// Decrypt a reply encrypted like a request.
+    fn decrypt(&self, encoded: &str) -> Result<Value> {
+        let mut data = BASE64
+            .decode(encoded.trim())
+            .context("invalid base64 in encrypted reply")?;
+        if data.len() % 16 != 0 {
+            bail!("encrypted reply is not a whole number of blocks");
+        }
+        for block in data.chunks_exact_mut(16) {
+            self.cipher
+                .decrypt_block(GenericArray::from_mut_slice(block));
+        }
+        while data.last() == Some(&0) {
+            data.pop();
+        }
+        serde_json::from_slice(&data).context("invalid JSON in encrypted reply")
+    }
+
+    /// The encrypted request for `cmd` with its parameters, e.g.
+    /// `{"percent": "90"}` for `adjust_power_limit`.
+    fn request(&self, cmd: &str, params: &serde_json::Map<String, Value>) -> String {
+        let mut plain = params.clone();
+        plain.insert("cmd".to_owned(), Value::String(cmd.to_owned()));
+        plain.insert("token".to_owned(), Value::String(self.sign.clone()));
+        let data = self.encrypt(&Value::Object(plain).to_string());
+        json!({"enc": 1, "data": data}).to_string()
+    }
+}
+
+/// Run a privileged command and return its decrypted reply.
+///
+/// A new token is fetched for every command. Error replies, encrypted or
+/// not, fail with their message.
+pub async fn privileged(
+    host: &str,
+    port: u16,
+    password: &str,
+    cmd: &str,
+    params: &serde_json::Map<String, Value>,
+) -> Result<Value> {
+    let reply = cgminer::send(host, port, r#"{"cmd":"get_token"}"#).await?;
+    if let Some(msg) = cgminer::status_error(&reply) {
+        bail!("get_token: {msg}");
+    }
+    let token = Token::new(password, reply.get("Msg").unwrap_or(&Value::Null))?;
+
+    let reply = cgminer::send(host, port, &token.request(cmd, params)).await?;
+    let reply = match reply.get("enc").and_then(Value::as_str) {
+        Some(encoded) => token.decrypt(encoded)?,
+        None => reply,
+    };
+    if let Some(msg) = cgminer::status_error(&reply) {
+        bail!("{cmd}: {msg}");
+    }
+    Ok(reply)
+}
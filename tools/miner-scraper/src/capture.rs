@@ -0,0 +1,221 @@
+//! Record cgminer API replies as test fixtures.
+//!
+//! Supporting a new firmware or model starts with its raw replies. This
+//! module detects the firmware of a miner, sends it every command the
+//! scraper knows plus any requested ones, and writes each reply as
+//! `<firmware>-cgminer-<command>-<model>.json`, the layout of `dumps/` that
+//! the tests and `fake_miner` read. Addresses, MACs and pool accounts are
+//! redacted, so the files can be committed as they are.
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::address::Address;
+use crate::cgminer::{self, Firmware};
+use crate::chips;
+use crate::info::MinerInfo;
+
+#[cfg(test)]
+#[path = "tests/capture.rs"]
+mod tests;
+
+/// Replacement for IPv4 addresses, from the documentation range.
+const REDACTED_IPV4: &str = "192.0.2.1";
+/// Replacement for IPv6 addresses, from the documentation range.
+const REDACTED_IPV6: &str = "2001:db8::1";
+/// Replacement for MAC addresses, from the documentation range.
+const REDACTED_MAC: &str = "00:00:5e:00:53:00";
+/// Replacement for pool users and worker names.
+const REDACTED_ACCOUNT: &str = "user.worker";
+
+/// Keys whose values name a pool account or worker, compared ignoring case.
+const ACCOUNT_KEYS: &[&str] = &["User", "Username", "Worker", "Stratum User"];
+
+/// Command line arguments of the `capture` subcommand.
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Miner address, `host` or `host:port`.
+    pub host: String,
+
+    /// Commands to capture in addition to the known ones.
+    #[arg(long, value_delimiter = ',', num_args = 1..)]
+    pub commands: Vec<String>,
+
+    /// Directory to write the replies to.
+    #[arg(long, default_value = "dumps")]
+    pub out: PathBuf,
+
+    /// Model suffix of the files, e.g. `s21xp`. Taken from the miner's
+    /// identity if not given.
+    #[arg(long)]
+    pub model: Option<String>,
+}
+
+/// Commands scraped or polled for identity on any firmware, then
+/// `requested`, without duplicates.
+fn commands(requested: &[String]) -> Vec<&str> {
+    let known = Firmware::ALL
+        .iter()
+        .flat_map(|fw| fw.commands().iter().chain(fw.info_commands()).copied());
+    let mut commands: Vec<&str> = Vec::new();
+    for cmd in known.chain(requested.iter().map(String::as_str)) {
+        if !commands.contains(&cmd) {
+            commands.push(cmd);
+        }
+    }
+    commands
+}
+
+/// Model suffix of the file names, e.g. `s21plus` for `Antminer S21+`.
+fn file_model(model: &str) -> String {
+    chips::model_key(model)
+        .replace('+', "plus")
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect()
+}
+
+/// Redact addresses and pool accounts in a reply, in place.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(obj) => {
+            for (key, value) in obj.iter_mut() {
+                let account = ACCOUNT_KEYS.iter().any(|k| k.eq_ignore_ascii_case(key));
+                match value {
+                    Value::String(s) if account && !s.is_empty() => {
+                        REDACTED_ACCOUNT.clone_into(s);
+                    }
+                    // Avalon reports its MAC as bare hex digits.
+                    Value::String(s)
+                        if key == "MAC"
+                            && s.len() == 12
+                            && s.chars().all(|c| c.is_ascii_hexdigit()) =>
+                    {
+                        *s = REDACTED_MAC.replace(':', "");
+                    }
+                    _ => redact(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(s) => *s = redact_str(s),
+        _ => {}
+    }
+}
+
+/// Redact the MAC and IP addresses in a string, including those embedded
+/// in URLs.
+fn redact_str(s: &str) -> String {
+    if let Ok(IpAddr::V6(ip)) = s.trim().parse() {
+        if !ip.is_unspecified() && !ip.is_loopback() {
+            return REDACTED_IPV6.to_owned();
+        }
+    }
+    let s = replace_tokens(
+        s,
+        |c| c.is_ascii_hexdigit() || c == ':' || c == '-',
+        |t| is_mac(t).then_some(REDACTED_MAC),
+    );
+    replace_tokens(
+        &s,
+        |c| c.is_ascii_digit() || c == '.',
+        |t| {
+            // Netmasks and unset addresses say nothing about the site.
+            let ip: Ipv4Addr = t.parse().ok()?;
+            let mask = ip.octets()[0] == 255;
+            (!mask && !ip.is_unspecified() && !ip.is_loopback()).then_some(REDACTED_IPV4)
+        },
+    )
+}
+
+/// Replace the runs of `in_token` characters that `replacement` maps.
+fn replace_tokens(
+    s: &str,
+    in_token: fn(char) -> bool,
+    replacement: impl Fn(&str) -> Option<&'static str>,
+) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find(in_token) {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        let end = tail.find(|c| !in_token(c)).unwrap_or(tail.len());
+        let token = &tail[..end];
+        out.push_str(replacement(token).unwrap_or(token));
+        rest = &tail[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Whether a token is a MAC address like `02:8b:11:49:72:3c`.
+fn is_mac(token: &str) -> bool {
+    let groups: Vec<&str> = token.split([':', '-']).collect();
+    groups.len() == 6
+        && (token.matches(':').count() == 5 || token.matches('-').count() == 5)
+        && groups
+            .iter()
+            .all(|g| g.len() == 2 && g.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Capture the replies of the miner at `address` into `out`, returning the
+/// files written. Commands the miner rejects are skipped.
+pub async fn capture(
+    address: &Address,
+    requested: &[String],
+    model: Option<&str>,
+    out: &Path,
+) -> Result<Vec<PathBuf>> {
+    let host = address.host.to_string();
+    let fw = Firmware::detect(&host, address.port).await;
+    log::info!("{address}: {fw} firmware");
+
+    let mut replies = Vec::new();
+    for cmd in commands(requested) {
+        match cgminer::command(&host, address.port, cmd).await {
+            Ok(reply) => match cgminer::status_error(&reply) {
+                Some(msg) => log::info!("{cmd}: {msg}, skipped"),
+                None => replies.push((cmd, reply)),
+            },
+            Err(e) => log::warn!("{cmd}: {e:#}, skipped"),
+        }
+    }
+    if replies.is_empty() {
+        bail!("{address} answered no command");
+    }
+
+    let model = if let Some(model) = model {
+        model.to_owned()
+    } else {
+        let responses: Vec<Value> = replies.iter().map(|(_, r)| r.clone()).collect();
+        let model = MinerInfo::parse(&responses).model;
+        if model.is_empty() {
+            bail!("{address} did not report its model, pass --model");
+        }
+        file_model(&model)
+    };
+
+    std::fs::create_dir_all(out).with_context(|| format!("failed to create {}", out.display()))?;
+    let mut written = Vec::new();
+    for (cmd, mut reply) in replies {
+        redact(&mut reply);
+        let path = out.join(format!("{fw}-cgminer-{cmd}-{model}.json"));
+        std::fs::write(&path, serde_json::to_string(&reply)?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Run the `capture` subcommand.
+pub async fn run(args: Args) -> Result<()> {
+    let address: Address = args.host.parse()?;
+    let written = capture(&address, &args.commands, args.model.as_deref(), &args.out).await?;
+    for path in &written {
+        log::info!("wrote {}", path.display());
+    }
+    Ok(())
+}
@@ -7,14 +7,18 @@
 //! Prometheus metric lines: preprocess dash-separated strings into arrays,
 //! then parse each field name and value into labeled metrics.
 
+use std::collections::{BTreeSet, HashMap};
 use std::time::Duration;
 
 use anyhow::Result;
-use serde_json::Value;
+use serde::Deserialize;
+use serde_json::{json, Value};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 
-use crate::metrics;
+use crate::schema::{self, Schema};
+use crate::{avalon, chips};
+use crate::{metrics, pools};
 
 /// Default cgminer API port.
 pub const DEFAULT_PORT: u16 = 4028;
@@ -25,14 +29,24 @@ const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 /// Timeout for reading a complete response from a miner.
 const READ_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Default timeout for one command of a scrape, from connect to parsed reply.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Known firmware types for Bitcoin mining hardware.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+///
+/// Deserialized from the same lowercase names used in the `firmware` label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Firmware {
     Stock,
     LuxOS,
     Vnish,
     Braiins,
     Mara,
+    Whatsminer,
+    Avalon,
+    /// Bitaxe and other `AxeOS` boards, which only have a web API.
+    AxeOS,
 }
 
 impl std::fmt::Display for Firmware {
@@ -43,28 +57,83 @@ impl std::fmt::Display for Firmware {
             Firmware::Vnish => write!(f, "vnish"),
             Firmware::Braiins => write!(f, "braiins"),
             Firmware::Mara => write!(f, "mara"),
+            Firmware::Whatsminer => write!(f, "whatsminer"),
+            Firmware::Avalon => write!(f, "avalon"),
+            Firmware::AxeOS => write!(f, "axeos"),
         }
     }
 }
 
 impl Firmware {
+    /// The firmwares with a cgminer API.
+    pub const ALL: [Firmware; 7] = [
+        Firmware::Stock,
+        Firmware::LuxOS,
+        Firmware::Vnish,
+        Firmware::Braiins,
+        Firmware::Mara,
+        Firmware::Whatsminer,
+        Firmware::Avalon,
+    ];
+
     /// Return the cgminer commands to scrape for this firmware.
-    pub fn commands(&self) -> &[&str] {
+    ///
+    /// Every firmware is asked for `pools`, which is parsed by `pools`
+    /// rather than the generic field parser. `AxeOS` has no cgminer API and
+    /// is scraped by the `axeos` backend instead.
+    pub fn commands(self) -> &'static [&'static str] {
         match self {
-            Firmware::Braiins => &["temps", "fans", "summary", "devs", "devdetails"],
-            Firmware::LuxOS => &["stats", "temps", "fans", "power"],
-            Firmware::Stock | Firmware::Vnish | Firmware::Mara => &["stats", "summary"],
+            Firmware::Braiins => &[
+                "temps",
+                "fans",
+                "summary",
+                "devs",
+                "devdetails",
+                "tunerstatus",
+                "pools",
+            ],
+            Firmware::LuxOS => &["stats", "temps", "fans", "power", "pools"],
+            Firmware::Stock | Firmware::Vnish | Firmware::Mara => &["stats", "summary", "pools"],
+            Firmware::Whatsminer => &["summary", "devs", "pools"],
+            Firmware::Avalon => &["estats", "summary", "pools"],
+            Firmware::AxeOS => &[],
+        }
+    }
+
+    /// Whether the firmware's API accepts joined commands like `stats+summary`.
+    ///
+    /// `BOSer`, MARA and btminer are not known to support them and always get
+    /// separate requests. A rejected joined request also falls back to separate ones.
+    pub fn supports_multi_command(self) -> bool {
+        matches!(
+            self,
+            Firmware::Stock | Firmware::LuxOS | Firmware::Vnish | Firmware::Avalon
+        )
+    }
+
+    /// Return the cgminer commands that describe the miner's identity.
+    ///
+    /// These are polled at the slower info cadence, see `info`.
+    pub fn info_commands(&self) -> &[&str] {
+        match self {
+            Firmware::Braiins => &["version", "devdetails"],
+            Firmware::LuxOS => &["version", "config"],
+            Firmware::Stock | Firmware::Vnish | Firmware::Mara | Firmware::Avalon => &["version"],
+            Firmware::Whatsminer => &["devdetails", "get_version"],
+            Firmware::AxeOS => &[],
         }
     }
 
     /// Determine firmware from stats response.
     ///
-    /// Checks the STATUS Description field for `BraiinsOS`, `LuxOS`, and MARA
-    /// identifiers. Falls back to the STATS Type field for Vnish. Returns
-    /// stock firmware if nothing matches.
+    /// Checks the STATUS Description field for `BraiinsOS`, `LuxOS`, MARA
+    /// and Whatsminer identifiers. Falls back to the STATS Type field for
+    /// Vnish and the `AVA` ID of Avalon's STATS entry. Returns stock
+    /// firmware if nothing matches.
     fn identify(stats: &serde_json::Value) -> Firmware {
         let description = stats
             .pointer("/STATUS/0/Description")
+            .or_else(|| stats.get("Description"))
             .and_then(|v| v.as_str())
             .unwrap_or("");
 
@@ -73,11 +142,18 @@ impl Firmware {
             .and_then(|v| v.as_str())
             .unwrap_or("");
 
+        let id_field = stats
+            .pointer("/STATS/0/ID")
+            .and_then(|v| v.as_str())
+            .unwrap_or("");
+
         match (description, type_field) {
             (desc, _) if desc.contains("BOSer") => Firmware::Braiins,
             (desc, _) if desc.contains("LUXminer") => Firmware::LuxOS,
             (desc, _) if desc.contains("kaonsu") => Firmware::Mara,
+            (desc, _) if desc.contains("btminer") => Firmware::Whatsminer,
             (_, typ) if typ.contains("(Vnish") => Firmware::Vnish,
+            _ if id_field.starts_with("AVA") => Firmware::Avalon,
             _ => Firmware::Stock,
         }
     }
@@ -85,24 +161,206 @@ impl Firmware {
     /// Detect the firmware running on a miner via cgminer API.
     ///
     /// Sends stats command and delegates to `identify()` for the actual
-    /// firmware classification.
-    pub async fn detect(host: &str) -> Self {
-        let stats = command(host, DEFAULT_PORT, "stats").await.ok();
+    /// firmware classification. Every Antminer firmware answers `stats`; a
+    /// miner that rejects it is identified from its `summary` instead,
+    /// which is how Whatsminers are told apart.
+    pub async fn detect(host: &str, port: u16) -> Self {
+        let stats = command(host, port, "stats").await.ok();
         match stats {
+            Some(ref resp) if status_error(resp).is_some() => {
+                let fw = Self::identify(resp);
+                if fw != Firmware::Stock {
+                    return fw;
+                }
+                match command(host, port, "summary").await {
+                    Ok(summary) => Self::identify(&summary),
+                    Err(_) => fw,
+                }
+            }
             Some(ref resp) => Self::identify(resp),
             None => Firmware::Stock,
         }
     }
 }
 
+/// Outcome of one cgminer command within a scrape.
+pub struct CommandResult {
+    pub command: &'static str,
+    pub lines: Result<Vec<String>>,
+}
+
+/// A miner's API address and the `host` label of its metrics.
+///
+/// The label differs from the address for targets on another port or
+/// given by hostname.
+#[derive(Debug, Clone, Copy)]
+pub struct Endpoint<'a> {
+    pub name: &'a str,
+    pub host: &'a str,
+    pub port: u16,
+}
+
 /// Scrape metrics from a miner using the specified firmware commands.
-pub async fn scrape(host: &str, fw: Firmware) -> Result<Vec<String>> {
-    let mut lines = Vec::new();
-    for cmd in fw.commands() {
-        let mut resp = command(host, DEFAULT_PORT, cmd).await?;
-        lines.extend(parse_response(host, &mut resp));
+///
+/// Firmwares that accept joined commands get a single `stats+summary+...`
+/// request whose reply is split back into per-command sections. Otherwise,
+/// or if the joined request is rejected or fails, the commands run
+/// concurrently, each over its own connection and with its own `timeout`.
+/// Every command has its own result, so one failing command does not
+/// discard the data of the others.
+pub async fn scrape(
+    endpoint: Endpoint<'_>,
+    fw: Firmware,
+    timeout: Duration,
+    schema: Schema,
+) -> Vec<CommandResult> {
+    let commands = fw.commands();
+    if fw.supports_multi_command() {
+        match scrape_multi(endpoint, fw, timeout, schema).await {
+            Ok(results) => return results,
+            Err(MultiError::Rejected(msg)) => {
+                log::debug!(
+                    "{} rejected joined commands ({msg}), sending them separately",
+                    endpoint.name
+                );
+            }
+            // A single slow or broken command would otherwise fail them all.
+            Err(MultiError::Failed(err)) => {
+                log::debug!(
+                    "{} joined request failed ({err:#}), sending commands separately",
+                    endpoint.name
+                );
+            }
+        }
+    }
+
+    let futures = commands.iter().map(|cmd| async move {
+        let lines = tokio::time::timeout(timeout, scrape_command(endpoint, fw, cmd, schema))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("timeout")));
+        CommandResult {
+            command: cmd,
+            lines,
+        }
+    });
+    futures_util::future::join_all(futures).await
+}
+
+/// Why a joined request produced no per-command results.
+enum MultiError {
+    /// The miner answered but does not understand joined commands.
+    Rejected(String),
+    /// The request itself failed, e.g. the miner is unreachable.
+    Failed(anyhow::Error),
+}
+
+/// Send all commands as one joined request and split the reply.
+async fn scrape_multi(
+    endpoint: Endpoint<'_>,
+    fw: Firmware,
+    timeout: Duration,
+    schema: Schema,
+) -> std::result::Result<Vec<CommandResult>, MultiError> {
+    let commands = fw.commands();
+    let joined = commands.join("+");
+    let request = command(endpoint.host, endpoint.port, &joined);
+    let mut reply = tokio::time::timeout(timeout, request)
+        .await
+        .map_err(|_| MultiError::Failed(anyhow::anyhow!("timeout")))?
+        .map_err(MultiError::Failed)?;
+    if let Some(msg) = status_error(&reply) {
+        return Err(MultiError::Rejected(msg.to_owned()));
+    }
+    if !commands.iter().any(|cmd| reply.get(cmd).is_some()) {
+        return Err(MultiError::Rejected("no per-command sections".to_owned()));
+    }
+
+    Ok(commands
+        .iter()
+        .map(|cmd| {
+            let lines = split_multi(&mut reply, cmd)
+                .and_then(|mut resp| parse_command(endpoint.name, fw, cmd, &mut resp, schema));
+            CommandResult {
+                command: cmd,
+                lines,
+            }
+        })
+        .collect())
+}
+
+/// Take the reply of one command out of a joined reply.
+///
+/// A joined reply has one key per command, each holding a one-element array
+/// with the reply that command alone would have produced.
+fn split_multi(reply: &mut Value, cmd: &str) -> Result<Value> {
+    reply
+        .get_mut(cmd)
+        .and_then(Value::as_array_mut)
+        .filter(|sections| !sections.is_empty())
+        .map(|sections| sections.swap_remove(0))
+        .ok_or_else(|| anyhow::anyhow!("missing from joined reply"))
+}
+
+/// Run one command and parse its reply into metric lines.
+async fn scrape_command(
+    endpoint: Endpoint<'_>,
+    fw: Firmware,
+    cmd: &str,
+    schema: Schema,
+) -> Result<Vec<String>> {
+    let mut resp = command(endpoint.host, endpoint.port, cmd).await?;
+    parse_command(endpoint.name, fw, cmd, &mut resp, schema)
+}
+
+/// Parse the reply of one command, failing on a cgminer error status.
+///
+/// `host` is only used as the label value. Pool metrics are the same in
+/// every schema.
+fn parse_command(
+    host: &str,
+    fw: Firmware,
+    cmd: &str,
+    resp: &mut Value,
+    schema: Schema,
+) -> Result<Vec<String>> {
+    if let Some(msg) = status_error(resp) {
+        anyhow::bail!("{msg}");
+    }
+    if cmd == "pools" {
+        return Ok(pools::parse_response(host, resp));
+    }
+    if fw == Firmware::Avalon && matches!(cmd, "estats" | "stats") {
+        avalon::expand(resp);
+    }
+    let rate_unit = schema::rate_unit(resp);
+    let mut samples = parse_samples(resp);
+    if cmd == "stats" {
+        samples.extend(chips::parse_stats(resp));
+    }
+    Ok(schema::render(host, fw, &samples, rate_unit, schema))
+}
+
+/// Return the message of a cgminer error reply.
+///
+/// Cgminer answers unsupported or failing commands with a normal JSON reply
+/// whose `STATUS` is `E` (error) or `F` (fatal) and no data sections.
+/// Whatsminer's btminer puts the status fields at the top level instead of
+/// in a `STATUS` array.
+pub fn status_error(response: &Value) -> Option<&str> {
+    let status = match response.get("STATUS")? {
+        Value::Array(statuses) => statuses.first()?,
+        Value::String(_) => response,
+        _ => return None,
+    };
+    match status.get("STATUS").and_then(Value::as_str) {
+        Some("E" | "F") => Some(
+            status
+                .get("Msg")
+                .and_then(Value::as_str)
+                .unwrap_or("error status"),
+        ),
+        _ => None,
     }
-    Ok(lines)
 }
 
 /// Parsed field name from a cgminer JSON key.
@@ -222,18 +480,53 @@ fn parse_dash_array(s: &str) -> Option<Vec<Value>> {
     Some(nums)
 }
 
-/// Parse a cgminer JSON response into Prometheus metric lines.
+/// One numeric field of a response, before the `host` label is added.
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    /// Labels of nested structures, then `hashboard` and `idx`.
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+impl Sample {
+    /// Format as a metric line named `name`, usually the sample's name,
+    /// with `host` as the first label.
+    pub fn to_line(&self, name: &str, host: &str) -> String {
+        let mut labels = Vec::with_capacity(self.labels.len() + 1);
+        labels.push(("host", host));
+        labels.extend(self.labels.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        metrics::gauge(name, &labels, self.value)
+    }
+
+    /// Prepend the labels of the structures containing this sample.
+    fn with_parents(mut self, parents: &[(String, String)]) -> Self {
+        if !parents.is_empty() {
+            self.labels.splice(0..0, parents.iter().cloned());
+        }
+        self
+    }
+
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Parse a cgminer JSON response into samples named after the fields.
 ///
 /// Iterates all data sections in the response (skipping STATUS and id),
 /// preprocesses dash-separated strings into arrays, then emits each numeric
-/// field as a gauge metric. The section name is lowercased and prepended to
-/// each metric name (e.g. FANS.RPM becomes `fans_rpm`).
-pub fn parse_response(host: &str, response: &mut Value) -> Vec<String> {
+/// field as a sample. The section name is lowercased and prepended to each
+/// metric name (e.g. FANS.RPM becomes `fans_rpm`).
+pub fn parse_samples(response: &mut Value) -> Vec<Sample> {
     let Some(obj) = response.as_object_mut() else {
         return Vec::new();
     };
 
-    let mut lines = Vec::new();
+    let mut samples = Vec::new();
     for (section, value) in obj {
         if section == "STATUS" || section == "id" {
             continue;
@@ -245,13 +538,13 @@ pub fn parse_response(host: &str, response: &mut Value) -> Vec<String> {
         };
 
         for entry in entries {
-            lines.extend(parse_entry(host, &prefix, entry));
+            samples.extend(parse_entry(&prefix, entry));
         }
     }
-    lines
+    samples
 }
 
-fn parse_entry(host: &str, prefix: &str, entry: &mut Value) -> Vec<String> {
+fn parse_entry(prefix: &str, entry: &mut Value) -> Vec<Sample> {
     let Some(entry_obj) = entry.as_object_mut() else {
         return Vec::new();
     };
@@ -261,35 +554,167 @@ fn parse_entry(host: &str, prefix: &str, entry: &mut Value) -> Vec<String> {
         .and_then(serde_json::Value::as_u64)
         .map(|id| id.to_string());
 
-    preprocess(entry_obj);
+    parse_object(prefix, entry_obj, &[], entry_id.as_deref())
+}
+
+/// Parse the fields of one object, recursing into nested structures.
+///
+/// `parents` are the labels identifying the object within its response.
+/// Nested objects extend the metric name with their key. Each object of a
+/// nested array gets a label from its identifier field, e.g.
+/// `HashchainIndex` becomes `hashchain`, or else its position, e.g. as
+/// `chips_idx` for an array under `Chips`.
+fn parse_object(
+    prefix: &str,
+    obj: &mut serde_json::Map<String, Value>,
+    parents: &[(String, String)],
+    entry_id: Option<&str>,
+) -> Vec<Sample> {
+    preprocess(obj);
 
-    let mut lines = Vec::new();
-    for (key, val) in entry_obj.iter() {
+    let mut samples = Vec::new();
+    for (key, val) in obj.iter_mut() {
         if key == "ID" {
             continue;
         }
         let field_name = FieldName::parse(key);
-        let Some(field_value) = FieldValue::parse(val) else {
+        if let Some(field_value) = FieldValue::parse(val) {
+            samples.extend(
+                emit_metric(prefix, &field_name, &field_value, entry_id)
+                    .into_iter()
+                    .map(|sample| sample.with_parents(parents)),
+            );
+            continue;
+        }
+        let FieldName::Plain(field) = field_name else {
             continue;
         };
-        lines.extend(emit_metric(
-            host,
-            prefix,
-            &field_name,
-            &field_value,
-            entry_id.as_deref(),
-        ));
+        let nested = format!("{prefix}_{field}");
+        match val {
+            Value::String(state) if is_state(&field, state) => {
+                let mut labels = parents.to_vec();
+                labels.extend(entry_id.map(|id| ("idx".to_owned(), id.to_owned())));
+                labels.push((field, state.clone()));
+                samples.push(Sample {
+                    name: nested,
+                    labels,
+                    value: 1.0,
+                });
+            }
+            Value::Object(child) => {
+                samples.extend(parse_object(&nested, child, parents, entry_id));
+            }
+            Value::Array(children) => {
+                let mut parents = parents.to_vec();
+                parents.extend(entry_id.map(|id| ("idx".to_owned(), id.to_owned())));
+                for (position, child) in children.iter_mut().enumerate() {
+                    let Some(child) = child.as_object_mut() else {
+                        continue;
+                    };
+                    let mut labels = parents.clone();
+                    let (name, id) = identifier(child)
+                        .unwrap_or_else(|| (format!("{field}_idx"), position.to_string()));
+                    labels.push((name, id));
+                    samples.extend(parse_object(&nested, child, &labels, None));
+                }
+            }
+            _ => {}
+        }
     }
-    lines
+    samples
+}
+
+/// Label of an object in a nested array, from a field like `HashchainIndex`.
+///
+/// The identifier field is removed so it is not also exported as a metric.
+fn identifier(obj: &mut serde_json::Map<String, Value>) -> Option<(String, String)> {
+    let (key, name) = obj.keys().find_map(|key| {
+        let FieldName::Plain(field) = FieldName::parse(key) else {
+            return None;
+        };
+        let name = field.strip_suffix("index")?.trim_end_matches('_');
+        (!name.is_empty()).then(|| (key.clone(), name.to_owned()))
+    })?;
+    let value = match obj.remove(&key)? {
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => s,
+        _ => return None,
+    };
+    Some((name, value))
+}
+
+/// Whether a string field is an enum to export as a state-set series.
+///
+/// Only fields named like a status, state or mode qualify, and only
+/// single-word values, so free text like model names is not exported.
+fn is_state(field: &str, value: &str) -> bool {
+    ["status", "state", "mode"]
+        .iter()
+        .any(|suffix| field.ends_with(suffix))
+        && !value.is_empty()
+        && value.len() <= 32
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// States seen so far per state-set series of one host.
+///
+/// A scrape only reports the current state of an enum field. To export a
+/// proper state set, each state line is completed with a 0 line for every
+/// other state the series has had since the scraper started.
+#[derive(Debug, Default)]
+pub struct StateSets {
+    /// States by series, keyed by the line up to the state label's name.
+    seen: HashMap<String, BTreeSet<String>>,
+}
+
+impl StateSets {
+    /// Record the state lines among `lines` and append the other states.
+    ///
+    /// Must run before extra labels are added, while the state label is
+    /// still the last one.
+    pub fn complete(&mut self, lines: &mut Vec<String>) {
+        let mut others = Vec::new();
+        for line in lines.iter() {
+            let Some((series, state, timestamp)) = split_state_line(line) else {
+                continue;
+            };
+            let states = self.seen.entry(series.to_owned()).or_default();
+            states.insert(state.to_owned());
+            others.extend(
+                states
+                    .iter()
+                    .filter(|s| *s != state)
+                    .map(|s| format!("{series}=\"{s}\"}} 0 {timestamp}")),
+            );
+        }
+        lines.extend(others);
+    }
+}
+
+/// Split a state line `name{...,field="state"} 1 ts` into the line up to
+/// the label name, the state and the timestamp.
+///
+/// State lines are the ones `parse_object` emits: their last label is the
+/// field the metric is named after, and its value passes `is_state`, so it
+/// needs no escaping.
+fn split_state_line(line: &str) -> Option<(&str, &str, &str)> {
+    let (rest, timestamp) = line.rsplit_once(' ')?;
+    let rest = rest.strip_suffix("\"} 1")?;
+    let (series, state) = rest.rsplit_once("=\"")?;
+    let (name, labels) = series.split_once('{')?;
+    let field = labels.rsplit(',').next()?;
+    (name.strip_suffix(field)?.ends_with('_') && is_state(field, state))
+        .then_some((series, state, timestamp))
 }
 
 fn emit_metric(
-    host: &str,
     prefix: &str,
     name: &FieldName,
     value: &FieldValue,
     entry_id: Option<&str>,
-) -> Vec<String> {
+) -> Vec<Sample> {
     // Fan fields should only have scalar RPM values. Skip unexpected arrays.
     if matches!(
         (name, value),
@@ -305,51 +730,42 @@ fn emit_metric(
 
     match value {
         FieldValue::Number(val) => {
-            vec![emit_one(host, &metric, name, entry_id, None, *val)]
+            vec![emit_one(&metric, name, entry_id, None, *val)]
         }
         FieldValue::IndexedArray(arr) => arr
             .iter()
             .enumerate()
-            .map(|(index, val)| emit_one(host, &metric, name, entry_id, Some(index), *val))
+            .map(|(index, val)| emit_one(&metric, name, entry_id, Some(index), *val))
             .collect(),
     }
 }
 
 fn emit_one(
-    host: &str,
     metric: &str,
     name: &FieldName,
     entry_id: Option<&str>,
     array_index: Option<usize>,
     val: f64,
-) -> String {
-    match name {
+) -> Sample {
+    let labels = match name {
         FieldName::Plain(_) => match (array_index, entry_id) {
-            (Some(index), _) => {
-                let idx = index.to_string();
-                metrics::gauge(metric, &[("host", host), ("idx", &idx)], val)
-            }
-            (None, Some(id)) => metrics::gauge(metric, &[("host", host), ("idx", id)], val),
-            (None, None) => metrics::gauge(metric, &[("host", host)], val),
+            (Some(index), _) => vec![("idx".to_owned(), index.to_string())],
+            (None, Some(id)) => vec![("idx".to_owned(), id.to_owned())],
+            (None, None) => Vec::new(),
         },
         FieldName::Indexed { index, .. } => {
-            let board = index.to_string();
+            let board = ("hashboard".to_owned(), index.to_string());
             match array_index {
-                Some(arr_idx) => {
-                    let idx = arr_idx.to_string();
-                    metrics::gauge(
-                        metric,
-                        &[("host", host), ("hashboard", &board), ("idx", &idx)],
-                        val,
-                    )
-                }
-                None => metrics::gauge(metric, &[("host", host), ("hashboard", &board)], val),
+                Some(arr_idx) => vec![board, ("idx".to_owned(), arr_idx.to_string())],
+                None => vec![board],
             }
         }
-        FieldName::Fan { index } => {
-            let idx = index.to_string();
-            metrics::gauge(metric, &[("host", host), ("idx", &idx)], val)
-        }
+        FieldName::Fan { index } => vec![("idx".to_owned(), index.to_string())],
+    };
+    Sample {
+        name: metric.to_owned(),
+        labels,
+        value: val,
     }
 }
 
@@ -365,17 +781,25 @@ pub async fn command_with_param(
     cmd: &str,
     param: Option<&str>,
 ) -> Result<Value> {
-    let addr = format!("{host}:{port}");
+    let request = match param {
+        Some(p) => json!({"command": cmd, "parameter": p}),
+        None => json!({"command": cmd}),
+    };
+    send(host, port, &format!("{request}\n")).await
+}
+
+/// Send a raw JSON request and return the parsed JSON response.
+pub async fn send(host: &str, port: u16, request: &str) -> Result<Value> {
+    let addr = if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    };
 
-    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr))
+    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
         .await
         .map_err(|_| anyhow::anyhow!("connect timeout: {addr}"))??;
 
-    let request = match param {
-        Some(p) => format!("{{\"command\":\"{cmd}\",\"parameter\":\"{p}\"}}\n"),
-        None => format!("{{\"command\":\"{cmd}\"}}\n"),
-    };
-
     stream.write_all(request.as_bytes()).await?;
     stream.shutdown().await?;
 
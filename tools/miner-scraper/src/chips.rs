@@ -0,0 +1,142 @@
+//! Per-chip health from the Antminer `stats` command.
+//!
+//! Antminer-style firmwares report the state of every ASIC of a hashboard as
+//! a string like `" ooooooo ooooooo xooooo-"` in `chain_acsN`, next to the
+//! number of chips found in `chain_acnN`. The generic parser drops the
+//! strings, but a few failing chips are the earliest sign that a hashboard
+//! needs an RMA. This module counts the chips of each board by state and
+//! compares the chip count with what the model should have.
+
+use serde_json::Value;
+
+use crate::cgminer::Sample;
+
+#[cfg(test)]
+#[path = "tests/chips.rs"]
+mod tests;
+
+/// Chips per hashboard of known models, keyed by `model_key`.
+const EXPECTED_CHIPS: &[(&str, u32)] = &[
+    ("s19", 76),
+    ("s19pro", 114),
+    ("s19jpro", 126),
+    ("s19kpro", 77),
+    ("s19xp", 110),
+    ("s21", 108),
+    ("s21pro", 65),
+    ("s21xp", 91),
+    ("s21imm", 156),
+];
+
+/// Chips of one hashboard by state.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ChipCounts {
+    pub ok: u32,
+    pub failed: u32,
+    pub unknown: u32,
+}
+
+impl ChipCounts {
+    /// Decode a `chain_acs` string. `o` is a healthy chip and `x` a failed
+    /// one; spaces only group the chips. Returns None for an empty string,
+    /// which is what firmwares report for an unpopulated slot.
+    pub fn decode(acs: &str) -> Option<Self> {
+        let mut counts = Self::default();
+        for c in acs.chars().filter(|c| !c.is_whitespace()) {
+            match c {
+                'o' | 'O' => counts.ok += 1,
+                'x' | 'X' => counts.failed += 1,
+                _ => counts.unknown += 1,
+            }
+        }
+        (counts != Self::default()).then_some(counts)
+    }
+}
+
+/// Normalize a model name like `Antminer S21 XP` or `Antminer S21 (Vnish
+/// 1.2.7)` to a key of `EXPECTED_CHIPS`, e.g. `s21xp`.
+pub fn model_key(model: &str) -> String {
+    let model = model.split('(').next().unwrap_or(model);
+    let model = model.trim().strip_prefix("Antminer").unwrap_or(model);
+    model
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase()
+}
+
+/// Chips per hashboard the model should have, if it is known.
+pub fn expected_chips(model: &str) -> Option<u32> {
+    let key = model_key(model);
+    EXPECTED_CHIPS
+        .iter()
+        .find(|(model, _)| *model == key)
+        .map(|(_, chips)| *chips)
+}
+
+/// Chip health samples of a `stats` response.
+///
+/// Emits `stats_chain_acs_chips{hashboard,status}` with `status` one of
+/// `ok`, `failed` and `unknown`, and, for known models,
+/// `stats_chain_acn_missing{hashboard}` with the number of chips the board
+/// lacks. Boards beyond `miner_count`, or with no chips and no status
+/// string if the count is not reported, are unpopulated slots and skipped.
+pub fn parse_stats(response: &Value) -> Vec<Sample> {
+    let Some(entries) = response.get("STATS").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+    let expected = entries
+        .iter()
+        .find_map(|entry| entry.get("Type").and_then(Value::as_str))
+        .and_then(expected_chips);
+
+    let mut samples = Vec::new();
+    for entry in entries.iter().filter_map(Value::as_object) {
+        let miner_count = entry.get("miner_count").and_then(Value::as_u64);
+        for board in 1..=16u64 {
+            let acn = entry
+                .get(&format!("chain_acn{board}"))
+                .and_then(|v| match v {
+                    Value::String(s) => s.trim().parse::<u32>().ok(),
+                    _ => v.as_u64().and_then(|n| u32::try_from(n).ok()),
+                });
+            let counts = entry
+                .get(&format!("chain_acs{board}"))
+                .and_then(Value::as_str)
+                .and_then(ChipCounts::decode);
+            let populated = match miner_count {
+                Some(count) => board <= count,
+                None => counts.is_some() || acn.is_some_and(|acn| acn > 0),
+            };
+            if !populated {
+                continue;
+            }
+            let hashboard = board.to_string();
+
+            if let Some(counts) = counts {
+                for (status, value) in [
+                    ("ok", counts.ok),
+                    ("failed", counts.failed),
+                    ("unknown", counts.unknown),
+                ] {
+                    samples.push(Sample {
+                        name: "stats_chain_acs_chips".to_owned(),
+                        labels: vec![
+                            ("hashboard".to_owned(), hashboard.clone()),
+                            ("status".to_owned(), status.to_owned()),
+                        ],
+                        value: f64::from(value),
+                    });
+                }
+            }
+            if let (Some(expected), Some(acn)) = (expected, acn) {
+                samples.push(Sample {
+                    name: "stats_chain_acn_missing".to_owned(),
+                    labels: vec![("hashboard".to_owned(), hashboard)],
+                    value: f64::from(expected.saturating_sub(acn)),
+                });
+            }
+        }
+    }
+    samples
+}
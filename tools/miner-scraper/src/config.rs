@@ -1,8 +1,10 @@
 //! Configuration file parsing and hot reload.
 //!
 //! Reads a TOML config file specifying the listen address, scrape interval,
-//! and target miner IPs. Watches the file with inotify for live changes.
+//! target miners, ranges to discover miners in, access to the control API
+//! and curtailment schedules. Watches the file with inotify for live changes.
 
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 use futures_util::StreamExt;
@@ -10,6 +12,13 @@ use inotify::{Inotify, WatchMask};
 use serde::Deserialize;
 use tokio::sync::watch;
 
+use crate::address::{Address, Host};
+use crate::cgminer::{self, Firmware};
+use crate::control::Action;
+use crate::schedule::Window;
+use crate::schema::Schema;
+use crate::{backend, web};
+
 #[cfg(test)]
 #[path = "tests/config.rs"]
 mod tests;
@@ -22,8 +31,161 @@ pub struct Config {
     #[serde(default = "default_scrape_interval")]
     pub scrape_interval_secs: u64,
 
+    /// Seconds between refreshes of the `miner_info` series.
+    #[serde(default = "default_info_interval")]
+    pub info_interval_secs: u64,
+
+    /// Metric names exported for cgminer fields, see `schema`.
+    #[serde(default)]
+    pub schema: Schema,
+
+    /// Seconds a resolved miner hostname is reused before resolving it again.
+    #[serde(default = "default_dns_ttl")]
+    pub dns_ttl_secs: u64,
+
+    /// Miner addresses, see `address` for the accepted forms.
     #[serde(default)]
     pub targets: Vec<String>,
+
+    /// `[[target]]` tables for miners that need settings of their own.
+    #[serde(default, rename = "target")]
+    pub target_configs: Vec<TargetConfig>,
+
+    /// IP ranges scanned for miners, see `discovery`.
+    #[serde(default)]
+    pub discover: Option<DiscoverConfig>,
+
+    /// The control API, see `control`. Disabled without this section.
+    #[serde(default)]
+    pub control: Option<ControlConfig>,
+
+    /// `[[schedule]]` tables curtailing miners at set times, see `schedule`.
+    #[serde(default, rename = "schedule")]
+    pub schedules: Vec<ScheduleConfig>,
+}
+
+/// One `[[target]]` table.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TargetConfig {
+    pub host: String,
+
+    /// Cgminer API port.
+    #[serde(default = "default_target_port")]
+    pub port: u16,
+
+    /// Firmware to assume instead of detecting it.
+    pub firmware: Option<Firmware>,
+
+    /// How the miner is read, see `backend`.
+    #[serde(default)]
+    pub backend: backend::Kind,
+
+    /// Web API port, for the web backends.
+    #[serde(default = "default_http_port")]
+    pub http_port: u16,
+
+    /// Web API password, for backends that need a session, and admin
+    /// password for control actions on Whatsminers.
+    pub password: Option<String>,
+
+    /// Seconds between scrapes, overriding `scrape_interval_secs`.
+    pub interval_secs: Option<u64>,
+
+    /// Seconds each command may take.
+    pub timeout_secs: Option<u64>,
+
+    /// Static labels added to every line of this target.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl TargetConfig {
+    /// Parse the host, an IP literal or DNS name, into an address.
+    pub fn address(&self) -> anyhow::Result<Address> {
+        let host: Host = self.host.parse()?;
+        Ok(Address::new(host, self.port))
+    }
+}
+
+fn default_target_port() -> u16 {
+    cgminer::DEFAULT_PORT
+}
+
+fn default_http_port() -> u16 {
+    web::DEFAULT_PORT
+}
+
+/// Labels set by the scraper itself, which static labels may not replace.
+const RESERVED_LABELS: [&str; 3] = ["host", "ip", "source"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoverConfig {
+    /// CIDR blocks, address ranges or single addresses.
+    pub ranges: Vec<String>,
+
+    /// Seconds between scans.
+    #[serde(default = "default_discover_interval")]
+    pub interval_secs: u64,
+
+    /// Maximum number of addresses probed at once.
+    #[serde(default = "default_discover_concurrency")]
+    pub concurrency: usize,
+
+    /// Seconds a discovered host is kept after it stops answering.
+    #[serde(default = "default_discover_grace")]
+    pub grace_secs: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ControlConfig {
+    /// Bearer token every control request must carry.
+    pub token: String,
+
+    /// File every action is appended to as a JSON line.
+    pub audit_log: Option<PathBuf>,
+
+    /// Maximum number of miners acted on at once by a bulk action.
+    #[serde(default = "default_control_concurrency")]
+    pub concurrency: usize,
+}
+
+/// One `[[schedule]]` table.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleConfig {
+    /// Value of the `schedule` label, also given as the audited reason.
+    pub name: String,
+
+    /// When the schedule is in force, parsed once when the config is read.
+    #[serde(flatten)]
+    pub window: Window,
+
+    /// `pause`, or `set_power_target` with `watts`.
+    #[serde(flatten)]
+    pub action: Action,
+
+    /// Power target set when a `set_power_target` window closes.
+    pub restore_watts: Option<u32>,
+
+    /// `host` labels of the miners to curtail.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+
+    /// Static labels selecting the miners to curtail, e.g. `site = "north"`.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// The window fields of a `[[schedule]]` table, see `schedule::Window`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WindowConfig {
+    /// When windows open, e.g. `0 17 * * mon-fri`, see `schedule::Cron`.
+    pub cron: String,
+
+    /// Minutes each window stays open.
+    pub duration_mins: u32,
+
+    /// IANA time zone of `cron`, e.g. `Europe/Oslo`; the system one if unset.
+    pub timezone: Option<String>,
 }
 
 pub const DEFAULT_IP: &str = "127.0.0.1";
@@ -39,12 +201,55 @@ fn default_scrape_interval() -> u64 {
     DEFAULT_SCRAPE_INTERVAL_SECS
 }
 
+const DEFAULT_INFO_INTERVAL_SECS: u64 = 300;
+
+fn default_info_interval() -> u64 {
+    DEFAULT_INFO_INTERVAL_SECS
+}
+
+const DEFAULT_DISCOVER_INTERVAL_SECS: u64 = 300;
+
+fn default_discover_interval() -> u64 {
+    DEFAULT_DISCOVER_INTERVAL_SECS
+}
+
+const DEFAULT_DISCOVER_CONCURRENCY: usize = 64;
+
+fn default_discover_concurrency() -> usize {
+    DEFAULT_DISCOVER_CONCURRENCY
+}
+
+const DEFAULT_DISCOVER_GRACE_SECS: u64 = 900;
+
+fn default_discover_grace() -> u64 {
+    DEFAULT_DISCOVER_GRACE_SECS
+}
+
+const DEFAULT_CONTROL_CONCURRENCY: usize = 16;
+
+fn default_control_concurrency() -> usize {
+    DEFAULT_CONTROL_CONCURRENCY
+}
+
+const DEFAULT_DNS_TTL_SECS: u64 = 60;
+
+fn default_dns_ttl() -> u64 {
+    DEFAULT_DNS_TTL_SECS
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             listen: default_listen(),
             scrape_interval_secs: default_scrape_interval(),
+            info_interval_secs: default_info_interval(),
+            schema: Schema::default(),
+            dns_ttl_secs: default_dns_ttl(),
             targets: Vec::new(),
+            target_configs: Vec::new(),
+            discover: None,
+            control: None,
+            schedules: Vec::new(),
         }
     }
 }
@@ -53,8 +258,70 @@ impl Config {
     pub fn load(path: &Path) -> anyhow::Result<Self> {
         let contents = std::fs::read_to_string(path)?;
         let config: Config = toml::from_str(&contents)?;
+        config.validate()?;
         Ok(config)
     }
+
+    /// Reject unparseable addresses, web backends without what they need,
+    /// static labels that are not valid Prometheus label names or that
+    /// would replace a label set by the scraper, a control API anyone
+    /// could use, and schedules that could not be carried out.
+    fn validate(&self) -> anyhow::Result<()> {
+        for (i, schedule) in self.schedules.iter().enumerate() {
+            let name = &schedule.name;
+            if name.trim().is_empty() {
+                anyhow::bail!("schedule {}: name must not be empty", i + 1);
+            }
+            if self.schedules[..i].iter().any(|s| &s.name == name) {
+                anyhow::bail!("schedule {name}: duplicate name");
+            }
+            match schedule.action {
+                Action::Pause => {}
+                Action::SetPowerTarget { .. } if schedule.restore_watts.is_some() => {}
+                Action::SetPowerTarget { .. } => {
+                    anyhow::bail!("schedule {name}: set_power_target needs restore_watts")
+                }
+                _ => anyhow::bail!("schedule {name}: action must be pause or set_power_target"),
+            }
+            if schedule.hosts.is_empty() && schedule.labels.is_empty() {
+                anyhow::bail!("schedule {name}: set hosts or labels");
+            }
+        }
+        if let Some(control) = &self.control {
+            if control.token.trim().is_empty() {
+                anyhow::bail!("control: token must not be empty");
+            }
+            if control.concurrency == 0 {
+                anyhow::bail!("control: concurrency must be at least 1");
+            }
+        }
+        for target in &self.targets {
+            target.parse::<Address>()?;
+        }
+        for target in &self.target_configs {
+            target.address()?;
+            if target.backend == backend::Kind::Vnish && target.password.is_none() {
+                anyhow::bail!("target {}: the vnish backend needs a password", target.host);
+            }
+            if target.firmware == Some(Firmware::AxeOS) && target.backend != backend::Kind::AxeOS {
+                anyhow::bail!(
+                    "target {}: axeos firmware needs the axeos backend",
+                    target.host
+                );
+            }
+            for name in target.labels.keys() {
+                let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if !valid || name.starts_with("__") {
+                    anyhow::bail!("target {}: invalid label name {name:?}", target.host);
+                }
+                if RESERVED_LABELS.contains(&name.as_str()) {
+                    anyhow::bail!("target {}: label {name:?} is reserved", target.host);
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Buffer size for inotify event reads.
@@ -0,0 +1,580 @@
+//! Fleet control API.
+//!
+//! `POST /api/miners/{host}/actions` runs one action on the miner whose
+//! `host` label is `{host}`, and `POST /api/actions` runs it on a list of
+//! miners or on all of them, at most `concurrency` at a time. Requests carry
+//! the `[control]` token as a bearer token; without a `[control]` section the
+//! API answers 404.
+//!
+//! An action becomes one request per firmware, see `plan`: `LuxOS` commands
+//! run within a `logon` session, Whatsminers get btminer privileged
+//! commands, the other cgminer firmwares plain cgminer commands, which the
+//! miner's API allow list must grant write access to, and `AxeOS` boards a
+//! web API call. A dry run detects the firmware and reports the request
+//! without sending it. Every action, dry or not, is logged and appended to
+//! the audit log, as are the actions of the curtailment schedules, see
+//! `apply`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::watch;
+
+use crate::address::Resolver;
+use crate::cgminer::{self, Endpoint, Firmware};
+use crate::config::{Config, ControlConfig};
+use crate::discovery::Discovered;
+use crate::scrape::{self, Target};
+use crate::{btminer, web};
+
+#[cfg(test)]
+#[path = "tests/control.rs"]
+mod tests;
+
+/// Timeout for one action on one miner, from detecting its firmware to its
+/// reply. A `LuxOS` action takes three round trips.
+const ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Something to do to a miner, as posted, e.g.
+/// `{"action": "set_power_target", "watts": 3000}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Restart the miner: the control board on `LuxOS`, Whatsminer and
+    /// `AxeOS`, the mining software over the cgminer API elsewhere.
+    Reboot,
+    /// Stop hashing, keeping the miner reachable.
+    Pause,
+    /// Hash again after a pause.
+    Resume,
+    /// Set the power target of the autotuner.
+    SetPowerTarget { watts: u32 },
+    /// Mine on another configured pool, by its index in `pools`.
+    SwitchPool { pool: u32 },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Reboot => write!(f, "reboot"),
+            Action::Pause => write!(f, "pause"),
+            Action::Resume => write!(f, "resume"),
+            Action::SetPowerTarget { watts } => write!(f, "set_power_target {watts}"),
+            Action::SwitchPool { pool } => write!(f, "switch_pool {pool}"),
+        }
+    }
+}
+
+/// How an action is sent to a miner.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// A cgminer command, e.g. `switchpool` with parameter `1`.
+    Cgminer {
+        command: &'static str,
+        parameter: Option<String>,
+    },
+    /// A `LuxOS` command within a `logon` session, whose ID is prepended to
+    /// the parameter.
+    LuxOS {
+        command: &'static str,
+        parameter: Option<String>,
+    },
+    /// A btminer privileged command, see `btminer::privileged`.
+    Btminer {
+        command: &'static str,
+        params: serde_json::Map<String, Value>,
+    },
+    /// A `POST` to the web API.
+    Web { path: &'static str },
+}
+
+impl fmt::Display for Request {
+    /// Cgminer commands are shown as `command|parameter`, like the cgminer
+    /// API clients take them.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Cgminer { command, parameter } => {
+                write!(f, "cgminer {command}")?;
+                parameter.iter().try_for_each(|p| write!(f, "|{p}"))
+            }
+            Request::LuxOS { command, parameter } => {
+                write!(f, "luxos session {command}")?;
+                parameter.iter().try_for_each(|p| write!(f, "|{p}"))
+            }
+            Request::Btminer { command, params } if params.is_empty() => {
+                write!(f, "btminer {command}")
+            }
+            Request::Btminer { command, params } => {
+                write!(f, "btminer {command} {}", Value::Object(params.clone()))
+            }
+            Request::Web { path } => write!(f, "POST {path}"),
+        }
+    }
+}
+
+/// The request carrying out `action` on `fw`, or None if the firmware has
+/// no way of doing it over the APIs the scraper speaks.
+///
+/// btminer has no pool switch, and the `BOSer` autotuner is only reachable
+/// over gRPC, so `BraiinsOS` takes no power target.
+pub fn plan(fw: Firmware, action: &Action) -> Option<Request> {
+    let cgminer = |command, parameter: Option<String>| Request::Cgminer { command, parameter };
+    let luxos = |command, parameter: Option<String>| Request::LuxOS { command, parameter };
+    let btminer = |command, params: &[(&str, String)]| Request::Btminer {
+        command,
+        params: params
+            .iter()
+            .map(|(k, v)| ((*k).to_owned(), Value::String(v.clone())))
+            .collect(),
+    };
+    let request = match (fw, action) {
+        (Firmware::LuxOS, Action::Reboot) => luxos("rebootdevice", None),
+        (Firmware::LuxOS, Action::Pause) => luxos("curtail", Some("sleep".to_owned())),
+        (Firmware::LuxOS, Action::Resume) => luxos("curtail", Some("wakeup".to_owned())),
+        (Firmware::LuxOS, Action::SetPowerTarget { watts }) => {
+            luxos("powertargetset", Some(watts.to_string()))
+        }
+        (Firmware::LuxOS, Action::SwitchPool { pool }) => {
+            luxos("switchpool", Some(pool.to_string()))
+        }
+        (Firmware::Whatsminer, Action::Reboot) => btminer("reboot", &[]),
+        (Firmware::Whatsminer, Action::Pause) => {
+            btminer("power_off", &[("respbefore", "true".to_owned())])
+        }
+        (Firmware::Whatsminer, Action::Resume) => btminer("power_on", &[]),
+        (Firmware::Whatsminer, Action::SetPowerTarget { watts }) => {
+            btminer("adjust_power_limit", &[("power_limit", watts.to_string())])
+        }
+        (Firmware::Braiins, Action::Pause) => cgminer("pause", None),
+        (Firmware::Braiins, Action::Resume) => cgminer("resume", None),
+        (
+            Firmware::Stock
+            | Firmware::Vnish
+            | Firmware::Mara
+            | Firmware::Avalon
+            | Firmware::Braiins,
+            Action::Reboot,
+        ) => cgminer("restart", None),
+        (
+            Firmware::Stock
+            | Firmware::Vnish
+            | Firmware::Mara
+            | Firmware::Avalon
+            | Firmware::Braiins,
+            Action::SwitchPool { pool },
+        ) => cgminer("switchpool", Some(pool.to_string())),
+        (Firmware::AxeOS, Action::Reboot) => Request::Web {
+            path: "/api/system/restart",
+        },
+        _ => return None,
+    };
+    Some(request)
+}
+
+/// Send a request to `target`, whose address resolved to `ip`, and return
+/// the reply of the miner.
+async fn send(target: &Target, ip: &str, request: &Request) -> Result<Value> {
+    let port = target.address.port;
+    match request {
+        Request::Cgminer { command, parameter } => {
+            let reply =
+                cgminer::command_with_param(ip, port, command, parameter.as_deref()).await?;
+            if let Some(msg) = cgminer::status_error(&reply) {
+                bail!("{command}: {msg}");
+            }
+            Ok(reply)
+        }
+        Request::LuxOS { command, parameter } => {
+            luxos_session(ip, port, command, parameter.as_deref()).await
+        }
+        Request::Btminer { command, params } => {
+            let password = target
+                .password
+                .as_deref()
+                .unwrap_or(btminer::DEFAULT_PASSWORD);
+            btminer::privileged(ip, port, password, command, params).await
+        }
+        Request::Web { path } => web::post(ip, target.http_port, path, None, &json!({})).await,
+    }
+}
+
+/// Run a `LuxOS` command that needs a session: `logon`, the command with the
+/// session ID as its first parameter, then `logoff`.
+///
+/// `LuxOS` allows one session at a time, so the session is closed even if the
+/// command failed.
+async fn luxos_session(
+    host: &str,
+    port: u16,
+    command: &str,
+    parameter: Option<&str>,
+) -> Result<Value> {
+    let logon = cgminer::command(host, port, "logon").await?;
+    if let Some(msg) = cgminer::status_error(&logon) {
+        bail!("logon: {msg}");
+    }
+    let session = logon
+        .pointer("/SESSION/0/SessionID")
+        .and_then(Value::as_str)
+        .context("logon reply without SessionID")?
+        .to_owned();
+
+    let parameter = match parameter {
+        Some(p) => format!("{session},{p}"),
+        None => session.clone(),
+    };
+    let reply = cgminer::command_with_param(host, port, command, Some(&parameter)).await;
+    if let Err(e) = cgminer::command_with_param(host, port, "logoff", Some(&session)).await {
+        log::warn!("logoff from {host} failed: {e:#}");
+    }
+    let reply = reply?;
+    if let Some(msg) = cgminer::status_error(&reply) {
+        bail!("{command}: {msg}");
+    }
+    Ok(reply)
+}
+
+/// An action as posted to a miner.
+#[derive(Debug, Deserialize)]
+struct ActionBody {
+    #[serde(flatten)]
+    action: Action,
+    /// Detect the firmware and plan the request without sending it.
+    #[serde(default)]
+    dry_run: bool,
+    /// Free text for the audit log, e.g. the grid event or a ticket.
+    reason: Option<String>,
+}
+
+/// An action as posted to several miners.
+#[derive(Debug, Deserialize)]
+struct BulkBody {
+    #[serde(flatten)]
+    body: ActionBody,
+    /// `host` labels of the miners to act on.
+    #[serde(default)]
+    hosts: Vec<String>,
+    /// Act on every configured and discovered miner instead.
+    #[serde(default)]
+    all: bool,
+    /// Lower limit of miners acted on at once than the configured one.
+    concurrency: Option<usize>,
+}
+
+/// Outcome of an action on one miner.
+struct Outcome {
+    host: String,
+    status: StatusCode,
+    firmware: Option<Firmware>,
+    request: Option<String>,
+    reply: Option<Value>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn new(host: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            status: StatusCode::OK,
+            firmware: None,
+            request: None,
+            reply: None,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, status: StatusCode, error: String) {
+        self.status = status;
+        self.error = Some(error);
+    }
+
+    /// The outcome as answered and audited.
+    fn to_json(&self, action: &Action, dry_run: bool, reason: Option<&str>) -> Value {
+        json!({
+            "host": self.host,
+            "action": action.to_string(),
+            "dry_run": dry_run,
+            "reason": reason,
+            "firmware": self.firmware.map(|fw| fw.to_string()),
+            "request": self.request,
+            "ok": self.error.is_none(),
+            "error": self.error,
+            "reply": self.reply,
+        })
+    }
+}
+
+/// Shared state of the control API.
+#[derive(Clone)]
+pub struct Control {
+    config: watch::Receiver<Config>,
+    discovered: watch::Receiver<Discovered>,
+}
+
+impl Control {
+    pub fn new(config: watch::Receiver<Config>, discovered: watch::Receiver<Discovered>) -> Self {
+        Self { config, discovered }
+    }
+
+    /// The `[control]` section, if the request carries its token.
+    fn authorize(&self, headers: &HeaderMap) -> Result<ControlConfig, StatusCode> {
+        let Some(control) = self.config.borrow().control.clone() else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        match token {
+            Some(token) if same_token(token.as_bytes(), control.token.as_bytes()) => Ok(control),
+            _ => {
+                log::warn!("control: rejected request without a valid token");
+                Err(StatusCode::UNAUTHORIZED)
+            }
+        }
+    }
+
+    /// Configured and discovered targets, by `host` label.
+    fn targets(&self) -> HashMap<String, Target> {
+        scrape::targets(&self.config.borrow(), &self.discovered.borrow())
+    }
+
+    /// Run `action` on the miner `host`, or only plan it on a dry run.
+    async fn act(
+        &self,
+        host: &str,
+        target: Option<&Target>,
+        action: &Action,
+        dry_run: bool,
+    ) -> Outcome {
+        let mut outcome = Outcome::new(host);
+        let Some(target) = target else {
+            outcome.fail(StatusCode::NOT_FOUND, "unknown host".to_owned());
+            return outcome;
+        };
+        let acted = run(target, action, dry_run, &mut outcome);
+        match tokio::time::timeout(ACTION_TIMEOUT, acted).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => outcome.fail(StatusCode::BAD_GATEWAY, format!("{err:#}")),
+            Err(_) => outcome.fail(StatusCode::GATEWAY_TIMEOUT, "timeout".to_owned()),
+        }
+        outcome
+    }
+}
+
+/// Detect the firmware of `target`, plan `action` and send it unless this
+/// is a dry run. Unsupported actions are recorded in `outcome`.
+async fn run(target: &Target, action: &Action, dry_run: bool, outcome: &mut Outcome) -> Result<()> {
+    let ip = Resolver::new(target.address.clone())
+        .resolve(Duration::ZERO)
+        .await?
+        .to_string();
+    let fw = if let Some(fw) = target.firmware {
+        fw
+    } else {
+        let endpoint = Endpoint {
+            name: &target.name,
+            host: &ip,
+            port: target.port(),
+        };
+        let backend = target.backend.build(target.password.as_deref());
+        backend.detect(endpoint).await
+    };
+    outcome.firmware = Some(fw);
+
+    let Some(request) = plan(fw, action) else {
+        outcome.fail(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("{action} is not supported on {fw}"),
+        );
+        return Ok(());
+    };
+    outcome.request = Some(request.to_string());
+    if !dry_run {
+        outcome.reply = Some(send(target, &ip, &request).await?);
+    }
+    Ok(())
+}
+
+/// Send `action` to a miner whose address resolved to `ip` and whose
+/// firmware is known, as the curtailment schedules do, and audit it with
+/// `reason`.
+pub async fn apply(
+    target: &Target,
+    ip: &str,
+    fw: Firmware,
+    action: &Action,
+    reason: &str,
+    audit_log: Option<&std::path::Path>,
+) -> Result<()> {
+    let mut outcome = Outcome::new(&target.name);
+    outcome.firmware = Some(fw);
+    let sent = async {
+        let request =
+            plan(fw, action).with_context(|| format!("{action} is not supported on {fw}"))?;
+        outcome.request = Some(request.to_string());
+        outcome.reply = Some(send(target, ip, &request).await?);
+        Ok(())
+    };
+    let result = match tokio::time::timeout(ACTION_TIMEOUT, sent).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow!("timeout")),
+    };
+    if let Err(err) = &result {
+        outcome.error = Some(format!("{err:#}"));
+    }
+    audit(audit_log, &outcome.to_json(action, false, Some(reason))).await;
+    result
+}
+
+/// Held while appending to the audit log, so entries do not interleave.
+static AUDIT: Mutex<()> = Mutex::new(());
+
+/// Log an action and append it to the audit log, if one is configured.
+///
+/// The file is written on the blocking thread pool, so a slow disk does not
+/// stall the runtime threads serving scrapes and requests.
+async fn audit(audit_log: Option<&std::path::Path>, entry: &Value) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut entry = entry.clone();
+    entry["time"] = json!(time);
+    log::info!("control: {entry}");
+
+    let Some(path) = audit_log.map(std::path::Path::to_path_buf) else {
+        return;
+    };
+    let written = tokio::task::spawn_blocking(move || {
+        let _guard = AUDIT.lock().unwrap_or_else(PoisonError::into_inner);
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| writeln!(file, "{entry}"))
+            .map_err(|e| anyhow!("failed to write audit log {}: {e}", path.display()))
+    })
+    .await
+    .unwrap_or_else(|e| Err(anyhow!("audit log writer failed: {e}")));
+    if let Err(e) = written {
+        log::error!("{e:#}");
+    }
+}
+
+/// Compare tokens in time independent of where they differ.
+fn same_token(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Miners acted on at once: the requested limit, within the configured one.
+fn concurrency(requested: Option<usize>, configured: usize) -> usize {
+    requested.map_or(configured, |n| n.clamp(1, configured))
+}
+
+fn error(status: StatusCode, msg: &str) -> (StatusCode, Json<Value>) {
+    (status, Json(json!({ "error": msg })))
+}
+
+/// Check the token before parsing the body, so unauthorized clients learn
+/// nothing about the request format.
+fn accept<T: serde::de::DeserializeOwned>(
+    control: &Control,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Result<(ControlConfig, T), (StatusCode, Json<Value>)> {
+    let config = control.authorize(headers).map_err(|status| match status {
+        StatusCode::NOT_FOUND => error(status, "control API is disabled"),
+        _ => error(status, "missing or invalid token"),
+    })?;
+    let body =
+        serde_json::from_slice(body).map_err(|e| error(StatusCode::BAD_REQUEST, &e.to_string()))?;
+    Ok((config, body))
+}
+
+async fn miner_action(
+    State(control): State<Control>,
+    Path(host): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<Value>) {
+    let (config, body): (_, ActionBody) = match accept(&control, &headers, &body) {
+        Ok(accepted) => accepted,
+        Err(rejected) => return rejected,
+    };
+    let target = control.targets().remove(&host);
+    let outcome = control
+        .act(&host, target.as_ref(), &body.action, body.dry_run)
+        .await;
+    let entry = outcome.to_json(&body.action, body.dry_run, body.reason.as_deref());
+    audit(config.audit_log.as_deref(), &entry).await;
+    (outcome.status, Json(entry))
+}
+
+/// Run one action on many miners. Failures of single miners are reported
+/// per miner, so the reply is 200 once the request is accepted.
+async fn bulk_action(
+    State(control): State<Control>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<Value>) {
+    let (config, bulk): (_, BulkBody) = match accept(&control, &headers, &body) {
+        Ok(accepted) => accepted,
+        Err(rejected) => return rejected,
+    };
+    let targets = control.targets();
+    let mut hosts = match (bulk.all, bulk.hosts.is_empty()) {
+        (true, true) => targets.keys().cloned().collect(),
+        (false, false) => bulk.hosts.clone(),
+        _ => return error(StatusCode::BAD_REQUEST, "set either hosts or all"),
+    };
+    hosts.sort_unstable();
+    hosts.dedup();
+
+    let limit = concurrency(bulk.concurrency, config.concurrency);
+    let body = &bulk.body;
+    let results: Vec<Value> = futures_util::stream::iter(hosts)
+        .map(|host| {
+            let (control, config, targets) = (&control, &config, &targets);
+            async move {
+                let outcome = control
+                    .act(&host, targets.get(&host), &body.action, body.dry_run)
+                    .await;
+                let entry = outcome.to_json(&body.action, body.dry_run, body.reason.as_deref());
+                audit(config.audit_log.as_deref(), &entry).await;
+                entry
+            }
+        })
+        .buffered(limit)
+        .collect()
+        .await;
+    let failed = results.iter().filter(|r| r["ok"] != true).count();
+    (
+        StatusCode::OK,
+        Json(json!({ "results": results, "failed": failed })),
+    )
+}
+
+pub fn router(control: Control) -> Router {
+    Router::new()
+        .route("/api/miners/{host}/actions", post(miner_action))
+        .route("/api/actions", post(bulk_action))
+        .with_state(control)
+}
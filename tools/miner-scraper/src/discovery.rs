@@ -0,0 +1,205 @@
+//! Discovery of miners in IP ranges.
+//!
+//! The `[discover]` config section lists CIDR blocks and address ranges.
+//! Every address is probed with the cgminer `version` command, a bounded
+//! number at a time, and the hosts that answer are published to the scrape
+//! loop tagged with the range they were found in. A host that stops
+//! answering stays in the scrape set for a grace period, so a reboot or a
+//! short network outage does not drop its series.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use futures_util::StreamExt;
+use tokio::sync::watch;
+
+use crate::cgminer::{self, DEFAULT_PORT};
+use crate::config::{Config, DiscoverConfig};
+
+#[cfg(test)]
+#[path = "tests/discovery.rs"]
+mod tests;
+
+/// Timeout for probing one address, from connect to parsed reply.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Smallest accepted CIDR prefix. A /16 is already 65534 probes.
+const MIN_PREFIX: u8 = 16;
+
+/// Discovered hosts mapped to the range they were found in.
+pub type Discovered = HashMap<String, String>;
+
+/// Expand a CIDR block, address range or single address.
+///
+/// Accepts `10.0.0.0/24`, `10.0.0.10-10.0.0.50`, the short form
+/// `10.0.0.10-50` and `10.0.0.7`. Network and broadcast addresses of blocks
+/// larger than /31 are skipped.
+pub fn expand(range: &str) -> Result<Vec<Ipv4Addr>> {
+    let range = range.trim();
+    if let Some((addr, prefix)) = range.split_once('/') {
+        let addr: Ipv4Addr = addr
+            .parse()
+            .with_context(|| format!("invalid range {range}"))?;
+        let prefix: u8 = prefix
+            .parse()
+            .ok()
+            .filter(|p| (MIN_PREFIX..=32).contains(p))
+            .with_context(|| format!("invalid prefix in {range}, expected /{MIN_PREFIX} to /32"))?;
+        let mask = u32::MAX.checked_shl(u32::from(32 - prefix)).unwrap_or(0);
+        let first = u32::from(addr) & mask;
+        let last = first | !mask;
+        if prefix >= 31 {
+            return Ok((first..=last).map(Ipv4Addr::from).collect());
+        }
+        return Ok((first + 1..last).map(Ipv4Addr::from).collect());
+    }
+
+    if let Some((start, end)) = range.split_once('-') {
+        let start: Ipv4Addr = start
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid range {range}"))?;
+        let end = end.trim();
+        let end: Ipv4Addr = match end.parse::<u8>() {
+            Ok(octet) => {
+                let [a, b, c, _] = start.octets();
+                Ipv4Addr::new(a, b, c, octet)
+            }
+            Err(_) => end
+                .parse()
+                .with_context(|| format!("invalid range {range}"))?,
+        };
+        let (start, end) = (u32::from(start), u32::from(end));
+        if end < start {
+            bail!("invalid range {range}: end before start");
+        }
+        if end - start >= 1 << (32 - MIN_PREFIX) {
+            bail!("range {range} is larger than a /{MIN_PREFIX}");
+        }
+        return Ok((start..=end).map(Ipv4Addr::from).collect());
+    }
+
+    let addr: Ipv4Addr = range
+        .parse()
+        .with_context(|| format!("invalid range {range}"))?;
+    Ok(vec![addr])
+}
+
+/// Probe every address of the ranges and return the responders with their range.
+///
+/// Invalid ranges are logged and skipped. At most `concurrency` probes are
+/// in flight at a time.
+pub async fn scan(ranges: &[String], port: u16, concurrency: usize) -> Discovered {
+    let mut candidates = Vec::new();
+    for range in ranges {
+        match expand(range) {
+            Ok(addrs) => {
+                candidates.extend(addrs.into_iter().map(|a| (a.to_string(), range.clone())));
+            }
+            Err(e) => log::warn!("discover: {e:#}"),
+        }
+    }
+
+    futures_util::stream::iter(candidates)
+        .map(|(host, range)| async move {
+            let found = probe(&host, port).await;
+            (host, range, found)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(|(host, range, found)| async move { found.then_some((host, range)) })
+        .collect()
+        .await
+}
+
+/// Whether a cgminer API answers `version` on the address.
+async fn probe(host: &str, port: u16) -> bool {
+    match tokio::time::timeout(PROBE_TIMEOUT, cgminer::command(host, port, "version")).await {
+        Ok(Ok(resp)) => cgminer::status_error(&resp).is_none(),
+        _ => false,
+    }
+}
+
+/// Hosts seen by past scans, with the time each last answered.
+#[derive(Default)]
+struct Seen {
+    hosts: HashMap<String, (String, Instant)>,
+}
+
+impl Seen {
+    /// Record a scan and drop hosts not seen within `grace`.
+    fn update(&mut self, found: Discovered, now: Instant, grace: Duration) {
+        for (host, range) in found {
+            self.hosts.insert(host, (range, now));
+        }
+        self.hosts.retain(|host, (_, last_seen)| {
+            let keep = now.duration_since(*last_seen) < grace;
+            if !keep {
+                log::info!("discover: {host} aged out");
+            }
+            keep
+        });
+    }
+
+    fn discovered(&self) -> Discovered {
+        self.hosts
+            .iter()
+            .map(|(host, (range, _))| (host.clone(), range.clone()))
+            .collect()
+    }
+}
+
+/// Rescan the configured ranges until the config channel closes.
+///
+/// The current set of discovered hosts is published on `tx` after every
+/// scan. Removing the `[discover]` section clears it.
+pub async fn run(mut config_rx: watch::Receiver<Config>, tx: watch::Sender<Discovered>) {
+    let mut seen = Seen::default();
+
+    loop {
+        let discover = config_rx.borrow_and_update().discover.clone();
+        let wait = if let Some(discover) = discover {
+            scan_once(&discover, &mut seen, &tx).await;
+            Duration::from_secs(discover.interval_secs.max(1))
+        } else {
+            seen = Seen::default();
+            tx.send_if_modified(|hosts| {
+                let changed = !hosts.is_empty();
+                hosts.clear();
+                changed
+            });
+            Duration::MAX
+        };
+
+        tokio::select! {
+            () = tokio::time::sleep(wait) => {}
+            changed = config_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn scan_once(discover: &DiscoverConfig, seen: &mut Seen, tx: &watch::Sender<Discovered>) {
+    let started = Instant::now();
+    let found = scan(&discover.ranges, DEFAULT_PORT, discover.concurrency).await;
+    log::info!(
+        "discover: {} miners answered in {:.1}s",
+        found.len(),
+        started.elapsed().as_secs_f64()
+    );
+    seen.update(
+        found,
+        Instant::now(),
+        Duration::from_secs(discover.grace_secs),
+    );
+    let discovered = seen.discovered();
+    tx.send_if_modified(|hosts| {
+        let changed = *hosts != discovered;
+        *hosts = discovered;
+        changed
+    });
+}
@@ -0,0 +1,333 @@
+//! Fake cgminer API server answering from captured dumps.
+//!
+//! Serves the replies in `dumps/` for one firmware and model, so the scrape
+//! loop can be run end to end without an ASIC. Joined commands are answered
+//! as the firmware would, and faults seen on real control boards can be
+//! injected: slow replies, `STATUS` `E` replies, truncated replies and
+//! dropped connections. Control commands are acknowledged and recorded, see
+//! `Dumps::received`. Run it with `miner-scraper fake-miner`, or start it
+//! in-process with `serve`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::cgminer::Firmware;
+
+#[cfg(test)]
+#[path = "tests/fake_miner.rs"]
+mod tests;
+
+/// Largest request accepted, far above any cgminer command.
+const MAX_REQUEST: usize = 64 * 1024;
+
+/// Commands that change the miner, as sent by `control`. They are
+/// acknowledged on every firmware.
+const CONTROL_COMMANDS: [&str; 9] = [
+    "logon",
+    "logoff",
+    "curtail",
+    "powertargetset",
+    "rebootdevice",
+    "switchpool",
+    "restart",
+    "pause",
+    "resume",
+];
+
+/// Session ID handed out by `logon`.
+pub const SESSION_ID: &str = "fake-session";
+
+/// Command line arguments of the `fake-miner` subcommand.
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Directory with `<firmware>-cgminer-<command>-<model>.json` dumps.
+    #[arg(long, default_value = "dumps")]
+    pub dumps: PathBuf,
+
+    /// Firmware to impersonate: stock, luxos, vnish, braiins, mara,
+    /// whatsminer or avalon.
+    #[arg(long)]
+    pub firmware: String,
+
+    /// Model suffix of the dump files, e.g. `s21xp`.
+    #[arg(long)]
+    pub model: String,
+
+    /// Address and port to listen on.
+    #[arg(long, default_value = "127.0.0.1:4028")]
+    pub listen: String,
+
+    #[command(flatten)]
+    pub faults: Faults,
+}
+
+/// Faults to inject. An `*_every` of N applies the fault to every Nth
+/// request, counted from the first; 0 disables it.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Faults {
+    /// Milliseconds to wait before every reply.
+    #[arg(long, default_value_t = 0)]
+    pub latency_ms: u64,
+
+    /// Apply `latency_ms` only to requests for this command, joined
+    /// requests that include it too.
+    #[arg(long)]
+    pub slow_command: Option<String>,
+
+    /// Answer every Nth request with a `STATUS` `E` reply.
+    #[arg(long, default_value_t = 0)]
+    pub error_every: u64,
+
+    /// Send only the first half of every Nth reply, without the NUL.
+    #[arg(long, default_value_t = 0)]
+    pub truncate_every: u64,
+
+    /// Close the connection without a reply on every Nth request.
+    #[arg(long, default_value_t = 0)]
+    pub disconnect_every: u64,
+}
+
+/// What to do with one request.
+#[derive(Debug, PartialEq, Eq)]
+enum Fault {
+    None,
+    Error,
+    Truncate,
+    Disconnect,
+}
+
+impl Faults {
+    /// Fault for the `n`th request, counted from 1. Disconnects take
+    /// precedence over truncation, and truncation over errors.
+    fn pick(&self, n: u64) -> Fault {
+        let hits = |every: u64| every > 0 && n.is_multiple_of(every);
+        if hits(self.disconnect_every) {
+            Fault::Disconnect
+        } else if hits(self.truncate_every) {
+            Fault::Truncate
+        } else if hits(self.error_every) {
+            Fault::Error
+        } else {
+            Fault::None
+        }
+    }
+}
+
+/// Replies of one miner, keyed by command.
+pub struct Dumps {
+    firmware: Firmware,
+    replies: HashMap<String, Value>,
+    /// Requests answered or refused so far, for picking faults.
+    requests: AtomicU64,
+    /// Control commands received, as `command|parameter`.
+    received: Mutex<Vec<String>>,
+}
+
+impl Dumps {
+    /// Replies of a firmware, keyed by command.
+    pub fn new(firmware: Firmware, replies: HashMap<String, Value>) -> Self {
+        Self {
+            firmware,
+            replies,
+            requests: AtomicU64::new(0),
+            received: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Load the dumps of a firmware and model, e.g. `luxos` and `s21pro`.
+    pub fn load(dir: &Path, firmware: Firmware, model: &str) -> Result<Self> {
+        let prefix = format!("{firmware}-cgminer-");
+        let suffix = format!("-{model}.json");
+        let mut replies = HashMap::new();
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(command) = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(&suffix))
+            else {
+                continue;
+            };
+            let data = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let reply = serde_json::from_str(&data)
+                .with_context(|| format!("invalid JSON in {}", path.display()))?;
+            replies.insert(command.to_owned(), reply);
+        }
+        if replies.is_empty() {
+            bail!("no dumps for {firmware} {model} in {}", dir.display());
+        }
+        Ok(Self::new(firmware, replies))
+    }
+
+    /// Reply to a command, joined commands included.
+    fn reply(&self, command: &str) -> Value {
+        if !command.contains('+') {
+            return self
+                .replies
+                .get(command)
+                .cloned()
+                .unwrap_or_else(|| status_error("Invalid command"));
+        }
+        if !self.firmware.supports_multi_command() {
+            return status_error("Invalid command");
+        }
+        let mut reply = serde_json::Map::new();
+        for part in command.split('+') {
+            let part_reply = self
+                .replies
+                .get(part)
+                .cloned()
+                .unwrap_or_else(|| status_error("Invalid command"));
+            reply.insert(part.to_owned(), Value::Array(vec![part_reply]));
+        }
+        Value::Object(reply)
+    }
+
+    /// Acknowledge and record a control command, or None for other commands.
+    fn control(&self, command: &str, parameter: Option<&str>) -> Option<Value> {
+        if !CONTROL_COMMANDS.contains(&command) {
+            return None;
+        }
+        let mut received = self.received.lock().unwrap_or_else(PoisonError::into_inner);
+        received.push(match parameter {
+            Some(p) => format!("{command}|{p}"),
+            None => command.to_owned(),
+        });
+        let mut reply =
+            serde_json::json!({"STATUS": [{"STATUS": "S", "Code": 0, "Msg": command}], "id": 1});
+        if command == "logon" {
+            reply["SESSION"] = serde_json::json!([{ "SessionID": SESSION_ID }]);
+        }
+        Some(reply)
+    }
+
+    /// Number of requests received so far, joined commands counting once.
+    #[cfg(test)]
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Control commands received so far, oldest first.
+    #[cfg(test)]
+    pub fn received(&self) -> Vec<String> {
+        self.received
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+fn status_error(msg: &str) -> Value {
+    serde_json::json!({"STATUS": [{"STATUS": "E", "Code": 14, "Msg": msg}], "id": 1})
+}
+
+/// Answer connections on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener, dumps: Arc<Dumps>, faults: Faults) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let n = dumps.requests.fetch_add(1, Ordering::SeqCst) + 1;
+        let fault = faults.pick(n);
+        let dumps = dumps.clone();
+        let faults = faults.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &dumps, fault, &faults).await {
+                log::debug!("fake miner: {e:#}");
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, dumps: &Dumps, fault: Fault, faults: &Faults) -> Result<()> {
+    let request = read_request(&mut stream).await?;
+    // btminer clients may send `cmd` instead of `command`.
+    let command = request
+        .get("command")
+        .or_else(|| request.get("cmd"))
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let parameter = request.get("parameter").and_then(Value::as_str);
+    log::debug!("fake miner: {command} ({fault:?})");
+
+    let slow = faults
+        .slow_command
+        .as_deref()
+        .is_none_or(|slow| command.split('+').any(|part| part == slow));
+    if slow {
+        tokio::time::sleep(Duration::from_millis(faults.latency_ms)).await;
+    }
+    let reply = match fault {
+        Fault::Disconnect => return Ok(()),
+        Fault::Error => status_error("Injected error"),
+        Fault::None | Fault::Truncate => dumps
+            .control(command, parameter)
+            .unwrap_or_else(|| dumps.reply(command)),
+    };
+    let mut body = serde_json::to_vec(&reply)?;
+    if fault == Fault::Truncate {
+        body.truncate(body.len() / 2);
+    } else {
+        body.push(0);
+    }
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Read one JSON request. Clients may or may not close their side after
+/// sending, so stop at the first complete JSON value.
+async fn read_request(stream: &mut TcpStream) -> Result<Value> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        buf.extend_from_slice(&chunk[..n]);
+        let trimmed = buf
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace() && *b != 0)
+            .map_or(&buf[..0], |end| &buf[..=end]);
+        if let Ok(request) = serde_json::from_slice(trimmed) {
+            return Ok(request);
+        }
+        if n == 0 {
+            bail!("connection closed before a complete request");
+        }
+        if buf.len() > MAX_REQUEST {
+            bail!("request too large");
+        }
+    }
+}
+
+/// Run the `fake-miner` subcommand until interrupted.
+pub async fn run(args: Args) -> Result<()> {
+    let firmware: Firmware = serde_json::from_value(Value::String(args.firmware.clone()))
+        .with_context(|| format!("unknown firmware {:?}", args.firmware))?;
+    let dumps = Dumps::load(&args.dumps, firmware, &args.model)?;
+    let mut commands: Vec<&str> = dumps.replies.keys().map(String::as_str).collect();
+    commands.sort_unstable();
+    let listener = TcpListener::bind(&args.listen)
+        .await
+        .with_context(|| format!("failed to bind {}", args.listen))?;
+    log::info!(
+        "fake {firmware} {} on {}, answering {}",
+        args.model,
+        args.listen,
+        commands.join(", ")
+    );
+    tokio::select! {
+        () = serve(listener, Arc::new(dumps), args.faults) => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    Ok(())
+}
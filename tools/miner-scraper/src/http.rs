@@ -1,7 +1,8 @@
 //! HTTP server for Prometheus metric scraping.
 //!
-//! Serves a single `GET /metrics` endpoint that returns all stored miner
-//! metrics in Prometheus text exposition format.
+//! Serves `GET /metrics`, which returns all stored miner metrics in
+//! Prometheus text exposition format, next to the routes of the control
+//! API, see `control`.
 
 use axum::extract::State;
 use axum::http::header;
@@ -9,6 +10,7 @@ use axum::response::IntoResponse;
 use axum::routing::get;
 use axum::Router;
 
+use crate::control::{self, Control};
 use crate::store::MetricsStore;
 
 /// Prometheus text exposition content type.
@@ -19,8 +21,9 @@ async fn metrics(State(store): State<MetricsStore>) -> impl IntoResponse {
     ([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], body)
 }
 
-pub fn router(store: MetricsStore) -> Router {
+pub fn router(store: MetricsStore, control: Control) -> Router {
     Router::new()
         .route("/metrics", get(metrics))
         .with_state(store)
+        .merge(control::router(control))
 }
@@ -0,0 +1,141 @@
+//! Static miner identity as a `miner_info` series.
+//!
+//! Model, firmware and version strings change only on a firmware upgrade or
+//! a swapped control board, so they are fetched at a slower cadence than the
+//! hot metrics and cached by the scrape loop. The result is a single
+//! `miner_info{host,firmware,model,...} 1` series per host that dashboards
+//! join on to filter and group the fleet.
+
+use serde_json::Value;
+
+use crate::cgminer::{self, Firmware};
+use crate::metrics;
+
+#[cfg(test)]
+#[path = "tests/info.rs"]
+mod tests;
+
+/// Version fields naming the mining software, by firmware, in lookup order.
+const FIRMWARE_VERSION_KEYS: [&str; 5] = ["LUXminer", "BOSer", "BMMiner", "CGMiner", "Cgminer"];
+
+/// Identity of a single miner. Empty fields are not reported.
+#[derive(Debug, Default, PartialEq)]
+pub struct MinerInfo {
+    pub model: String,
+    pub api_version: String,
+    pub miner_version: String,
+    pub firmware_version: String,
+    pub mac: String,
+}
+
+impl MinerInfo {
+    /// Collect identity fields from `version`, `devdetails`, `config` and
+    /// `get_version` responses.
+    ///
+    /// Each response is optional; commands a firmware does not support are
+    /// simply missing.
+    pub fn parse(responses: &[Value]) -> Self {
+        let mut info = Self::default();
+        for response in responses {
+            if let Some(version) = response.pointer("/VERSION/0") {
+                string_field(version, "API").clone_into(&mut info.api_version);
+                string_field(version, "Miner").clone_into(&mut info.miner_version);
+                FIRMWARE_VERSION_KEYS
+                    .iter()
+                    .map(|key| string_field(version, key))
+                    .find(|v| !v.is_empty())
+                    .unwrap_or("")
+                    .clone_into(&mut info.firmware_version);
+                // MARA reports a clean Model, Avalon a PROD; others only a
+                // Type with a suffix.
+                let model = ["Model", "PROD", "Type"]
+                    .iter()
+                    .map(|key| string_field(version, key))
+                    .find(|v| !v.is_empty())
+                    .unwrap_or("");
+                set_if_empty(&mut info.model, strip_suffix(model));
+                // Avalon's MAC is bare hex digits.
+                let mac = string_field(version, "MAC");
+                if mac.len() == 12 && mac.chars().all(|c| c.is_ascii_hexdigit()) {
+                    info.mac = mac
+                        .as_bytes()
+                        .chunks(2)
+                        .map(|pair| String::from_utf8_lossy(pair).to_lowercase())
+                        .collect::<Vec<_>>()
+                        .join(":");
+                }
+            }
+            if let Some(details) = response.pointer("/DEVDETAILS/0") {
+                set_if_empty(&mut info.model, string_field(details, "Model"));
+            }
+            // btminer's `get_version` has its fields in a `Msg` object.
+            if let Some(version) = response.get("Msg").filter(|msg| msg.is_object()) {
+                set_if_empty(&mut info.api_version, string_field(version, "api_ver"));
+                set_if_empty(&mut info.firmware_version, string_field(version, "fw_ver"));
+            }
+            if let Some(config) = response.pointer("/CONFIG/0") {
+                set_if_empty(&mut info.model, string_field(config, "Model"));
+                info.mac = string_field(config, "MACAddr").to_lowercase();
+            }
+        }
+        // BOSer has no separate miner version; its daemon version is the closest.
+        if info.miner_version.is_empty() {
+            info.miner_version.clone_from(&info.firmware_version);
+        }
+        info
+    }
+
+    /// Format as a `miner_info` line with value 1.
+    ///
+    /// Called on every hot scrape so the sample timestamp stays current
+    /// while the fields themselves are cached.
+    pub fn to_metric(&self, host: &str, fw: Firmware) -> String {
+        let firmware = fw.to_string();
+        let labels: Vec<(&str, &str)> = [
+            ("host", host),
+            ("firmware", firmware.as_str()),
+            ("model", self.model.as_str()),
+            ("api_version", self.api_version.as_str()),
+            ("miner_version", self.miner_version.as_str()),
+            ("firmware_version", self.firmware_version.as_str()),
+            ("mac", self.mac.as_str()),
+        ]
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+        metrics::gauge("miner_info", &labels, 1.0)
+    }
+}
+
+/// Fetch the identity commands for a host.
+///
+/// A failing command only leaves its fields empty; the series is still
+/// emitted with the firmware known from detection.
+pub async fn scrape(host: &str, port: u16, fw: Firmware) -> MinerInfo {
+    let mut responses = Vec::new();
+    for cmd in fw.info_commands() {
+        match cgminer::command(host, port, cmd).await {
+            Ok(resp) => responses.push(resp),
+            Err(err) => log::debug!("info command {cmd} on {host} failed: {err}"),
+        }
+    }
+    MinerInfo::parse(&responses)
+}
+
+fn string_field<'a>(obj: &'a Value, key: &str) -> &'a str {
+    obj.get(key).and_then(Value::as_str).unwrap_or("").trim()
+}
+
+fn set_if_empty(field: &mut String, value: &str) {
+    if field.is_empty() && !value.is_empty() {
+        value.clone_into(field);
+    }
+}
+
+/// Drop a trailing parenthesized note, as in `Antminer S21 (Vnish 1.2.7)`.
+fn strip_suffix(model: &str) -> &str {
+    match model.find(" (") {
+        Some(i) if model.ends_with(')') => &model[..i],
+        _ => model,
+    }
+}
@@ -5,17 +5,35 @@
 
 use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use clap::Parser;
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 
+mod address;
+mod avalon;
+mod axeos;
+mod backend;
+mod btminer;
+mod capture;
 mod cgminer;
+mod chips;
 mod config;
+mod control;
+mod discovery;
+mod fake_miner;
 mod http;
+mod info;
+mod metadata;
 mod metrics;
+mod pools;
+mod schedule;
+mod schema;
 mod scrape;
 mod store;
+mod vnish;
+mod web;
 
 #[derive(Parser)]
 #[command(about = "Scrape Bitcoin mining hardware metrics for Prometheus")]
@@ -28,9 +46,20 @@ struct Args {
     #[arg(long)]
     ip: Option<IpAddr>,
 
-    /// Target miner IPs to scrape. Overrides the config file.
+    /// Target miner addresses to scrape. Overrides the config file.
     #[arg(long, num_args = 1..)]
     targets: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Record redacted cgminer replies of a miner as test fixtures.
+    Capture(capture::Args),
+    /// Serve captured cgminer replies, for testing without a miner.
+    FakeMiner(fake_miner::Args),
 }
 
 #[tokio::main]
@@ -68,6 +97,11 @@ async fn main() {
 
 async fn run() -> anyhow::Result<()> {
     let args = Args::parse();
+    match args.command {
+        Some(Command::Capture(args)) => return capture::run(args).await,
+        Some(Command::FakeMiner(args)) => return fake_miner::run(args).await,
+        None => {}
+    }
     let mut cfg = config::Config::load(&args.config).unwrap_or_else(|e| {
         log::warn!(
             "config file {}: {}, using defaults",
@@ -84,6 +118,7 @@ async fn run() -> anyhow::Result<()> {
     }
     if !args.targets.is_empty() {
         cfg.targets = args.targets;
+        cfg.target_configs.clear();
     }
 
     // Bind the listener early so we fail fast if the port is in use.
@@ -103,13 +138,23 @@ async fn run() -> anyhow::Result<()> {
         config::watch_config(config_path, watcher_tx).await;
     });
 
+    // Scan the configured ranges for miners.
+    let (discovered_tx, discovered_rx) = watch::channel(discovery::Discovered::new());
+    let discovery_rx = config_rx.clone();
+    let discovery_handle = tokio::spawn(async move {
+        discovery::run(discovery_rx, discovered_tx).await;
+    });
+
+    let control = control::Control::new(config_rx.clone(), discovered_rx.clone());
+
     // Start the scrape loop. Dropping config_tx signals it to stop.
     let scrape_store = metrics_store.clone();
     let scrape_handle = tokio::spawn(async move {
-        scrape::run(config_rx, scrape_store).await;
+        let clock = Arc::new(schedule::SystemClock);
+        scrape::run(config_rx, discovered_rx, scrape_store, clock).await;
     });
 
-    let router = http::router(metrics_store);
+    let router = http::router(metrics_store, control);
     axum::serve(listener, router)
         .with_graceful_shutdown(shutdown_signal())
         .await?;
@@ -117,6 +162,7 @@ async fn run() -> anyhow::Result<()> {
     // Drop the config sender to signal the scrape loop to stop.
     drop(config_tx);
     watcher_handle.abort();
+    discovery_handle.abort();
     if let Err(e) = scrape_handle.await {
         log::error!("scrape task panicked: {e}");
     }
@@ -0,0 +1,266 @@
+//! Metric types and help texts.
+//!
+//! Cgminer reports share, error and work counts as plain numbers, which the
+//! generic parser would export as gauges. This module marks the fields that
+//! only ever increase, per firmware and command section, so they are
+//! exported as counters with a `_total` suffix and `rate()` and
+//! `increase()` handle miner restarts. It also provides the `# HELP` and
+//! `# TYPE` lines written by `MetricsStore::render`.
+
+use std::borrow::Cow;
+
+use crate::cgminer::Firmware;
+
+#[cfg(test)]
+#[path = "tests/metadata.rs"]
+mod tests;
+
+/// Prometheus metric type of a family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Gauge,
+    Counter,
+}
+
+impl Kind {
+    /// Type of a family, from its name: counters end in `_total`.
+    pub fn of(family: &str) -> Self {
+        if family.ends_with("_total") {
+            Kind::Counter
+        } else {
+            Kind::Gauge
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Gauge => "gauge",
+            Kind::Counter => "counter",
+        }
+    }
+}
+
+const ALL: &[Firmware] = &Firmware::ALL;
+
+/// Firmwares with the Antminer `stats` layout.
+const ANTMINER: &[Firmware] = &[
+    Firmware::Stock,
+    Firmware::LuxOS,
+    Firmware::Vnish,
+    Firmware::Mara,
+];
+
+/// Counter fields by firmware and command section, as normalized by
+/// `FieldName::parse`. Indexed fields like `chain_hw1` are listed without
+/// the index.
+const COUNTERS: &[(&[Firmware], &str, &[&str])] = &[
+    (
+        ALL,
+        "summary",
+        &[
+            "accepted",
+            "rejected",
+            "stale",
+            "discarded",
+            "getwork",
+            "getworks",
+            "get_failures",
+            "local_work",
+            "remote_failures",
+            "network_blocks",
+            "found_blocks",
+            "hardware_errors",
+            "difficulty_accepted",
+            "difficulty_rejected",
+            "difficulty_stale",
+            "total_mh",
+            "elapsed",
+        ],
+    ),
+    (
+        ALL,
+        "devs",
+        &[
+            "accepted",
+            "rejected",
+            "hardware_errors",
+            "diff1_work",
+            "difficulty_accepted",
+            "difficulty_rejected",
+            "total_mh",
+            "device_elapsed",
+        ],
+    ),
+    (
+        ANTMINER,
+        "stats",
+        &["elapsed", "chain_hw", "no_matching_work"],
+    ),
+    (&[Firmware::Vnish], "summary", &["hw_errors"]),
+    (&[Firmware::Vnish], "summary_chains", &["hw_errors"]),
+    (
+        &[Firmware::Vnish],
+        "summary_pools",
+        &["accepted", "rejected", "stale"],
+    ),
+    (&[Firmware::Avalon], "stats", &["elapsed"]),
+    (
+        &[Firmware::Avalon],
+        "stats_mm",
+        &["elapsed", "lw", "hw", "dhw"],
+    ),
+    (
+        &[Firmware::AxeOS],
+        "system",
+        &["sharesaccepted", "sharesrejected", "uptimeseconds"],
+    ),
+];
+
+/// Whether a generic metric, e.g. `summary_accepted`, is a counter.
+pub fn is_counter(fw: Firmware, name: &str) -> bool {
+    COUNTERS.iter().any(|(firmwares, section, fields)| {
+        firmwares.contains(&fw)
+            && name
+                .strip_prefix(section)
+                .and_then(|rest| rest.strip_prefix('_'))
+                .is_some_and(|field| fields.contains(&field))
+    })
+}
+
+/// Exported name of a generic metric: counters get a `_total` suffix.
+pub fn raw_name(fw: Firmware, name: &str) -> Cow<'_, str> {
+    if is_counter(fw, name) {
+        Cow::Owned(format!("{name}_total"))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+/// Help texts of the families that are not named after a cgminer field.
+const HELP: &[(&str, &str)] = &[
+    (
+        "miner_up",
+        "Whether any command of the last scrape succeeded.",
+    ),
+    (
+        "miner_scrape_duration_seconds",
+        "Duration of the last scrape.",
+    ),
+    (
+        "miner_scrape_failures_total",
+        "Scrapes in which no command succeeded.",
+    ),
+    (
+        "miner_scrape_command_success",
+        "Whether the command succeeded in the last scrape.",
+    ),
+    (
+        "miner_target_info",
+        "Where the target was configured or discovered.",
+    ),
+    ("miner_info", "Identity of the miner."),
+    (
+        "miner_schedule_active",
+        "Whether a window of the curtailment schedule is open.",
+    ),
+    (
+        "miner_schedule_in_line",
+        "Whether the miner is in the state its schedules want.",
+    ),
+    (
+        "miner_schedule_actions_total",
+        "Actions sent to the miner by its schedules.",
+    ),
+    (
+        "miner_schedule_action_failures_total",
+        "Actions sent by schedules that failed.",
+    ),
+    (
+        "miner_hashrate_hashes_per_second",
+        "Hashrate over the last seconds.",
+    ),
+    (
+        "miner_hashrate_average_hashes_per_second",
+        "Hashrate since the miner started.",
+    ),
+    (
+        "miner_hashrate_expected_hashes_per_second",
+        "Nominal hashrate.",
+    ),
+    (
+        "miner_board_hashrate_hashes_per_second",
+        "Hashrate of a hashboard.",
+    ),
+    (
+        "miner_board_hashrate_expected_hashes_per_second",
+        "Nominal hashrate of a hashboard.",
+    ),
+    ("miner_board_chips", "Chips detected on a hashboard."),
+    (
+        "miner_board_chips_by_status",
+        "Chips of a hashboard by status.",
+    ),
+    (
+        "miner_board_chips_missing",
+        "Chips the model should have that were not detected.",
+    ),
+    (
+        "miner_board_frequency_hertz",
+        "Chip frequency of a hashboard.",
+    ),
+    ("miner_board_voltage_volts", "Voltage of a hashboard."),
+    ("miner_board_power_watts", "Power of a hashboard."),
+    (
+        "miner_board_hardware_errors_total",
+        "Hardware errors of a hashboard.",
+    ),
+    (
+        "miner_board_temperature_celsius",
+        "PCB temperature of a hashboard.",
+    ),
+    (
+        "miner_chip_temperature_celsius",
+        "Chip temperature of a hashboard.",
+    ),
+    ("miner_fan_speed_rpm", "Fan speed."),
+    ("miner_power_watts", "Power of the miner."),
+    ("miner_power_limit_watts", "Power target of the autotuner."),
+    ("miner_shares_accepted_total", "Accepted shares."),
+    ("miner_shares_rejected_total", "Rejected shares."),
+    ("miner_hardware_errors_total", "Hardware errors."),
+    (
+        "miner_uptime_seconds",
+        "Seconds since the mining software started.",
+    ),
+    ("pools_accepted_total", "Accepted shares of a pool."),
+    ("pools_rejected_total", "Rejected shares of a pool."),
+    ("pools_stale_total", "Stale shares of a pool."),
+    (
+        "pools_priority",
+        "Failover priority of a pool, 0 is the primary.",
+    ),
+    ("pools_difficulty", "Current stratum difficulty of a pool."),
+    (
+        "pools_last_share_seconds",
+        "Seconds since the last share to a pool.",
+    ),
+];
+
+/// Format the `# HELP` and `# TYPE` lines of a family.
+pub fn header(family: &str) -> String {
+    let help = HELP.iter().find(|(name, _)| *name == family).map_or_else(
+        || Cow::Owned(format!("Cgminer field {family}.")),
+        |(_, help)| Cow::Borrowed(*help),
+    );
+    format!(
+        "# HELP {family} {help}\n# TYPE {family} {}\n",
+        Kind::of(family).as_str()
+    )
+}
+
+/// Family name of a metric line, the part before the labels or value.
+pub fn family(line: &str) -> &str {
+    line.split(|c: char| c == '{' || c.is_ascii_whitespace())
+        .next()
+        .unwrap_or(line)
+}
@@ -12,8 +12,11 @@ mod tests;
 
 /// Format a gauge metric with labels and a millisecond timestamp.
 ///
-/// Labels are provided as a slice of `(key, value)` pairs. The timestamp is
-/// the current wall-clock time in milliseconds since the Unix epoch.
+/// Labels are provided as a slice of `(key, value)` pairs. Values are escaped,
+/// so strings reported by a miner such as pool URLs are safe to use. The
+/// timestamp is the current wall-clock time in milliseconds since the Unix epoch.
+/// Counter lines look the same; their type is declared from the `_total`
+/// suffix when the store is rendered, see `metadata`.
 pub fn gauge(name: &str, labels: &[(&str, &str)], value: f64) -> String {
     let ts = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -27,7 +30,7 @@ pub fn gauge(name: &str, labels: &[(&str, &str)], value: f64) -> String {
         line.push('{');
         let label_str: String = labels
             .iter()
-            .map(|(key, val)| format!("{key}=\"{val}\""))
+            .map(|(key, val)| format!("{key}=\"{}\"", escape_label(val)))
             .collect::<Vec<_>>()
             .join(",");
         line.push_str(&label_str);
@@ -38,6 +41,54 @@ pub fn gauge(name: &str, labels: &[(&str, &str)], value: f64) -> String {
     line
 }
 
+/// Append extra labels to a formatted metric line.
+///
+/// Used for static per-target labels such as `site` or `rack`, which are
+/// added to every line of a host after parsing.
+pub fn add_labels(line: &str, labels: &[(String, String)]) -> String {
+    if labels.is_empty() {
+        return line.to_owned();
+    }
+    let extra = labels
+        .iter()
+        .map(|(key, val)| format!("{key}=\"{}\"", escape_label(val)))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    let (name, rest) = line.split_at(name_end);
+    let Some(existing) = rest.strip_prefix('{') else {
+        return format!("{name}{{{extra}}}{rest}");
+    };
+    // Find the closing brace outside of quoted label values.
+    let mut in_quotes = false;
+    let mut escaped = false;
+    for (i, c) in existing.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            '}' if !in_quotes => {
+                let (inner, tail) = existing.split_at(i);
+                let sep = if inner.is_empty() { "" } else { "," };
+                return format!("{name}{{{inner}{sep}{extra}{tail}");
+            }
+            _ => {}
+        }
+    }
+    line.to_owned()
+}
+
+/// Escape backslashes, double quotes and newlines in a label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 /// Format a float value, using integer representation when possible.
 fn format_value(v: f64) -> String {
     if v.fract() == 0.0 && v.is_finite() {
@@ -0,0 +1,168 @@
+//! Pool metrics from the cgminer `pools` command.
+//!
+//! The generic parser in `cgminer` drops string fields, but for pools the
+//! strings are what matters: which URL and worker a miner is hashing on, and
+//! whether that pool is alive. Each pool becomes a set of series labelled
+//! with `pool`, `url`, `user`, `status` and `stratum_active`, so a miner
+//! quietly sitting on a dead or backup pool shows up on a dashboard.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+use crate::metrics;
+
+#[cfg(test)]
+#[path = "tests/pools.rs"]
+mod tests;
+
+/// Numeric pool fields exported as-is, with their metric names.
+const COUNTERS: [(&str, &str); 4] = [
+    ("Accepted", "pools_accepted_total"),
+    ("Rejected", "pools_rejected_total"),
+    ("Stale", "pools_stale_total"),
+    ("Priority", "pools_priority"),
+];
+
+/// Parse a `pools` response into Prometheus metric lines.
+pub fn parse_response(host: &str, response: &Value) -> Vec<String> {
+    let Some(pools) = response.get("POOLS").and_then(Value::as_array) else {
+        return Vec::new();
+    };
+
+    let mut lines = Vec::new();
+    for pool in pools {
+        let Some(index) = pool.get("POOL").and_then(Value::as_u64) else {
+            continue;
+        };
+        let index = index.to_string();
+        let url = string_field(pool, "URL");
+        let user = string_field(pool, "User");
+        let status = string_field(pool, "Status").to_lowercase();
+        let stratum_active = pool
+            .get("Stratum Active")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+            .to_string();
+        let labels = [
+            ("host", host),
+            ("pool", index.as_str()),
+            ("url", url),
+            ("user", user),
+            ("status", status.as_str()),
+            ("stratum_active", stratum_active.as_str()),
+        ];
+
+        for (field, metric) in COUNTERS {
+            if let Some(value) = pool.get(field).and_then(Value::as_f64) {
+                lines.push(metrics::gauge(metric, &labels, value));
+            }
+        }
+        if let Some(difficulty) = difficulty(pool) {
+            lines.push(metrics::gauge("pools_difficulty", &labels, difficulty));
+        }
+        let last_share = pool
+            .get("Last Share Time")
+            .and_then(|v| parse_last_share(v, now_secs()));
+        if let Some(seconds) = last_share {
+            lines.push(metrics::gauge("pools_last_share_seconds", &labels, seconds));
+        }
+    }
+    lines
+}
+
+fn string_field<'a>(pool: &'a Value, key: &str) -> &'a str {
+    pool.get(key).and_then(Value::as_str).unwrap_or("")
+}
+
+/// Current stratum difficulty of a pool.
+///
+/// Prefers the numeric `Stratum Difficulty` (`LuxOS`), then the abbreviated
+/// `Diff` string every firmware reports, e.g. `"262K"`.
+fn difficulty(pool: &Value) -> Option<f64> {
+    if let Some(d) = pool.get("Stratum Difficulty").and_then(Value::as_f64) {
+        return Some(d);
+    }
+    parse_si(pool.get("Diff")?.as_str()?)
+}
+
+/// Parse a number with an optional SI suffix, as in `"262K"` or `"1.5M"`.
+fn parse_si(s: &str) -> Option<f64> {
+    let s = s.trim();
+    let (number, multiplier) = match s.chars().last()? {
+        'K' | 'k' => (&s[..s.len() - 1], 1e3),
+        'M' => (&s[..s.len() - 1], 1e6),
+        'G' => (&s[..s.len() - 1], 1e9),
+        'T' => (&s[..s.len() - 1], 1e12),
+        _ => (s, 1.0),
+    };
+    number.parse::<f64>().ok().map(|n| n * multiplier)
+}
+
+/// Seconds since the last accepted share, from the `Last Share Time` field.
+///
+/// Firmwares disagree on the format: an elapsed `H:MM:SS` (stock, `LuxOS`), a
+/// Go duration such as `5.13s` (MARA), or a Unix timestamp (upstream
+/// cgminer). Zero means no share was ever submitted and yields None.
+fn parse_last_share(value: &Value, now: f64) -> Option<f64> {
+    let seconds = match value {
+        Value::Number(n) => since_epoch(n.as_f64()?, now)?,
+        Value::String(s) if s.contains(':') => parse_hms(s)?,
+        Value::String(s) => match s.parse::<f64>() {
+            Ok(n) => since_epoch(n, now)?,
+            Err(_) => parse_go_duration(s)?,
+        },
+        _ => return None,
+    };
+    Some(seconds)
+}
+
+/// Convert a Unix timestamp to elapsed seconds. Zero means never.
+fn since_epoch(timestamp: f64, now: f64) -> Option<f64> {
+    if timestamp <= 0.0 {
+        return None;
+    }
+    Some((now - timestamp).max(0.0))
+}
+
+/// Parse `H:MM:SS` or `MM:SS` into seconds.
+fn parse_hms(s: &str) -> Option<f64> {
+    s.split(':').try_fold(0.0, |total, part| {
+        part.trim().parse::<f64>().ok().map(|n| total * 60.0 + n)
+    })
+}
+
+/// Parse a Go `time.Duration` string such as `1m5.2s` or `350ms` into seconds.
+fn parse_go_duration(s: &str) -> Option<f64> {
+    let mut total = 0.0;
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    while !rest.is_empty() {
+        let unit_start = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let number: f64 = rest[..unit_start].parse().ok()?;
+        let unit_end = rest[unit_start..]
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .map_or(rest.len(), |i| unit_start + i);
+        let scale = match &rest[unit_start..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 1e-3,
+            "us" | "µs" => 1e-6,
+            "ns" => 1e-9,
+            _ => return None,
+        };
+        total += number * scale;
+        rest = &rest[unit_end..];
+    }
+    Some(total)
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
@@ -0,0 +1,491 @@
+//! Time-of-use curtailment.
+//!
+//! `[[schedule]]` tables open windows of `duration_mins` at cron times, e.g.
+//! `0 17 * * mon-fri`, in which the miners they select are paused or held at
+//! a power target. Each scrape loop reconciles its miner with the
+//! schedules after every scrape, see `Curtailment`: entering a window sends
+//! the action, leaving it resumes the miner or restores `restore_watts`, and
+//! a miner found out of line, e.g. hashing again after a reboot, gets the
+//! action again once `RETRY_AFTER` has passed. Actions are sent and audited
+//! as the control API does, see `control::apply`; an action the firmware
+//! cannot carry out is not sent at all.
+//!
+//! Time comes from a `Clock`, so windows can be tested with a mocked one.
+
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use jiff::civil::DateTime;
+use jiff::tz::TimeZone;
+use jiff::{SignedDuration, Timestamp};
+use serde::Deserialize;
+
+use crate::cgminer::Firmware;
+use crate::config::{ScheduleConfig, WindowConfig};
+use crate::control::{self, Action};
+use crate::metrics;
+use crate::scrape::Target;
+
+#[cfg(test)]
+#[path = "tests/schedule.rs"]
+mod tests;
+
+/// Longest window, a week.
+const MAX_DURATION_MINS: u32 = 7 * 24 * 60;
+
+/// Time to wait for an action to show in the scrapes before sending it
+/// again. Hashrate is averaged over seconds and the autotuner ramps slowly.
+const RETRY_AFTER: SignedDuration = SignedDuration::from_mins(5);
+
+/// Source of the current time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Timestamp;
+}
+
+/// The system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::now()
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A cron expression: minute, hour, day of month, month and day of week.
+///
+/// Each field is `*` or a list of values and ranges, optionally stepped,
+/// e.g. `*/15`, `8-18/2` or `mon,wed`. Months and days of week may be
+/// given by name, and Sunday is 0 or 7. As in cron, a day matches if either
+/// day field does when both are restricted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether both day fields are restricted.
+    either_day: bool,
+}
+
+impl FromStr for Cron {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("cron {s:?}: expected 5 fields, got {}", fields.len());
+        };
+        let field = |field: &str, min, max, names: &[&str]| {
+            parse_field(field, min, max, names).with_context(|| format!("cron {s:?}"))
+        };
+        let mut weekdays = field(weekday, 0, 7, &WEEKDAYS)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            minutes: field(minute, 0, 59, &[])?,
+            hours: field(hour, 0, 23, &[])?,
+            days: field(day, 1, 31, &[])?,
+            months: field(month, 1, 12, &MONTHS)?,
+            weekdays,
+            either_day: !day.starts_with('*') && !weekday.starts_with('*'),
+        })
+    }
+}
+
+/// Parse one field into a bit set of its values. Names are numbered from
+/// `min`.
+fn parse_field(field: &str, min: u8, max: u8, names: &[&str]) -> Result<u64> {
+    let value = |s: &str| -> Result<u8> {
+        let n = match names.iter().position(|n| s.eq_ignore_ascii_case(n)) {
+            Some(i) => u8::try_from(i)? + min,
+            None => s.parse().with_context(|| format!("invalid value {s:?}"))?,
+        };
+        if !(min..=max).contains(&n) {
+            bail!("{n} is out of range {min}-{max}");
+        }
+        Ok(n)
+    };
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse().context("invalid step")?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            bail!("step must be at least 1");
+        }
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // `5/15` runs from 5 to the end, like `5-59/15`.
+            None if step > 1 => (value(range)?, max),
+            None => {
+                let n = value(range)?;
+                (n, n)
+            }
+        };
+        if first > last {
+            bail!("range {range:?} runs backwards");
+        }
+        for n in (first..=last).step_by(step) {
+            bits |= 1 << n;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    /// Whether the minute of `t` is a cron time.
+    pub fn matches(&self, t: DateTime) -> bool {
+        let has = |bits: u64, n: i8| bits & (1 << n) != 0;
+        let day = has(self.days, t.day());
+        let weekday = has(self.weekdays, t.weekday().to_sunday_zero_offset());
+        let day = if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        };
+        day && has(self.minutes, t.minute())
+            && has(self.hours, t.hour())
+            && has(self.months, t.month())
+    }
+}
+
+/// When a schedule is in force.
+///
+/// Parsed from the `cron`, `duration_mins` and `timezone` of a
+/// `[[schedule]]` table when the config is read, so an invalid window
+/// rejects the config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "WindowConfig")]
+pub struct Window {
+    cron: Cron,
+    duration_mins: u32,
+    tz: TimeZone,
+}
+
+impl TryFrom<WindowConfig> for Window {
+    type Error = anyhow::Error;
+
+    /// Serde shows only the top of the error, so the chain is flattened.
+    fn try_from(config: WindowConfig) -> Result<Self> {
+        Self::new(&config).map_err(|e| anyhow!("{e:#}"))
+    }
+}
+
+impl Window {
+    pub fn new(config: &WindowConfig) -> Result<Self> {
+        if !(1..=MAX_DURATION_MINS).contains(&config.duration_mins) {
+            bail!("duration_mins must be within 1-{MAX_DURATION_MINS}");
+        }
+        let tz = match &config.timezone {
+            Some(name) => TimeZone::get(name).with_context(|| format!("time zone {name:?}"))?,
+            None => TimeZone::system(),
+        };
+        Ok(Self {
+            cron: config.cron.parse()?,
+            duration_mins: config.duration_mins,
+            tz,
+        })
+    }
+
+    /// Whether a window is open at `now`: a cron time, in local time, lies
+    /// within the last `duration_mins` minutes. Windows are measured on the
+    /// wall clock, so one spanning a DST change is an hour shorter or longer.
+    pub fn is_open(&self, now: Timestamp) -> bool {
+        let local = now.to_zoned(self.tz.clone()).datetime();
+        (0..i64::from(self.duration_mins)).any(|mins| {
+            local
+                .checked_sub(SignedDuration::from_mins(mins))
+                .is_ok_and(|t| self.cron.matches(t))
+        })
+    }
+}
+
+/// Whether `schedule` applies to `target`: it lists its `host` label, or
+/// sets labels the target has.
+pub fn selects(schedule: &ScheduleConfig, target: &Target) -> bool {
+    schedule.hosts.contains(&target.name)
+        || (!schedule.labels.is_empty()
+            && schedule
+                .labels
+                .iter()
+                .all(|(k, v)| target.labels.iter().any(|(tk, tv)| tk == k && tv == v)))
+}
+
+/// State a miner is put in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Normal,
+    Paused,
+    PowerTarget(u32),
+}
+
+/// What the schedules selecting a miner want of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wanted {
+    state: State,
+    /// Names of the schedules with an open window.
+    active: Vec<String>,
+    /// Power target to restore, the highest `restore_watts` of the
+    /// schedules.
+    restore_watts: Option<u32>,
+    /// Whether any of the schedules pauses.
+    pauses: bool,
+}
+
+impl Wanted {
+    /// Combine the schedules selecting a miner at `now`. Pausing wins over
+    /// power targets, and the lowest power target over higher ones.
+    pub fn at(schedules: &[&ScheduleConfig], now: Timestamp) -> Self {
+        let mut wanted = Self {
+            state: State::Normal,
+            active: Vec::new(),
+            restore_watts: schedules.iter().filter_map(|s| s.restore_watts).max(),
+            pauses: schedules.iter().any(|s| s.action == Action::Pause),
+        };
+        for schedule in schedules {
+            if !schedule.window.is_open(now) {
+                continue;
+            }
+            wanted.active.push(schedule.name.clone());
+            wanted.state = match (wanted.state, &schedule.action) {
+                (_, Action::Pause) | (State::Paused, _) => State::Paused,
+                (State::PowerTarget(w), Action::SetPowerTarget { watts }) => {
+                    State::PowerTarget(w.min(*watts))
+                }
+                (_, Action::SetPowerTarget { watts }) => State::PowerTarget(*watts),
+                (state, _) => state,
+            };
+        }
+        wanted
+    }
+}
+
+/// What a scrape shows of the state a schedule controls. Fields are None
+/// if the firmware does not report them or the schema is `raw`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Live {
+    hashing: Option<bool>,
+    power_limit: Option<u32>,
+}
+
+impl Live {
+    /// Read the canonical hashrate and power limit series of a scrape.
+    pub fn from_lines<'a>(lines: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut live = Self::default();
+        for line in lines {
+            // Lines end in the value and the timestamp.
+            let value = || line.rsplit(' ').nth(1).and_then(|v| v.parse::<f64>().ok());
+            if line.starts_with("miner_hashrate_hashes_per_second{") {
+                live.hashing = value().map(|v| v > 0.0);
+            } else if line.starts_with("miner_power_limit_watts{") {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let watts = value().map(|v| v.round() as u32);
+                live.power_limit = watts;
+            }
+        }
+        live
+    }
+}
+
+/// Curtailment of one miner, kept by `scrape::run` across restarts of its
+/// scrape loop.
+pub struct Curtailment {
+    clock: Arc<dyn Clock>,
+    /// State the miner was last put in; None until the first reconcile.
+    applied: Option<State>,
+    /// `restore_watts` in force when the miner was last put in that state,
+    /// so it is restored even if its schedule is changed or removed before
+    /// the window closes.
+    restore_watts: Option<u32>,
+    /// When the last action was sent, and the state it was for.
+    last: Option<(Timestamp, State)>,
+    /// Action the firmware cannot carry out, already warned about.
+    unsupported: Option<Action>,
+    actions_total: u32,
+    failures_total: u32,
+}
+
+impl Curtailment {
+    /// A miner nothing was done to yet, whose windows follow `clock`.
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            applied: None,
+            restore_watts: None,
+            last: None,
+            unsupported: None,
+            actions_total: 0,
+            failures_total: 0,
+        }
+    }
+
+    /// Whether the miner is where `wanted` wants it, as far as the scrape
+    /// shows.
+    fn in_line(&self, wanted: &Wanted, live: Live) -> bool {
+        match (wanted.state, self.applied) {
+            (State::Normal, Some(applied)) => applied == State::Normal,
+            // Curtailed before the scraper started, or by hand.
+            (State::Normal, None) => self.restore(wanted, live).is_none(),
+            (State::Paused, applied) => {
+                applied == Some(State::Paused) && live.hashing != Some(true)
+            }
+            (State::PowerTarget(watts), applied) => {
+                applied == Some(State::PowerTarget(watts))
+                    && live.power_limit.is_none_or(|limit| limit == watts)
+            }
+        }
+    }
+
+    /// Note that the miner was put where `wanted` wants it.
+    fn record(&mut self, wanted: &Wanted) {
+        self.applied = Some(wanted.state);
+        self.restore_watts = wanted.restore_watts;
+    }
+
+    /// Action ending a curtailment, after what was applied or, before
+    /// anything was, after what the scrape shows.
+    fn restore(&self, wanted: &Wanted, live: Live) -> Option<Action> {
+        let resume = Some(Action::Resume);
+        let power = |watts: Option<u32>| watts.map(|watts| Action::SetPowerTarget { watts });
+        match self.applied {
+            Some(State::Normal) => None,
+            Some(State::Paused) => resume,
+            Some(State::PowerTarget(_)) => power(self.restore_watts),
+            None if wanted.pauses && live.hashing == Some(false) => resume,
+            None => power(wanted.restore_watts).filter(|_| {
+                live.power_limit
+                    .is_some_and(|limit| Some(limit) != wanted.restore_watts)
+            }),
+        }
+    }
+
+    /// Action bringing the miner into line with `wanted` at `now`, if one
+    /// is due.
+    pub fn next(&self, wanted: &Wanted, live: Live, now: Timestamp) -> Option<Action> {
+        if self.in_line(wanted, live) {
+            return None;
+        }
+        if let Some((at, state)) = self.last {
+            if state == wanted.state && now.duration_since(at) < RETRY_AFTER {
+                return None;
+            }
+        }
+        match wanted.state {
+            State::Normal => self.restore(wanted, live),
+            State::Paused => Some(Action::Pause),
+            State::PowerTarget(watts) => Some(Action::SetPowerTarget { watts }),
+        }
+    }
+
+    /// Whether `fw` can carry out `action`. An action it cannot is warned
+    /// about once rather than sent, failed and audited on every retry.
+    fn supported(&mut self, host: &str, fw: Firmware, action: &Action) -> bool {
+        if control::plan(fw, action).is_some() {
+            self.unsupported = None;
+            return true;
+        }
+        if self.unsupported.as_ref() != Some(action) {
+            log::warn!("{host}: {action} is not supported on {fw}, not sending it");
+            self.unsupported = Some(action.clone());
+        }
+        false
+    }
+
+    /// Bring `target` into line with the schedules selecting it and return
+    /// its schedule series. `miner` is its IP and firmware while it is up;
+    /// a miner that is down is only reported on. Miners no schedule ever
+    /// selected have no series.
+    pub async fn reconcile(
+        &mut self,
+        schedules: &[&ScheduleConfig],
+        target: &Target,
+        miner: Option<(IpAddr, Firmware)>,
+        live: Live,
+        audit_log: Option<&Path>,
+    ) -> Vec<String> {
+        if schedules.is_empty() && self.applied.is_none() {
+            return Vec::new();
+        }
+        let host = target.name.as_str();
+        let now = self.clock.now();
+        let wanted = Wanted::at(schedules, now);
+        if let Some((ip, fw)) = miner {
+            match self.next(&wanted, live, now) {
+                Some(action) if self.supported(host, fw, &action) => {
+                    let reason = if wanted.active.is_empty() {
+                        "schedule ended".to_owned()
+                    } else {
+                        format!("schedule {}", wanted.active.join(","))
+                    };
+                    log::info!("{host}: {action} for {reason}");
+                    self.last = Some((now, wanted.state));
+                    self.actions_total += 1;
+                    let ip = ip.to_string();
+                    match control::apply(target, &ip, fw, &action, &reason, audit_log).await {
+                        Ok(()) => self.record(&wanted),
+                        Err(e) => {
+                            log::warn!("{host}: {action} failed: {e:#}");
+                            self.failures_total += 1;
+                        }
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    self.unsupported = None;
+                    if self.applied.is_none() && wanted.state == State::Normal {
+                        self.record(&wanted);
+                    }
+                }
+            }
+        }
+
+        let mut lines: Vec<String> = schedules
+            .iter()
+            .map(|schedule| {
+                let active = wanted.active.contains(&schedule.name);
+                metrics::gauge(
+                    "miner_schedule_active",
+                    &[("host", host), ("schedule", &schedule.name)],
+                    f64::from(u8::from(active)),
+                )
+            })
+            .collect();
+        let labels = [("host", host)];
+        if miner.is_some() {
+            lines.push(metrics::gauge(
+                "miner_schedule_in_line",
+                &labels,
+                f64::from(u8::from(self.in_line(&wanted, live))),
+            ));
+            lines.push(metrics::gauge(
+                "miner_schedule_applicable",
+                &labels,
+                f64::from(u8::from(self.unsupported.is_none())),
+            ));
+        }
+        lines.push(metrics::gauge(
+            "miner_schedule_actions_total",
+            &labels,
+            f64::from(self.actions_total),
+        ));
+        lines.push(metrics::gauge(
+            "miner_schedule_action_failures_total",
+            &labels,
+            f64::from(self.failures_total),
+        ));
+        lines
+    }
+}
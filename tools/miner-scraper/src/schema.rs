@@ -0,0 +1,575 @@
+//! Canonical metric schema shared by all firmwares.
+//!
+//! The generic parser names metrics after the cgminer fields, so the same
+//! quantity is `stats_temp_chip` on stock firmware and `temps_chip` on
+//! `BraiinsOS`, and hashrate is in GH/s on one and MH/s on another. This
+//! module maps each firmware's samples onto one set of `miner_*` metrics in
+//! base units, so a dashboard needs a single query for the whole fleet.
+//! The generic output stays available with `schema = "raw"` or `"both"`.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::cgminer::{Firmware, Sample};
+use crate::{metadata, metrics};
+
+#[cfg(test)]
+#[path = "tests/schema.rs"]
+mod tests;
+
+/// Which metric names to export for cgminer fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Schema {
+    /// Only the canonical `miner_*` metrics.
+    #[default]
+    Canonical,
+    /// Only the generic metrics named after the cgminer fields.
+    Raw,
+    /// Both, e.g. while migrating dashboards.
+    Both,
+}
+
+/// How a raw value converts to base units.
+#[derive(Clone, Copy)]
+enum Scale {
+    Factor(f64),
+    /// A hashrate in the unit given by the `rate_unit` field of `stats`.
+    RateUnit,
+}
+
+/// How a canonical label gets its value.
+#[derive(Clone, Copy)]
+enum Label {
+    /// Copied from a label of the raw sample.
+    From(&'static str, &'static str),
+    /// A constant, for firmwares with fewer sensors than the schema.
+    Fixed(&'static str, &'static str),
+}
+
+/// Mapping of one raw metric onto a canonical one.
+struct Rule {
+    raw: &'static str,
+    name: &'static str,
+    scale: Scale,
+    labels: &'static [Label],
+}
+
+const fn rule(
+    raw: &'static str,
+    name: &'static str,
+    scale: Scale,
+    labels: &'static [Label],
+) -> Rule {
+    Rule {
+        raw,
+        name,
+        scale,
+        labels,
+    }
+}
+
+const ONE: Scale = Scale::Factor(1.0);
+const MEGA: Scale = Scale::Factor(1e6);
+
+const NONE: &[Label] = &[];
+const BOARD: &[Label] = &[Label::From("hashboard", "board")];
+const BOARD_SENSOR: &[Label] = &[
+    Label::From("hashboard", "board"),
+    Label::From("idx", "sensor"),
+];
+const BOARD_STATUS: &[Label] = &[
+    Label::From("hashboard", "board"),
+    Label::From("status", "status"),
+];
+const FAN: &[Label] = &[Label::From("idx", "fan")];
+/// `BOSer` and btminer report boards as entries with an `ID` rather than an
+/// index suffix, and Avalon as arrays.
+const ENTRY_BOARD: &[Label] = &[Label::From("idx", "board")];
+const ENTRY_BOARD_SENSOR: &[Label] = &[Label::From("idx", "board"), Label::Fixed("sensor", "0")];
+/// The Vnish web API labels chains and chips by their `id`.
+const CHAIN: &[Label] = &[Label::From("chain", "board")];
+const CHAIN_SENSOR: &[Label] = &[Label::From("chain", "board"), Label::Fixed("sensor", "0")];
+const CHAIN_CHIP: &[Label] = &[Label::From("chain", "board"), Label::From("chip", "sensor")];
+const FAN_ID: &[Label] = &[Label::From("fan", "fan")];
+/// `AxeOS` boards are one board.
+const SINGLE_BOARD: &[Label] = &[Label::Fixed("board", "0")];
+const SINGLE_BOARD_SENSOR: &[Label] = &[Label::Fixed("board", "0"), Label::Fixed("sensor", "0")];
+const SINGLE_FAN: &[Label] = &[Label::Fixed("fan", "0")];
+/// Whatsminers have one intake and one exhaust fan, numbered in that order.
+const FAN_IN: &[Label] = &[Label::Fixed("fan", "0")];
+const FAN_OUT: &[Label] = &[Label::Fixed("fan", "1")];
+
+/// The Antminer `stats` layout used by stock, `LuxOS`, Vnish and MARA.
+const ANTMINER_STATS: &[Rule] = &[
+    rule(
+        "stats_ghs_5s",
+        "miner_hashrate_hashes_per_second",
+        Scale::RateUnit,
+        NONE,
+    ),
+    rule(
+        "stats_ghs_av",
+        "miner_hashrate_average_hashes_per_second",
+        Scale::RateUnit,
+        NONE,
+    ),
+    rule(
+        "stats_total_rateideal",
+        "miner_hashrate_expected_hashes_per_second",
+        Scale::RateUnit,
+        NONE,
+    ),
+    rule(
+        "stats_chain_rate",
+        "miner_board_hashrate_hashes_per_second",
+        Scale::RateUnit,
+        BOARD,
+    ),
+    rule(
+        "stats_chain_rateideal",
+        "miner_board_hashrate_expected_hashes_per_second",
+        Scale::RateUnit,
+        BOARD,
+    ),
+    rule("stats_chain_acn", "miner_board_chips", ONE, BOARD),
+    rule(
+        "stats_chain_acs_chips",
+        "miner_board_chips_by_status",
+        ONE,
+        BOARD_STATUS,
+    ),
+    rule(
+        "stats_chain_acn_missing",
+        "miner_board_chips_missing",
+        ONE,
+        BOARD,
+    ),
+    rule(
+        "stats_chain_hw",
+        "miner_board_hardware_errors_total",
+        ONE,
+        BOARD,
+    ),
+    rule("stats_freq", "miner_board_frequency_hertz", MEGA, BOARD),
+    rule("stats_freq_avg", "miner_board_frequency_hertz", MEGA, BOARD),
+    rule(
+        "stats_chain_vol",
+        "miner_board_voltage_volts",
+        Scale::Factor(1e-3),
+        BOARD,
+    ),
+    rule(
+        "stats_chain_consumption",
+        "miner_board_power_watts",
+        ONE,
+        BOARD,
+    ),
+    rule(
+        "stats_temp_chip",
+        "miner_chip_temperature_celsius",
+        ONE,
+        BOARD_SENSOR,
+    ),
+    rule(
+        "stats_temp_pcb",
+        "miner_board_temperature_celsius",
+        ONE,
+        BOARD_SENSOR,
+    ),
+    rule("stats_fan", "miner_fan_speed_rpm", ONE, FAN),
+    rule("stats_elapsed", "miner_uptime_seconds", ONE, NONE),
+];
+
+/// Share counters of the cgminer `summary` command.
+const SUMMARY: &[Rule] = &[
+    rule("summary_accepted", "miner_shares_accepted_total", ONE, NONE),
+    rule("summary_rejected", "miner_shares_rejected_total", ONE, NONE),
+    rule(
+        "summary_hardware_errors",
+        "miner_hardware_errors_total",
+        ONE,
+        NONE,
+    ),
+];
+
+const LUXOS: &[Rule] = &[rule("power_watts", "miner_power_watts", ONE, NONE)];
+
+const BRAIINS: &[Rule] = &[
+    rule(
+        "summary_mhs_5s",
+        "miner_hashrate_hashes_per_second",
+        MEGA,
+        NONE,
+    ),
+    rule(
+        "summary_mhs_av",
+        "miner_hashrate_average_hashes_per_second",
+        MEGA,
+        NONE,
+    ),
+    rule("summary_elapsed", "miner_uptime_seconds", ONE, NONE),
+    rule(
+        "devs_mhs_5s",
+        "miner_board_hashrate_hashes_per_second",
+        MEGA,
+        ENTRY_BOARD,
+    ),
+    rule(
+        "devs_nominal_mhs",
+        "miner_board_hashrate_expected_hashes_per_second",
+        MEGA,
+        ENTRY_BOARD,
+    ),
+    rule(
+        "devs_hardware_errors",
+        "miner_board_hardware_errors_total",
+        ONE,
+        ENTRY_BOARD,
+    ),
+    rule(
+        "temps_chip",
+        "miner_chip_temperature_celsius",
+        ONE,
+        ENTRY_BOARD_SENSOR,
+    ),
+    rule(
+        "temps_board",
+        "miner_board_temperature_celsius",
+        ONE,
+        ENTRY_BOARD_SENSOR,
+    ),
+    rule("fans_rpm", "miner_fan_speed_rpm", ONE, FAN),
+    rule(
+        "tunerstatus_approximateminerpowerconsumption",
+        "miner_power_watts",
+        ONE,
+        NONE,
+    ),
+    rule(
+        "tunerstatus_powerlimit",
+        "miner_power_limit_watts",
+        ONE,
+        NONE,
+    ),
+];
+
+/// Whatsminer's btminer reports totals in `summary`, with the intake and
+/// exhaust fans as two fields, and each hashboard as a `devs` entry.
+const WHATSMINER: &[Rule] = &[
+    rule(
+        "summary_mhs_5s",
+        "miner_hashrate_hashes_per_second",
+        MEGA,
+        NONE,
+    ),
+    rule(
+        "summary_mhs_av",
+        "miner_hashrate_average_hashes_per_second",
+        MEGA,
+        NONE,
+    ),
+    rule(
+        "summary_factory_ghs",
+        "miner_hashrate_expected_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule("summary_elapsed", "miner_uptime_seconds", ONE, NONE),
+    rule("summary_power", "miner_power_watts", ONE, NONE),
+    rule("summary_power_limit", "miner_power_limit_watts", ONE, NONE),
+    rule("summary_fan_speed_in", "miner_fan_speed_rpm", ONE, FAN_IN),
+    rule("summary_fan_speed_out", "miner_fan_speed_rpm", ONE, FAN_OUT),
+    rule(
+        "devs_mhs_5s",
+        "miner_board_hashrate_hashes_per_second",
+        MEGA,
+        ENTRY_BOARD,
+    ),
+    rule(
+        "devs_effective_chips",
+        "miner_board_chips",
+        ONE,
+        ENTRY_BOARD,
+    ),
+    rule(
+        "devs_chip_frequency",
+        "miner_board_frequency_hertz",
+        MEGA,
+        ENTRY_BOARD,
+    ),
+    rule(
+        "devs_hardware_errors",
+        "miner_board_hardware_errors_total",
+        ONE,
+        ENTRY_BOARD,
+    ),
+    rule(
+        "devs_chip_temp_avg",
+        "miner_chip_temperature_celsius",
+        ONE,
+        ENTRY_BOARD_SENSOR,
+    ),
+    rule(
+        "devs_temperature",
+        "miner_board_temperature_celsius",
+        ONE,
+        ENTRY_BOARD_SENSOR,
+    ),
+];
+
+/// Avalon reports everything in the `MM ID0` string of `estats`, in GH/s,
+/// with per-board values as arrays. Like `BOSer`, it has one chip
+/// temperature per board.
+const AVALON: &[Rule] = &[
+    rule(
+        "stats_mm_ghsspd",
+        "miner_hashrate_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule(
+        "stats_mm_ghsavg",
+        "miner_hashrate_average_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule(
+        "stats_mm_mghs",
+        "miner_board_hashrate_hashes_per_second",
+        Scale::Factor(1e9),
+        ENTRY_BOARD,
+    ),
+    rule(
+        "stats_mm_mtavg",
+        "miner_chip_temperature_celsius",
+        ONE,
+        ENTRY_BOARD_SENSOR,
+    ),
+    rule("stats_mm_fan", "miner_fan_speed_rpm", ONE, FAN),
+    rule("stats_mm_elapsed", "miner_uptime_seconds", ONE, NONE),
+    rule("stats_mm_wallpower", "miner_power_watts", ONE, NONE),
+    rule("stats_mm_mpo", "miner_power_limit_watts", ONE, NONE),
+];
+
+/// Vnish's web API, see `vnish`. Hashrates are in GH/s, chains and chips
+/// are labelled by their `id`.
+const VNISH_WEB: &[Rule] = &[
+    rule(
+        "summary_instant_hashrate",
+        "miner_hashrate_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule(
+        "summary_average_hashrate",
+        "miner_hashrate_average_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule(
+        "summary_hr_stock",
+        "miner_hashrate_expected_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule(
+        "summary_chains_hashrate_rt",
+        "miner_board_hashrate_hashes_per_second",
+        Scale::Factor(1e9),
+        CHAIN,
+    ),
+    rule(
+        "summary_chains_hashrate_ideal",
+        "miner_board_hashrate_expected_hashes_per_second",
+        Scale::Factor(1e9),
+        CHAIN,
+    ),
+    rule(
+        "summary_chains_frequency",
+        "miner_board_frequency_hertz",
+        MEGA,
+        CHAIN,
+    ),
+    rule(
+        "summary_chains_voltage",
+        "miner_board_voltage_volts",
+        Scale::Factor(1e-3),
+        CHAIN,
+    ),
+    rule(
+        "summary_chains_power_consumption",
+        "miner_board_power_watts",
+        ONE,
+        CHAIN,
+    ),
+    rule(
+        "summary_chains_hw_errors",
+        "miner_board_hardware_errors_total",
+        ONE,
+        CHAIN,
+    ),
+    rule(
+        "summary_chains_pcb_temp_max",
+        "miner_board_temperature_celsius",
+        ONE,
+        CHAIN_SENSOR,
+    ),
+    rule(
+        "chips_chains_chips_temp",
+        "miner_chip_temperature_celsius",
+        ONE,
+        CHAIN_CHIP,
+    ),
+    rule(
+        "summary_cooling_fans_rpm",
+        "miner_fan_speed_rpm",
+        ONE,
+        FAN_ID,
+    ),
+    rule("summary_power_consumption", "miner_power_watts", ONE, NONE),
+    rule(
+        "summary_hw_errors",
+        "miner_hardware_errors_total",
+        ONE,
+        NONE,
+    ),
+];
+
+/// `AxeOS` boards have a single ASIC, or a few on one board, and one fan.
+const AXEOS: &[Rule] = &[
+    rule(
+        "system_hashrate",
+        "miner_hashrate_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule(
+        "system_expectedhashrate",
+        "miner_hashrate_expected_hashes_per_second",
+        Scale::Factor(1e9),
+        NONE,
+    ),
+    rule("system_asiccount", "miner_board_chips", ONE, SINGLE_BOARD),
+    rule(
+        "system_frequency",
+        "miner_board_frequency_hertz",
+        MEGA,
+        SINGLE_BOARD,
+    ),
+    rule(
+        "system_corevoltageactual",
+        "miner_board_voltage_volts",
+        Scale::Factor(1e-3),
+        SINGLE_BOARD,
+    ),
+    rule(
+        "system_temp",
+        "miner_chip_temperature_celsius",
+        ONE,
+        SINGLE_BOARD_SENSOR,
+    ),
+    rule("system_fanrpm", "miner_fan_speed_rpm", ONE, SINGLE_FAN),
+    rule("system_power", "miner_power_watts", ONE, NONE),
+    rule(
+        "system_sharesaccepted",
+        "miner_shares_accepted_total",
+        ONE,
+        NONE,
+    ),
+    rule(
+        "system_sharesrejected",
+        "miner_shares_rejected_total",
+        ONE,
+        NONE,
+    ),
+    rule("system_uptimeseconds", "miner_uptime_seconds", ONE, NONE),
+];
+
+/// Rule tables of a firmware. The first matching rule wins.
+fn rules(fw: Firmware) -> &'static [&'static [Rule]] {
+    match fw {
+        Firmware::Stock | Firmware::Mara => &[ANTMINER_STATS, SUMMARY],
+        Firmware::Vnish => &[ANTMINER_STATS, SUMMARY, VNISH_WEB],
+        Firmware::LuxOS => &[ANTMINER_STATS, LUXOS],
+        Firmware::Braiins => &[BRAIINS, SUMMARY],
+        Firmware::Whatsminer => &[WHATSMINER, SUMMARY],
+        Firmware::Avalon => &[AVALON, SUMMARY],
+        Firmware::AxeOS => &[AXEOS],
+    }
+}
+
+/// Format the samples of one command according to the schema.
+pub fn render(
+    host: &str,
+    fw: Firmware,
+    samples: &[Sample],
+    rate_unit: f64,
+    schema: Schema,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    if schema != Schema::Raw {
+        lines.extend(normalize(host, fw, samples, rate_unit));
+    }
+    if schema != Schema::Canonical {
+        lines.extend(
+            samples
+                .iter()
+                .map(|sample| sample.to_line(&metadata::raw_name(fw, &sample.name), host)),
+        );
+    }
+    lines
+}
+
+/// Map samples onto canonical metric lines. Unmapped samples are dropped.
+pub fn normalize(host: &str, fw: Firmware, samples: &[Sample], rate_unit: f64) -> Vec<String> {
+    let tables = rules(fw);
+    let mut lines = Vec::new();
+    for sample in samples {
+        let Some(rule) = tables
+            .iter()
+            .flat_map(|table| table.iter())
+            .find(|rule| rule.raw == sample.name)
+        else {
+            continue;
+        };
+        let value = match rule.scale {
+            Scale::Factor(factor) => sample.value * factor,
+            Scale::RateUnit => sample.value * rate_unit,
+        };
+        let mut labels = vec![("host", host)];
+        for label in rule.labels {
+            match *label {
+                Label::From(raw, name) => {
+                    if let Some(value) = sample.label(raw) {
+                        labels.push((name, value));
+                    }
+                }
+                Label::Fixed(name, value) => labels.push((name, value)),
+            }
+        }
+        lines.push(metrics::gauge(rule.name, &labels, value));
+    }
+    lines
+}
+
+/// Hashes per second of one unit of the `rate_unit` field in `stats`.
+///
+/// Antminer-style firmwares report `"rate_unit": "GH"`; GH/s is assumed
+/// when the field is missing or unknown.
+pub fn rate_unit(response: &Value) -> f64 {
+    let unit = response
+        .get("STATS")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .find_map(|entry| entry.get("rate_unit").and_then(Value::as_str));
+    match unit.map(|u| u.trim().to_ascii_uppercase()).as_deref() {
+        Some("H" | "H/S") => 1.0,
+        Some("KH" | "KH/S") => 1e3,
+        Some("MH" | "MH/S") => 1e6,
+        Some("TH" | "TH/S") => 1e12,
+        Some("PH" | "PH/S") => 1e15,
+        _ => 1e9,
+    }
+}
@@ -1,116 +1,502 @@
 //! Per-target scrape loops.
 //!
 //! Each target gets an independent scrape loop that detects firmware on first
-//! contact and caches the result. The main function manages loop lifecycle:
+//! contact and caches the result, and keeps the miner in line with the
+//! curtailment schedules. The main function manages loop lifecycle:
 //! spawning new loops when targets appear and cancelling them when removed.
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::time::Duration;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tokio::sync::watch;
+use tokio::sync::{watch, Mutex};
 use tokio::task::JoinHandle;
 
-use crate::cgminer::{self, Firmware};
-use crate::config::Config;
+use crate::address::{Address, Resolver};
+use crate::backend::{self, Backend};
+use crate::cgminer::{self, CommandResult, Firmware};
+use crate::config::{Config, TargetConfig};
+use crate::discovery::Discovered;
+use crate::schedule::{self, Clock, Curtailment, Live};
+use crate::schema::Schema;
 use crate::store::MetricsStore;
+use crate::{info, metrics, web};
 
-/// Number of consecutive failures before clearing the firmware cache for a host.
+/// Number of consecutive failures before clearing the firmware cache and the
+/// stale metrics of a host.
 const MAX_FAILURES: u32 = 3;
 
-/// Timeout for a single host scrape.
+/// Timeout for refreshing the miner identity.
 /// Prevents slow miners from delaying the next scrape cycle.
 const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
 
-/// Manage per-target scrape loops, spawning and cancelling as the config changes.
-pub async fn run(mut config_rx: watch::Receiver<Config>, store: MetricsStore) {
-    let mut tasks: HashMap<String, JoinHandle<()>> = HashMap::new();
+/// Source of targets listed in the config file rather than discovered.
+const CONFIG_SOURCE: &str = "config";
+
+/// A miner to scrape, resolved from the config or from discovery.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    /// Value of the `host` label, see `Address::name`.
+    pub name: String,
+    pub address: Address,
+    pub firmware: Option<Firmware>,
+    pub backend: backend::Kind,
+    pub http_port: u16,
+    pub password: Option<String>,
+    /// Scrape interval; None follows `scrape_interval_secs`.
+    pub interval: Option<Duration>,
+    pub timeout: Duration,
+    pub labels: Vec<(String, String)>,
+    pub source: String,
+}
+
+impl Target {
+    fn new(address: Address, source: &str) -> Self {
+        Self {
+            name: address.name(),
+            address,
+            firmware: None,
+            backend: backend::Kind::default(),
+            http_port: web::DEFAULT_PORT,
+            password: None,
+            interval: None,
+            timeout: cgminer::COMMAND_TIMEOUT,
+            labels: Vec::new(),
+            source: source.to_owned(),
+        }
+    }
+
+    fn from_config(config: &TargetConfig, address: Address) -> Self {
+        Self {
+            name: address.name(),
+            address,
+            firmware: config.firmware,
+            backend: config.backend,
+            http_port: config.http_port,
+            password: config.password.clone(),
+            interval: config.interval_secs.map(Duration::from_secs),
+            timeout: config
+                .timeout_secs
+                .map_or(cgminer::COMMAND_TIMEOUT, Duration::from_secs),
+            labels: config
+                .labels
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            source: CONFIG_SOURCE.to_owned(),
+        }
+    }
+
+    /// Port of the API its backend reads.
+    pub fn port(&self) -> u16 {
+        if self.backend.is_web() {
+            self.http_port
+        } else {
+            self.address.port
+        }
+    }
+
+    /// Format the `miner_target_info` line recording where the target came from.
+    fn info_line(&self) -> String {
+        let labels = [
+            ("host", self.name.as_str()),
+            ("source", self.source.as_str()),
+        ];
+        metrics::gauge("miner_target_info", &labels, 1.0)
+    }
+
+    /// Labels added to every line: the resolved IP, then the static labels.
+    fn extra_labels(&self, ip: Option<IpAddr>) -> Vec<(String, String)> {
+        let ip = ip.map(|ip| ("ip".to_owned(), ip.to_string()));
+        ip.into_iter().chain(self.labels.iter().cloned()).collect()
+    }
+}
+
+/// Manage per-target scrape loops, spawning and cancelling as the config
+/// and the set of discovered hosts change.
+///
+/// A target whose settings change is restarted with the new settings. Its
+/// curtailment is kept across the restart, so a miner the change takes out
+/// of a schedule is still restored. Schedules are evaluated against `clock`.
+pub async fn run(
+    mut config_rx: watch::Receiver<Config>,
+    mut discovered_rx: watch::Receiver<Discovered>,
+    store: MetricsStore,
+    clock: Arc<dyn Clock>,
+) {
+    let mut tasks: HashMap<String, (Target, JoinHandle<()>)> = HashMap::new();
+    let mut curtailments: HashMap<String, Arc<Mutex<Curtailment>>> = HashMap::new();
 
     loop {
-        let config = config_rx.borrow_and_update().clone();
+        let targets = targets(
+            &config_rx.borrow_and_update(),
+            &discovered_rx.borrow_and_update(),
+        );
 
-        // Cancel tasks for removed targets.
+        // Cancel tasks for removed targets and for targets whose settings changed.
         let stale: Vec<String> = tasks
-            .keys()
-            .filter(|host| !config.targets.contains(host))
-            .cloned()
+            .iter()
+            .filter(|(name, (target, _))| targets.get(*name) != Some(target))
+            .map(|(name, _)| name.clone())
             .collect();
-        for host in stale {
-            if let Some(handle) = tasks.remove(&host) {
+        for name in stale {
+            if let Some((_, handle)) = tasks.remove(&name) {
                 handle.abort();
             }
-            store.remove(&host).await;
-            log::info!("removed stale host {host}");
+            // A restarted target may have dropped labels; start from scratch.
+            store.remove(&name).await;
+            if !targets.contains_key(&name) {
+                log::info!("removed stale host {name}");
+                curtailments.remove(&name);
+            }
         }
 
         // Spawn loops for new targets.
-        for target in &config.targets {
-            if !tasks.contains_key(target) {
+        for (name, target) in targets {
+            if let Entry::Vacant(entry) = tasks.entry(name) {
+                let curtailment = curtailments
+                    .entry(entry.key().clone())
+                    .or_insert_with(|| Arc::new(Mutex::new(Curtailment::new(clock.clone()))));
                 let handle = tokio::spawn(scrape_loop(
                     target.clone(),
                     config_rx.clone(),
                     store.clone(),
+                    curtailment.clone(),
                 ));
-                tasks.insert(target.clone(), handle);
+                entry.insert((target, handle));
             }
         }
 
-        if config_rx.changed().await.is_err() {
+        let closed = tokio::select! {
+            changed = config_rx.changed() => changed.is_err(),
+            changed = discovered_rx.changed() => changed.is_err(),
+        };
+        if closed {
             log::info!("config channel closed, stopping scrape loops");
             break;
         }
     }
 
-    for (_, handle) in tasks {
+    for (_, (_, handle)) in tasks {
         handle.abort();
     }
 }
 
+/// Combine configured and discovered targets, keyed by `host` label.
+///
+/// `[[target]]` tables take precedence over `targets`, and both over
+/// discovered hosts. Addresses that do not parse are logged and skipped;
+/// the config file is validated on load, but CLI targets are not.
+pub fn targets(config: &Config, discovered: &Discovered) -> HashMap<String, Target> {
+    let mut found = Vec::new();
+    for (host, range) in discovered {
+        found.push(host.parse().map(|address| Target::new(address, range)));
+    }
+    for host in &config.targets {
+        found.push(
+            host.parse()
+                .map(|address| Target::new(address, CONFIG_SOURCE)),
+        );
+    }
+    for target in &config.target_configs {
+        found.push(
+            target
+                .address()
+                .map(|address| Target::from_config(target, address)),
+        );
+    }
+
+    let mut targets = HashMap::new();
+    for target in found {
+        match target {
+            Ok(target) => {
+                targets.insert(target.name.clone(), target);
+            }
+            Err(e) => log::warn!("skipping target: {e:#}"),
+        }
+    }
+    targets
+}
+
+/// Store section holding the health series of a host.
+const HEALTH_SECTION: &str = "health";
+
+/// Store section holding the `miner_info` series of a host.
+const INFO_SECTION: &str = "miner_info";
+
+/// Store section holding the `miner_target_info` series of a host.
+const TARGET_SECTION: &str = "target";
+
+/// Store section holding the schedule series of a host.
+const SCHEDULE_SECTION: &str = "schedule";
+
+/// Scrape health of one host, exported next to its metrics.
+#[derive(Default)]
+struct HostHealth {
+    failures_total: u32,
+    consecutive_failures: u32,
+    /// Consecutive failures per command, for dropping stale sections.
+    command_failures: HashMap<&'static str, u32>,
+}
+
+impl HostHealth {
+    /// Split the results of a scrape into the sections to store, with their
+    /// state sets completed, and the outcome of each command. A section is
+    /// removed from the store once its command has failed `MAX_FAILURES`
+    /// times in a row.
+    async fn record(
+        &mut self,
+        host: &str,
+        results: Vec<CommandResult>,
+        states: &mut cgminer::StateSets,
+        store: &MetricsStore,
+    ) -> (Vec<(&'static str, Vec<String>)>, Vec<(&'static str, bool)>) {
+        let mut sections = Vec::new();
+        let mut commands = Vec::with_capacity(results.len());
+        for result in results {
+            let cmd = result.command;
+            match result.lines {
+                Ok(mut lines) => {
+                    states.complete(&mut lines);
+                    self.command_failures.remove(cmd);
+                    sections.push((cmd, lines));
+                    commands.push((cmd, true));
+                }
+                Err(err) => {
+                    log::warn!("scrape {host} command {cmd} failed: {err:#}");
+                    let failures = self.command_failures.entry(cmd).or_default();
+                    *failures += 1;
+                    if *failures == MAX_FAILURES {
+                        store.remove_section(host, cmd).await;
+                    }
+                    commands.push((cmd, false));
+                }
+            }
+        }
+        (sections, commands)
+    }
+}
+
 /// Independent scrape loop for a single target.
-async fn scrape_loop(host: String, config_rx: watch::Receiver<Config>, store: MetricsStore) {
-    let mut firmware: Option<Firmware> = None;
-    let mut failure_count: u32 = 0;
+///
+/// Each command's lines are stored as a separate section. A failed command
+/// keeps its previous section, with the original timestamps, until it has
+/// failed `MAX_FAILURES` times in a row. The host counts as up while any
+/// command succeeds. After `MAX_FAILURES` scrapes with no successful command,
+/// the firmware cache is cleared and only the health series remain.
+///
+/// The miner identity is refreshed every `info_interval_secs`; its
+/// `miner_info` line is rewritten on every hot scrape in between.
+/// `miner_target_info` records whether the host was configured or
+/// discovered, and in which range. The resolved IP, as an `ip` label, and
+/// the static labels of the target are added to every line.
+///
+/// After every scrape the miner is reconciled with the schedules selecting
+/// it, see `schedule::Curtailment`.
+async fn scrape_loop(
+    target: Target,
+    config_rx: watch::Receiver<Config>,
+    store: MetricsStore,
+    curtailment: Arc<Mutex<Curtailment>>,
+) {
+    let host = &target.name;
+    let backend = target.backend.build(target.password.as_deref());
+    let mut resolver = Resolver::new(target.address.clone());
+    let mut firmware = target.firmware;
+    let mut health = HostHealth::default();
+    let mut miner_info: Option<(Instant, info::MinerInfo)> = None;
+    let mut states = cgminer::StateSets::default();
 
     loop {
-        let interval = Duration::from_secs(config_rx.borrow().scrape_interval_secs);
+        let (interval, info_interval, dns_ttl, schema) = {
+            let config = config_rx.borrow();
+            (
+                target
+                    .interval
+                    .unwrap_or(Duration::from_secs(config.scrape_interval_secs)),
+                Duration::from_secs(config.info_interval_secs),
+                Duration::from_secs(config.dns_ttl_secs),
+                config.schema,
+            )
+        };
 
-        match scrape_host(&host, firmware).await {
-            Ok((fw, lines)) => {
-                firmware = Some(fw);
-                failure_count = 0;
-                store.update(&host, lines).await;
-            }
-            Err(err) => {
-                log::warn!("scrape {host} failed: {err}");
-                failure_count += 1;
-                if failure_count >= MAX_FAILURES {
-                    log::info!("clearing firmware cache for {host} after {failure_count} failures");
-                    firmware = None;
-                    failure_count = 0;
+        let started = Instant::now();
+        let previous_fw = firmware;
+        let ip = resolver
+            .resolve(dns_ttl)
+            .await
+            .inspect_err(|e| log::warn!("scrape {host}: {e:#}"))
+            .ok();
+        let results = match ip {
+            Some(ip) => scrape_host(&target, backend.as_ref(), ip, schema, &mut firmware).await,
+            None => Vec::new(),
+        };
+        let duration = started.elapsed();
+
+        let (mut sections, commands) = health.record(host, results, &mut states, &store).await;
+
+        let up = !sections.is_empty();
+        if up {
+            health.consecutive_failures = 0;
+            let info_due = firmware != previous_fw
+                || miner_info
+                    .as_ref()
+                    .is_none_or(|(at, _)| at.elapsed() >= info_interval);
+            if let (true, Some(fw), Some(ip)) = (info_due, firmware, ip) {
+                if let Some(fetched) = fetch_info(&target, backend.as_ref(), ip, fw).await {
+                    miner_info = Some((Instant::now(), fetched));
                 }
             }
+        } else {
+            health.failures_total += 1;
+            health.consecutive_failures += 1;
+            if health.consecutive_failures % MAX_FAILURES == 0 {
+                log::info!(
+                    "clearing firmware cache and stale metrics for {host} after {} failures",
+                    health.consecutive_failures
+                );
+                firmware = target.firmware;
+                miner_info = None;
+                health.command_failures.clear();
+                store.remove(host).await;
+            }
+        }
+
+        if let (Some((_, miner_info)), Some(fw)) = (&miner_info, firmware) {
+            sections.push((INFO_SECTION, vec![miner_info.to_metric(host, fw)]));
+        }
+        let miner = ip.zip(firmware).filter(|_| up);
+        let curtailed = curtail(&curtailment, &target, &config_rx, miner, &sections);
+        sections.push((SCHEDULE_SECTION, curtailed.await));
+        sections.push((TARGET_SECTION, vec![target.info_line()]));
+        sections.push((
+            HEALTH_SECTION,
+            health_lines(host, up, duration, &health, &commands),
+        ));
+        let labels = target.extra_labels(ip);
+        for (_, lines) in &mut sections {
+            for line in lines.iter_mut() {
+                *line = metrics::add_labels(line, &labels);
+            }
         }
+        store.merge(host, sections).await;
 
         tokio::time::sleep(interval).await;
     }
 }
 
-/// Scrape a single host, detecting firmware if not cached.
-async fn scrape_host(
+/// Reconcile a miner with the schedules selecting it, after what `sections`
+/// of its last scrape show, and return its schedule series. `miner` is its
+/// IP and firmware while it is up.
+async fn curtail(
+    curtailment: &Mutex<Curtailment>,
+    target: &Target,
+    config_rx: &watch::Receiver<Config>,
+    miner: Option<(IpAddr, Firmware)>,
+    sections: &[(&str, Vec<String>)],
+) -> Vec<String> {
+    let live = Live::from_lines(sections.iter().flat_map(|(_, lines)| lines));
+    let (schedules, audit_log) = {
+        let config = config_rx.borrow();
+        let schedules: Vec<_> = config
+            .schedules
+            .iter()
+            .filter(|s| schedule::selects(s, target))
+            .cloned()
+            .collect();
+        let audit_log = config.control.as_ref().and_then(|c| c.audit_log.clone());
+        (schedules, audit_log)
+    };
+    let schedules: Vec<_> = schedules.iter().collect();
+    curtailment
+        .lock()
+        .await
+        .reconcile(&schedules, target, miner, live, audit_log.as_deref())
+        .await
+}
+
+/// Refresh the identity of a miner, bounded by `SCRAPE_TIMEOUT`.
+async fn fetch_info(
+    target: &Target,
+    backend: &dyn Backend,
+    ip: IpAddr,
+    fw: Firmware,
+) -> Option<info::MinerInfo> {
+    let ip = ip.to_string();
+    let endpoint = cgminer::Endpoint {
+        name: &target.name,
+        host: &ip,
+        port: target.port(),
+    };
+    let scrape = backend.info(endpoint, fw);
+    let fetched = tokio::time::timeout(SCRAPE_TIMEOUT, scrape).await;
+    if fetched.is_err() {
+        log::warn!("info scrape timeout for {}", target.name);
+    }
+    fetched.ok()
+}
+
+/// Format the `miner_up`, duration, failure and per-command series of a host.
+fn health_lines(
     host: &str,
-    cached_fw: Option<Firmware>,
-) -> anyhow::Result<(Firmware, Vec<String>)> {
-    tokio::time::timeout(SCRAPE_TIMEOUT, async {
-        let fw = if let Some(fw) = cached_fw {
-            fw
-        } else {
-            let detected = Firmware::detect(host).await;
-            log::info!("detected {detected} firmware on {host}");
-            detected
-        };
+    up: bool,
+    duration: Duration,
+    health: &HostHealth,
+    commands: &[(&str, bool)],
+) -> Vec<String> {
+    let labels = [("host", host)];
+    let mut lines = vec![
+        metrics::gauge("miner_up", &labels, f64::from(u8::from(up))),
+        metrics::gauge(
+            "miner_scrape_duration_seconds",
+            &labels,
+            duration.as_secs_f64(),
+        ),
+        metrics::gauge(
+            "miner_scrape_failures_total",
+            &labels,
+            f64::from(health.failures_total),
+        ),
+    ];
+    for (cmd, ok) in commands {
+        lines.push(metrics::gauge(
+            "miner_scrape_command_success",
+            &[("host", host), ("command", cmd)],
+            f64::from(u8::from(*ok)),
+        ));
+    }
+    lines
+}
 
-        let lines = cgminer::scrape(host, fw).await?;
-        Ok((fw, lines))
-    })
-    .await
-    .map_err(|_| anyhow::anyhow!("scrape timeout for {host}"))?
+/// Scrape a single target, detecting firmware if not cached or configured.
+///
+/// Detected firmware is stored in `firmware` even if every command then fails.
+async fn scrape_host(
+    target: &Target,
+    backend: &dyn Backend,
+    ip: IpAddr,
+    schema: Schema,
+    firmware: &mut Option<Firmware>,
+) -> Vec<CommandResult> {
+    let ip = ip.to_string();
+    let endpoint = cgminer::Endpoint {
+        name: &target.name,
+        host: &ip,
+        port: target.port(),
+    };
+    let fw = if let Some(fw) = *firmware {
+        fw
+    } else {
+        let detected = backend.detect(endpoint).await;
+        log::info!("detected {detected} firmware on {}", target.name);
+        *firmware = Some(detected);
+        detected
+    };
+    backend.scrape(endpoint, fw, target.timeout, schema).await
 }
+
+#[cfg(test)]
+#[path = "tests/scrape.rs"]
+mod tests;
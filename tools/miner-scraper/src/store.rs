@@ -1,21 +1,29 @@
 //! Per-host Prometheus metrics storage.
 //!
-//! Scrape tasks write metric lines per miner host. The HTTP handler reads all
-//! hosts and concatenates them into a single response.
+//! Scrape tasks write metric lines per miner host, split into sections by
+//! the cgminer command that produced them. A failed command leaves its
+//! section untouched, so one transient error does not blank the host. The
+//! HTTP handler reads all hosts and groups their lines by metric family
+//! into a single response.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 use tokio::sync::RwLock;
 
+use crate::metadata;
+
 #[cfg(test)]
 #[path = "tests/store.rs"]
 mod tests;
 
+/// Metric lines of one host, keyed by section name.
+type Sections = BTreeMap<String, Vec<String>>;
+
 /// Thread-safe store of per-host Prometheus metric lines.
 #[derive(Clone)]
 pub struct MetricsStore {
-    inner: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    inner: Arc<RwLock<HashMap<String, Sections>>>,
 }
 
 impl MetricsStore {
@@ -25,10 +33,21 @@ impl MetricsStore {
         }
     }
 
-    /// Replace all stored metrics for a host with new lines.
-    pub async fn update(&self, host: &str, lines: Vec<String>) {
+    /// Replace the given sections of a host, keeping all other sections.
+    pub async fn merge(&self, host: &str, sections: Vec<(&str, Vec<String>)>) {
         let mut store = self.inner.write().await;
-        store.insert(host.to_owned(), lines);
+        let host_sections = store.entry(host.to_owned()).or_default();
+        for (section, lines) in sections {
+            host_sections.insert(section.to_owned(), lines);
+        }
+    }
+
+    /// Drop one section of a host, e.g. after the command kept failing.
+    pub async fn remove_section(&self, host: &str, section: &str) {
+        let mut store = self.inner.write().await;
+        if let Some(host_sections) = store.get_mut(host) {
+            host_sections.remove(section);
+        }
     }
 
     /// Remove metrics for a host that is no longer in the target list.
@@ -45,10 +64,23 @@ impl MetricsStore {
     }
 
     /// Render all stored metrics into a single Prometheus-compatible response.
+    ///
+    /// The exposition format requires the lines of a family to be adjacent,
+    /// so lines are grouped by family, each under its `# HELP` and `# TYPE`
+    /// lines.
     pub async fn render(&self) -> String {
         let store = self.inner.read().await;
+        let mut families: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for line in store.values().flat_map(BTreeMap::values).flatten() {
+            families
+                .entry(metadata::family(line))
+                .or_default()
+                .push(line);
+        }
+
         let mut output = String::new();
-        for lines in store.values() {
+        for (family, lines) in families {
+            output.push_str(&metadata::header(family));
             for line in lines {
                 output.push_str(line);
                 output.push('\n');
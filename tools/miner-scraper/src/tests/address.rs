@@ -0,0 +1,109 @@
+use super::*;
+
+fn address(s: &str) -> Address {
+    s.parse().expect("BUG: test address is valid")
+}
+
+#[test]
+fn parse_ipv4() {
+    let a = address("10.0.0.1");
+    assert_eq!(a.host, Host::Ip("10.0.0.1".parse().expect("BUG: valid ip")));
+    assert_eq!(a.port, DEFAULT_PORT);
+    assert_eq!(a.name(), "10.0.0.1");
+
+    let a = address("10.0.0.1:4029");
+    assert_eq!(a.port, 4029);
+    assert_eq!(a.name(), "10.0.0.1:4029");
+}
+
+#[test]
+fn parse_ipv6() {
+    let a = address("fe80::1");
+    assert_eq!(a.host, Host::Ip("fe80::1".parse().expect("BUG: valid ip")));
+    assert_eq!(a.port, DEFAULT_PORT);
+    assert_eq!(a.name(), "fe80::1");
+    assert_eq!(a.to_string(), "[fe80::1]:4028");
+
+    assert_eq!(address("[fe80::1]").port, DEFAULT_PORT);
+    let a = address("[fe80::1]:4029");
+    assert_eq!(a.port, 4029);
+    assert_eq!(a.name(), "[fe80::1]:4029");
+}
+
+#[test]
+fn parse_hostname() {
+    let a = address("Miner-7.lan");
+    assert_eq!(a.host, Host::Name("miner-7.lan".to_owned()));
+    assert_eq!(a.name(), "miner-7.lan");
+
+    let a = address("miner-7.lan:4030");
+    assert_eq!(a.port, 4030);
+    assert_eq!(a.name(), "miner-7.lan:4030");
+}
+
+#[test]
+fn reject_invalid_addresses() {
+    for s in [
+        "",
+        "10.0.0.1:",
+        "10.0.0.1:65536",
+        "10.0.0.300",
+        "[fe80::1",
+        "[fe80::1]4029",
+        "-miner.lan",
+        "miner..lan",
+        "miner_7.lan",
+        "http://10.0.0.1",
+    ] {
+        assert!(s.parse::<Address>().is_err(), "{s:?} should be rejected");
+    }
+}
+
+#[tokio::test]
+async fn resolver_passes_ip_literals_through() {
+    let mut resolver = Resolver::new(address("[::1]:4029"));
+    let ip = resolver
+        .resolve(Duration::ZERO)
+        .await
+        .expect("BUG: literal needs no lookup");
+    assert_eq!(ip, IpAddr::from([0u16, 0, 0, 0, 0, 0, 0, 1]));
+}
+
+#[tokio::test]
+async fn resolver_caches_names() {
+    let mut resolver = Resolver::new(address("localhost"));
+    let ip = resolver
+        .resolve(Duration::from_mins(1))
+        .await
+        .expect("BUG: localhost resolves");
+    assert!(ip.is_loopback());
+
+    // A cached entry is used as-is until it expires.
+    let cached = IpAddr::from([192, 0, 2, 1]);
+    resolver.cached = Some((cached, Instant::now()));
+    let again = resolver
+        .resolve(Duration::from_mins(1))
+        .await
+        .expect("BUG: cached");
+    assert_eq!(again, cached);
+
+    let expired = resolver
+        .resolve(Duration::ZERO)
+        .await
+        .expect("BUG: localhost resolves");
+    assert!(expired.is_loopback());
+}
+
+#[tokio::test]
+async fn resolver_keeps_last_ip_on_failure() {
+    let mut resolver = Resolver::new(address("nonexistent.invalid"));
+    assert!(resolver.resolve(Duration::ZERO).await.is_err());
+
+    let last = IpAddr::from([192, 0, 2, 1]);
+    resolver.cached = Some((last, Instant::now()));
+    let ip = resolver
+        .resolve(Duration::ZERO)
+        .await
+        .expect("BUG: falls back to the cached ip");
+    assert_eq!(ip, last);
+}
@@ -0,0 +1,60 @@
+use serde_json::json;
+
+use super::*;
+
+const ESTATS: &str = include_str!("../../dumps/avalon-cgminer-estats-avalonminer1466.json");
+
+#[test]
+fn parse_mm_values() {
+    let mm =
+        parse_mm("Ver[1466-N-24051501_a3b0c8e] Elapsed[5866] FanR[53%] MTavg[77 76 78] SSID[]");
+    assert_eq!(
+        Value::Object(mm),
+        json!({
+            "Ver": "1466-N-24051501_a3b0c8e",
+            "Elapsed": 5866.0,
+            "FanR": 53.0,
+            "MTavg": [77.0, 76.0, 78.0],
+            "SSID": "",
+        })
+    );
+}
+
+#[test]
+fn parse_mm_free_text() {
+    let mm = parse_mm("SYSTEMSTATU[Work: In Work, Hash Board: 3 ] BOOTBY[0x04.00000000]");
+    assert_eq!(mm["SYSTEMSTATU"], "Work: In Work, Hash Board: 3");
+    assert_eq!(mm["BOOTBY"], "0x04.00000000");
+}
+
+#[test]
+fn parse_mm_nested() {
+    let mm = parse_mm("HB0[Temp[60] Volt[303 302]] HB1[Temp[61] Volt[304 301]] TMax[84]");
+    assert_eq!(mm["HB0"], json!({"Temp": 60.0, "Volt": [303.0, 302.0]}));
+    assert_eq!(mm["HB1"]["Temp"], 61.0);
+    assert_eq!(mm["TMax"], 84.0);
+}
+
+#[test]
+fn parse_mm_unclosed_bracket() {
+    let mm = parse_mm("TMax[84] TAvg[77");
+    assert_eq!(Value::Object(mm), json!({"TMax": 84.0}));
+}
+
+#[test]
+fn expand_estats() {
+    let mut estats: Value = serde_json::from_str(ESTATS).expect("BUG: dump data is valid JSON");
+    expand(&mut estats);
+    let entries = estats["STATS"].as_array().expect("BUG: dump has STATS");
+    assert_eq!(entries.len(), 1, "pool entries are dropped");
+    let entry = &entries[0];
+    assert!(entry.get("MM ID0").is_none());
+    let mm = &entry["MM"][0];
+    assert_eq!(mm["MMIndex"], 0);
+    assert_eq!(mm["GHSspd"], 150_183.86);
+    assert_eq!(mm["PVT_T0"].as_array().map(Vec::len), Some(40));
+    assert_eq!(
+        mm["PS"],
+        json!([0.0, 1210.0, 1287.0, 65.0, 3272.0, 1288.0, 3295.0])
+    );
+}
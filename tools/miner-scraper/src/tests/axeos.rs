@@ -0,0 +1,105 @@
+use axum::routing::get;
+use axum::{Json, Router};
+
+use super::*;
+
+const SYSTEM_INFO_GAMMA: &str = include_str!("../../dumps/axeos-web-system_info-gamma.json");
+
+fn system_info() -> Value {
+    serde_json::from_str(SYSTEM_INFO_GAMMA).expect("BUG: dump data is valid JSON")
+}
+
+/// Serve the dump like a Bitaxe Gamma.
+async fn stand_in() -> u16 {
+    let router = Router::new().route(SYSTEM_INFO, get(|| async { Json(system_info()) }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind stand-in server");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    port
+}
+
+fn endpoint(port: u16) -> Endpoint<'static> {
+    Endpoint {
+        name: "bitaxe",
+        host: "127.0.0.1",
+        port,
+    }
+}
+
+fn line<'a>(lines: &'a [String], prefix: &str) -> Option<&'a str> {
+    lines
+        .iter()
+        .map(String::as_str)
+        .find(|l| l.starts_with(prefix))
+}
+
+#[test]
+fn info_of_gamma() {
+    let info = parse_info(&system_info());
+    assert_eq!(info.model, "Bitaxe Gamma");
+    assert_eq!(info.firmware_version, "v2.6.1");
+    assert_eq!(info.mac, "00:00:5e:00:53:00");
+
+    let older = serde_json::json!({"ASICModel": "BM1366", "version": "v2.1.0"});
+    assert_eq!(parse_info(&older).model, "BM1366");
+}
+
+#[tokio::test]
+async fn scrape_system_info() {
+    let port = stand_in().await;
+    let backend = AxeOS;
+    assert_eq!(backend.detect(endpoint(port)).await, Firmware::AxeOS);
+
+    let results = backend
+        .scrape(
+            endpoint(port),
+            Firmware::AxeOS,
+            Duration::from_secs(5),
+            Schema::Both,
+        )
+        .await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].command, "system");
+    let lines = results[0].lines.as_ref().expect("BUG: stand-in answers");
+
+    for expected in [
+        "miner_hashrate_hashes_per_second{host=\"bitaxe\"} 1183420000000 ",
+        "miner_chip_temperature_celsius{host=\"bitaxe\",board=\"0\",sensor=\"0\"} 58.625 ",
+        "miner_fan_speed_rpm{host=\"bitaxe\",fan=\"0\"} 4012 ",
+        "miner_shares_accepted_total{host=\"bitaxe\"} 48211 ",
+        "miner_uptime_seconds{host=\"bitaxe\"} 261734 ",
+        "system_sharesaccepted_total{host=\"bitaxe\"} 48211 ",
+        "system_vrtemp{host=\"bitaxe\"} 49 ",
+    ] {
+        assert!(line(lines, expected).is_some(), "{expected} in {lines:#?}");
+    }
+
+    let info = backend.info(endpoint(port), Firmware::AxeOS).await;
+    assert_eq!(info.model, "Bitaxe Gamma");
+}
+
+#[tokio::test]
+async fn scrape_unreachable_board() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind unused port");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    drop(listener);
+
+    let results = AxeOS
+        .scrape(
+            endpoint(port),
+            Firmware::AxeOS,
+            Duration::from_secs(5),
+            Schema::Canonical,
+        )
+        .await;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].lines.is_err());
+    assert_eq!(
+        AxeOS.info(endpoint(port), Firmware::AxeOS).await,
+        MinerInfo::default()
+    );
+}
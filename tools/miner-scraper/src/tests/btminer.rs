@@ -0,0 +1,106 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use super::*;
+
+const PASSWORD: &str = "admin";
+
+/// `Msg` of the `get_token` replies of the fake miner.
+fn token_msg() -> Value {
+    json!({"time": "1234", "salt": "BQ5hoXV9", "newsalt": "Zq3pN7"})
+}
+
+#[test]
+fn md5_crypt_matches_crypt3() {
+    // `openssl passwd -1 -salt BQ5hoXV9 admin`
+    assert_eq!(md5_crypt(b"admin", b"BQ5hoXV9"), "RxmaDUO33TS7O26yeMHZ81");
+    assert_eq!(
+        md5_crypt(b"RxmaDUO33TS7O26yeMHZ811234", b"Zq3pN7"),
+        "TD3o1eEqaOF7ySUEYD9sY0"
+    );
+}
+
+#[test]
+fn token_from_get_token_reply() {
+    let token = Token::new(PASSWORD, &token_msg()).expect("BUG: reply has all fields");
+    assert_eq!(token.sign, "TD3o1eEqaOF7ySUEYD9sY0");
+    assert!(Token::new(PASSWORD, &json!({"salt": "BQ5hoXV9"})).is_err());
+}
+
+#[test]
+fn encrypt_pads_with_nul() {
+    let token = Token::new(PASSWORD, &token_msg()).expect("BUG: reply has all fields");
+    // AES-256-ECB with the SHA-256 of the password hash, as by openssl.
+    let encrypted = token.encrypt(r#"{"cmd":"reboot","token":"x"}"#);
+    assert_eq!(encrypted, "eueoy6m7l/fNCJ0Xjzk3qs+6gAWXkabQQPITus7HECg=");
+    assert_eq!(
+        token.decrypt(&encrypted).expect("BUG: valid ciphertext"),
+        json!({"cmd": "reboot", "token": "x"})
+    );
+    assert!(token.decrypt("AAAA").is_err());
+}
+
+/// Serve `get_token` and encrypted commands like btminer, checking the
+/// signature against `PASSWORD` and echoing the decrypted command.
+async fn fake_btminer() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    let token = Token::new(PASSWORD, &token_msg()).expect("BUG: reply has all fields");
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = Vec::new();
+            stream
+                .read_to_end(&mut buf)
+                .await
+                .expect("BUG: read request");
+            let request: Value = serde_json::from_slice(&buf).expect("BUG: request is JSON");
+            let reply = if request["cmd"] == "get_token" {
+                json!({"STATUS": "S", "Code": 134, "Msg": token_msg(), "Description": ""})
+            } else {
+                let data = request["data"].as_str().expect("BUG: request has data");
+                // A wrong password gives another key, so the request may not
+                // even decrypt.
+                match token.decrypt(data) {
+                    Ok(command) if command["token"] == token.sign.as_str() => {
+                        let reply = json!({"STATUS": "S", "Code": 131, "Msg": command});
+                        json!({"enc": token.encrypt(&reply.to_string())})
+                    }
+                    _ => json!({"STATUS": "E", "Code": 135, "Msg": "check token err"}),
+                }
+            };
+            let mut body = reply.to_string().into_bytes();
+            body.push(0);
+            stream.write_all(&body).await.expect("BUG: write reply");
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn privileged_command_round_trip() {
+    let port = fake_btminer().await;
+    let mut params = serde_json::Map::new();
+    params.insert("percent".to_owned(), json!("90"));
+    let reply = privileged("127.0.0.1", port, PASSWORD, "adjust_power_limit", &params)
+        .await
+        .expect("BUG: fake miner accepts the token");
+    assert_eq!(reply["Msg"]["cmd"], "adjust_power_limit");
+    assert_eq!(reply["Msg"]["percent"], "90");
+}
+
+#[tokio::test]
+async fn wrong_password_is_rejected() {
+    let port = fake_btminer().await;
+    let err = privileged(
+        "127.0.0.1",
+        port,
+        "hunter2",
+        "reboot",
+        &serde_json::Map::new(),
+    )
+    .await
+    .expect_err("BUG: fake miner rejects the token");
+    assert!(format!("{err:#}").contains("check token err"), "{err:#}");
+}
@@ -0,0 +1,152 @@
+use std::sync::Arc;
+
+use super::*;
+use crate::fake_miner::{self, Dumps, Faults};
+
+const LUXOS_CONFIG: &str = include_str!("../../dumps/luxos-cgminer-config-s21pro.json");
+const LUXOS_POOLS: &str = include_str!("../../dumps/luxos-cgminer-pools-s21pro.json");
+
+fn redacted(data: &str) -> Value {
+    let mut value = serde_json::from_str(data).expect("BUG: dump data is valid JSON");
+    redact(&mut value);
+    value
+}
+
+#[test]
+fn commands_known_then_requested() {
+    let requested = ["lcd".to_owned(), "stats".to_owned()];
+    let commands = commands(&requested);
+    for cmd in [
+        "stats",
+        "summary",
+        "pools",
+        "tunerstatus",
+        "power",
+        "version",
+        "config",
+    ] {
+        assert_eq!(commands.iter().filter(|c| **c == cmd).count(), 1, "{cmd}");
+    }
+    assert_eq!(commands.last(), Some(&"lcd"));
+}
+
+#[test]
+fn file_model_of_reported_models() {
+    assert_eq!(file_model("Antminer S21 XP"), "s21xp");
+    assert_eq!(file_model("Antminer S21+"), "s21plus");
+    assert_eq!(file_model("Antminer S21 (Vnish 1.2.7)"), "s21");
+}
+
+#[test]
+fn redact_network_config() {
+    let config = redacted(LUXOS_CONFIG);
+    let config = &config["CONFIG"][0];
+    assert_eq!(config["IPAddr"], REDACTED_IPV4);
+    assert_eq!(config["Gateway"], REDACTED_IPV4);
+    assert_eq!(config["MACAddr"], REDACTED_MAC);
+    assert_eq!(config["Netmask"], "255.255.255.0");
+    assert_eq!(config["Model"], "Antminer S21 Pro");
+    assert_eq!(config["PowerLimit"], 3000);
+}
+
+#[test]
+fn redact_pool_accounts() {
+    let pools = redacted(LUXOS_POOLS);
+    for pool in pools["POOLS"].as_array().expect("BUG: dump has pools") {
+        assert_eq!(pool["User"], REDACTED_ACCOUNT);
+    }
+    assert_eq!(
+        pools["POOLS"][0]["URL"],
+        "stratum+tcp://stratum.slushpool.com:3333"
+    );
+    assert_eq!(
+        pools["STATUS"][0]["Description"],
+        "LUXminer 2026.3.2.193145-42668da4d"
+    );
+}
+
+#[test]
+fn redact_embedded_addresses() {
+    assert_eq!(
+        redact_str("stratum+tcp://10.0.0.5:3333"),
+        "stratum+tcp://192.0.2.1:3333"
+    );
+    assert_eq!(redact_str("fe80::1c2:3ff:fe45:6789"), REDACTED_IPV6);
+    assert_eq!(
+        redact_str("eth0 02-8B-11-49-72-3C up"),
+        format!("eth0 {REDACTED_MAC} up")
+    );
+    // Times, versions and unset addresses are kept.
+    for s in [
+        "00:00:06",
+        "1.0.0",
+        "0.0.0.0",
+        "127.0.0.1",
+        "::",
+        "BMMiner 1.0.0",
+    ] {
+        assert_eq!(redact_str(s), s);
+    }
+    let mut version = serde_json::json!({"MAC": "b4a2eb42c8ae", "DNA": "020100008c5a5b45"});
+    redact(&mut version);
+    assert_eq!(
+        version,
+        serde_json::json!({"MAC": "00005e005300", "DNA": "020100008c5a5b45"})
+    );
+    // An empty user, as on an unconfigured pool, stays empty.
+    let mut pool = serde_json::json!({"User": "", "Worker": "rig7.a1"});
+    redact(&mut pool);
+    assert_eq!(
+        pool,
+        serde_json::json!({"User": "", "Worker": REDACTED_ACCOUNT})
+    );
+}
+
+#[tokio::test]
+async fn capture_fake_miner() {
+    let dumps_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("dumps");
+    let dumps = Dumps::load(&dumps_dir, Firmware::LuxOS, "s21pro").expect("BUG: dumps exist");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(fake_miner::serve(
+        listener,
+        Arc::new(dumps),
+        Faults::default(),
+    ));
+
+    let out = std::env::temp_dir().join(format!("miner-scraper-capture-{}", std::process::id()));
+    let address: Address = format!("127.0.0.1:{port}")
+        .parse()
+        .expect("BUG: valid address");
+    let written = capture(&address, &["estats".to_owned()], None, &out)
+        .await
+        .expect("BUG: fake miner answers");
+
+    // Every dump but the rejected `estats` is captured, named like the
+    // originals.
+    let mut names: Vec<String> = written
+        .iter()
+        .filter_map(|p| p.file_name()?.to_str().map(str::to_owned))
+        .collect();
+    names.sort_unstable();
+    let mut expected: Vec<String> = std::fs::read_dir(&dumps_dir)
+        .expect("BUG: dumps exist")
+        .filter_map(|e| e.ok()?.file_name().into_string().ok())
+        .filter(|name| name.starts_with("luxos-"))
+        .collect();
+    expected.sort_unstable();
+    assert_eq!(names, expected);
+
+    let config = std::fs::read_to_string(out.join("luxos-cgminer-config-s21pro.json"))
+        .expect("BUG: config was captured");
+    let pools = std::fs::read_to_string(out.join("luxos-cgminer-pools-s21pro.json"))
+        .expect("BUG: pools were captured");
+    let recaptured = Dumps::load(&out, Firmware::LuxOS, "s21pro");
+    std::fs::remove_dir_all(&out).expect("BUG: remove capture dir");
+
+    assert!(!config.contains("192.168.4.11") && !config.contains("02:8b:11:49:72:3c"));
+    assert!(!pools.contains("braiinstest"));
+    assert!(recaptured.is_ok());
+}
@@ -1,13 +1,9 @@
 use super::*;
+use crate::fake_miner::{self, Dumps, Faults};
+use std::sync::Arc;
 
 fn is_error(response: &Value) -> bool {
-    response
-        .get("STATUS")
-        .and_then(|s| s.as_array())
-        .and_then(|a| a.first())
-        .and_then(|s| s.get("STATUS"))
-        .and_then(|s| s.as_str())
-        == Some("E")
+    status_error(response).is_some()
 }
 
 fn parse_raw(data: &[u8]) -> Result<Value> {
@@ -35,9 +31,20 @@ const VNISH_STATS: &str = include_str!("../../dumps/vnish-cgminer-stats-s21.json
 const BRAIINS_DEVS: &str = include_str!("../../dumps/braiins-cgminer-devs-s21plus.json");
 const BRAIINS_DEVDETAILS: &str =
     include_str!("../../dumps/braiins-cgminer-devdetails-s21plus.json");
+const BRAIINS_TUNERSTATUS: &str =
+    include_str!("../../dumps/braiins-cgminer-tunerstatus-s21plus.json");
+const LUXOS_TUNERSTATUS: &str = include_str!("../../dumps/luxos-cgminer-tunerstatus-s21pro.json");
 const LUXOS_POWER: &str = include_str!("../../dumps/luxos-cgminer-power-s21pro.json");
 const MARA_SUMMARY: &str = include_str!("../../dumps/mara-cgminer-summary-s21imm.json");
 const VNISH_SUMMARY: &str = include_str!("../../dumps/vnish-cgminer-summary-s21.json");
+const WHATSMINER_STATS: &str =
+    include_str!("../../dumps/whatsminer-cgminer-stats-m30splusve40.json");
+const WHATSMINER_SUMMARY: &str =
+    include_str!("../../dumps/whatsminer-cgminer-summary-m30splusve40.json");
+const WHATSMINER_GET_VERSION: &str =
+    include_str!("../../dumps/whatsminer-cgminer-get_version-m30splusve40.json");
+const AVALON_STATS: &str = include_str!("../../dumps/avalon-cgminer-stats-avalonminer1466.json");
+const AVALON_ESTATS: &str = include_str!("../../dumps/avalon-cgminer-estats-avalonminer1466.json");
 
 /// Find all metric lines matching an exact metric name.
 fn find_metric<'a>(lines: &'a [String], name: &str) -> Vec<&'a String> {
@@ -51,6 +58,14 @@ fn parse_json(data: &str) -> Value {
     serde_json::from_str(data).expect("BUG: dump data is valid JSON")
 }
 
+/// Format a response with the generic field names, as `Schema::Raw` does.
+fn parse_response(host: &str, response: &mut Value) -> Vec<String> {
+    parse_samples(response)
+        .iter()
+        .map(|sample| sample.to_line(&sample.name, host))
+        .collect()
+}
+
 fn parse_to_lines(host: &str, data: &str) -> Vec<String> {
     let mut value = parse_json(data);
     parse_response(host, &mut value)
@@ -264,6 +279,27 @@ fn devdetails_is_error() {
     assert!(is_error(&value));
 }
 
+#[test]
+fn devdetails_error_message() {
+    let value = parse_json(STOCK_DEVDETAILS);
+    assert_eq!(status_error(&value), Some("Invalid command"));
+}
+
+#[test]
+fn fatal_status_is_error() {
+    let value = serde_json::json!({"STATUS": [{"STATUS": "F", "Msg": "Access denied"}]});
+    assert_eq!(status_error(&value), Some("Access denied"));
+    assert_eq!(status_error(&serde_json::json!({})), None);
+}
+
+#[test]
+fn top_level_status_is_error() {
+    let value = serde_json::json!({"STATUS": "E", "Code": 14, "Msg": "invalid cmd"});
+    assert_eq!(status_error(&value), Some("invalid cmd"));
+    let version = parse_json(WHATSMINER_GET_VERSION);
+    assert_eq!(status_error(&version), None);
+}
+
 #[test]
 fn stats_is_not_error() {
     let data = STOCK_STATS.as_bytes();
@@ -326,6 +362,35 @@ fn braiins_summary_emits_hashrate() {
     assert_eq!(mhs.len(), 1);
 }
 
+#[test]
+fn braiins_tunerstatus_emits_nested_chain_status() {
+    let lines = parse_to_lines("10.0.0.1", BRAIINS_TUNERSTATUS);
+    let limit = find_metric(&lines, "tunerstatus_powerlimit");
+    assert_eq!(limit.len(), 1);
+    assert!(limit[0].starts_with("tunerstatus_powerlimit{host=\"10.0.0.1\"} 3878 "));
+
+    let power = find_metric(
+        &lines,
+        "tunerstatus_tunerchainstatus_approximatepowerconsumptionwatt",
+    );
+    assert_eq!(power.len(), 1);
+    assert!(power[0].contains("{host=\"10.0.0.1\",hashchain=\"0\"} 4070 "));
+
+    // The identifier becomes a label, not a metric.
+    assert!(find_metric(&lines, "tunerstatus_tunerchainstatus_hashchainindex").is_empty());
+}
+
+#[test]
+fn braiins_tunerstatus_emits_state_sets() {
+    let lines = parse_to_lines("10.0.0.1", BRAIINS_TUNERSTATUS);
+    let status = find_metric(&lines, "tunerstatus_tunerchainstatus_status");
+    assert_eq!(status.len(), 1);
+    assert!(status[0].contains("{host=\"10.0.0.1\",hashchain=\"0\",status=\"Stable\"} 1 "));
+    let mode = find_metric(&lines, "tunerstatus_tunermode");
+    assert_eq!(mode.len(), 1);
+    assert!(mode[0].contains("tunermode=\"power_target\""));
+}
+
 // --- Integration tests: luxos ---
 
 #[test]
@@ -355,6 +420,11 @@ fn luxos_fans_emits_rpm_with_idx() {
     assert!(id.is_empty());
 }
 
+#[test]
+fn luxos_tunerstatus_has_no_numeric_fields() {
+    assert!(parse_to_lines("10.0.0.1", LUXOS_TUNERSTATUS).is_empty());
+}
+
 // --- Integration tests: mara ---
 
 #[test]
@@ -442,8 +512,7 @@ fn stock_stats_host_label_present() {
     for line in &lines {
         assert!(
             line.contains("host=\"10.36.1.51\""),
-            "missing host label in: {}",
-            line
+            "missing host label in: {line}"
         );
     }
 }
@@ -480,6 +549,59 @@ fn detect_vnish_firmware() {
     assert_eq!(Firmware::identify(&stats), Firmware::Vnish);
 }
 
+#[test]
+fn detect_whatsminer_firmware() {
+    // btminer rejects `stats`, but its replies name it.
+    let stats = parse_json(WHATSMINER_STATS);
+    assert!(status_error(&stats).is_some());
+    assert_eq!(Firmware::identify(&stats), Firmware::Whatsminer);
+    let summary = parse_json(WHATSMINER_SUMMARY);
+    assert_eq!(Firmware::identify(&summary), Firmware::Whatsminer);
+}
+
+#[test]
+fn detect_avalon_firmware() {
+    let stats = parse_json(AVALON_STATS);
+    assert_eq!(Firmware::identify(&stats), Firmware::Avalon);
+}
+
+#[test]
+fn avalon_estats_emits_mm_fields() {
+    let mut estats = parse_json(AVALON_ESTATS);
+    let lines = parse_command("m", Firmware::Avalon, "estats", &mut estats, Schema::Raw)
+        .expect("BUG: dump is not an error reply");
+    let fans = find_metric(&lines, "stats_mm_fan");
+    assert_eq!(fans.len(), 4);
+    assert!(fans[0].starts_with("stats_mm_fan{host=\"m\",mm=\"0\",idx=\"1\"} 3760 "));
+    let boards = find_metric(&lines, "stats_mm_mghs");
+    assert_eq!(boards.len(), 3);
+    assert!(
+        boards[2].contains("mm=\"0\",idx=\"2\"} 50210.33 "),
+        "{}",
+        boards[2]
+    );
+    let chips = find_metric(&lines, "stats_mm_pvt_t");
+    assert_eq!(chips.len(), 120);
+    assert!(
+        chips[40].contains("mm=\"0\",hashboard=\"1\",idx=\"0\"}"),
+        "{}",
+        chips[40]
+    );
+    assert_eq!(find_metric(&lines, "stats_mm_fanr").len(), 1);
+    // The per-pool entries are left to `pools`.
+    assert_eq!(find_metric(&lines, "stats_elapsed_total").len(), 1);
+}
+
+#[test]
+fn every_firmware_scrapes_pools() {
+    for fw in Firmware::ALL {
+        assert!(
+            fw.commands().contains(&"pools"),
+            "{fw} does not scrape pools"
+        );
+    }
+}
+
 #[test]
 fn detect_fallback_to_stock() {
     let empty = serde_json::json!({
@@ -515,6 +637,7 @@ fn braiins_all_dumps_produce_metrics() {
         BRAIINS_SUMMARY,
         BRAIINS_DEVS,
         BRAIINS_DEVDETAILS,
+        BRAIINS_TUNERSTATUS,
     ] {
         let mut value = parse_json(data);
         if !is_error(&value) {
@@ -572,6 +695,77 @@ fn mara_all_dumps_produce_metrics() {
     );
 }
 
+// --- Nested structures ---
+
+#[test]
+fn nested_object_extends_metric_name() {
+    let mut value = serde_json::json!({
+        "CONFIG": [{"Limits": {"Power": 3000, "Temp": 80}}],
+    });
+    let lines = parse_response("h", &mut value);
+    assert_eq!(find_metric(&lines, "config_limits_power").len(), 1);
+    assert_eq!(find_metric(&lines, "config_limits_temp").len(), 1);
+}
+
+#[test]
+fn nested_array_without_identifier_uses_position() {
+    let mut value = serde_json::json!({
+        "DEVS": [{"ID": 2, "Chips": [{"Temp": 60}, {"Temp": 61}]}],
+    });
+    let lines = parse_response("h", &mut value);
+    let temps = find_metric(&lines, "devs_chips_temp");
+    assert_eq!(temps.len(), 2);
+    assert!(temps[1].contains("{host=\"h\",idx=\"2\",chips_idx=\"1\"} 61 "));
+}
+
+#[test]
+fn state_set_requires_enum_like_field() {
+    let mut value = serde_json::json!({
+        "DEVS": [{
+            "ID": 0,
+            "Status": "Alive",
+            "Model": "Antminer S21",
+            "Mode": "normal mode",
+        }],
+    });
+    let lines = parse_response("h", &mut value);
+    let status = find_metric(&lines, "devs_status");
+    assert_eq!(status.len(), 1);
+    assert!(status[0].contains("{host=\"h\",idx=\"0\",status=\"Alive\"} 1 "));
+    assert!(find_metric(&lines, "devs_model").is_empty());
+    assert!(find_metric(&lines, "devs_mode").is_empty());
+}
+
+#[test]
+fn state_sets_keep_every_state_seen() {
+    let scrape = |state: &str| {
+        let mut value = serde_json::json!({
+            "DEVS": [{"ID": 0, "Status": state, "Temperature": 60}],
+        });
+        parse_response("h", &mut value)
+    };
+    let mut sets = StateSets::default();
+
+    let mut lines = scrape("Alive");
+    sets.complete(&mut lines);
+    assert_eq!(find_metric(&lines, "devs_status").len(), 1);
+
+    let mut lines = scrape("Dead");
+    sets.complete(&mut lines);
+    let status = find_metric(&lines, "devs_status");
+    assert_eq!(status.len(), 2);
+    assert!(status[0].contains("{host=\"h\",idx=\"0\",status=\"Dead\"} 1 "));
+    assert!(status[1].contains("{host=\"h\",idx=\"0\",status=\"Alive\"} 0 "));
+
+    let mut lines = scrape("Alive");
+    sets.complete(&mut lines);
+    let status = find_metric(&lines, "devs_status");
+    assert!(status[0].contains("status=\"Alive\"} 1 "));
+    assert!(status[1].contains("status=\"Dead\"} 0 "));
+    // Numeric series are left alone.
+    assert_eq!(find_metric(&lines, "devs_temperature").len(), 1);
+}
+
 // --- STATUS and id skipping ---
 
 #[test]
@@ -580,10 +774,228 @@ fn skips_status_and_id() {
     for line in &lines {
         assert!(
             !line.starts_with("status_"),
-            "STATUS should be skipped: {}",
-            line
+            "STATUS should be skipped: {line}"
         );
     }
     // The "id" field at the top level should not produce a metric.
     assert!(find_metric(&lines, "stats_id").is_empty());
 }
+
+// --- Joined and concurrent commands against a fake miner ---
+
+fn local(port: u16) -> Endpoint<'static> {
+    Endpoint {
+        name: "127.0.0.1",
+        host: "127.0.0.1",
+        port,
+    }
+}
+
+/// Serve `replies` like a miner running `firmware`, each after `latency_ms`.
+///
+/// Joined commands are answered only if the firmware supports them.
+async fn fake_miner(
+    firmware: Firmware,
+    replies: &[(&str, &str)],
+    latency_ms: u64,
+) -> (u16, Arc<Dumps>) {
+    let faults = Faults {
+        latency_ms,
+        ..Faults::default()
+    };
+    fake_miner_with(firmware, replies, faults).await
+}
+
+async fn fake_miner_with(
+    firmware: Firmware,
+    replies: &[(&str, &str)],
+    faults: Faults,
+) -> (u16, Arc<Dumps>) {
+    let replies = replies
+        .iter()
+        .map(|(cmd, data)| ((*cmd).to_owned(), parse_json(data)))
+        .collect();
+    let dumps = Arc::new(Dumps::new(firmware, replies));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(fake_miner::serve(listener, dumps.clone(), faults));
+    (port, dumps)
+}
+
+const STOCK_POOLS: &str = include_str!("../../dumps/stock-cgminer-pools-s21xp.json");
+const LUXOS_POOLS: &str = include_str!("../../dumps/luxos-cgminer-pools-s21pro.json");
+
+fn stock_replies() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("stats", STOCK_STATS),
+        ("summary", STOCK_SUMMARY),
+        ("pools", STOCK_POOLS),
+    ]
+}
+
+#[tokio::test]
+async fn scrape_splits_joined_reply() {
+    let (port, miner) = fake_miner(Firmware::Stock, &stock_replies(), 0).await;
+    let results = scrape(local(port), Firmware::Stock, COMMAND_TIMEOUT, Schema::Raw).await;
+
+    assert_eq!(miner.requests(), 1);
+    let commands: Vec<&str> = results.iter().map(|r| r.command).collect();
+    assert_eq!(commands, ["stats", "summary", "pools"]);
+    for result in &results {
+        let lines = result.lines.as_ref().expect("BUG: command succeeded");
+        assert!(!lines.is_empty(), "{} produced no lines", result.command);
+    }
+    let pools = results[2].lines.as_ref().expect("BUG: pools succeeded");
+    assert!(pools.iter().any(|l| l.starts_with("pools_accepted_total{")));
+}
+
+#[tokio::test]
+async fn scrape_joined_reply_with_failed_command() {
+    let replies = [("stats", STOCK_STATS), ("summary", STOCK_SUMMARY)];
+    let (port, miner) = fake_miner(Firmware::Stock, &replies, 0).await;
+    let results = scrape(local(port), Firmware::Stock, COMMAND_TIMEOUT, Schema::Raw).await;
+
+    assert_eq!(miner.requests(), 1);
+    assert!(results[0].lines.is_ok());
+    let err = results[2].lines.as_ref().expect_err("BUG: pools failed");
+    assert_eq!(err.to_string(), "Invalid command");
+}
+
+#[tokio::test]
+async fn scrape_falls_back_when_joined_rejected() {
+    // Braiins rejects joined commands.
+    let (port, miner) = fake_miner(Firmware::Braiins, &stock_replies(), 0).await;
+    let results = scrape(local(port), Firmware::Stock, COMMAND_TIMEOUT, Schema::Raw).await;
+
+    // One rejected joined request, then one request per command.
+    assert_eq!(miner.requests(), 4);
+    assert!(results.iter().all(|r| r.lines.is_ok()));
+}
+
+#[tokio::test]
+async fn scrape_falls_back_when_joined_times_out() {
+    let faults = Faults {
+        latency_ms: 2_000,
+        slow_command: Some("pools".to_owned()),
+        ..Faults::default()
+    };
+    let (port, miner) = fake_miner_with(Firmware::Stock, &stock_replies(), faults).await;
+    let timeout = Duration::from_millis(200);
+    let results = scrape(local(port), Firmware::Stock, timeout, Schema::Raw).await;
+
+    // The joined request times out on pools, then one request per command.
+    assert_eq!(miner.requests(), 4);
+    assert!(results[0].lines.is_ok());
+    assert!(results[1].lines.is_ok());
+    let err = results[2].lines.as_ref().expect_err("BUG: pools timed out");
+    assert_eq!(err.to_string(), "timeout");
+}
+
+#[tokio::test]
+async fn scrape_joins_luxos_commands() {
+    let replies = [
+        ("stats", LUXOS_STATS),
+        ("temps", LUXOS_TEMPS),
+        ("fans", LUXOS_FANS),
+        ("power", LUXOS_POWER),
+        ("pools", LUXOS_POOLS),
+    ];
+    let (port, miner) = fake_miner(Firmware::LuxOS, &replies, 0).await;
+    let results = scrape(local(port), Firmware::LuxOS, COMMAND_TIMEOUT, Schema::Raw).await;
+
+    assert_eq!(miner.requests(), 1);
+    assert!(results.iter().all(|r| r.lines.is_ok()));
+}
+
+#[tokio::test]
+async fn command_parameter_is_escaped() {
+    let (port, miner) = fake_miner(Firmware::LuxOS, &[], 0).await;
+    let parameter = r#"fake-session","command":"rebootdevice\"#;
+    command_with_param("127.0.0.1", port, "switchpool", Some(parameter))
+        .await
+        .expect("BUG: fake miner answers");
+    assert_eq!(miner.received(), [format!("switchpool|{parameter}")]);
+}
+
+#[tokio::test]
+async fn scrape_unreachable_fails_every_command() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("BUG: bind");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    drop(listener);
+
+    let results = scrape(local(port), Firmware::Stock, COMMAND_TIMEOUT, Schema::Raw).await;
+    assert_eq!(results.len(), Firmware::Stock.commands().len());
+    assert!(results.iter().all(|r| r.lines.is_err()));
+}
+
+/// Compare one-after-another commands against joined and concurrent scrapes.
+///
+/// Each request to the fake miner takes 200 ms, like a busy control board.
+/// Run with `cargo test benchmark -- --ignored --nocapture` to see the
+/// timings.
+#[tokio::test]
+#[ignore = "depends on timing"]
+async fn benchmark_scrape_against_slow_server() {
+    let latency_ms = 200;
+    let delay = Duration::from_millis(latency_ms);
+    let replies = [
+        ("stats", LUXOS_STATS),
+        ("temps", LUXOS_TEMPS),
+        ("fans", LUXOS_FANS),
+        ("power", LUXOS_POWER),
+        ("pools", LUXOS_POOLS),
+    ];
+
+    let (port, _) = fake_miner(Firmware::LuxOS, &replies, latency_ms).await;
+    let started = std::time::Instant::now();
+    for cmd in Firmware::LuxOS.commands() {
+        command("127.0.0.1", port, cmd)
+            .await
+            .expect("BUG: fake miner answers");
+    }
+    let sequential = started.elapsed();
+
+    let (port, _) = fake_miner(Firmware::LuxOS, &replies, latency_ms).await;
+    let started = std::time::Instant::now();
+    let results = scrape(local(port), Firmware::LuxOS, COMMAND_TIMEOUT, Schema::Raw).await;
+    let joined = started.elapsed();
+    assert!(results.iter().all(|r| r.lines.is_ok()));
+
+    let (port, _) = fake_miner(Firmware::Braiins, &replies, latency_ms).await;
+    let started = std::time::Instant::now();
+    let results = scrape(local(port), Firmware::Braiins, COMMAND_TIMEOUT, Schema::Raw).await;
+    let concurrent = started.elapsed();
+    assert_eq!(results.len(), Firmware::Braiins.commands().len());
+
+    eprintln!(
+        "5 commands at {delay:?} each: sequential {sequential:?}, \
+         joined {joined:?}, concurrent {concurrent:?}"
+    );
+    assert!(sequential >= delay * 5);
+    assert!(joined < sequential / 2);
+    assert!(concurrent < sequential / 2);
+}
+
+#[tokio::test]
+async fn command_over_ipv6() {
+    let Ok(listener) = tokio::net::TcpListener::bind("[::1]:0").await else {
+        eprintln!("no IPv6 loopback, skipping");
+        return;
+    };
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(async move {
+        if let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = Vec::new();
+            let _ = stream.read_to_end(&mut buf).await;
+            let _ = stream
+                .write_all(b"{\"STATUS\":[{\"STATUS\":\"S\"}]}\0")
+                .await;
+        }
+    });
+    let resp = command("::1", port, "version")
+        .await
+        .expect("BUG: fake miner answers");
+    assert!(status_error(&resp).is_none());
+}
@@ -0,0 +1,132 @@
+use super::*;
+
+const STOCK_STATS: &str = include_str!("../../dumps/stock-cgminer-stats-s21xp.json");
+const LUXOS_STATS: &str = include_str!("../../dumps/luxos-cgminer-stats-s21pro.json");
+const MARA_STATS: &str = include_str!("../../dumps/mara-cgminer-stats-s21imm.json");
+const VNISH_STATS: &str = include_str!("../../dumps/vnish-cgminer-stats-s21.json");
+
+fn parse(data: &str) -> Vec<Sample> {
+    let value: Value = serde_json::from_str(data).expect("BUG: dump data is valid JSON");
+    parse_stats(&value)
+}
+
+/// Value of the sample with the given name and labels.
+fn value(samples: &[Sample], name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+    samples
+        .iter()
+        .find(|s| s.name == name && labels.iter().all(|(k, v)| s.label(k) == Some(v)))
+        .map(|s| s.value)
+}
+
+#[test]
+fn decode_counts_chips_by_state() {
+    assert_eq!(
+        ChipCounts::decode(" oooo xoo- o"),
+        Some(ChipCounts {
+            ok: 7,
+            failed: 1,
+            unknown: 1,
+        })
+    );
+}
+
+#[test]
+fn decode_empty_is_unpopulated() {
+    assert_eq!(ChipCounts::decode(""), None);
+    assert_eq!(ChipCounts::decode("   "), None);
+}
+
+#[test]
+fn expected_chips_of_known_models() {
+    assert_eq!(expected_chips("Antminer S21 XP"), Some(91));
+    assert_eq!(expected_chips("Antminer S21 Pro"), Some(65));
+    assert_eq!(expected_chips("Antminer S21 (Vnish 1.2.7)"), Some(108));
+    assert_eq!(expected_chips("Antminer S21Imm (239TH)"), Some(156));
+    assert_eq!(expected_chips("Antminer L7"), None);
+}
+
+#[test]
+fn stock_boards_are_healthy() {
+    let samples = parse(STOCK_STATS);
+    for board in ["1", "2", "3"] {
+        let chips = |status| {
+            value(
+                &samples,
+                "stats_chain_acs_chips",
+                &[("hashboard", board), ("status", status)],
+            )
+        };
+        assert_eq!(chips("ok"), Some(91.0));
+        assert_eq!(chips("failed"), Some(0.0));
+        assert_eq!(chips("unknown"), Some(0.0));
+        assert_eq!(
+            value(&samples, "stats_chain_acn_missing", &[("hashboard", board)]),
+            Some(0.0)
+        );
+    }
+}
+
+#[test]
+fn empty_slot_beyond_miner_count_is_skipped() {
+    for data in [STOCK_STATS, LUXOS_STATS, MARA_STATS] {
+        let samples = parse(data);
+        assert!(samples.iter().all(|s| s.label("hashboard") != Some("4")));
+        assert_eq!(samples.len(), 3 * 4);
+    }
+}
+
+#[test]
+fn mara_reports_failed_chips() {
+    let samples = parse(MARA_STATS);
+    let failed = value(
+        &samples,
+        "stats_chain_acs_chips",
+        &[("hashboard", "1"), ("status", "failed")],
+    )
+    .expect("BUG: board 1 has a status string");
+    let ok = value(
+        &samples,
+        "stats_chain_acs_chips",
+        &[("hashboard", "1"), ("status", "ok")],
+    )
+    .expect("BUG: board 1 has a status string");
+    assert!(failed > 0.0);
+    assert!((ok + failed - 156.0).abs() < f64::EPSILON);
+}
+
+#[test]
+fn vnish_without_status_strings_reports_missing_chips() {
+    let samples = parse(VNISH_STATS);
+    assert!(samples.iter().all(|s| s.name == "stats_chain_acn_missing"));
+    assert_eq!(samples.len(), 3);
+}
+
+#[test]
+fn missing_chips_against_model() {
+    let response = serde_json::json!({
+        "STATS": [
+            {"Type": "Antminer S21 XP"},
+            {"miner_count": 2, "chain_acn1": 91, "chain_acn2": "88",
+             "chain_acs1": "ooooooo", "chain_acs2": ""},
+        ],
+    });
+    let samples = parse_stats(&response);
+    assert_eq!(
+        value(&samples, "stats_chain_acn_missing", &[("hashboard", "2")]),
+        Some(3.0)
+    );
+    // A populated board with an empty status string gets no chip states.
+    assert!(value(&samples, "stats_chain_acs_chips", &[("hashboard", "2")]).is_none());
+}
+
+#[test]
+fn unknown_model_has_no_missing_chips() {
+    let response = serde_json::json!({
+        "STATS": [
+            {"Type": "Antminer L7"},
+            {"miner_count": 1, "chain_acn1": 120, "chain_acs1": "oooo"},
+        ],
+    });
+    let samples = parse_stats(&response);
+    assert!(samples.iter().all(|s| s.name == "stats_chain_acs_chips"));
+}
@@ -8,6 +8,7 @@ targets = ["10.36.1.51"]
     let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
     assert_eq!(config.listen, "127.0.0.1:8889");
     assert_eq!(config.scrape_interval_secs, 5);
+    assert_eq!(config.info_interval_secs, 300);
     assert_eq!(config.targets, vec!["10.36.1.51"]);
 }
 
@@ -16,11 +17,13 @@ fn parse_full_config() {
     let toml = r#"
 listen = "127.0.0.1:9090"
 scrape_interval_secs = 30
+info_interval_secs = 600
 targets = ["10.36.1.51", "10.36.1.52"]
 "#;
     let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
     assert_eq!(config.listen, "127.0.0.1:9090");
     assert_eq!(config.scrape_interval_secs, 30);
+    assert_eq!(config.info_interval_secs, 600);
     assert_eq!(config.targets.len(), 2);
 }
 
@@ -32,3 +35,258 @@ listen = "0.0.0.0:8081"
     let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
     assert!(config.targets.is_empty());
 }
+
+#[test]
+fn parse_schema() {
+    let parse = |toml: &str| toml::from_str::<Config>(toml).map(|c| c.schema);
+    assert_eq!(parse("").ok(), Some(Schema::Canonical));
+    assert_eq!(parse(r#"schema = "raw""#).ok(), Some(Schema::Raw));
+    assert_eq!(parse(r#"schema = "both""#).ok(), Some(Schema::Both));
+    assert!(parse(r#"schema = "prometheus""#).is_err());
+}
+
+#[test]
+fn parse_discover_section() {
+    let toml = r#"
+targets = ["10.36.1.51"]
+
+[discover]
+ranges = ["10.36.2.0/24", "10.36.3.10-50"]
+grace_secs = 60
+"#;
+    let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+    let discover = config.discover.expect("BUG: discover section is set");
+    assert_eq!(discover.ranges, ["10.36.2.0/24", "10.36.3.10-50"]);
+    assert_eq!(discover.interval_secs, 300);
+    assert_eq!(discover.concurrency, 64);
+    assert_eq!(discover.grace_secs, 60);
+}
+
+#[test]
+fn discover_is_optional() {
+    let config: Config = toml::from_str("").expect("BUG: test toml is valid");
+    assert!(config.discover.is_none());
+}
+
+#[test]
+fn parse_target_tables() {
+    let toml = r#"
+targets = ["10.36.1.51"]
+
+[[target]]
+host = "10.36.1.52"
+firmware = "luxos"
+interval_secs = 30
+timeout_secs = 3
+labels = { site = "north", rack = "3" }
+
+[[target]]
+host = "192.0.2.1"
+port = 14028
+"#;
+    let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+    config.validate().expect("BUG: labels are valid");
+    assert_eq!(config.targets, ["10.36.1.51"]);
+    assert_eq!(config.target_configs.len(), 2);
+
+    let first = &config.target_configs[0];
+    let address = first.address().expect("BUG: host is valid");
+    assert_eq!(address.name(), "10.36.1.52");
+    assert_eq!(first.port, 4028);
+    assert_eq!(first.firmware, Some(Firmware::LuxOS));
+    assert_eq!(first.interval_secs, Some(30));
+    assert_eq!(first.timeout_secs, Some(3));
+    assert_eq!(first.labels["site"], "north");
+
+    let second = &config.target_configs[1];
+    let address = second.address().expect("BUG: host is valid");
+    assert_eq!(address.name(), "192.0.2.1:14028");
+    assert_eq!(second.firmware, None);
+    assert!(second.labels.is_empty());
+}
+
+#[test]
+fn reject_invalid_target_labels() {
+    for labels in [
+        r#"{ host = "x" }"#,
+        r#"{ "rack-id" = "3" }"#,
+        r#"{ __name__ = "x" }"#,
+        r#"{ 1st = "x" }"#,
+    ] {
+        let toml = format!("[[target]]\nhost = \"10.0.0.1\"\nlabels = {labels}\n");
+        let config: Config = toml::from_str(&toml).expect("BUG: test toml is valid");
+        assert!(config.validate().is_err(), "{labels} should be rejected");
+    }
+}
+
+#[test]
+fn reject_unknown_firmware() {
+    let toml = "[[target]]\nhost = \"10.0.0.1\"\nfirmware = \"antminer\"\n";
+    assert!(toml::from_str::<Config>(toml).is_err());
+}
+
+#[test]
+fn validate_target_addresses() {
+    let toml = r#"
+targets = ["10.0.0.1", "[fe80::1]:4029", "miner-7.lan:4030"]
+
+[[target]]
+host = "fe80::2"
+"#;
+    let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+    assert_eq!(config.dns_ttl_secs, 60);
+    config.validate().expect("BUG: addresses are valid");
+
+    for toml in [
+        r#"targets = ["10.0.0.1:port"]"#,
+        r#"targets = ["miner_7.lan"]"#,
+        "[[target]]\nhost = \"10.0.0.1:4029\"",
+    ] {
+        let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+        assert!(config.validate().is_err(), "{toml} should be rejected");
+    }
+}
+
+#[test]
+fn parse_web_backends() {
+    let toml = r#"
+[[target]]
+host = "10.36.4.20"
+backend = "axeos"
+
+[[target]]
+host = "10.36.1.53"
+backend = "vnish"
+http_port = 8080
+password = "admin"
+"#;
+    let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+    config.validate().expect("BUG: backends are complete");
+
+    let bitaxe = &config.target_configs[0];
+    assert_eq!(bitaxe.backend, backend::Kind::AxeOS);
+    assert_eq!(bitaxe.http_port, 80);
+    assert_eq!(bitaxe.password, None);
+
+    let s21 = &config.target_configs[1];
+    assert_eq!(s21.backend, backend::Kind::Vnish);
+    assert_eq!(s21.http_port, 8080);
+    assert_eq!(s21.password.as_deref(), Some("admin"));
+
+    let default = toml::from_str::<Config>("[[target]]\nhost = \"10.0.0.1\"\n")
+        .expect("BUG: test toml is valid");
+    assert_eq!(default.target_configs[0].backend, backend::Kind::Cgminer);
+    assert!(
+        toml::from_str::<Config>("[[target]]\nhost = \"10.0.0.1\"\nbackend = \"rest\"\n").is_err()
+    );
+}
+
+#[test]
+fn reject_incomplete_web_backends() {
+    for toml in [
+        "[[target]]\nhost = \"10.0.0.1\"\nbackend = \"vnish\"\n",
+        "[[target]]\nhost = \"10.0.0.1\"\nfirmware = \"axeos\"\n",
+        "[[target]]\nhost = \"10.0.0.1\"\nbackend = \"vnish\"\npassword = \"admin\"\nfirmware = \"axeos\"\n",
+    ] {
+        let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+        assert!(config.validate().is_err(), "{toml} should be rejected");
+    }
+}
+
+#[test]
+fn parse_control_section() {
+    let toml = r#"
+[control]
+token = "s3cr3t"
+audit_log = "/var/log/miner-scraper/audit.jsonl"
+"#;
+    let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+    config.validate().expect("BUG: control section is valid");
+    let control = config.control.expect("BUG: control section is set");
+    assert_eq!(control.token, "s3cr3t");
+    assert_eq!(
+        control.audit_log.as_deref(),
+        Some(Path::new("/var/log/miner-scraper/audit.jsonl"))
+    );
+    assert_eq!(control.concurrency, 16);
+    assert!(Config::default().control.is_none());
+
+    for toml in [
+        "[control]\ntoken = \" \"\n",
+        "[control]\ntoken = \"s3cr3t\"\nconcurrency = 0\n",
+    ] {
+        let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+        assert!(config.validate().is_err(), "{toml} should be rejected");
+    }
+    assert!(toml::from_str::<Config>("[control]\naudit_log = \"/tmp/audit\"\n").is_err());
+}
+
+#[test]
+fn parse_schedule_tables() {
+    let toml = r#"
+[[schedule]]
+name = "peak"
+cron = "0 17 * * mon-fri"
+duration_mins = 240
+timezone = "UTC"
+action = "pause"
+labels = { site = "north" }
+
+[[schedule]]
+name = "shoulder"
+cron = "0 7 * * *"
+duration_mins = 120
+action = "set_power_target"
+watts = 2500
+restore_watts = 3500
+hosts = ["10.0.0.1"]
+"#;
+    let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+    config.validate().expect("BUG: schedules are valid");
+    let [peak, shoulder] = &config.schedules[..] else {
+        panic!("BUG: two schedules expected");
+    };
+    assert_eq!(peak.action, Action::Pause);
+    assert_eq!(peak.labels["site"], "north");
+    // 2026-10-16 is a Friday; the window is in UTC.
+    assert!(peak
+        .window
+        .is_open("2026-10-16T17:00:00Z".parse().expect("BUG: valid")));
+    assert!(!peak
+        .window
+        .is_open("2026-10-16T16:59:00Z".parse().expect("BUG: valid")));
+    assert_eq!(shoulder.action, Action::SetPowerTarget { watts: 2500 });
+    assert_eq!(shoulder.restore_watts, Some(3500));
+    assert_eq!(shoulder.hosts, ["10.0.0.1"]);
+    assert!(Config::default().schedules.is_empty());
+
+    let schedule = |name: &str, cron: &str, rest: &str| {
+        format!("[[schedule]]\nname = \"{name}\"\ncron = \"{cron}\"\nduration_mins = 60\n{rest}")
+    };
+    let pause = "action = \"pause\"\nhosts = [\"a\"]\n";
+    for toml in [
+        schedule(
+            "peak",
+            "0 17 * * *",
+            "action = \"reboot\"\nhosts = [\"a\"]\n",
+        ),
+        schedule(
+            "peak",
+            "0 17 * * *",
+            "action = \"set_power_target\"\nwatts = 2500\nhosts = [\"a\"]\n",
+        ),
+        schedule("peak", "0 17 * * *", "action = \"pause\"\n"),
+        schedule("peak", "0 17 * *", pause),
+        schedule("", "0 17 * * *", pause),
+        schedule(
+            "peak",
+            "0 17 * * *",
+            &format!("{pause}timezone = \"Mars/Olympus\"\n"),
+        ),
+        schedule("peak", "0 17 * * *", pause) + &schedule("peak", "0 18 * * *", pause),
+    ] {
+        // Invalid windows are rejected while parsing, the rest by `validate`.
+        let rejected = toml::from_str::<Config>(&toml).map_or(true, |c| c.validate().is_err());
+        assert!(rejected, "{toml} should be rejected");
+    }
+}
@@ -0,0 +1,351 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+
+use super::*;
+use crate::config::TargetConfig;
+use crate::fake_miner::{self, Dumps, Faults, SESSION_ID};
+
+const TOKEN: &str = "s3cr3t";
+
+fn action(body: Value) -> Action {
+    serde_json::from_value(body).expect("BUG: valid action")
+}
+
+#[test]
+fn parse_actions() {
+    assert_eq!(action(json!({"action": "reboot"})), Action::Reboot);
+    assert_eq!(
+        action(json!({"action": "set_power_target", "watts": 3000})),
+        Action::SetPowerTarget { watts: 3000 }
+    );
+    assert_eq!(
+        action(json!({"action": "switch_pool", "pool": 1})),
+        Action::SwitchPool { pool: 1 }
+    );
+    for body in [
+        json!({"action": "set_power_target"}),
+        json!({"action": "overclock"}),
+        json!({"watts": 3000}),
+    ] {
+        assert!(
+            serde_json::from_value::<Action>(body.clone()).is_err(),
+            "{body}"
+        );
+    }
+
+    let body: BulkBody = serde_json::from_value(
+        json!({"action": "pause", "dry_run": true, "hosts": ["10.0.0.1"], "reason": "grid event"}),
+    )
+    .expect("BUG: valid bulk action");
+    assert_eq!(body.body.action, Action::Pause);
+    assert!(body.body.dry_run);
+    assert_eq!(body.hosts, ["10.0.0.1"]);
+    assert_eq!(body.body.reason.as_deref(), Some("grid event"));
+}
+
+#[test]
+fn plan_per_firmware() {
+    let planned = |fw, body| plan(fw, &action(body)).map(|r| r.to_string());
+    let pause = json!({"action": "pause"});
+    let power = json!({"action": "set_power_target", "watts": 3000});
+    let pool = json!({"action": "switch_pool", "pool": 1});
+
+    assert_eq!(
+        planned(Firmware::LuxOS, pause.clone()).as_deref(),
+        Some("luxos session curtail|sleep")
+    );
+    assert_eq!(
+        planned(Firmware::LuxOS, pool.clone()).as_deref(),
+        Some("luxos session switchpool|1")
+    );
+    assert_eq!(
+        planned(Firmware::LuxOS, power.clone()).as_deref(),
+        Some("luxos session powertargetset|3000")
+    );
+
+    assert_eq!(
+        planned(Firmware::Whatsminer, power.clone()).as_deref(),
+        Some(r#"btminer adjust_power_limit {"power_limit":"3000"}"#)
+    );
+    assert_eq!(
+        planned(Firmware::Whatsminer, json!({"action": "reboot"})).as_deref(),
+        Some("btminer reboot")
+    );
+    assert_eq!(planned(Firmware::Whatsminer, pool.clone()), None);
+
+    assert_eq!(
+        planned(Firmware::Braiins, pause.clone()).as_deref(),
+        Some("cgminer pause")
+    );
+    assert_eq!(planned(Firmware::Braiins, power.clone()), None);
+    assert_eq!(
+        planned(Firmware::Stock, pool.clone()).as_deref(),
+        Some("cgminer switchpool|1")
+    );
+    assert_eq!(
+        planned(Firmware::Avalon, json!({"action": "reboot"})).as_deref(),
+        Some("cgminer restart")
+    );
+    assert_eq!(planned(Firmware::Stock, pause), None);
+
+    assert_eq!(
+        planned(Firmware::AxeOS, json!({"action": "reboot"})).as_deref(),
+        Some("POST /api/system/restart")
+    );
+    assert_eq!(planned(Firmware::AxeOS, pool), None);
+}
+
+#[test]
+fn tokens_and_limits() {
+    assert!(same_token(b"s3cr3t", b"s3cr3t"));
+    assert!(!same_token(b"s3cr3T", b"s3cr3t"));
+    assert!(!same_token(b"s3cr3", b"s3cr3t"));
+    assert!(!same_token(b"", b"s3cr3t"));
+
+    assert_eq!(concurrency(None, 16), 16);
+    assert_eq!(concurrency(Some(4), 16), 4);
+    assert_eq!(concurrency(Some(64), 16), 16);
+    assert_eq!(concurrency(Some(0), 16), 1);
+}
+
+/// Start a fake miner and return its port and what it received.
+async fn fake_miner(firmware: Firmware, model: &str) -> (u16, Arc<Dumps>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    let dumps = Dumps::load(
+        &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("dumps"),
+        firmware,
+        model,
+    )
+    .expect("BUG: dumps exist");
+    let dumps = Arc::new(dumps);
+    tokio::spawn(fake_miner::serve(
+        listener,
+        dumps.clone(),
+        Faults::default(),
+    ));
+    (port, dumps)
+}
+
+/// Serve the control API for `config` and return its port.
+async fn serve(config: Config) -> u16 {
+    let (config_tx, config_rx) = watch::channel(config);
+    let (discovered_tx, discovered_rx) = watch::channel(Discovered::new());
+    let control = Control::new(config_rx, discovered_rx);
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind control API");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(async move {
+        // Keep the senders alive for the lifetime of the server.
+        let _senders = (config_tx, discovered_tx);
+        axum::serve(listener, router(control)).await
+    });
+    port
+}
+
+fn target(port: u16, firmware: Option<Firmware>) -> TargetConfig {
+    let toml = format!("host = \"127.0.0.1\"\nport = {port}\n");
+    let mut target: TargetConfig = toml::from_str(&toml).expect("BUG: valid target");
+    target.firmware = firmware;
+    target
+}
+
+fn audit_log(test: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "miner-scraper-audit-{test}-{}.jsonl",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn post(port: u16, path: &str, token: Option<&str>, body: Value) -> Result<Value> {
+    web::post("127.0.0.1", port, path, token, &body).await
+}
+
+#[tokio::test]
+async fn act_on_one_miner() {
+    let (luxos, luxos_dumps) = fake_miner(Firmware::LuxOS, "s21pro").await;
+    let (stock, _) = fake_miner(Firmware::Stock, "s21xp").await;
+    let audit = audit_log("one");
+    let api = serve(Config {
+        target_configs: vec![target(luxos, None), target(stock, Some(Firmware::Stock))],
+        control: Some(ControlConfig {
+            token: TOKEN.to_owned(),
+            audit_log: Some(audit.clone()),
+            concurrency: 16,
+        }),
+        ..Config::default()
+    })
+    .await;
+    let luxos_path = format!("/api/miners/127.0.0.1:{luxos}/actions");
+    let stock_path = format!("/api/miners/127.0.0.1:{stock}/actions");
+    let pause = json!({"action": "pause", "reason": "grid event"});
+
+    for token in [None, Some("wrong")] {
+        let err = post(api, &luxos_path, token, pause.clone())
+            .await
+            .expect_err("BUG: token is checked");
+        assert_eq!(web::status(&err), Some(401));
+    }
+    assert!(luxos_dumps.received().is_empty());
+
+    // The firmware is detected, then the command runs in a session.
+    let reply = post(api, &luxos_path, Some(TOKEN), pause)
+        .await
+        .expect("BUG: fake miner accepts the action");
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(reply["firmware"], "luxos");
+    assert_eq!(reply["request"], "luxos session curtail|sleep");
+    assert_eq!(
+        luxos_dumps.received(),
+        [
+            "logon".to_owned(),
+            format!("curtail|{SESSION_ID},sleep"),
+            format!("logoff|{SESSION_ID}"),
+        ]
+    );
+
+    let dry_run = json!({"action": "reboot", "dry_run": true});
+    let reply = post(api, &luxos_path, Some(TOKEN), dry_run)
+        .await
+        .expect("BUG: dry runs succeed");
+    assert_eq!(reply["request"], "luxos session rebootdevice");
+    assert_eq!(reply["reply"], Value::Null);
+    assert_eq!(luxos_dumps.received().len(), 3, "dry runs send nothing");
+
+    let power = json!({"action": "set_power_target", "watts": 3000});
+    let reply = post(api, &luxos_path, Some(TOKEN), power.clone())
+        .await
+        .expect("BUG: fake miner accepts the power target");
+    assert_eq!(reply["ok"], true, "{reply}");
+    assert_eq!(
+        luxos_dumps.received()[3..],
+        [
+            "logon".to_owned(),
+            format!("powertargetset|{SESSION_ID},3000"),
+            format!("logoff|{SESSION_ID}"),
+        ]
+    );
+    let err = post(api, &stock_path, Some(TOKEN), power)
+        .await
+        .expect_err("BUG: stock has no power target");
+    assert_eq!(web::status(&err), Some(422));
+    let err = post(
+        api,
+        "/api/miners/10.0.0.9/actions",
+        Some(TOKEN),
+        json!({"action": "pause"}),
+    )
+    .await
+    .expect_err("BUG: no such miner");
+    assert_eq!(web::status(&err), Some(404));
+    let err = post(
+        api,
+        &luxos_path,
+        Some(TOKEN),
+        json!({"action": "overclock"}),
+    )
+    .await
+    .expect_err("BUG: no such action");
+    assert_eq!(web::status(&err), Some(400));
+
+    let entries: Vec<Value> = std::fs::read_to_string(&audit)
+        .expect("BUG: audit log is written")
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("BUG: entries are JSON"))
+        .collect();
+    let _ = std::fs::remove_file(&audit);
+    assert_eq!(entries.len(), 5, "{entries:#?}");
+    assert_eq!(entries[0]["action"], "pause");
+    assert_eq!(entries[0]["reason"], "grid event");
+    assert_eq!(entries[0]["ok"], true);
+    assert!(entries[0]["time"].as_u64().is_some());
+    assert_eq!(entries[1]["dry_run"], true);
+    assert_eq!(entries[2]["ok"], true);
+    assert_eq!(
+        entries[3]["error"],
+        "set_power_target 3000 is not supported on stock"
+    );
+    assert_eq!(entries[4]["error"], "unknown host");
+}
+
+#[tokio::test]
+async fn act_on_the_fleet() {
+    let (luxos, luxos_dumps) = fake_miner(Firmware::LuxOS, "s21pro").await;
+    let (braiins, braiins_dumps) = fake_miner(Firmware::Braiins, "s21plus").await;
+    let (stock, stock_dumps) = fake_miner(Firmware::Stock, "s21xp").await;
+    let api = serve(Config {
+        targets: vec![format!("127.0.0.1:{braiins}"), format!("127.0.0.1:{stock}")],
+        target_configs: vec![target(luxos, Some(Firmware::LuxOS))],
+        control: Some(ControlConfig {
+            token: TOKEN.to_owned(),
+            audit_log: None,
+            concurrency: 2,
+        }),
+        ..Config::default()
+    })
+    .await;
+
+    let reply = post(
+        api,
+        "/api/actions",
+        Some(TOKEN),
+        json!({"action": "switch_pool", "pool": 1, "all": true}),
+    )
+    .await
+    .expect("BUG: bulk actions are accepted");
+    assert_eq!(reply["failed"], 0, "{reply}");
+    let results = reply["results"].as_array().expect("BUG: results");
+    assert_eq!(results.len(), 3);
+    assert!(luxos_dumps
+        .received()
+        .contains(&format!("switchpool|{SESSION_ID},1")));
+    assert_eq!(braiins_dumps.received(), ["switchpool|1"]);
+    assert_eq!(stock_dumps.received(), ["switchpool|1"]);
+
+    // Stock firmware cannot pause, and unknown hosts are reported per host.
+    let reply = post(
+        api,
+        "/api/actions",
+        Some(TOKEN),
+        json!({
+            "action": "pause",
+            "hosts": [format!("127.0.0.1:{braiins}"), format!("127.0.0.1:{stock}"), "10.0.0.9"],
+            "concurrency": 1,
+        }),
+    )
+    .await
+    .expect("BUG: bulk actions are accepted");
+    assert_eq!(reply["failed"], 2, "{reply}");
+    assert_eq!(braiins_dumps.received(), ["switchpool|1", "pause"]);
+
+    for body in [
+        json!({"action": "pause"}),
+        json!({"action": "pause", "all": true, "hosts": ["10.0.0.9"]}),
+    ] {
+        let err = post(api, "/api/actions", Some(TOKEN), body)
+            .await
+            .expect_err("BUG: hosts are required");
+        assert_eq!(web::status(&err), Some(400));
+    }
+}
+
+#[tokio::test]
+async fn disabled_without_control_section() {
+    let api = serve(Config::default()).await;
+    let err = post(
+        api,
+        "/api/actions",
+        Some(TOKEN),
+        json!({"action": "pause", "all": true}),
+    )
+    .await
+    .expect_err("BUG: control API is disabled");
+    assert_eq!(web::status(&err), Some(404));
+}
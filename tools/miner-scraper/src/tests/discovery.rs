@@ -0,0 +1,104 @@
+use super::*;
+use crate::cgminer::Firmware;
+use crate::fake_miner::{self, Dumps, Faults};
+use std::path::Path;
+use std::sync::Arc;
+
+fn addrs(range: &str) -> Vec<String> {
+    expand(range)
+        .expect("BUG: range is valid")
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[test]
+fn expand_cidr_skips_network_and_broadcast() {
+    let hosts = addrs("10.0.0.0/30");
+    assert_eq!(hosts, ["10.0.0.1", "10.0.0.2"]);
+    assert_eq!(expand("10.0.0.77/24").expect("BUG: valid").len(), 254);
+}
+
+#[test]
+fn expand_small_cidr() {
+    assert_eq!(addrs("10.0.0.5/32"), ["10.0.0.5"]);
+    assert_eq!(addrs("10.0.0.4/31"), ["10.0.0.4", "10.0.0.5"]);
+}
+
+#[test]
+fn expand_ranges() {
+    assert_eq!(
+        addrs("10.0.0.254-10.0.1.1"),
+        ["10.0.0.254", "10.0.0.255", "10.0.1.0", "10.0.1.1"]
+    );
+    assert_eq!(
+        addrs("10.0.0.10-12"),
+        ["10.0.0.10", "10.0.0.11", "10.0.0.12"]
+    );
+    assert_eq!(addrs(" 10.0.0.7 "), ["10.0.0.7"]);
+}
+
+#[test]
+fn expand_rejects_invalid_ranges() {
+    for range in [
+        "10.0.0.0/8",
+        "10.0.0.0/33",
+        "10.0.0.0/x",
+        "10.0.0.20-10",
+        "10.0.0.0-10.2.0.0",
+        "miner.local",
+        "",
+    ] {
+        assert!(expand(range).is_err(), "{range} should be rejected");
+    }
+}
+
+#[test]
+fn seen_hosts_age_out_after_grace() {
+    let grace = Duration::from_mins(1);
+    let start = Instant::now();
+    let mut seen = Seen::default();
+    seen.update(
+        Discovered::from([
+            ("10.0.0.1".to_owned(), "10.0.0.0/24".to_owned()),
+            ("10.0.0.2".to_owned(), "10.0.0.0/24".to_owned()),
+        ]),
+        start,
+        grace,
+    );
+
+    let only_first = Discovered::from([("10.0.0.1".to_owned(), "10.0.0.0/24".to_owned())]);
+    seen.update(only_first.clone(), start + Duration::from_secs(30), grace);
+    assert_eq!(seen.discovered().len(), 2);
+
+    seen.update(only_first.clone(), start + Duration::from_secs(61), grace);
+    assert_eq!(seen.discovered(), only_first);
+}
+
+/// Answer on a loopback port like a stock miner would.
+async fn fake_miner() -> u16 {
+    let dumps_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("dumps");
+    let dumps = Dumps::load(&dumps_dir, Firmware::Stock, "s21xp").expect("BUG: dumps exist");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(fake_miner::serve(
+        listener,
+        Arc::new(dumps),
+        Faults::default(),
+    ));
+    port
+}
+
+#[tokio::test]
+async fn scan_finds_responding_hosts() {
+    let port = fake_miner().await;
+    // Only 127.0.0.1 is bound; the rest of the range refuses the connection.
+    let ranges = vec!["127.0.0.1-3".to_owned(), "not a range".to_owned()];
+    let found = scan(&ranges, port, 2).await;
+    assert_eq!(
+        found,
+        Discovered::from([("127.0.0.1".to_owned(), "127.0.0.1-3".to_owned())])
+    );
+}
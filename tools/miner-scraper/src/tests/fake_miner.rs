@@ -0,0 +1,309 @@
+use super::*;
+use crate::cgminer;
+use crate::config::Config;
+use crate::control::Control;
+use crate::discovery::Discovered;
+use crate::schedule::SystemClock;
+use crate::store::MetricsStore;
+use crate::{http, scrape};
+use tokio::sync::watch;
+
+fn dumps_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("dumps")
+}
+
+fn load(firmware: Firmware, model: &str) -> Dumps {
+    Dumps::load(&dumps_dir(), firmware, model).expect("BUG: dumps exist")
+}
+
+/// Start a fake miner on an ephemeral port and return the port.
+async fn start(firmware: Firmware, model: &str, faults: Faults) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(serve(listener, Arc::new(load(firmware, model)), faults));
+    port
+}
+
+#[test]
+fn faults_every_nth_request() {
+    let faults = Faults {
+        error_every: 2,
+        truncate_every: 3,
+        disconnect_every: 5,
+        ..Faults::default()
+    };
+    let picked: Vec<Fault> = (1..=6).map(|n| faults.pick(n)).collect();
+    assert_eq!(
+        picked,
+        [
+            Fault::None,
+            Fault::Error,
+            Fault::Truncate,
+            Fault::Error,
+            Fault::Disconnect,
+            Fault::Truncate,
+        ]
+    );
+    assert_eq!(Faults::default().pick(1), Fault::None);
+}
+
+#[test]
+fn load_dumps_of_one_model() {
+    let dumps = load(Firmware::LuxOS, "s21pro");
+    assert!(dumps.replies.contains_key("stats"));
+    assert!(dumps.replies.contains_key("tunerstatus"));
+    assert!(Dumps::load(&dumps_dir(), Firmware::LuxOS, "s9").is_err());
+}
+
+#[test]
+fn reply_to_joined_commands() {
+    let dumps = load(Firmware::Stock, "s21xp");
+    let reply = dumps.reply("stats+summary");
+    assert!(reply.pointer("/stats/0/STATS").is_some());
+    assert!(reply.pointer("/summary/0/SUMMARY").is_some());
+    assert!(cgminer::status_error(&dumps.reply("tunerstatus")).is_some());
+
+    // BOSer rejects joined commands.
+    let dumps = load(Firmware::Braiins, "s21plus");
+    assert!(cgminer::status_error(&dumps.reply("summary+devs")).is_some());
+}
+
+#[tokio::test]
+async fn answers_cgminer_commands() {
+    let port = start(Firmware::Braiins, "s21plus", Faults::default()).await;
+    let stats = cgminer::command("127.0.0.1", port, "stats")
+        .await
+        .expect("BUG: fake miner answers");
+    assert!(stats.get("STATS").is_some());
+    assert_eq!(Firmware::detect("127.0.0.1", port).await, Firmware::Braiins);
+}
+
+#[tokio::test]
+async fn injects_faults() {
+    let faults = Faults {
+        error_every: 1,
+        ..Faults::default()
+    };
+    let port = start(Firmware::Stock, "s21xp", faults).await;
+    let reply = cgminer::command("127.0.0.1", port, "summary")
+        .await
+        .expect("BUG: error replies are JSON");
+    assert_eq!(cgminer::status_error(&reply), Some("Injected error"));
+
+    for faults in [
+        Faults {
+            truncate_every: 1,
+            ..Faults::default()
+        },
+        Faults {
+            disconnect_every: 1,
+            ..Faults::default()
+        },
+    ] {
+        let port = start(Firmware::Stock, "s21xp", faults).await;
+        assert!(cgminer::command("127.0.0.1", port, "summary")
+            .await
+            .is_err());
+    }
+}
+
+#[tokio::test]
+async fn injects_latency() {
+    let faults = Faults {
+        latency_ms: 300,
+        ..Faults::default()
+    };
+    let port = start(Firmware::Stock, "s21xp", faults).await;
+    let started = std::time::Instant::now();
+    cgminer::command("127.0.0.1", port, "version")
+        .await
+        .expect("BUG: fake miner answers");
+    assert!(started.elapsed() >= Duration::from_millis(300));
+}
+
+// --- End to end: scrape loop and HTTP server against fake miners ---
+
+/// Fetch `/metrics` with a minimal HTTP/1.1 request.
+async fn get_metrics(port: u16) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port))
+        .await
+        .expect("BUG: connect to the scraper");
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .expect("BUG: send request");
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .await
+        .expect("BUG: read response");
+    response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_owned())
+        .unwrap_or_default()
+}
+
+/// Run the scrape loop and HTTP server against `targets` until `done`
+/// accepts the metrics page, or fail after 15 seconds.
+async fn scrape_until(targets: Vec<String>, done: impl Fn(&str) -> bool) -> String {
+    let config = Config {
+        targets,
+        scrape_interval_secs: 1,
+        ..Config::default()
+    };
+    let (_config_tx, config_rx) = watch::channel(config);
+    let (_discovered_tx, discovered_rx) = watch::channel(Discovered::new());
+    let store = MetricsStore::new();
+    let control = Control::new(config_rx.clone(), discovered_rx.clone());
+    let scraper = tokio::spawn(scrape::run(
+        config_rx,
+        discovered_rx,
+        store.clone(),
+        Arc::new(SystemClock),
+    ));
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind HTTP server");
+    let http_port = listener.local_addr().expect("BUG: local addr").port();
+    let server =
+        tokio::spawn(async move { axum::serve(listener, http::router(store, control)).await });
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(15);
+    let metrics = loop {
+        let metrics = get_metrics(http_port).await;
+        if done(&metrics) || std::time::Instant::now() > deadline {
+            break metrics;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    };
+    scraper.abort();
+    server.abort();
+    metrics
+}
+
+fn line_for<'a>(metrics: &'a str, name: &str, host: &str) -> Option<&'a str> {
+    let prefix = format!("{name}{{host=\"{host}\"");
+    metrics.lines().find(|l| l.starts_with(&prefix))
+}
+
+#[tokio::test]
+async fn scrape_fleet_end_to_end() {
+    let miners = [
+        (Firmware::Stock, "s21xp"),
+        (Firmware::LuxOS, "s21pro"),
+        (Firmware::Braiins, "s21plus"),
+        (Firmware::Mara, "s21imm"),
+        (Firmware::Vnish, "s21"),
+        (Firmware::Whatsminer, "m30splusve40"),
+        (Firmware::Avalon, "avalonminer1466"),
+    ];
+    let mut hosts = Vec::new();
+    for (firmware, model) in miners {
+        let port = start(firmware, model, Faults::default()).await;
+        hosts.push((firmware, format!("127.0.0.1:{port}")));
+    }
+    let targets = hosts.iter().map(|(_, host)| host.clone()).collect();
+    let ready = |metrics: &str| {
+        hosts.iter().all(|(_, host)| {
+            line_for(metrics, "miner_info", host).is_some()
+                && line_for(metrics, "miner_up", host).is_some_and(|l| l.contains("} 1 "))
+        })
+    };
+    let metrics = scrape_until(targets, ready).await;
+    assert!(ready(&metrics), "{metrics}");
+
+    for (firmware, host) in &hosts {
+        let info = line_for(&metrics, "miner_info", host).expect("BUG: checked above");
+        assert!(info.contains(&format!("firmware=\"{firmware}\"")), "{info}");
+        assert!(
+            line_for(&metrics, "miner_hashrate_hashes_per_second", host).is_some(),
+            "no hashrate for {firmware}"
+        );
+    }
+    assert!(metrics.contains("# TYPE miner_shares_accepted_total counter\n"));
+    // Each family is rendered once, with all hosts under it.
+    assert_eq!(metrics.matches("# TYPE miner_up gauge\n").count(), 1);
+}
+
+#[tokio::test]
+async fn scrape_faulty_miners_end_to_end() {
+    let erroring = start(
+        Firmware::Stock,
+        "s21xp",
+        Faults {
+            error_every: 1,
+            ..Faults::default()
+        },
+    )
+    .await;
+    let disconnecting = start(
+        Firmware::Stock,
+        "s21xp",
+        Faults {
+            disconnect_every: 1,
+            ..Faults::default()
+        },
+    )
+    .await;
+    // Every other request is truncated. BOSer answers one command per
+    // connection, so some commands of every scrape fail while the host
+    // stays up.
+    let flaky = start(
+        Firmware::Braiins,
+        "s21plus",
+        Faults {
+            truncate_every: 2,
+            ..Faults::default()
+        },
+    )
+    .await;
+    let hosts = [erroring, disconnecting, flaky].map(|port| format!("127.0.0.1:{port}"));
+
+    let ready = |metrics: &str| {
+        hosts.iter().all(|host| {
+            line_for(metrics, "miner_scrape_failures_total", host).is_some()
+                && line_for(metrics, "miner_scrape_command_success", host).is_some()
+        }) && line_for(metrics, "miner_up", &hosts[2]).is_some_and(|l| l.contains("} 1 "))
+    };
+    let metrics = scrape_until(hosts.to_vec(), ready).await;
+    assert!(ready(&metrics), "{metrics}");
+
+    for host in &hosts[..2] {
+        let up = line_for(&metrics, "miner_up", host).expect("BUG: checked above");
+        assert!(up.contains("} 0 "), "{up}");
+        assert!(line_for(&metrics, "miner_hashrate_hashes_per_second", host).is_none());
+    }
+    let failed = metrics
+        .lines()
+        .filter(|l| l.starts_with("miner_scrape_command_success{"))
+        .filter(|l| l.contains(&format!("host=\"{}\"", hosts[2])) && l.contains("} 0 "))
+        .count();
+    assert!(failed > 0, "{metrics}");
+}
+
+#[tokio::test]
+async fn acknowledges_control_commands() {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    let dumps = Arc::new(load(Firmware::LuxOS, "s21pro"));
+    tokio::spawn(serve(listener, dumps.clone(), Faults::default()));
+
+    let logon = cgminer::command("127.0.0.1", port, "logon")
+        .await
+        .expect("BUG: fake miner answers");
+    assert_eq!(logon["SESSION"][0]["SessionID"], SESSION_ID);
+    let reply =
+        cgminer::command_with_param("127.0.0.1", port, "curtail", Some("fake-session,sleep"))
+            .await
+            .expect("BUG: fake miner answers");
+    assert_eq!(cgminer::status_error(&reply), None);
+    cgminer::command("127.0.0.1", port, "stats")
+        .await
+        .expect("BUG: fake miner answers");
+    assert_eq!(dumps.received(), ["logon", "curtail|fake-session,sleep"]);
+}
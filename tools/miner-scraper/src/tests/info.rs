@@ -0,0 +1,101 @@
+use super::*;
+
+const STOCK_VERSION: &str = include_str!("../../dumps/stock-cgminer-version-s21xp.json");
+const STOCK_DEVDETAILS: &str = include_str!("../../dumps/stock-cgminer-devdetails-s21xp.json");
+const BRAIINS_VERSION: &str = include_str!("../../dumps/braiins-cgminer-version-s21plus.json");
+const BRAIINS_DEVDETAILS: &str =
+    include_str!("../../dumps/braiins-cgminer-devdetails-s21plus.json");
+const LUXOS_VERSION: &str = include_str!("../../dumps/luxos-cgminer-version-s21pro.json");
+const LUXOS_CONFIG: &str = include_str!("../../dumps/luxos-cgminer-config-s21pro.json");
+const MARA_VERSION: &str = include_str!("../../dumps/mara-cgminer-version-s21imm.json");
+const WHATSMINER_DEVDETAILS: &str =
+    include_str!("../../dumps/whatsminer-cgminer-devdetails-m30splusve40.json");
+const WHATSMINER_GET_VERSION: &str =
+    include_str!("../../dumps/whatsminer-cgminer-get_version-m30splusve40.json");
+const AVALON_VERSION: &str =
+    include_str!("../../dumps/avalon-cgminer-version-avalonminer1466.json");
+
+fn parse(dumps: &[&str]) -> MinerInfo {
+    let responses: Vec<Value> = dumps
+        .iter()
+        .map(|d| serde_json::from_str(d).expect("BUG: dump data is valid JSON"))
+        .collect();
+    MinerInfo::parse(&responses)
+}
+
+#[test]
+fn stock_info() {
+    let info = parse(&[STOCK_VERSION, STOCK_DEVDETAILS]);
+    assert_eq!(
+        info,
+        MinerInfo {
+            model: "Antminer S21 XP".into(),
+            api_version: "3.1".into(),
+            miner_version: "uart_trans.1.3".into(),
+            firmware_version: "1.0.0".into(),
+            mac: String::new(),
+        }
+    );
+}
+
+#[test]
+fn braiins_info_model_from_devdetails() {
+    let info = parse(&[BRAIINS_VERSION, BRAIINS_DEVDETAILS]);
+    assert_eq!(info.model, "Antminer S21+");
+    assert_eq!(info.api_version, "3.7");
+    assert_eq!(info.firmware_version, "boser-buildroot 0.1.0-f8935b4e");
+    assert_eq!(info.miner_version, info.firmware_version);
+}
+
+#[test]
+fn luxos_info_with_mac() {
+    let info = parse(&[LUXOS_VERSION, LUXOS_CONFIG]);
+    assert_eq!(info.model, "Antminer S21 Pro");
+    assert_eq!(info.miner_version, "2026.3.2.193145");
+    assert_eq!(info.firmware_version, "2026.3.2.193145-42668da4d");
+    assert_eq!(info.mac, "02:8b:11:49:72:3c");
+}
+
+#[test]
+fn mara_info_prefers_model_over_type() {
+    let info = parse(&[MARA_VERSION]);
+    assert_eq!(info.model, "Antminer S21Imm");
+    assert_eq!(info.firmware_version, "MaraFW rel 3.15_968");
+}
+
+#[test]
+fn whatsminer_info_from_get_version() {
+    let info = parse(&[WHATSMINER_DEVDETAILS, WHATSMINER_GET_VERSION]);
+    assert_eq!(info.model, "M30S+VE40");
+    assert_eq!(info.api_version, "2.0.5");
+    assert_eq!(info.firmware_version, "20230911.22.REL");
+    assert_eq!(info.miner_version, info.firmware_version);
+}
+
+#[test]
+fn avalon_info_from_prod() {
+    let info = parse(&[AVALON_VERSION]);
+    assert_eq!(info.model, "AvalonMiner 1466");
+    assert_eq!(info.api_version, "3.7");
+    assert_eq!(info.firmware_version, "4.11.1");
+    assert_eq!(info.mac, "00:00:5e:00:53:00");
+}
+
+#[test]
+fn type_suffix_is_stripped() {
+    assert_eq!(strip_suffix("Antminer S21 (Vnish 1.2.7)"), "Antminer S21");
+    assert_eq!(strip_suffix("Antminer S21 XP"), "Antminer S21 XP");
+}
+
+#[test]
+fn metric_line_skips_empty_labels() {
+    let info = parse(&[STOCK_VERSION]);
+    let line = info.to_metric("10.0.0.1", Firmware::Stock);
+    assert!(line.starts_with(
+        "miner_info{host=\"10.0.0.1\",firmware=\"stock\",model=\"Antminer S21 XP\",\
+         api_version=\"3.1\",miner_version=\"uart_trans.1.3\",firmware_version=\"1.0.0\"} 1 "
+    ));
+
+    let line = MinerInfo::default().to_metric("10.0.0.2", Firmware::Vnish);
+    assert!(line.starts_with("miner_info{host=\"10.0.0.2\",firmware=\"vnish\"} 1 "));
+}
@@ -0,0 +1,58 @@
+use super::*;
+
+#[test]
+fn counters_by_section() {
+    assert!(is_counter(Firmware::Stock, "summary_accepted"));
+    assert!(is_counter(Firmware::Braiins, "summary_hardware_errors"));
+    assert!(is_counter(Firmware::Mara, "summary_getwork"));
+    assert!(is_counter(Firmware::Vnish, "summary_getworks"));
+    assert!(is_counter(Firmware::LuxOS, "devs_device_elapsed"));
+    assert!(is_counter(Firmware::Stock, "stats_chain_hw"));
+    assert!(!is_counter(Firmware::Stock, "summary_ghs_5s"));
+    assert!(!is_counter(Firmware::Stock, "stats_chain_acn"));
+    // A field is only a counter in the sections it is listed for.
+    assert!(!is_counter(Firmware::Stock, "temps_accepted"));
+    assert!(!is_counter(Firmware::Stock, "summary_accepted_extra"));
+}
+
+#[test]
+fn counters_by_firmware() {
+    assert!(is_counter(Firmware::LuxOS, "stats_elapsed"));
+    // BOSer `stats` is a cgminer API timing table, not the Antminer layout.
+    assert!(!is_counter(Firmware::Braiins, "stats_elapsed"));
+}
+
+#[test]
+fn raw_name_adds_total_to_counters() {
+    assert_eq!(
+        raw_name(Firmware::Stock, "summary_accepted"),
+        "summary_accepted_total"
+    );
+    assert_eq!(raw_name(Firmware::Stock, "stats_fan"), "stats_fan");
+}
+
+#[test]
+fn kind_from_suffix() {
+    assert_eq!(Kind::of("miner_shares_accepted_total"), Kind::Counter);
+    assert_eq!(Kind::of("miner_uptime_seconds"), Kind::Gauge);
+}
+
+#[test]
+fn header_of_known_and_generic_families() {
+    assert_eq!(
+        header("miner_up"),
+        "# HELP miner_up Whether any command of the last scrape succeeded.\n\
+         # TYPE miner_up gauge\n"
+    );
+    assert_eq!(
+        header("summary_accepted_total"),
+        "# HELP summary_accepted_total Cgminer field summary_accepted_total.\n\
+         # TYPE summary_accepted_total counter\n"
+    );
+}
+
+#[test]
+fn family_of_line() {
+    assert_eq!(family(r#"miner_up{host="a"} 1 1"#), "miner_up");
+    assert_eq!(family("up 1"), "up");
+}
@@ -20,6 +20,12 @@ fn gauge_with_labels() {
     assert!(line.starts_with("pcb_temperature_celsius{host=\"10.0.0.1\",hashboard=\"1\"} 65 "));
 }
 
+#[test]
+fn gauge_escapes_label_values() {
+    let line = gauge("pools_accepted", &[("user", "a\"b\\c\nd")], 1.0);
+    assert!(line.starts_with(r#"pools_accepted{user="a\"b\\c\nd"} 1 "#));
+}
+
 #[test]
 fn gauge_fractional_value() {
     let line = gauge(
@@ -50,3 +56,33 @@ fn format_value_fractional() {
     assert_eq!(format_value(23.5), "23.5");
     assert_eq!(format_value(90858.66), "90858.66");
 }
+
+#[test]
+fn add_labels_appends_to_label_set() {
+    let labels = [
+        ("site".to_owned(), "north".to_owned()),
+        ("rack".to_owned(), "3".to_owned()),
+    ];
+    assert_eq!(
+        add_labels("miner_up{host=\"10.0.0.1\"} 1 1000", &labels),
+        "miner_up{host=\"10.0.0.1\",site=\"north\",rack=\"3\"} 1 1000"
+    );
+    assert_eq!(
+        add_labels("up 1 1000", &labels),
+        "up{site=\"north\",rack=\"3\"} 1 1000"
+    );
+    assert_eq!(
+        add_labels("up{} 1", &labels),
+        "up{site=\"north\",rack=\"3\"} 1"
+    );
+    assert_eq!(add_labels("up 1", &[]), "up 1");
+}
+
+#[test]
+fn add_labels_skips_braces_in_values() {
+    let labels = [("site".to_owned(), "a\"b".to_owned())];
+    assert_eq!(
+        add_labels(r#"pools_accepted{url="x}\"}"} 1"#, &labels),
+        r#"pools_accepted{url="x}\"}",site="a\"b"} 1"#
+    );
+}
@@ -0,0 +1,112 @@
+use super::*;
+
+const LUXOS_POOLS: &str = include_str!("../../dumps/luxos-cgminer-pools-s21pro.json");
+const MARA_POOLS: &str = include_str!("../../dumps/mara-cgminer-pools-s21imm.json");
+const STOCK_POOLS: &str = include_str!("../../dumps/stock-cgminer-pools-s21xp.json");
+
+fn parse_to_lines(data: &str) -> Vec<String> {
+    let value: Value = serde_json::from_str(data).expect("BUG: dump data is valid JSON");
+    parse_response("10.0.0.1", &value)
+}
+
+/// Find the metric line for a pool index, returning everything before the timestamp.
+fn find_pool<'a>(lines: &'a [String], name: &str, pool: &str) -> Option<&'a str> {
+    let pool_label = format!("pool=\"{pool}\"");
+    lines
+        .iter()
+        .find(|l| l.starts_with(&format!("{name}{{")) && l.contains(&pool_label))
+        .and_then(|l| l.rsplit_once(' '))
+        .map(|(metric, _)| metric)
+}
+
+#[test]
+fn luxos_pools_labels_and_values() {
+    let lines = parse_to_lines(LUXOS_POOLS);
+    assert_eq!(
+        find_pool(&lines, "pools_accepted_total", "0"),
+        Some(
+            "pools_accepted_total{host=\"10.0.0.1\",pool=\"0\",\
+             url=\"stratum+tcp://stratum.slushpool.com:3333\",user=\"braiinstest\",\
+             status=\"alive\",stratum_active=\"true\"} 1557"
+        )
+    );
+    let backup = find_pool(&lines, "pools_priority", "1").expect("BUG: backup pool present");
+    assert!(backup.contains("status=\"disabled\""));
+    assert!(backup.contains("stratum_active=\"false\""));
+    assert!(backup.ends_with(" 1"));
+
+    assert!(find_pool(&lines, "pools_difficulty", "0")
+        .expect("BUG: difficulty present")
+        .ends_with(" 262144"));
+    assert!(find_pool(&lines, "pools_last_share_seconds", "0")
+        .expect("BUG: last share present")
+        .ends_with(" 6"));
+    assert!(find_pool(&lines, "pools_last_share_seconds", "1")
+        .expect("BUG: last share present")
+        .ends_with(" 6917"));
+}
+
+#[test]
+fn mara_pools_go_duration_and_diff_string() {
+    let lines = parse_to_lines(MARA_POOLS);
+    assert!(find_pool(&lines, "pools_rejected_total", "0")
+        .expect("BUG: rejected present")
+        .ends_with(" 1"));
+    assert!(find_pool(&lines, "pools_difficulty", "0")
+        .expect("BUG: difficulty present")
+        .ends_with(" 262144"));
+    assert!(find_pool(&lines, "pools_last_share_seconds", "0")
+        .expect("BUG: last share present")
+        .ends_with(" 5.133775948"));
+}
+
+#[test]
+fn stock_pools_unused_slots() {
+    let lines = parse_to_lines(STOCK_POOLS);
+    assert_eq!(
+        lines
+            .iter()
+            .filter(|l| l.starts_with("pools_accepted_total{"))
+            .count(),
+        3
+    );
+    // "262K" expands; unused slots have no difficulty and never shared.
+    assert!(find_pool(&lines, "pools_difficulty", "0")
+        .expect("BUG: difficulty present")
+        .ends_with(" 262000"));
+    assert!(find_pool(&lines, "pools_difficulty", "1").is_none());
+    assert!(find_pool(&lines, "pools_last_share_seconds", "1").is_none());
+    assert!(find_pool(&lines, "pools_stale_total", "2")
+        .expect("BUG: stale present")
+        .contains("status=\"deed\""));
+}
+
+#[test]
+fn last_share_formats() {
+    let now = 1_000_000.0;
+    let parse = |v: Value| parse_last_share(&v, now);
+    assert_eq!(parse(Value::from("0:01:05")), Some(65.0));
+    assert_eq!(parse(Value::from("02:00")), Some(120.0));
+    assert_eq!(parse(Value::from("1m5.5s")), Some(65.5));
+    let ms = parse(Value::from("350ms")).expect("BUG: milliseconds parse");
+    assert!((ms - 0.35).abs() < 1e-9);
+    assert_eq!(parse(Value::from(999_990)), Some(10.0));
+    assert_eq!(parse(Value::from("999990")), Some(10.0));
+    assert_eq!(parse(Value::from(0)), None);
+    assert_eq!(parse(Value::from("0")), None);
+    assert_eq!(parse(Value::from("soon")), None);
+    assert_eq!(parse(Value::from("")), None);
+}
+
+#[test]
+fn si_suffixes() {
+    assert_eq!(parse_si("262K"), Some(262_000.0));
+    assert_eq!(parse_si("1.5M"), Some(1_500_000.0));
+    assert_eq!(parse_si("512"), Some(512.0));
+    assert_eq!(parse_si(""), None);
+}
+
+#[test]
+fn missing_pools_section() {
+    assert!(parse_response("10.0.0.1", &serde_json::json!({"STATUS": []})).is_empty());
+}
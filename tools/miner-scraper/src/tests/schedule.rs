@@ -0,0 +1,458 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use super::*;
+use crate::config::{Config, TargetConfig};
+use crate::discovery::Discovered;
+use crate::fake_miner::{self, Dumps, Faults, SESSION_ID};
+use crate::scrape;
+use crate::store::MetricsStore;
+
+fn schedule(toml: &str) -> ScheduleConfig {
+    toml::from_str(toml).expect("BUG: valid schedule")
+}
+
+fn peak() -> ScheduleConfig {
+    schedule(
+        r#"
+        name = "peak"
+        cron = "0 17 * * mon-fri"
+        duration_mins = 240
+        timezone = "UTC"
+        action = "pause"
+        labels = { site = "north" }
+        "#,
+    )
+}
+
+fn new_window(cron: &str, duration_mins: u32, timezone: &str) -> Result<Window> {
+    Window::new(&WindowConfig {
+        cron: cron.to_owned(),
+        duration_mins,
+        timezone: Some(timezone.to_owned()),
+    })
+}
+
+fn at(time: &str) -> Timestamp {
+    time.parse().expect("BUG: valid timestamp")
+}
+
+fn datetime(time: &str) -> DateTime {
+    time.parse().expect("BUG: valid datetime")
+}
+
+#[test]
+fn parse_cron() {
+    let cron: Cron = "*/15 8-18/2 * jan,jul mon-fri"
+        .parse()
+        .expect("BUG: valid cron");
+    // 2026-01-05 is a Monday.
+    assert!(cron.matches(datetime("2026-01-05T08:45")));
+    assert!(cron.matches(datetime("2026-07-10T18:00")));
+    assert!(!cron.matches(datetime("2026-01-05T09:00")), "odd hour");
+    assert!(!cron.matches(datetime("2026-01-05T08:50")), "off step");
+    assert!(!cron.matches(datetime("2026-01-04T08:45")), "Sunday");
+    assert!(!cron.matches(datetime("2026-02-02T08:45")), "February");
+
+    // Sunday is 0 or 7, and a day matches either restricted day field.
+    let cron: Cron = "30 6 1 * 7".parse().expect("BUG: valid cron");
+    assert!(cron.matches(datetime("2026-01-04T06:30")), "a Sunday");
+    assert!(cron.matches(datetime("2026-01-01T06:30")), "the first");
+    assert!(!cron.matches(datetime("2026-01-02T06:30")));
+
+    for invalid in [
+        "0 17 * *",
+        "60 17 * * *",
+        "0 17 0 * *",
+        "0 17 * * fri-mon",
+        "0 17 * * someday",
+        "*/0 17 * * *",
+    ] {
+        assert!(invalid.parse::<Cron>().is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn windows_open_for_their_duration() {
+    let window = peak().window;
+    // 2026-10-16 is a Friday.
+    assert!(!window.is_open(at("2026-10-16T16:59:59Z")));
+    assert!(window.is_open(at("2026-10-16T17:00:00Z")));
+    assert!(window.is_open(at("2026-10-16T20:59:59Z")));
+    assert!(!window.is_open(at("2026-10-16T21:00:00Z")));
+    assert!(!window.is_open(at("2026-10-17T17:30:00Z")), "Saturday");
+
+    // Windows run past midnight, and cron times are local.
+    let window = new_window("0 22 * * *", 8 * 60, "Europe/Berlin").expect("BUG: valid window");
+    assert!(window.is_open(at("2026-07-01T20:00:00Z")), "22:00 CEST");
+    assert!(window.is_open(at("2026-07-02T03:59:00Z")));
+    assert!(!window.is_open(at("2026-07-02T04:00:00Z")));
+
+    for (duration, timezone) in [
+        (0, "UTC"),
+        (MAX_DURATION_MINS + 1, "UTC"),
+        (60, "Mars/Olympus"),
+    ] {
+        assert!(
+            new_window("0 17 * * *", duration, timezone).is_err(),
+            "{duration} {timezone}"
+        );
+    }
+}
+
+fn target(name: &str, labels: &[(&str, &str)]) -> Target {
+    let mut target = scrape::targets(
+        &Config {
+            targets: vec![name.to_owned()],
+            ..Config::default()
+        },
+        &Discovered::new(),
+    )
+    .remove(name)
+    .expect("BUG: valid target");
+    target.labels = labels
+        .iter()
+        .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+        .collect();
+    target
+}
+
+#[test]
+fn select_by_host_or_labels() {
+    let mut peak = peak();
+    let north = target("10.0.0.1", &[("site", "north"), ("rack", "a1")]);
+    let south = target("10.0.0.2", &[("site", "south")]);
+    assert!(selects(&peak, &north));
+    assert!(!selects(&peak, &south));
+
+    peak.hosts = vec!["10.0.0.2".to_owned()];
+    assert!(selects(&peak, &south));
+    peak.labels.clear();
+    assert!(!selects(&peak, &north));
+}
+
+#[test]
+fn combine_schedules() {
+    let peak = peak();
+    let mut shoulder = peak.clone();
+    shoulder.name = "shoulder".to_owned();
+    shoulder.window = new_window("0 15 * * *", 240, "UTC").expect("BUG: valid window");
+    shoulder.action = Action::SetPowerTarget { watts: 3000 };
+    shoulder.restore_watts = Some(3500);
+    let mut evening = shoulder.clone();
+    evening.name = "evening".to_owned();
+    evening.window = new_window("0 16 * * *", 240, "UTC").expect("BUG: valid window");
+    evening.action = Action::SetPowerTarget { watts: 2500 };
+    evening.restore_watts = Some(3600);
+    let schedules = [&peak, &shoulder, &evening];
+
+    let wanted = Wanted::at(&schedules, at("2026-10-16T14:00:00Z"));
+    assert_eq!(wanted.state, State::Normal);
+    assert!(wanted.active.is_empty());
+    assert_eq!(wanted.restore_watts, Some(3600));
+    assert!(wanted.pauses);
+
+    let wanted = Wanted::at(&schedules, at("2026-10-16T15:30:00Z"));
+    assert_eq!(wanted.state, State::PowerTarget(3000));
+    let wanted = Wanted::at(&schedules, at("2026-10-16T16:30:00Z"));
+    assert_eq!(wanted.state, State::PowerTarget(2500), "lowest target");
+    let wanted = Wanted::at(&schedules, at("2026-10-16T17:30:00Z"));
+    assert_eq!(wanted.state, State::Paused, "pausing wins");
+    assert_eq!(wanted.active, ["peak", "shoulder", "evening"]);
+}
+
+#[test]
+fn read_live_state() {
+    let lines = [
+        r#"miner_hashrate_hashes_per_second{host="a b"} 0 1700000000000"#.to_owned(),
+        r#"miner_power_limit_watts{host="a b"} 3499.6 1700000000000"#.to_owned(),
+    ];
+    assert_eq!(
+        Live::from_lines(&lines),
+        Live {
+            hashing: Some(false),
+            power_limit: Some(3500),
+        }
+    );
+    assert_eq!(Live::from_lines(&[]), Live::default());
+}
+
+#[test]
+fn reconcile_with_a_mocked_clock() {
+    let peak = peak();
+    let schedules = [&peak];
+    let hashing = Live {
+        hashing: Some(true),
+        power_limit: None,
+    };
+    let idle = Live {
+        hashing: Some(false),
+        power_limit: None,
+    };
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let mut curtailment = Curtailment::new(clock.clone());
+    let before = Wanted::at(&schedules, at("2026-10-16T16:00:00Z"));
+    assert_eq!(
+        curtailment.next(&before, hashing, at("2026-10-16T16:00:00Z")),
+        None
+    );
+    curtailment.applied = Some(State::Normal);
+
+    // The window opens: pause, then wait for the pause to show.
+    let now = at("2026-10-16T17:00:02Z");
+    let during = Wanted::at(&schedules, now);
+    assert_eq!(curtailment.next(&during, hashing, now), Some(Action::Pause));
+    curtailment.applied = Some(State::Paused);
+    curtailment.last = Some((now, State::Paused));
+    let now = at("2026-10-16T17:00:07Z");
+    assert_eq!(curtailment.next(&during, hashing, now), None);
+    let now = at("2026-10-16T17:10:00Z");
+    assert_eq!(curtailment.next(&during, idle, now), None);
+
+    // A reboot brings back hashing, and the miner is paused again.
+    assert_eq!(curtailment.next(&during, hashing, now), Some(Action::Pause));
+
+    // The window closes.
+    let now = at("2026-10-16T21:00:00Z");
+    let after = Wanted::at(&schedules, now);
+    assert_eq!(curtailment.next(&after, idle, now), Some(Action::Resume));
+
+    // After a restart of the scraper, a miner left paused is resumed, and
+    // one hashing is left alone.
+    let fresh = Curtailment::new(clock.clone());
+    assert_eq!(fresh.next(&after, idle, now), Some(Action::Resume));
+    assert_eq!(fresh.next(&after, hashing, now), None);
+
+    // Power targets are restored to `restore_watts`.
+    let mut power = peak.clone();
+    power.action = Action::SetPowerTarget { watts: 2500 };
+    power.restore_watts = Some(3500);
+    let schedules = [&power];
+    let limit = |watts| Live {
+        hashing: Some(true),
+        power_limit: Some(watts),
+    };
+    let now = at("2026-10-16T17:00:00Z");
+    let during = Wanted::at(&schedules, now);
+    assert_eq!(
+        fresh.next(&during, limit(3500), now),
+        Some(Action::SetPowerTarget { watts: 2500 })
+    );
+    let mut curtailed = Curtailment::new(clock);
+    curtailed.record(&during);
+    assert_eq!(curtailed.next(&during, limit(2500), now), None);
+    let now = at("2026-10-16T21:00:00Z");
+    let after = Wanted::at(&schedules, now);
+    assert_eq!(
+        curtailed.next(&after, limit(2500), now),
+        Some(Action::SetPowerTarget { watts: 3500 })
+    );
+    assert_eq!(
+        fresh.next(&after, limit(2500), now),
+        Some(Action::SetPowerTarget { watts: 3500 })
+    );
+    assert_eq!(fresh.next(&after, limit(3500), now), None);
+
+    // A reload drops the schedule mid-window; the miner is still restored
+    // to the `restore_watts` it was curtailed under.
+    let now = at("2026-10-16T18:00:00Z");
+    let dropped = Wanted::at(&[], now);
+    assert_eq!(
+        curtailed.next(&dropped, limit(2500), now),
+        Some(Action::SetPowerTarget { watts: 3500 })
+    );
+}
+
+/// A clock the test sets.
+struct MockClock(Mutex<Timestamp>);
+
+impl MockClock {
+    fn set(&self, now: Timestamp) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[tokio::test]
+async fn skip_actions_the_firmware_cannot_carry_out() {
+    let peak = peak();
+    let clock = Arc::new(MockClock(Mutex::new(at("2026-10-16T17:00:00Z"))));
+    let mut curtailment = Curtailment::new(clock.clone());
+    let target = target("10.0.0.3", &[("site", "north")]);
+    let hashing = Live {
+        hashing: Some(true),
+        power_limit: None,
+    };
+    let audit = std::env::temp_dir().join(format!(
+        "miner-scraper-schedule-unsupported-{}.jsonl",
+        std::process::id()
+    ));
+    let miner = Some(("10.0.0.3".parse().expect("BUG: valid IP"), Firmware::Stock));
+
+    // Stock firmware cannot pause. Nothing is sent, counted or audited,
+    // however often the miner is reconciled.
+    for minutes in [0, 6, 12] {
+        clock.set(at("2026-10-16T17:00:00Z") + SignedDuration::from_mins(minutes));
+        let lines = curtailment
+            .reconcile(&[&peak], &target, miner, hashing, Some(&audit))
+            .await;
+        let value = |name: &str| {
+            let line = lines.iter().find(|l| l.starts_with(name));
+            line.and_then(|l| l.split(' ').nth(1)).map(str::to_owned)
+        };
+        assert_eq!(value("miner_schedule_applicable{").as_deref(), Some("0"));
+        assert_eq!(value("miner_schedule_actions_total{").as_deref(), Some("0"));
+        assert_eq!(
+            value("miner_schedule_action_failures_total{").as_deref(),
+            Some("0")
+        );
+    }
+    assert!(!audit.exists());
+
+    // Once the window closes the miner is in line and the schedule
+    // applicable again.
+    clock.set(at("2026-10-16T21:00:00Z"));
+    let lines = curtailment
+        .reconcile(&[&peak], &target, miner, hashing, Some(&audit))
+        .await;
+    assert!(lines
+        .iter()
+        .any(|l| l.starts_with("miner_schedule_applicable{") && l.contains(" 1 ")));
+}
+
+/// Wait until the fake miner has received `count` control commands, or
+/// fail after 15 seconds.
+async fn wait_for(dumps: &Dumps, count: usize) -> Vec<String> {
+    let deadline = std::time::Instant::now() + Duration::from_secs(15);
+    loop {
+        let received = dumps.received();
+        if received.len() >= count || std::time::Instant::now() > deadline {
+            return received;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Start a fake `LuxOS` miner and return its port and dumps.
+async fn luxos_miner() -> (u16, Arc<Dumps>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    let dumps = Dumps::load(
+        &std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("dumps"),
+        Firmware::LuxOS,
+        "s21pro",
+    )
+    .expect("BUG: dumps exist");
+    let dumps = Arc::new(dumps);
+    tokio::spawn(fake_miner::serve(
+        listener,
+        dumps.clone(),
+        Faults::default(),
+    ));
+    (port, dumps)
+}
+
+/// Config scraping the miner on `port`, labelled `site`, every second.
+fn config(port: u16, site: &str) -> Config {
+    let toml = format!("host = \"127.0.0.1\"\nport = {port}\nlabels = {{ site = \"{site}\" }}\n");
+    let target: TargetConfig = toml::from_str(&toml).expect("BUG: valid target");
+    Config {
+        scrape_interval_secs: 1,
+        target_configs: vec![target],
+        schedules: vec![peak()],
+        ..Config::default()
+    }
+}
+
+#[tokio::test]
+async fn curtail_a_fake_miner() {
+    let (port, dumps) = luxos_miner().await;
+    let config = config(port, "north");
+    let clock = Arc::new(MockClock(Mutex::new(at("2026-10-16T16:59:00Z"))));
+    let (_config_tx, config_rx) = watch::channel(config);
+    let (_discovered_tx, discovered_rx) = watch::channel(Discovered::new());
+    let store = MetricsStore::new();
+    let scraper = tokio::spawn(scrape::run(
+        config_rx,
+        discovered_rx,
+        store.clone(),
+        clock.clone(),
+    ));
+
+    // Before the window the miner is left alone.
+    let host = format!("127.0.0.1:{port}");
+    let deadline = std::time::Instant::now() + Duration::from_secs(15);
+    let metrics = loop {
+        let metrics = store.render().await;
+        if metrics.contains("miner_schedule_in_line") || std::time::Instant::now() > deadline {
+            break metrics;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    };
+    let prefix = format!("miner_schedule_active{{host=\"{host}\",schedule=\"peak\"");
+    let active = |metrics: &str| {
+        let line = metrics.lines().find(|l| l.starts_with(&prefix));
+        line.map(|l| l.split(' ').nth(1).unwrap_or_default().to_owned())
+    };
+    assert_eq!(active(&metrics).as_deref(), Some("0"), "{metrics}");
+    assert!(dumps.received().is_empty());
+
+    // The dumps keep hashing, so the pause is sent once, then again after
+    // `RETRY_AFTER`.
+    clock.set(at("2026-10-16T17:00:00Z"));
+    let sleep = format!("curtail|{SESSION_ID},sleep");
+    assert_eq!(wait_for(&dumps, 3).await[1], sleep);
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(dumps.received().len(), 3);
+    let metrics = store.render().await;
+    assert_eq!(active(&metrics).as_deref(), Some("1"), "{metrics}");
+    assert!(metrics.contains("miner_schedule_actions_total{host=\""));
+
+    clock.set(at("2026-10-16T17:06:00Z"));
+    assert_eq!(wait_for(&dumps, 6).await[4], sleep);
+
+    clock.set(at("2026-10-16T21:00:00Z"));
+    let received = wait_for(&dumps, 9).await;
+    scraper.abort();
+    assert_eq!(received[7], format!("curtail|{SESSION_ID},wakeup"));
+}
+
+#[tokio::test]
+async fn restore_a_miner_a_reload_takes_out_of_its_schedule() {
+    let (port, dumps) = luxos_miner().await;
+    let clock = Arc::new(MockClock(Mutex::new(at("2026-10-16T17:00:00Z"))));
+    let (config_tx, config_rx) = watch::channel(config(port, "north"));
+    let (_discovered_tx, discovered_rx) = watch::channel(Discovered::new());
+    let scraper = tokio::spawn(scrape::run(
+        config_rx,
+        discovered_rx,
+        MetricsStore::new(),
+        clock,
+    ));
+    assert_eq!(
+        wait_for(&dumps, 3).await[1],
+        format!("curtail|{SESSION_ID},sleep")
+    );
+
+    // The new label restarts the scrape loop, and the schedule no longer
+    // selects the miner.
+    config_tx
+        .send(config(port, "south"))
+        .expect("BUG: scraper is running");
+    let received = wait_for(&dumps, 6).await;
+    scraper.abort();
+    assert_eq!(
+        received.get(4),
+        Some(&format!("curtail|{SESSION_ID},wakeup"))
+    );
+}
@@ -0,0 +1,228 @@
+use super::*;
+use crate::cgminer::parse_samples;
+
+const STOCK_STATS: &str = include_str!("../../dumps/stock-cgminer-stats-s21xp.json");
+const STOCK_SUMMARY: &str = include_str!("../../dumps/stock-cgminer-summary-s21xp.json");
+const LUXOS_STATS: &str = include_str!("../../dumps/luxos-cgminer-stats-s21pro.json");
+const LUXOS_POWER: &str = include_str!("../../dumps/luxos-cgminer-power-s21pro.json");
+const VNISH_STATS: &str = include_str!("../../dumps/vnish-cgminer-stats-s21.json");
+const VNISH_SUMMARY: &str = include_str!("../../dumps/vnish-cgminer-summary-s21.json");
+const MARA_STATS: &str = include_str!("../../dumps/mara-cgminer-stats-s21imm.json");
+const MARA_SUMMARY: &str = include_str!("../../dumps/mara-cgminer-summary-s21imm.json");
+const BRAIINS_SUMMARY: &str = include_str!("../../dumps/braiins-cgminer-summary-s21plus.json");
+const BRAIINS_DEVS: &str = include_str!("../../dumps/braiins-cgminer-devs-s21plus.json");
+const BRAIINS_TEMPS: &str = include_str!("../../dumps/braiins-cgminer-temps-s21plus.json");
+const BRAIINS_FANS: &str = include_str!("../../dumps/braiins-cgminer-fans-s21plus.json");
+const BRAIINS_TUNERSTATUS: &str =
+    include_str!("../../dumps/braiins-cgminer-tunerstatus-s21plus.json");
+const WHATSMINER_SUMMARY: &str =
+    include_str!("../../dumps/whatsminer-cgminer-summary-m30splusve40.json");
+const WHATSMINER_DEVS: &str = include_str!("../../dumps/whatsminer-cgminer-devs-m30splusve40.json");
+const AVALON_ESTATS: &str = include_str!("../../dumps/avalon-cgminer-estats-avalonminer1466.json");
+const AVALON_SUMMARY: &str =
+    include_str!("../../dumps/avalon-cgminer-summary-avalonminer1466.json");
+
+fn samples(data: &str) -> (Vec<Sample>, f64) {
+    let mut value: Value = serde_json::from_str(data).expect("BUG: dump data is valid JSON");
+    let unit = rate_unit(&value);
+    (parse_samples(&mut value), unit)
+}
+
+/// Canonical lines of all dumps of one miner.
+fn canonical(fw: Firmware, dumps: &[&str]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for data in dumps {
+        let (samples, unit) = samples(data);
+        lines.extend(normalize("m", fw, &samples, unit));
+    }
+    lines
+}
+
+/// Values of all lines of a metric, without the timestamps.
+fn values(lines: &[String], name: &str) -> Vec<f64> {
+    lines
+        .iter()
+        .filter(|l| l.starts_with(name) && l.as_bytes().get(name.len()) == Some(&b'{'))
+        .map(|l| {
+            l.rsplit_once('}')
+                .and_then(|(_, rest)| rest.split_whitespace().next())
+                .and_then(|v| v.parse().ok())
+                .expect("BUG: line ends with a value")
+        })
+        .collect()
+}
+
+/// Check the metrics every Antminer S21 exposes, whatever the firmware.
+fn assert_common(lines: &[String], with_fans: bool) {
+    let hashrate = values(lines, "miner_hashrate_hashes_per_second");
+    assert_eq!(hashrate.len(), 1, "{lines:#?}");
+    assert!(
+        (1e14..5e14).contains(&hashrate[0]),
+        "hashrate {} H/s",
+        hashrate[0]
+    );
+
+    let boards = values(lines, "miner_board_hashrate_hashes_per_second");
+    assert!(boards.len() >= 3, "{lines:#?}");
+    let sum: f64 = boards.iter().sum();
+    assert!((sum / hashrate[0] - 1.0).abs() < 0.2, "boards sum to {sum}");
+
+    let chips = values(lines, "miner_chip_temperature_celsius");
+    assert!(!chips.is_empty(), "{lines:#?}");
+    assert!(chips.iter().all(|t| (0.0..130.0).contains(t)), "{chips:?}");
+    assert!(lines
+        .iter()
+        .filter(|l| l.starts_with("miner_chip_temperature_celsius{"))
+        .all(|l| l.contains("board=\"") && l.contains("sensor=\"")));
+
+    if with_fans {
+        let fans = values(lines, "miner_fan_speed_rpm");
+        assert!(!fans.is_empty(), "{lines:#?}");
+        assert!(lines
+            .iter()
+            .filter(|l| l.starts_with("miner_fan_speed_rpm{"))
+            .all(|l| l.contains("fan=\"")));
+    }
+
+    assert_eq!(values(lines, "miner_uptime_seconds").len(), 1);
+    assert!(lines.iter().all(|l| l.starts_with("miner_")));
+}
+
+#[test]
+fn stock() {
+    let lines = canonical(Firmware::Stock, &[STOCK_STATS, STOCK_SUMMARY]);
+    assert_common(&lines, true);
+    assert_eq!(values(&lines, "miner_shares_accepted_total").len(), 1);
+}
+
+#[test]
+fn luxos() {
+    let lines = canonical(Firmware::LuxOS, &[LUXOS_STATS, LUXOS_POWER]);
+    assert_common(&lines, true);
+    let power = values(&lines, "miner_power_watts");
+    assert_eq!(power.len(), 1);
+    assert!((1000.0..10000.0).contains(&power[0]), "{power:?}");
+}
+
+#[test]
+fn vnish() {
+    let lines = canonical(Firmware::Vnish, &[VNISH_STATS, VNISH_SUMMARY]);
+    assert_common(&lines, true);
+}
+
+#[test]
+fn mara() {
+    let lines = canonical(Firmware::Mara, &[MARA_STATS, MARA_SUMMARY]);
+    assert_common(&lines, true);
+}
+
+#[test]
+fn braiins() {
+    let lines = canonical(
+        Firmware::Braiins,
+        &[
+            BRAIINS_SUMMARY,
+            BRAIINS_DEVS,
+            BRAIINS_TEMPS,
+            BRAIINS_FANS,
+            BRAIINS_TUNERSTATUS,
+        ],
+    );
+    assert_common(&lines, true);
+    assert_eq!(values(&lines, "miner_shares_accepted_total").len(), 1);
+    assert_eq!(values(&lines, "miner_power_watts"), [4070.0]);
+    assert_eq!(values(&lines, "miner_power_limit_watts"), [3878.0]);
+}
+
+#[test]
+fn whatsminer() {
+    let lines = canonical(Firmware::Whatsminer, &[WHATSMINER_SUMMARY, WHATSMINER_DEVS]);
+    assert_common(&lines, true);
+    assert_eq!(values(&lines, "miner_fan_speed_rpm"), [4650.0, 4620.0]);
+    assert_eq!(values(&lines, "miner_power_watts"), [3345.0]);
+    assert_eq!(values(&lines, "miner_power_limit_watts"), [3600.0]);
+    assert_eq!(
+        values(&lines, "miner_hashrate_expected_hashes_per_second"),
+        [100_340e9]
+    );
+    assert_eq!(values(&lines, "miner_board_chips"), [215.0; 3]);
+    assert_eq!(values(&lines, "miner_shares_accepted_total"), [20137.0]);
+}
+
+#[test]
+fn avalon() {
+    let mut estats: Value =
+        serde_json::from_str(AVALON_ESTATS).expect("BUG: dump data is valid JSON");
+    crate::avalon::expand(&mut estats);
+    let lines = canonical(Firmware::Avalon, &[&estats.to_string(), AVALON_SUMMARY]);
+    assert_common(&lines, true);
+    assert_eq!(values(&lines, "miner_fan_speed_rpm").len(), 4);
+    assert_eq!(values(&lines, "miner_chip_temperature_celsius"), [77.0; 3]);
+    assert_eq!(values(&lines, "miner_power_watts"), [3295.0]);
+    assert_eq!(values(&lines, "miner_power_limit_watts"), [3300.0]);
+    assert_eq!(values(&lines, "miner_shares_accepted_total"), [1650.0]);
+}
+
+#[test]
+fn board_labels_match_across_firmwares() {
+    let stock = canonical(Firmware::Stock, &[STOCK_STATS]);
+    let braiins = canonical(Firmware::Braiins, &[BRAIINS_DEVS]);
+    let whatsminer = canonical(Firmware::Whatsminer, &[WHATSMINER_DEVS]);
+    for lines in [stock, braiins, whatsminer] {
+        let board = lines
+            .iter()
+            .find(|l| l.starts_with("miner_board_hashrate_hashes_per_second{"))
+            .expect("BUG: board hashrate is exported");
+        assert!(board.contains("board=\""), "{board}");
+    }
+}
+
+#[test]
+fn rate_unit_scales_hashrate() {
+    let stats = |unit: &str| {
+        serde_json::json!({
+            "STATUS": [{"STATUS": "S"}],
+            "STATS": [{"Type": "Antminer S21"}, {"GHS 5s": 200.0, "rate_unit": unit}],
+        })
+    };
+    for (unit, expected) in [("GH", 200e9), ("TH", 200e12), ("MH", 200e6), ("", 200e9)] {
+        let mut value = stats(unit);
+        let unit_scale = rate_unit(&value);
+        let lines = normalize("m", Firmware::Stock, &parse_samples(&mut value), unit_scale);
+        assert_eq!(
+            values(&lines, "miner_hashrate_hashes_per_second"),
+            vec![expected],
+            "rate_unit {unit:?}"
+        );
+    }
+}
+
+#[test]
+fn rate_unit_defaults_to_gh() {
+    assert!((rate_unit(&serde_json::json!({"SUMMARY": []})) - 1e9).abs() < f64::EPSILON);
+}
+
+#[test]
+fn render_schemas() {
+    let (samples, unit) = samples(STOCK_SUMMARY);
+    let raw = render("m", Firmware::Stock, &samples, unit, Schema::Raw);
+    let canonical = render("m", Firmware::Stock, &samples, unit, Schema::Canonical);
+    let both = render("m", Firmware::Stock, &samples, unit, Schema::Both);
+
+    assert!(raw.iter().any(|l| l.starts_with("summary_accepted_total{")));
+    assert!(!raw.iter().any(|l| l.starts_with("miner_")));
+    assert!(canonical.iter().all(|l| l.starts_with("miner_")));
+    assert_eq!(both.len(), raw.len() + canonical.len());
+}
+
+#[test]
+fn chip_health() {
+    let value: Value = serde_json::from_str(STOCK_STATS).expect("BUG: dump data is valid JSON");
+    let samples = crate::chips::parse_stats(&value);
+    let lines = normalize("m", Firmware::Stock, &samples, 1e9);
+    assert_eq!(values(&lines, "miner_board_chips_by_status").len(), 9);
+    assert_eq!(values(&lines, "miner_board_chips_missing"), [0.0; 3]);
+    assert!(lines
+        .iter()
+        .any(|l| l.contains("board=\"1\",status=\"failed\"")));
+}
@@ -0,0 +1,155 @@
+use super::*;
+
+fn address(s: &str) -> Address {
+    s.parse().expect("BUG: test address is valid")
+}
+
+fn value_of<'a>(lines: &'a [String], prefix: &str) -> Option<&'a str> {
+    lines
+        .iter()
+        .find(|l| l.starts_with(prefix))
+        .and_then(|l| l.split(' ').nth(1))
+}
+
+#[test]
+fn health_lines_on_success() {
+    let health = HostHealth {
+        failures_total: 2,
+        ..HostHealth::default()
+    };
+    let lines = health_lines(
+        "10.0.0.1",
+        true,
+        Duration::from_millis(1500),
+        &health,
+        &[("stats", true), ("summary", true)],
+    );
+    assert_eq!(value_of(&lines, "miner_up{host=\"10.0.0.1\"}"), Some("1"));
+    assert_eq!(
+        value_of(&lines, "miner_scrape_duration_seconds{host=\"10.0.0.1\"}"),
+        Some("1.5")
+    );
+    assert_eq!(
+        value_of(&lines, "miner_scrape_failures_total{host=\"10.0.0.1\"}"),
+        Some("2")
+    );
+    assert_eq!(
+        value_of(
+            &lines,
+            "miner_scrape_command_success{host=\"10.0.0.1\",command=\"summary\"}"
+        ),
+        Some("1")
+    );
+}
+
+#[test]
+fn health_lines_on_partial_failure() {
+    let health = HostHealth {
+        failures_total: 1,
+        consecutive_failures: 1,
+        ..HostHealth::default()
+    };
+    let lines = health_lines(
+        "10.0.0.1",
+        true,
+        Duration::from_secs(10),
+        &health,
+        &[("stats", true), ("temps", false), ("fans", false)],
+    );
+    assert_eq!(value_of(&lines, "miner_up{"), Some("1"));
+    let command = |cmd: &str| {
+        let prefix = format!("miner_scrape_command_success{{host=\"10.0.0.1\",command=\"{cmd}\"}}");
+        value_of(&lines, &prefix).map(str::to_owned)
+    };
+    assert_eq!(command("stats").as_deref(), Some("1"));
+    assert_eq!(command("temps").as_deref(), Some("0"));
+    assert_eq!(command("fans").as_deref(), Some("0"));
+}
+
+#[test]
+fn health_lines_without_firmware() {
+    let lines = health_lines(
+        "10.0.0.1",
+        false,
+        Duration::ZERO,
+        &HostHealth::default(),
+        &[],
+    );
+    assert_eq!(lines.len(), 3);
+}
+
+#[test]
+fn configured_targets_take_precedence() {
+    let toml = r#"
+targets = ["10.0.0.1", "10.0.0.3"]
+
+[[target]]
+host = "10.0.0.3"
+firmware = "braiins"
+labels = { rack = "7" }
+
+[[target]]
+host = "10.0.0.3"
+port = 4029
+"#;
+    let config: Config = toml::from_str(toml).expect("BUG: test toml is valid");
+    let discovered = Discovered::from([
+        ("10.0.0.1".to_owned(), "10.0.0.0/24".to_owned()),
+        ("10.0.0.2".to_owned(), "10.0.0.0/24".to_owned()),
+    ]);
+    let targets = targets(&config, &discovered);
+    assert_eq!(targets.len(), 4);
+    assert_eq!(
+        targets["10.0.0.1"],
+        Target::new(address("10.0.0.1"), CONFIG_SOURCE)
+    );
+    assert_eq!(targets["10.0.0.2"].source, "10.0.0.0/24");
+
+    let table = &targets["10.0.0.3"];
+    assert_eq!(table.firmware, Some(Firmware::Braiins));
+    assert_eq!(table.labels, [("rack".to_owned(), "7".to_owned())]);
+    assert_eq!(table.timeout, cgminer::COMMAND_TIMEOUT);
+
+    let other_port = &targets["10.0.0.3:4029"];
+    assert_eq!(other_port.address, address("10.0.0.3:4029"));
+}
+
+#[tokio::test]
+async fn target_on_other_port_is_labelled_by_name() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind fake miner");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = Vec::new();
+            let _ = stream.read_to_end(&mut buf).await;
+            let reply = br#"{"STATUS":[{"STATUS":"S"}],"SUMMARY":[{"Accepted":12}]}"#;
+            let _ = stream.write_all(reply).await;
+            let _ = stream.write_all(&[0]).await;
+        }
+    });
+
+    let target = Target {
+        firmware: Some(Firmware::Mara),
+        ..Target::new(address(&format!("localhost:{port}")), CONFIG_SOURCE)
+    };
+    let mut firmware = target.firmware;
+    let ip = Resolver::new(target.address.clone())
+        .resolve(Duration::from_mins(1))
+        .await
+        .expect("BUG: localhost resolves");
+    let results = scrape_host(&target, &backend::Cgminer, ip, Schema::Raw, &mut firmware).await;
+    let summary = results
+        .iter()
+        .find(|r| r.command == "summary")
+        .and_then(|r| r.lines.as_ref().ok())
+        .expect("BUG: summary succeeded");
+    let expected = format!("summary_accepted_total{{host=\"localhost:{port}\"}} 12 ");
+    assert!(
+        summary.iter().any(|l| l.starts_with(&expected)),
+        "{summary:?}"
+    );
+}
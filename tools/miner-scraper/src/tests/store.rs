@@ -3,18 +3,72 @@ use super::*;
 #[tokio::test]
 async fn update_and_render() {
     let store = MetricsStore::new();
-    store.update("10.0.0.1", vec!["up 1".to_owned()]).await;
-    store.update("10.0.0.2", vec!["temp 23.5".to_owned()]).await;
+    store
+        .merge("10.0.0.1", vec![("stats", vec!["up 1".to_owned()])])
+        .await;
+    store
+        .merge("10.0.0.2", vec![("stats", vec!["temp 23.5".to_owned()])])
+        .await;
 
     let output = store.render().await;
     assert!(output.contains("up 1\n"));
     assert!(output.contains("temp 23.5\n"));
 }
 
+#[tokio::test]
+async fn render_groups_families_with_metadata() {
+    let store = MetricsStore::new();
+    for host in ["10.0.0.1", "10.0.0.2"] {
+        let lines = vec![
+            format!("miner_up{{host=\"{host}\"}} 1"),
+            format!("summary_accepted_total{{host=\"{host}\"}} 7"),
+        ];
+        store.merge(host, vec![("summary", lines)]).await;
+    }
+
+    let output = store.render().await;
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 8);
+    assert_eq!(lines[1], "# TYPE miner_up gauge");
+    assert!(lines[2].starts_with("miner_up{"));
+    assert!(lines[3].starts_with("miner_up{"));
+    assert_eq!(lines[5], "# TYPE summary_accepted_total counter");
+    assert!(lines[6].starts_with("summary_accepted_total{"));
+    assert!(lines[7].starts_with("summary_accepted_total{"));
+}
+
+#[tokio::test]
+async fn merge_keeps_other_sections() {
+    let store = MetricsStore::new();
+    store
+        .merge(
+            "10.0.0.1",
+            vec![
+                ("stats", vec!["stats_a 1".to_owned()]),
+                ("fans", vec!["fans_a 1".to_owned()]),
+            ],
+        )
+        .await;
+    // The next scrape only got stats; the old fans section stays.
+    store
+        .merge("10.0.0.1", vec![("stats", vec!["stats_a 2".to_owned()])])
+        .await;
+
+    let output = store.render().await;
+    assert!(output.contains("stats_a 2\n"));
+    assert!(!output.contains("stats_a 1\n"));
+    assert!(output.contains("fans_a 1\n"));
+
+    store.remove_section("10.0.0.1", "fans").await;
+    assert!(!store.render().await.contains("fans_a"));
+}
+
 #[tokio::test]
 async fn remove_host() {
     let store = MetricsStore::new();
-    store.update("10.0.0.1", vec!["up 1".to_owned()]).await;
+    store
+        .merge("10.0.0.1", vec![("stats", vec!["up 1".to_owned()])])
+        .await;
     store.remove("10.0.0.1").await;
 
     let output = store.render().await;
@@ -24,8 +78,12 @@ async fn remove_host() {
 #[tokio::test]
 async fn hosts_list() {
     let store = MetricsStore::new();
-    store.update("10.0.0.1", vec!["up 1".to_owned()]).await;
-    store.update("10.0.0.2", vec!["up 1".to_owned()]).await;
+    store
+        .merge("10.0.0.1", vec![("stats", vec!["up 1".to_owned()])])
+        .await;
+    store
+        .merge("10.0.0.2", vec![("stats", vec!["up 1".to_owned()])])
+        .await;
 
     let mut hosts = store.hosts().await;
     hosts.sort();
@@ -0,0 +1,186 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use super::*;
+
+const SUMMARY_S21: &str = include_str!("../../dumps/vnish-web-summary-s21.json");
+const CHIPS_S21: &str = include_str!("../../dumps/vnish-web-chips-s21.json");
+const INFO_S21: &str = include_str!("../../dumps/vnish-web-info-s21.json");
+
+fn dump(data: &str) -> Value {
+    serde_json::from_str(data).expect("BUG: dump data is valid JSON")
+}
+
+/// Session of the stand-in: the number of unlocks, the last of which issued
+/// the only valid token.
+type Session = Arc<AtomicU32>;
+
+fn authorized(session: &Session, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let expected = format!("Bearer token-{}", session.load(Ordering::SeqCst));
+    match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+        Some(token) if token == expected => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+fn document(data: &'static str) -> axum::routing::MethodRouter<Session> {
+    get(
+        move |State(session): State<Session>, headers: HeaderMap| async move {
+            authorized(&session, &headers).map(|()| Json(dump(data)))
+        },
+    )
+}
+
+/// Serve the dumps like a Vnish S21 with the web password "admin".
+async fn stand_in() -> (u16, Session) {
+    let session = Session::default();
+    let router = Router::new()
+        .route(
+            UNLOCK,
+            post(
+                |State(session): State<Session>, Json(body): Json<Value>| async move {
+                    if body["pw"] != "admin" {
+                        return Err(StatusCode::UNAUTHORIZED);
+                    }
+                    let n = session.fetch_add(1, Ordering::SeqCst) + 1;
+                    Ok(Json(json!({ "token": format!("token-{n}") })))
+                },
+            ),
+        )
+        .route("/api/v1/summary", document(SUMMARY_S21))
+        .route("/api/v1/chips", document(CHIPS_S21))
+        .route(INFO, document(INFO_S21))
+        .with_state(session.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind stand-in server");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    (port, session)
+}
+
+fn endpoint(port: u16) -> Endpoint<'static> {
+    Endpoint {
+        name: "s21",
+        host: "127.0.0.1",
+        port,
+    }
+}
+
+async fn scrape(vnish: &Vnish, port: u16) -> Vec<CommandResult> {
+    vnish
+        .scrape(
+            endpoint(port),
+            Firmware::Vnish,
+            Duration::from_secs(5),
+            Schema::Both,
+        )
+        .await
+}
+
+fn lines(result: &CommandResult) -> &[String] {
+    result.lines.as_ref().expect("BUG: stand-in answers")
+}
+
+fn has_line(lines: &[String], prefix: &str) -> bool {
+    lines.iter().any(|l| l.starts_with(prefix))
+}
+
+#[test]
+fn ids_become_index_fields() {
+    let mut summary = dump(SUMMARY_S21)["miner"].take();
+    label_ids(&mut summary);
+    assert_eq!(summary["chains"][0]["chain_index"], 1);
+    assert_eq!(summary["cooling"]["fans"][3]["fan_index"], 3);
+    assert_eq!(summary["pools"][1]["pool_index"], 1);
+    assert!(summary["chains"][0].get("id").is_none());
+
+    let mut chips = dump(CHIPS_S21);
+    label_ids(&mut chips);
+    assert_eq!(chips["chains"][2]["chains_index"], Value::Null);
+    assert_eq!(chips["chains"][2]["chain_index"], 3);
+    assert_eq!(chips["chains"][2]["chips"][107]["chip_index"], 107);
+}
+
+#[test]
+fn info_of_s21() {
+    let info = parse_info(&dump(INFO_S21));
+    assert_eq!(info.model, "Antminer S21");
+    assert_eq!(info.firmware_version, "1.2.7");
+    assert_eq!(info.mac, "00:00:5e:00:53:00");
+}
+
+#[tokio::test]
+async fn scrape_summary_and_chips() {
+    let (port, session) = stand_in().await;
+    let vnish = Vnish::new("admin");
+    let results = scrape(&vnish, port).await;
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].command, "summary");
+    assert_eq!(results[1].command, "chips");
+
+    let summary = lines(&results[0]);
+    for expected in [
+        "miner_hashrate_hashes_per_second{host=\"s21\"} 201713630000000 ",
+        "miner_board_power_watts{host=\"s21\",board=\"2\"} 1187 ",
+        "miner_fan_speed_rpm{host=\"s21\",fan=\"3\"} 3660 ",
+        "miner_power_watts{host=\"s21\"} 3561 ",
+        "summary_pools_accepted_total{host=\"s21\",pool=\"0\"} 20137 ",
+        "summary_pools_status{host=\"s21\",pool=\"1\",status=\"offline\"} 1 ",
+    ] {
+        assert!(has_line(summary, expected), "{expected} in {summary:#?}");
+    }
+    let chips = lines(&results[1]);
+    for expected in [
+        "miner_chip_temperature_celsius{host=\"s21\",board=\"1\",sensor=\"0\"} 68 ",
+        "chips_chains_chips_temp{host=\"s21\",chain=\"1\",chip=\"3\"} 74 ",
+    ] {
+        assert!(has_line(chips, expected), "{expected} in {chips:#?}");
+    }
+    assert_eq!(session.load(Ordering::SeqCst), 1, "one session for both");
+
+    let info = vnish.info(endpoint(port), Firmware::Vnish).await;
+    assert_eq!(info.model, "Antminer S21");
+    assert_eq!(session.load(Ordering::SeqCst), 1, "session is kept");
+}
+
+#[tokio::test]
+async fn expired_token_is_renewed_once() {
+    let (port, session) = stand_in().await;
+    let vnish = Vnish::new("admin");
+    assert!(scrape(&vnish, port).await.iter().all(|r| r.lines.is_ok()));
+
+    // Another client unlocking, or a reboot, expires our token.
+    session.fetch_add(1, Ordering::SeqCst);
+    let results = scrape(&vnish, port).await;
+    for result in &results {
+        assert!(result.lines.is_ok(), "{}", result.command);
+    }
+    assert_eq!(
+        session.load(Ordering::SeqCst),
+        3,
+        "both sections share the renewed token"
+    );
+}
+
+#[tokio::test]
+async fn wrong_password_fails() {
+    let (port, session) = stand_in().await;
+    let vnish = Vnish::new("hunter2");
+    let results = scrape(&vnish, port).await;
+    assert_eq!(results.len(), 2);
+    for result in &results {
+        let err = result.lines.as_ref().expect_err("BUG: unlock is refused");
+        assert_eq!(web::status(err), Some(401), "{err:#}");
+    }
+    assert_eq!(session.load(Ordering::SeqCst), 0);
+    assert_eq!(
+        vnish.info(endpoint(port), Firmware::Vnish).await,
+        MinerInfo::default()
+    );
+}
@@ -0,0 +1,103 @@
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get as get_route, post as post_route};
+use axum::{Json, Router};
+use serde_json::json;
+
+use super::*;
+
+#[test]
+fn parse_content_length() {
+    let response =
+        b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\n{\"a\":1}";
+    let (status, body) = parse_response(response).expect("BUG: valid response");
+    assert_eq!(status, 200);
+    assert_eq!(body, b"{\"a\":1}");
+    assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n{\"a\":1}").is_err());
+}
+
+#[test]
+fn parse_chunked() {
+    let response =
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{\"a\"\r\n3;x=y\r\n:1}\r\n0\r\n\r\n";
+    let (status, body) = parse_response(response).expect("BUG: valid response");
+    assert_eq!(status, 200);
+    assert_eq!(body, b"{\"a\":1}");
+    let truncated = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n9\r\n{\"a\"";
+    assert!(parse_response(truncated).is_err());
+    let oversized =
+        b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffff\r\n{\"a\"";
+    assert!(parse_response(oversized).is_err());
+}
+
+#[test]
+fn parse_without_length() {
+    let (status, body) =
+        parse_response(b"HTTP/1.0 404 Not Found\r\n\r\nnope").expect("BUG: valid response");
+    assert_eq!(status, 404);
+    assert_eq!(body, b"nope");
+    assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+}
+
+/// Serve a document, echo bodies posted with the right token, and answer
+/// a restart in plain text.
+async fn stand_in() -> u16 {
+    let router = Router::new()
+        .route(
+            "/doc",
+            get_route(|| async { Json(json!({"hashRate": 1150.5})) }),
+        )
+        .route("/huge", get_route(|| async { "x".repeat(MAX_RESPONSE) }))
+        .route(
+            "/restart",
+            post_route(|| async { "System will restart shortly.\n" }),
+        )
+        .route(
+            "/echo",
+            post_route(|headers: HeaderMap, Json(body): Json<Value>| async move {
+                match headers.get("authorization").and_then(|v| v.to_str().ok()) {
+                    Some("Bearer t0k3n") => Ok(Json(body)),
+                    _ => Err(StatusCode::UNAUTHORIZED),
+                }
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: bind stand-in server");
+    let port = listener.local_addr().expect("BUG: local addr").port();
+    tokio::spawn(async move { axum::serve(listener, router).await });
+    port
+}
+
+#[tokio::test]
+async fn get_and_post_json() {
+    let port = stand_in().await;
+    let doc = get("127.0.0.1", port, "/doc", None)
+        .await
+        .expect("BUG: stand-in serves /doc");
+    assert_eq!(doc, json!({"hashRate": 1150.5}));
+
+    let body = json!({"pw": "admin"});
+    let echo = post("127.0.0.1", port, "/echo", Some("t0k3n"), &body)
+        .await
+        .expect("BUG: stand-in accepts the token");
+    assert_eq!(echo, body);
+
+    let err = post("127.0.0.1", port, "/echo", Some("expired"), &body)
+        .await
+        .expect_err("BUG: stand-in rejects the token");
+    assert_eq!(status(&err), Some(401));
+    let text = post("127.0.0.1", port, "/restart", None, &json!({}))
+        .await
+        .expect("BUG: stand-in restarts");
+    assert_eq!(text, json!("System will restart shortly."));
+    assert!(get("127.0.0.1", port, "/restart", None).await.is_err());
+    let err = get("127.0.0.1", port, "/huge", None)
+        .await
+        .expect_err("BUG: response is too large");
+    assert!(err.to_string().contains("exceeds"), "{err:#}");
+
+    let err = get("127.0.0.1", port, "/missing", None)
+        .await
+        .expect_err("BUG: no such route");
+    assert_eq!(status(&err), Some(404));
+}
@@ -0,0 +1,167 @@
+//! The Vnish web API.
+//!
+//! Vnish answers on the cgminer port with the stock `stats` layout, but its
+//! web API reports more: the temperature and frequency of every chip, the
+//! power of every chain and the state of every fan. It needs a session:
+//! `POST /api/v1/unlock` with the web password returns a bearer token. The
+//! token is kept for later scrapes and renewed once when a request is
+//! refused with 401.
+//!
+//! Chains, chips, fans and pools are arrays of objects with an `id`, which
+//! becomes the `chain`, `chip`, `fan` or `pool` label, e.g.
+//! `chips_chains_chips_temp{chain="1",chip="7"}`.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures_util::future::{self, BoxFuture};
+use futures_util::FutureExt;
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use crate::backend::{self, Backend};
+use crate::cgminer::{CommandResult, Endpoint, Firmware};
+use crate::info::MinerInfo;
+use crate::schema::Schema;
+use crate::web;
+
+#[cfg(test)]
+#[path = "tests/vnish.rs"]
+mod tests;
+
+const UNLOCK: &str = "/api/v1/unlock";
+const INFO: &str = "/api/v1/info";
+
+/// Documents scraped on every scrape, with the section they are named after.
+const SECTIONS: [(&str, &str); 2] = [("summary", "/api/v1/summary"), ("chips", "/api/v1/chips")];
+
+/// The Vnish web API of one miner.
+pub struct Vnish {
+    password: String,
+    token: Mutex<Option<String>>,
+}
+
+impl Vnish {
+    pub fn new(password: &str) -> Self {
+        Self {
+            password: password.to_owned(),
+            token: Mutex::new(None),
+        }
+    }
+
+    /// The session token, unlocking a new session if there is none or the
+    /// current one is `expired`.
+    async fn token(&self, endpoint: Endpoint<'_>, expired: Option<&str>) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some(current) = token.as_deref() {
+            if Some(current) != expired {
+                return Ok(current.to_owned());
+            }
+        }
+        let body = json!({ "pw": self.password });
+        let reply = web::post(endpoint.host, endpoint.port, UNLOCK, None, &body)
+            .await
+            .context("unlock")?;
+        let unlocked = reply
+            .get("token")
+            .and_then(Value::as_str)
+            .context("unlock reply without token")?
+            .to_owned();
+        *token = Some(unlocked.clone());
+        Ok(unlocked)
+    }
+
+    /// `GET` a document within the session.
+    async fn get(&self, endpoint: Endpoint<'_>, path: &str) -> Result<Value> {
+        let token = self.token(endpoint, None).await?;
+        match web::get(endpoint.host, endpoint.port, path, Some(&token)).await {
+            Err(err) if web::status(&err) == Some(401) => {
+                let token = self.token(endpoint, Some(&token)).await?;
+                web::get(endpoint.host, endpoint.port, path, Some(&token)).await
+            }
+            reply => reply,
+        }
+    }
+}
+
+impl Backend for Vnish {
+    fn detect<'a>(&'a self, _endpoint: Endpoint<'a>) -> BoxFuture<'a, Firmware> {
+        future::ready(Firmware::Vnish).boxed()
+    }
+
+    fn scrape<'a>(
+        &'a self,
+        endpoint: Endpoint<'a>,
+        fw: Firmware,
+        timeout: Duration,
+        schema: Schema,
+    ) -> BoxFuture<'a, Vec<CommandResult>> {
+        let sections = SECTIONS.iter().map(move |&(section, path)| {
+            let document = async move {
+                let mut document = self.get(endpoint, path).await?;
+                // The summary has everything under one `miner` object.
+                if let Some(miner) = document.get_mut("miner") {
+                    document = miner.take();
+                }
+                label_ids(&mut document);
+                Ok(document)
+            };
+            backend::scrape_json(endpoint, fw, section, document, timeout, schema)
+        });
+        future::join_all(sections).boxed()
+    }
+
+    fn info<'a>(&'a self, endpoint: Endpoint<'a>, _fw: Firmware) -> BoxFuture<'a, MinerInfo> {
+        async move {
+            match self.get(endpoint, INFO).await {
+                Ok(info) => parse_info(&info),
+                Err(err) => {
+                    log::debug!("{INFO} on {} failed: {err:#}", endpoint.name);
+                    MinerInfo::default()
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Rename the `id` of objects in arrays like `chains` to `chain_index`, so
+/// the generic parser labels them `chain` rather than by position.
+fn label_ids(value: &mut Value) {
+    match value {
+        Value::Object(obj) => {
+            for (key, child) in obj.iter_mut() {
+                if let Value::Array(items) = child {
+                    let name = key.strip_suffix('s').unwrap_or(key);
+                    for item in items.iter_mut().filter_map(Value::as_object_mut) {
+                        if let Some(id) = item.remove("id") {
+                            item.insert(format!("{name}_index"), id);
+                        }
+                    }
+                }
+                label_ids(child);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(label_ids),
+        _ => {}
+    }
+}
+
+/// Identity fields of an `/api/v1/info` reply.
+fn parse_info(info: &Value) -> MinerInfo {
+    let field = |pointer: &str| {
+        info.pointer(pointer)
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .trim()
+            .to_owned()
+    };
+    let version = field("/fw_version");
+    MinerInfo {
+        model: field("/miner"),
+        api_version: String::new(),
+        miner_version: version.clone(),
+        firmware_version: version,
+        mac: field("/system/network_status/mac").to_lowercase(),
+    }
+}
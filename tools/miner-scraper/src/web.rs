@@ -0,0 +1,200 @@
+//! Minimal HTTP/1.1 client for the JSON web APIs of miners.
+//!
+//! Miners serve their web API as plain HTTP on the local network, so like
+//! the cgminer client a request is one exchange over its own TCP stream:
+//! the request is sent with `Connection: close` and the reply is read to the
+//! end. Replies may be sent with a `Content-Length` or chunked.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[cfg(test)]
+#[path = "tests/web.rs"]
+mod tests;
+
+/// Default port of miner web APIs.
+pub const DEFAULT_PORT: u16 = 80;
+
+/// Timeout for establishing a TCP connection to a miner.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout for reading a complete response from a miner.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest response read, headers included, far above any miner document.
+const MAX_RESPONSE: usize = 4 * 1024 * 1024;
+
+/// A reply with a status other than 2xx.
+///
+/// Returned inside the `anyhow::Error` of `get` and `post`, so callers can
+/// tell an expired session (401) from other failures.
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: u16,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP status {}", self.status)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Status of a failed request, if the miner answered at all.
+pub fn status(err: &anyhow::Error) -> Option<u16> {
+    err.downcast_ref::<StatusError>().map(|e| e.status)
+}
+
+/// `GET` a JSON document, with a bearer token if given.
+pub async fn get(host: &str, port: u16, path: &str, token: Option<&str>) -> Result<Value> {
+    let body = request(host, port, "GET", path, token, None).await?;
+    serde_json::from_slice(&body).with_context(|| format!("invalid JSON from GET {path}"))
+}
+
+/// `POST` a JSON body and return the reply, with a bearer token if given.
+///
+/// Actions such as a restart may answer in plain text, which is returned
+/// as a JSON string.
+pub async fn post(
+    host: &str,
+    port: u16,
+    path: &str,
+    token: Option<&str>,
+    body: &Value,
+) -> Result<Value> {
+    let body = request(host, port, "POST", path, token, Some(body)).await?;
+    match serde_json::from_slice(&body) {
+        Ok(reply) => Ok(reply),
+        Err(err) => match String::from_utf8(body) {
+            Ok(text) => Ok(Value::String(text.trim().to_owned())),
+            Err(_) => Err(err).with_context(|| format!("invalid JSON from POST {path}")),
+        },
+    }
+}
+
+async fn request(
+    host: &str,
+    port: u16,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: Option<&Value>,
+) -> Result<Vec<u8>> {
+    let authority = if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    };
+
+    let mut request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {authority}\r\nAccept: application/json\r\nConnection: close\r\n"
+    );
+    if let Some(token) = token {
+        let _ = write!(request, "Authorization: Bearer {token}\r\n");
+    }
+    let body = body.map(Value::to_string).unwrap_or_default();
+    if !body.is_empty() {
+        let _ = write!(
+            request,
+            "Content-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+    }
+    request.push_str("\r\n");
+    request.push_str(&body);
+
+    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| anyhow::anyhow!("connect timeout: {authority}"))??;
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut buf = Vec::with_capacity(4096);
+    let mut limited = (&mut stream).take(MAX_RESPONSE as u64 + 1);
+    tokio::time::timeout(READ_TIMEOUT, limited.read_to_end(&mut buf))
+        .await
+        .map_err(|_| anyhow::anyhow!("read timeout: {authority}"))??;
+    if buf.len() > MAX_RESPONSE {
+        bail!("response from {authority} exceeds {MAX_RESPONSE} bytes");
+    }
+
+    let (status, body) = parse_response(&buf)?;
+    if !(200..300).contains(&status) {
+        return Err(StatusError { status }.into());
+    }
+    Ok(body)
+}
+
+/// Split a complete HTTP/1.1 response into its status and decoded body.
+fn parse_response(buf: &[u8]) -> Result<(u16, Vec<u8>)> {
+    let header_end = buf
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .context("incomplete HTTP response")?;
+    let head = std::str::from_utf8(&buf[..header_end]).context("invalid HTTP header")?;
+    let body = &buf[header_end + 4..];
+
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .context("invalid HTTP status line")?;
+
+    let mut chunked = false;
+    let mut length = None;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            length = value.parse::<usize>().ok();
+        }
+    }
+
+    let body = if chunked {
+        dechunk(body)?
+    } else if let Some(length) = length {
+        if body.len() < length {
+            bail!("truncated HTTP body");
+        }
+        body[..length].to_vec()
+    } else {
+        body.to_vec()
+    };
+    Ok((status, body))
+}
+
+/// Decode a chunked body, ignoring chunk extensions and trailers.
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(data.len());
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .context("truncated chunk size")?;
+        let size = std::str::from_utf8(&data[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .context("invalid chunk size")?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        let end = size.checked_add(2).context("invalid chunk size")?;
+        if data.len() < end {
+            bail!("truncated chunk");
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[end..];
+    }
+}
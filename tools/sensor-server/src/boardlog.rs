@@ -0,0 +1,208 @@
+//! Classification of non-metric lines printed by sensor firmware.
+//!
+//! Besides metrics, the USB serial console carries ROM boot messages, panic
+//! reports from esp-backtrace and occasional log output. These lines are
+//! classified, forwarded to the server log with the device identity attached,
+//! and kept in a short per-port history.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::discovery::Device;
+
+/// Log target for forwarded board lines, so they can be filtered via `RUST_LOG`.
+const LOG_TARGET: &str = "board";
+
+/// Prefixes of lines printed by the ESP32-C3 ROM bootloader on reset.
+const BOOT_PREFIXES: &[&str] = &[
+    "ESP-ROM:",
+    "Build:",
+    "rst:",
+    "boot:",
+    "Saved PC:",
+    "SPIWP:",
+    "mode:",
+    "load:",
+    "entry 0x",
+];
+
+/// Category of a non-metric line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Boot,
+    Panic,
+    Error,
+    Warning,
+    Info,
+}
+
+impl LineKind {
+    /// Classify a non-metric line by its content.
+    pub fn classify(line: &str) -> Self {
+        if line.contains("panicked at")
+            || line.contains("=== PANIC")
+            || line.starts_with("Backtrace")
+            || line.starts_with("0x")
+        {
+            return LineKind::Panic;
+        }
+        if BOOT_PREFIXES.iter().any(|p| line.starts_with(p)) {
+            return LineKind::Boot;
+        }
+
+        // Log lines start with a level, optionally in brackets and after a timestamp.
+        let level = line
+            .split_whitespace()
+            .take(2)
+            .map(|t| t.trim_matches(|c| c == '[' || c == ']'))
+            .find(|t| !t.is_empty() && !t.starts_with(|c: char| c.is_ascii_digit()));
+        match level {
+            Some(l) if l.eq_ignore_ascii_case("error") => LineKind::Error,
+            Some(l) if l.eq_ignore_ascii_case("warn") || l.eq_ignore_ascii_case("warning") => {
+                LineKind::Warning
+            }
+            _ => LineKind::Info,
+        }
+    }
+
+    fn log_level(self) -> log::Level {
+        match self {
+            LineKind::Panic | LineKind::Error => log::Level::Error,
+            LineKind::Warning => log::Level::Warn,
+            LineKind::Boot | LineKind::Info => log::Level::Info,
+        }
+    }
+}
+
+impl std::fmt::Display for LineKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LineKind::Boot => write!(f, "boot"),
+            LineKind::Panic => write!(f, "panic"),
+            LineKind::Error => write!(f, "error"),
+            LineKind::Warning => write!(f, "warning"),
+            LineKind::Info => write!(f, "info"),
+        }
+    }
+}
+
+/// Discrete board events counted as metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardEvent {
+    Boot,
+    Panic,
+}
+
+/// A timestamped non-metric line from a board.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub timestamp_ms: u128,
+    pub kind: LineKind,
+    pub text: String,
+}
+
+impl LogLine {
+    /// Classify a line and stamp it with the current wall-clock time.
+    pub fn new(text: &str) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        Self {
+            timestamp_ms,
+            kind: LineKind::classify(text),
+            text: text.to_owned(),
+        }
+    }
+
+    /// Return the event this line marks, if any.
+    ///
+    /// A reset prints several ROM lines and a panic prints a banner plus a
+    /// backtrace, so only the first line of each is counted.
+    pub fn event(&self) -> Option<BoardEvent> {
+        match self.kind {
+            LineKind::Boot if self.text.starts_with("ESP-ROM:") => Some(BoardEvent::Boot),
+            LineKind::Panic if self.text.contains("panicked at") => Some(BoardEvent::Panic),
+            _ => None,
+        }
+    }
+
+    /// Forward the line to the server log with the device identity attached.
+    pub fn forward(&self, device: &Device) {
+        log::log!(
+            target: LOG_TARGET,
+            self.kind.log_level(),
+            "{}: [{}] {}",
+            device,
+            self.kind,
+            self.text
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_boot_lines() {
+        for line in [
+            "ESP-ROM:esp32c3-api1-20210207",
+            "Build:Feb  7 2021",
+            "rst:0x15 (USB_UART_CHIP_RESET),boot:0xd (SPI_FAST_FLASH_BOOT)",
+            "Saved PC:0x40380b8e",
+            "entry 0x403c98d0",
+        ] {
+            assert_eq!(LineKind::classify(line), LineKind::Boot, "{line}");
+        }
+    }
+
+    #[test]
+    fn classify_panic_lines() {
+        for line in [
+            "====================== PANIC ======================",
+            "panicked at src/main.rs:42:5:",
+            "Backtrace:",
+            "0x42001234",
+        ] {
+            assert_eq!(LineKind::classify(line), LineKind::Panic, "{line}");
+        }
+    }
+
+    #[test]
+    fn classify_log_levels() {
+        assert_eq!(
+            LineKind::classify("WARN - sensor 28FF read error"),
+            LineKind::Warning
+        );
+        assert_eq!(
+            LineKind::classify("[ERROR] sensor discovery failed"),
+            LineKind::Error
+        );
+        assert_eq!(
+            LineKind::classify("0.512 WARN conversion trigger error"),
+            LineKind::Warning
+        );
+        assert_eq!(
+            LineKind::classify("temp-sensor firmware started"),
+            LineKind::Info
+        );
+    }
+
+    #[test]
+    fn events_count_first_line_only() {
+        assert_eq!(
+            LogLine::new("ESP-ROM:esp32c3-api1-20210207").event(),
+            Some(BoardEvent::Boot)
+        );
+        assert_eq!(LogLine::new("rst:0x15 (USB_UART_CHIP_RESET)").event(), None);
+        assert_eq!(
+            LogLine::new("panicked at src/main.rs:42:5:").event(),
+            Some(BoardEvent::Panic)
+        );
+        assert_eq!(
+            LogLine::new("====================== PANIC ======================").event(),
+            None
+        );
+        assert_eq!(LogLine::new("WARN - read error").event(), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Minimal plain HTTP/1.1 client for outbound requests.
+//!
+//! Built on hyper-util's pooled client without TLS, so the static musl
+//! build needs no system libraries. Targets are expected on the local network.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::{Method, Request};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+
+/// Timeout for a complete request, including connect and body transfer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct HttpClient {
+    inner: Client<HttpConnector, Full<Bytes>>,
+}
+
+impl HttpClient {
+    pub fn new() -> Self {
+        Self {
+            inner: Client::builder(TokioExecutor::new()).build_http(),
+        }
+    }
+
+    /// Send a GET request and fail on any non-2xx status.
+    pub async fn get(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<Bytes> {
+        self.request(Method::GET, url, headers, Bytes::new()).await
+    }
+
+    /// Send a POST request and fail on any non-2xx status.
+    pub async fn post(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Vec<u8>,
+    ) -> anyhow::Result<Bytes> {
+        self.request(Method::POST, url, headers, Bytes::from(body))
+            .await
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Bytes,
+    ) -> anyhow::Result<Bytes> {
+        let mut builder = Request::builder().method(method).uri(url);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let request = builder
+            .body(Full::new(body))
+            .with_context(|| format!("invalid request to {url}"))?;
+
+        let (status, body) = tokio::time::timeout(REQUEST_TIMEOUT, async {
+            let response = self.inner.request(request).await?;
+            let status = response.status();
+            let body = response.into_body().collect().await?.to_bytes();
+            anyhow::Ok((status, body))
+        })
+        .await
+        .map_err(|_| anyhow::anyhow!("request timeout: {url}"))??;
+
+        if !status.is_success() {
+            let reason = status.canonical_reason().unwrap_or("");
+            let detail = String::from_utf8_lossy(&body);
+            anyhow::bail!("{url}: {} {reason}: {}", status.as_u16(), detail.trim());
+        }
+        Ok(body)
+    }
+}
@@ -0,0 +1,444 @@
+//! Optional TOML configuration for output sinks.
+//!
+//! The server runs without a config file. The file only enables extra
+//! outputs beyond the Prometheus endpoint.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub influx: Option<InfluxConfig>,
+    pub graphite: Option<GraphiteConfig>,
+    pub otlp: Option<OtlpConfig>,
+    pub federation: Option<FederationConfig>,
+    #[serde(default)]
+    pub derived: Vec<DerivedConfig>,
+}
+
+impl Config {
+    /// Load the config file, or return defaults if it does not exist.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::info!("no config file at {}, using defaults", path.display());
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(toml::from_str(&contents)?)
+    }
+}
+
+/// Buffering, retry and naming options shared by all sinks.
+#[derive(Debug, Clone)]
+pub struct SinkOptions {
+    /// Maximum samples held while the destination is unreachable. Oldest are dropped first.
+    pub buffer_size: usize,
+
+    /// Seconds between flushes of buffered samples.
+    pub flush_interval_secs: u64,
+
+    /// Upper bound for the exponential retry backoff, in seconds.
+    pub max_backoff_secs: u64,
+
+    /// Prefix for every metric name written by this sink.
+    pub prefix: String,
+
+    /// Exact Prometheus metric names mapped to sink-specific names.
+    pub rename: HashMap<String, String>,
+}
+
+const DEFAULT_BUFFER_SIZE: usize = 10_000;
+const DEFAULT_FLUSH_INTERVAL_SECS: u64 = 10;
+const DEFAULT_MAX_BACKOFF_SECS: u64 = 300;
+
+fn default_buffer_size() -> usize {
+    DEFAULT_BUFFER_SIZE
+}
+
+fn default_flush_interval() -> u64 {
+    DEFAULT_FLUSH_INTERVAL_SECS
+}
+
+fn default_max_backoff() -> u64 {
+    DEFAULT_MAX_BACKOFF_SECS
+}
+
+impl Default for SinkOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: default_buffer_size(),
+            flush_interval_secs: default_flush_interval(),
+            max_backoff_secs: default_max_backoff(),
+            prefix: String::new(),
+            rename: HashMap::new(),
+        }
+    }
+}
+
+impl SinkOptions {
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_secs(self.flush_interval_secs.max(1))
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_secs(self.max_backoff_secs.max(1))
+    }
+
+    /// Map a Prometheus metric name through the rename table.
+    pub fn metric_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.rename.get(name).map_or(name, String::as_str)
+    }
+}
+
+/// InfluxDB line protocol output, over the v2 HTTP API or UDP.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "InfluxFields")]
+pub struct InfluxConfig {
+    pub transport: InfluxTransport,
+    pub options: SinkOptions,
+}
+
+#[derive(Debug, Clone)]
+pub enum InfluxTransport {
+    /// HTTP `POST /api/v2/write` on an InfluxDB 2.x server.
+    Http {
+        /// Base URL of the server, e.g. `http://influx:8086`.
+        url: String,
+        org: String,
+        bucket: String,
+        /// API token. Falls back to the `INFLUX_TOKEN` environment variable.
+        token: Option<String>,
+    },
+    /// UDP listener of an InfluxDB 1.x server or Telegraf.
+    Udp { udp: SocketAddr },
+}
+
+/// The `[influx]` table as written. Fields are spelled out rather than
+/// flattened so that unknown keys are rejected.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InfluxFields {
+    url: Option<String>,
+    org: Option<String>,
+    bucket: Option<String>,
+    token: Option<String>,
+    udp: Option<SocketAddr>,
+
+    #[serde(default = "default_buffer_size")]
+    buffer_size: usize,
+    #[serde(default = "default_flush_interval")]
+    flush_interval_secs: u64,
+    #[serde(default = "default_max_backoff")]
+    max_backoff_secs: u64,
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    rename: HashMap<String, String>,
+}
+
+impl TryFrom<InfluxFields> for InfluxConfig {
+    type Error = String;
+
+    fn try_from(fields: InfluxFields) -> Result<Self, String> {
+        let transport = match (fields.url, fields.org, fields.bucket, fields.udp) {
+            (Some(url), Some(org), Some(bucket), None) => InfluxTransport::Http {
+                url,
+                org,
+                bucket,
+                token: fields.token,
+            },
+            (None, None, None, Some(udp)) if fields.token.is_none() => InfluxTransport::Udp { udp },
+            _ => return Err("set either `url`, `org` and `bucket`, or `udp`".to_owned()),
+        };
+        Ok(Self {
+            transport,
+            options: SinkOptions {
+                buffer_size: fields.buffer_size,
+                flush_interval_secs: fields.flush_interval_secs,
+                max_backoff_secs: fields.max_backoff_secs,
+                prefix: fields.prefix,
+                rename: fields.rename,
+            },
+        })
+    }
+}
+
+/// Graphite plaintext protocol output over TCP.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "GraphiteFields")]
+pub struct GraphiteConfig {
+    /// Carbon plaintext listener, e.g. `graphite:2003`.
+    pub address: String,
+    pub options: SinkOptions,
+}
+
+/// The `[graphite]` table as written, see [`InfluxFields`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GraphiteFields {
+    address: String,
+
+    #[serde(default = "default_buffer_size")]
+    buffer_size: usize,
+    #[serde(default = "default_flush_interval")]
+    flush_interval_secs: u64,
+    #[serde(default = "default_max_backoff")]
+    max_backoff_secs: u64,
+    #[serde(default)]
+    prefix: String,
+    #[serde(default)]
+    rename: HashMap<String, String>,
+}
+
+impl From<GraphiteFields> for GraphiteConfig {
+    fn from(fields: GraphiteFields) -> Self {
+        Self {
+            address: fields.address,
+            options: SinkOptions {
+                buffer_size: fields.buffer_size,
+                flush_interval_secs: fields.flush_interval_secs,
+                max_backoff_secs: fields.max_backoff_secs,
+                prefix: fields.prefix,
+                rename: fields.rename,
+            },
+        }
+    }
+}
+
+/// OpenTelemetry OTLP/HTTP metrics export.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// Collector base URL, e.g. `http://collector:4318`. Metrics are posted to `/v1/metrics`.
+    pub endpoint: String,
+
+    /// Seconds between exports of the current values.
+    #[serde(default = "default_otlp_interval")]
+    pub interval_secs: u64,
+
+    /// Extra HTTP headers, e.g. for collector authentication.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+const DEFAULT_OTLP_INTERVAL_SECS: u64 = 30;
+
+fn default_otlp_interval() -> u64 {
+    DEFAULT_OTLP_INTERVAL_SECS
+}
+
+/// Other sensor-server instances whose series are merged into this one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FederationConfig {
+    /// Seconds between scrapes of each peer.
+    #[serde(default = "default_federation_interval")]
+    pub interval_secs: u64,
+
+    pub peers: Vec<PeerConfig>,
+}
+
+const DEFAULT_FEDERATION_INTERVAL_SECS: u64 = 15;
+
+fn default_federation_interval() -> u64 {
+    DEFAULT_FEDERATION_INTERVAL_SECS
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PeerConfig {
+    /// Base URL of the peer, e.g. `http://pi-boiler:8888`. `/metrics` is appended.
+    pub url: String,
+
+    /// Value of the `instance` label on the peer's series. Defaults to the
+    /// host and port of the URL.
+    pub instance: Option<String>,
+}
+
+impl PeerConfig {
+    pub fn instance(&self) -> String {
+        if let Some(instance) = &self.instance {
+            return instance.clone();
+        }
+        let rest = self.url.split_once("://").map_or(&*self.url, |(_, r)| r);
+        rest.split('/').next().unwrap_or(rest).to_owned()
+    }
+
+    pub fn metrics_url(&self) -> String {
+        format!("{}/metrics", self.url.trim_end_matches('/'))
+    }
+}
+
+/// A series computed from an expression over the stored series.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DerivedConfig {
+    /// Metric name of the computed series.
+    pub name: String,
+
+    /// Expression, e.g. `temperature_celsius{sensor="outlet"} - temperature_celsius{sensor="inlet"}`.
+    pub expr: String,
+
+    /// Labels attached to the computed series.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.influx.is_none());
+        assert!(config.graphite.is_none());
+        assert!(config.otlp.is_none());
+        assert!(config.federation.is_none());
+        assert!(config.derived.is_empty());
+    }
+
+    #[test]
+    fn derived_config() {
+        let config: Config = toml::from_str(
+            r#"
+[[derived]]
+name = "cooling_delta_celsius"
+expr = 'temperature_celsius{sensor="outlet"} - temperature_celsius{sensor="inlet"}'
+labels = { loop = "primary" }
+
+[[derived]]
+name = "heater_power_watts"
+expr = 'voltage_volts{sensor="heater"} * 2.5'
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.derived.len(), 2);
+        assert_eq!(config.derived[0].labels["loop"], "primary");
+        assert!(config.derived[1].labels.is_empty());
+    }
+
+    #[test]
+    fn federation_config() {
+        let config: Config = toml::from_str(
+            r#"
+[federation]
+[[federation.peers]]
+url = "http://pi-boiler:8888/"
+
+[[federation.peers]]
+url = "http://10.0.0.7:8888"
+instance = "attic"
+"#,
+        )
+        .unwrap();
+        let federation = config.federation.unwrap();
+        assert_eq!(federation.interval_secs, DEFAULT_FEDERATION_INTERVAL_SECS);
+        let peers = &federation.peers;
+        assert_eq!(peers[0].instance(), "pi-boiler:8888");
+        assert_eq!(peers[0].metrics_url(), "http://pi-boiler:8888/metrics");
+        assert_eq!(peers[1].instance(), "attic");
+    }
+
+    #[test]
+    fn otlp_config() {
+        let config: Config = toml::from_str(
+            r#"
+[otlp]
+endpoint = "http://collector:4318"
+
+[otlp.headers]
+authorization = "Bearer abc"
+"#,
+        )
+        .unwrap();
+        let otlp = config.otlp.unwrap();
+        assert_eq!(otlp.endpoint, "http://collector:4318");
+        assert_eq!(otlp.interval_secs, DEFAULT_OTLP_INTERVAL_SECS);
+        assert_eq!(otlp.headers["authorization"], "Bearer abc");
+    }
+
+    #[test]
+    fn influx_http_config() {
+        let config: Config = toml::from_str(
+            r#"
+[influx]
+url = "http://influx:8086"
+org = "lab"
+bucket = "sensors"
+token = "secret"
+prefix = "lab_"
+flush_interval_secs = 30
+
+[influx.rename]
+temperature_celsius = "temperature"
+"#,
+        )
+        .unwrap();
+        let influx = config.influx.unwrap();
+        assert!(matches!(
+            influx.transport,
+            InfluxTransport::Http { ref bucket, .. } if bucket == "sensors"
+        ));
+        assert_eq!(influx.options.prefix, "lab_");
+        assert_eq!(influx.options.flush_interval_secs, 30);
+        assert_eq!(influx.options.buffer_size, DEFAULT_BUFFER_SIZE);
+        assert_eq!(
+            influx.options.metric_name("temperature_celsius"),
+            "temperature"
+        );
+        assert_eq!(influx.options.metric_name("up"), "up");
+    }
+
+    #[test]
+    fn influx_udp_and_graphite_config() {
+        let config: Config = toml::from_str(
+            r#"
+[influx]
+udp = "10.0.0.5:8089"
+
+[graphite]
+address = "graphite:2003"
+prefix = "building.sensors"
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            config.influx.unwrap().transport,
+            InfluxTransport::Udp { .. }
+        ));
+        let graphite = config.graphite.unwrap();
+        assert_eq!(graphite.address, "graphite:2003");
+        assert_eq!(graphite.options.prefix, "building.sensors");
+    }
+
+    #[test]
+    fn sink_config_rejects_unknown_keys() {
+        for config in [
+            "[influx]\nudp = \"10.0.0.5:8089\"\nflush_interval = 30\n",
+            "[graphite]\naddress = \"graphite:2003\"\nprefx = \"lab\"\n",
+        ] {
+            let error = toml::from_str::<Config>(config).unwrap_err().to_string();
+            assert!(error.contains("unknown field"), "{error}");
+        }
+    }
+
+    #[test]
+    fn influx_config_needs_one_transport() {
+        for config in [
+            "[influx]\nurl = \"http://influx:8086\"\norg = \"lab\"\n",
+            "[influx]\nudp = \"10.0.0.5:8089\"\nurl = \"http://influx:8086\"\n",
+        ] {
+            let error = toml::from_str::<Config>(config).unwrap_err().to_string();
+            assert!(error.contains("set either"), "{error}");
+        }
+    }
+}
@@ -0,0 +1,618 @@
+//! Derived series computed from config expressions.
+//!
+//! Each `[[derived]]` rule names an expression over the stored series and is
+//! evaluated after every metric batch. The grammar is a small subset of
+//! PromQL:
+//!
+//! ```text
+//! expr     = term (("+" | "-") term)*
+//! term     = unary (("*" | "/") unary)*
+//! unary    = "-" unary | primary
+//! primary  = number | "(" expr ")" | selector
+//!          | ("min" | "max" | "avg" | "sum") "(" selector ")"
+//!          | "rate" "(" expr ")"
+//! selector = name ["{" label ("=" | "!=") "string" ("," ...)* "}"]
+//! ```
+//!
+//! A bare selector must match exactly one series. Aggregations reduce every
+//! matching series to one value. `rate` is the per-second change of its
+//! argument between two evaluations with different sample timestamps. A rule
+//! whose inputs are missing or ambiguous emits nothing. A rule that reads no
+//! series is a constant, stamped with the evaluation time.
+
+use std::collections::HashSet;
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+use anyhow::{bail, Context};
+
+use crate::config::DerivedConfig;
+use crate::sample::{now_ms, Sample};
+
+/// Evaluates all configured rules against the current series.
+pub struct Evaluator {
+    rules: Vec<Rule>,
+}
+
+/// Result of one evaluation.
+pub struct Evaluation {
+    /// Current value of every rule with complete inputs.
+    pub current: Vec<Sample>,
+    /// Values whose inputs changed since the previous evaluation.
+    pub updated: Vec<Sample>,
+}
+
+struct Rule {
+    name: String,
+    labels: Vec<(String, String)>,
+    expr: Expr,
+    /// One slot per `rate()` call in the expression, in parse order.
+    rates: Vec<RateState>,
+    /// Whether the expression reads no series, so has no input timestamp.
+    constant: bool,
+    /// Timestamp of the last emitted value, to report only changed results.
+    last_timestamp_ms: Option<u64>,
+}
+
+#[derive(Default)]
+struct RateState {
+    previous: Option<Value>,
+    rate: Option<f64>,
+}
+
+/// A computed value and the newest timestamp among its inputs.
+#[derive(Clone, Copy)]
+struct Value {
+    value: f64,
+    timestamp_ms: u64,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(f64),
+    Selector(Selector),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Aggregate(Aggregation, Selector),
+    Rate(usize, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Aggregation {
+    Min,
+    Max,
+    Avg,
+    Sum,
+}
+
+#[derive(Debug)]
+struct Selector {
+    name: String,
+    matchers: Vec<Matcher>,
+}
+
+#[derive(Debug)]
+struct Matcher {
+    label: String,
+    value: String,
+    equal: bool,
+}
+
+impl Evaluator {
+    /// Parse all rules. Fails on the first invalid expression.
+    pub fn new(configs: &[DerivedConfig]) -> anyhow::Result<Self> {
+        let mut names = HashSet::new();
+        let mut rules = Vec::with_capacity(configs.len());
+        for config in configs {
+            if !names.insert((&config.name, &config.labels)) {
+                bail!("duplicate derived series {}", config.name);
+            }
+            let mut parser = Parser::new(&config.expr);
+            let expr = parser
+                .parse()
+                .with_context(|| format!("derived series {}: `{}`", config.name, config.expr))?;
+            rules.push(Rule {
+                name: config.name.clone(),
+                labels: config
+                    .labels
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+                rates: (0..parser.rates).map(|_| RateState::default()).collect(),
+                constant: !expr.reads_series(),
+                expr,
+                last_timestamp_ms: None,
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Evaluate every rule against the given series.
+    pub fn evaluate(&mut self, samples: &[Sample]) -> Evaluation {
+        let mut evaluation = Evaluation {
+            current: Vec::new(),
+            updated: Vec::new(),
+        };
+        for rule in &mut self.rules {
+            let Some(mut result) = eval(&rule.expr, samples, &mut rule.rates) else {
+                continue;
+            };
+            if rule.constant {
+                result.timestamp_ms = now_ms();
+            }
+            let sample = Sample {
+                name: rule.name.clone(),
+                labels: rule.labels.clone(),
+                value: result.value,
+                timestamp_ms: result.timestamp_ms,
+            };
+            if rule.last_timestamp_ms != Some(result.timestamp_ms) {
+                rule.last_timestamp_ms = Some(result.timestamp_ms);
+                evaluation.updated.push(sample.clone());
+            }
+            evaluation.current.push(sample);
+        }
+        evaluation
+    }
+}
+
+impl Expr {
+    /// Whether evaluating the expression looks up any stored series.
+    fn reads_series(&self) -> bool {
+        match self {
+            Expr::Number(_) => false,
+            Expr::Selector(_) | Expr::Aggregate(..) => true,
+            Expr::Neg(inner) | Expr::Rate(_, inner) => inner.reads_series(),
+            Expr::Binary(_, lhs, rhs) => lhs.reads_series() || rhs.reads_series(),
+        }
+    }
+}
+
+fn eval(expr: &Expr, samples: &[Sample], rates: &mut [RateState]) -> Option<Value> {
+    match expr {
+        Expr::Number(value) => Some(Value {
+            value: *value,
+            timestamp_ms: 0,
+        }),
+        Expr::Selector(selector) => {
+            let mut matches = samples.iter().filter(|s| selector.matches(s));
+            let sample = matches.next()?;
+            if matches.next().is_some() {
+                log::debug!("selector {} matches several series", selector.name);
+                return None;
+            }
+            Some(Value {
+                value: sample.value,
+                timestamp_ms: sample.timestamp_ms,
+            })
+        }
+        Expr::Neg(inner) => {
+            let v = eval(inner, samples, rates)?;
+            Some(Value {
+                value: -v.value,
+                ..v
+            })
+        }
+        Expr::Binary(op, lhs, rhs) => {
+            let a = eval(lhs, samples, rates)?;
+            let b = eval(rhs, samples, rates)?;
+            let value = match op {
+                Op::Add => a.value + b.value,
+                Op::Sub => a.value - b.value,
+                Op::Mul => a.value * b.value,
+                Op::Div => a.value / b.value,
+            };
+            Some(Value {
+                value,
+                timestamp_ms: a.timestamp_ms.max(b.timestamp_ms),
+            })
+        }
+        Expr::Aggregate(aggregation, selector) => {
+            let matches: Vec<&Sample> = samples.iter().filter(|s| selector.matches(s)).collect();
+            if matches.is_empty() {
+                return None;
+            }
+            let values = matches.iter().map(|s| s.value);
+            let value = match aggregation {
+                Aggregation::Min => values.fold(f64::INFINITY, f64::min),
+                Aggregation::Max => values.fold(f64::NEG_INFINITY, f64::max),
+                Aggregation::Sum => values.sum(),
+                Aggregation::Avg => values.sum::<f64>() / matches.len() as f64,
+            };
+            let timestamp_ms = matches.iter().map(|s| s.timestamp_ms).max().unwrap_or(0);
+            Some(Value {
+                value,
+                timestamp_ms,
+            })
+        }
+        Expr::Rate(slot, inner) => {
+            let current = eval(inner, samples, rates)?;
+            let state = &mut rates[*slot];
+            if let Some(previous) = state.previous {
+                if current.timestamp_ms > previous.timestamp_ms {
+                    let secs = (current.timestamp_ms - previous.timestamp_ms) as f64 / 1000.0;
+                    state.rate = Some((current.value - previous.value) / secs);
+                    state.previous = Some(current);
+                }
+            } else {
+                state.previous = Some(current);
+            }
+            state.rate.map(|value| Value {
+                value,
+                timestamp_ms: current.timestamp_ms,
+            })
+        }
+    }
+}
+
+impl Selector {
+    fn matches(&self, sample: &Sample) -> bool {
+        sample.name == self.name
+            && self.matchers.iter().all(|m| {
+                let value = sample
+                    .labels
+                    .iter()
+                    .find(|(k, _)| *k == m.label)
+                    .map_or("", |(_, v)| v.as_str());
+                (value == m.value) == m.equal
+            })
+    }
+}
+
+/// Recursive descent parser over the expression text.
+struct Parser<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    /// Number of `rate()` calls seen, used to assign state slots.
+    rates: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            chars: input.char_indices().peekable(),
+            rates: 0,
+        }
+    }
+
+    fn parse(&mut self) -> anyhow::Result<Expr> {
+        let expr = self.expr()?;
+        self.skip_whitespace();
+        if let Some((i, c)) = self.chars.peek() {
+            bail!("unexpected '{c}' at offset {i}");
+        }
+        Ok(expr)
+    }
+
+    fn expr(&mut self) -> anyhow::Result<Expr> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Op::Add,
+                Some('-') => Op::Sub,
+                _ => return Ok(lhs),
+            };
+            self.chars.next();
+            let rhs = self.term()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn term(&mut self) -> anyhow::Result<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => Op::Mul,
+                Some('/') => Op::Div,
+                _ => return Ok(lhs),
+            };
+            self.chars.next();
+            let rhs = self.unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        if self.peek() == Some('-') {
+            self.chars.next();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Expr> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => self.number(),
+            Some(c) if is_name_start(c) => {
+                let name = self.name();
+                if self.peek() != Some('(') {
+                    return Ok(Expr::Selector(self.selector(name)?));
+                }
+                self.chars.next();
+                let aggregation = match name.as_str() {
+                    "rate" => {
+                        let slot = self.rates;
+                        self.rates += 1;
+                        let inner = self.expr()?;
+                        self.expect(')')?;
+                        return Ok(Expr::Rate(slot, Box::new(inner)));
+                    }
+                    "min" => Aggregation::Min,
+                    "max" => Aggregation::Max,
+                    "avg" => Aggregation::Avg,
+                    "sum" => Aggregation::Sum,
+                    _ => bail!("unknown function {name}"),
+                };
+                self.skip_whitespace();
+                if !self.peek().is_some_and(is_name_start) {
+                    bail!("{name}() takes a series selector");
+                }
+                let metric = self.name();
+                let selector = self.selector(metric)?;
+                self.expect(')')?;
+                Ok(Expr::Aggregate(aggregation, selector))
+            }
+            Some(c) => bail!("unexpected '{c}'"),
+            None => bail!("unexpected end of expression"),
+        }
+    }
+
+    fn number(&mut self) -> anyhow::Result<Expr> {
+        let start = self.offset();
+        let mut prev = ' ';
+        while let Some(&(_, c)) = self.chars.peek() {
+            let exponent_sign = (c == '+' || c == '-') && (prev == 'e' || prev == 'E');
+            if !(c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E') || exponent_sign) {
+                break;
+            }
+            prev = c;
+            self.chars.next();
+        }
+        let text = &self.input[start..self.offset()];
+        let value = text
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid number {text}"))?;
+        Ok(Expr::Number(value))
+    }
+
+    /// Parse the optional `{...}` matcher list after a metric name.
+    fn selector(&mut self, name: String) -> anyhow::Result<Selector> {
+        let mut matchers = Vec::new();
+        if self.peek() == Some('{') {
+            self.chars.next();
+            loop {
+                match self.peek() {
+                    Some('}') => {
+                        self.chars.next();
+                        break;
+                    }
+                    Some(c) if is_name_start(c) => {}
+                    _ => bail!("expected label name in selector for {name}"),
+                }
+                let label = self.name();
+                let equal = match self.peek() {
+                    Some('=') => true,
+                    Some('!') => {
+                        self.chars.next();
+                        false
+                    }
+                    _ => bail!("expected '=' or '!=' after {label}"),
+                };
+                self.expect('=')?;
+                let value = self.string()?;
+                matchers.push(Matcher {
+                    label,
+                    value,
+                    equal,
+                });
+                if self.peek() == Some(',') {
+                    self.chars.next();
+                }
+            }
+        }
+        Ok(Selector { name, matchers })
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(value),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => break,
+                },
+                Some((_, c)) => value.push(c),
+                None => break,
+            }
+        }
+        bail!("unterminated string")
+    }
+
+    fn name(&mut self) -> String {
+        let start = self.offset();
+        while self
+            .chars
+            .peek()
+            .is_some_and(|&(_, c)| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+        {
+            self.chars.next();
+        }
+        self.input[start..self.offset()].to_owned()
+    }
+
+    fn expect(&mut self, expected: char) -> anyhow::Result<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            Some(c) => bail!("expected '{expected}', found '{c}'"),
+            None => bail!("expected '{expected}' at end of expression"),
+        }
+    }
+
+    /// Skip whitespace and return the next character without consuming it.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |&(i, _)| i)
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == ':'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn rule(name: &str, expr: &str) -> DerivedConfig {
+        DerivedConfig {
+            name: name.to_owned(),
+            expr: expr.to_owned(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    fn samples(lines: &[&str]) -> Vec<Sample> {
+        lines.iter().map(|l| Sample::parse(l).unwrap()).collect()
+    }
+
+    fn eval_one(expr: &str, lines: &[&str]) -> Option<f64> {
+        let mut evaluator = Evaluator::new(&[rule("out", expr)]).unwrap();
+        let evaluation = evaluator.evaluate(&samples(lines));
+        evaluation.current.first().map(|s| s.value)
+    }
+
+    #[test]
+    fn arithmetic_with_precedence() {
+        assert_eq!(eval_one("1 + 2 * 3", &[]), Some(7.0));
+        assert_eq!(eval_one("(1 + 2) * 3", &[]), Some(9.0));
+        assert_eq!(eval_one("-2 - -3", &[]), Some(1.0));
+        assert_eq!(eval_one("1.5e3 / 2", &[]), Some(750.0));
+    }
+
+    #[test]
+    fn constant_is_stamped_with_evaluation_time() {
+        let mut evaluator = Evaluator::new(&[rule("threshold_celsius", "30")]).unwrap();
+        let before = now_ms();
+        let first = evaluator.evaluate(&[]);
+        assert!(first.updated[0].timestamp_ms >= before);
+
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = evaluator.evaluate(&[]);
+        assert_eq!(second.updated.len(), 1);
+        assert!(second.updated[0].timestamp_ms > first.updated[0].timestamp_ms);
+    }
+
+    #[test]
+    fn difference_between_two_probes() {
+        let lines = [
+            "temperature_celsius{sensor=\"inlet\"} 20.5 1000",
+            "temperature_celsius{sensor=\"outlet\"} 27 2000",
+        ];
+        let mut evaluator = Evaluator::new(&[DerivedConfig {
+            name: "cooling_delta_celsius".to_owned(),
+            expr: "temperature_celsius{sensor=\"outlet\"} - temperature_celsius{sensor=\"inlet\"}"
+                .to_owned(),
+            labels: BTreeMap::from([("loop".to_owned(), "primary".to_owned())]),
+        }])
+        .unwrap();
+        let evaluation = evaluator.evaluate(&samples(&lines));
+        let sample = &evaluation.current[0];
+        assert_eq!(sample.name, "cooling_delta_celsius");
+        assert_eq!(sample.label("loop"), Some("primary"));
+        assert_eq!(sample.value, 6.5);
+        assert_eq!(sample.timestamp_ms, 2000);
+    }
+
+    #[test]
+    fn missing_or_ambiguous_selector_emits_nothing() {
+        let lines = ["t{sensor=\"a\"} 1 1", "t{sensor=\"b\"} 2 1"];
+        assert_eq!(eval_one("t * 2", &lines), None);
+        assert_eq!(eval_one("missing + 1", &lines), None);
+        assert_eq!(eval_one("t{sensor!=\"a\"} * 2", &lines), Some(4.0));
+    }
+
+    #[test]
+    fn aggregations_over_matching_series() {
+        let lines = [
+            "t{room=\"lab\",sensor=\"a\"} 20 1",
+            "t{room=\"lab\",sensor=\"b\"} 24 1",
+            "t{room=\"hall\",sensor=\"c\"} 10 1",
+        ];
+        assert_eq!(eval_one("min(t{room=\"lab\"})", &lines), Some(20.0));
+        assert_eq!(eval_one("max(t)", &lines), Some(24.0));
+        assert_eq!(eval_one("avg(t{room=\"lab\"})", &lines), Some(22.0));
+        assert_eq!(eval_one("sum(t) / 3", &lines), Some(18.0));
+        assert_eq!(eval_one("avg(t{room=\"roof\"})", &lines), None);
+    }
+
+    #[test]
+    fn rate_between_evaluations() {
+        let mut evaluator = Evaluator::new(&[rule("r", "rate(level * 100)")]).unwrap();
+        assert!(evaluator
+            .evaluate(&samples(&["level 1.0 1000"]))
+            .current
+            .is_empty());
+
+        let evaluation = evaluator.evaluate(&samples(&["level 1.5 3000"]));
+        assert_eq!(evaluation.current[0].value, 25.0);
+        assert_eq!(evaluation.updated.len(), 1);
+
+        // A batch from another port does not move the input; the rate holds.
+        let evaluation = evaluator.evaluate(&samples(&["level 1.5 3000", "other 1 4000"]));
+        assert_eq!(evaluation.current[0].value, 25.0);
+        assert!(evaluation.updated.is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        for expr in [
+            "1 +",
+            "(1",
+            "foo(t)",
+            "max(1)",
+            "t{a=b}",
+            "t{a=\"x}",
+            "1 2",
+            "t{a~\"x\"}",
+        ] {
+            assert!(Evaluator::new(&[rule("out", expr)]).is_err(), "{expr}");
+        }
+        assert!(Evaluator::new(&[rule("a", "1"), rule("a", "2")]).is_err());
+    }
+}
@@ -1,11 +1,13 @@
-//! Serial port autodiscovery via inotify.
+//! Serial port autodiscovery via netlink uevents, with inotify and polling fallbacks.
 //!
-//! Watches /dev/ for device node creation and deletion. When an ESP32-C3 USB
+//! Listens for udev add/remove events on tty devices. When an ESP32-C3 USB
 //! serial port appears or disappears, spawns or tears down the corresponding
-//! serial reader task. Falls back to periodic polling if inotify is unavailable.
+//! serial reader task. Udev properties identify each board, so a replugged
+//! board is recognized even if it comes back on a different port. If udev is
+//! not running, watches /dev/ with inotify instead. A periodic full rescan
+//! runs in either case as a safety net.
 
 use std::collections::HashMap;
-use std::collections::HashSet;
 use std::time::Duration;
 
 use inotify::{Inotify, WatchMask};
@@ -15,6 +17,7 @@ use tokio_util::sync::CancellationToken;
 
 use crate::serial::{self, MetricBatch};
 use crate::store::MetricsStore;
+use crate::uevent::{self, Action, Uevent, UeventListener};
 
 /// Espressif USB VID.
 const ESPRESSIF_VID: u16 = 0x303a;
@@ -22,7 +25,7 @@ const ESPRESSIF_VID: u16 = 0x303a;
 /// ESP32-C3 USB-JTAG PID.
 const ESP32_C3_PID: u16 = 0x1001;
 
-/// Fallback poll interval when no inotify events arrive.
+/// Fallback poll interval for a full port rescan.
 const FALLBACK_POLL: Duration = Duration::from_secs(60);
 
 /// Buffer size for inotify event reads.
@@ -30,24 +33,138 @@ const INOTIFY_BUF_SIZE: usize = 512;
 
 type DeviceEventStream = inotify::EventStream<[u8; INOTIFY_BUF_SIZE]>;
 
+/// Identity of a sensor board attached to a serial port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    /// Device node, e.g. `/dev/ttyACM0`.
+    pub port: String,
+    /// Udev `ID_SERIAL`, unique per board. Derived from the chip MAC on ESP32-C3.
+    pub serial: Option<String>,
+    /// Udev `ID_PATH`, the position in the USB topology.
+    pub path: Option<String>,
+}
+
+impl Device {
+    fn from_properties(port: String, props: &HashMap<String, String>) -> Self {
+        Self {
+            port,
+            serial: props.get("ID_SERIAL").cloned(),
+            path: props.get("ID_PATH").cloned(),
+        }
+    }
+}
+
+impl std::fmt::Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.port)?;
+        if let Some(serial) = &self.serial {
+            write!(f, " serial={serial}")?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, " path={path}")?;
+        }
+        Ok(())
+    }
+}
+
 struct ReaderHandle {
+    device: Device,
     token: CancellationToken,
     join: JoinHandle<()>,
 }
 
-/// Run the discovery loop with inotify for instant detection and a fallback poll.
+/// Reader lifecycle state shared by the event handlers.
+struct Discovery {
+    readers: HashMap<String, ReaderHandle>,
+    /// Last known port per board serial, used to tell a replug from a new board.
+    known: HashMap<String, String>,
+    tx: mpsc::Sender<MetricBatch>,
+    store: MetricsStore,
+    parent_token: CancellationToken,
+}
+
+/// Run the discovery loop until the token is cancelled.
 ///
-/// Watches /dev/ for device node creation/deletion. When a ttyACM device
-/// appears or disappears, triggers an immediate scan. Falls back to periodic
-/// polling every 60 seconds as a safety net.
+/// Uses the udev netlink listener when udevd is running, otherwise inotify on
+/// /dev/. Both fall back to a full rescan every 60 seconds.
 pub async fn run(
     tx: mpsc::Sender<MetricBatch>,
     store: MetricsStore,
     parent_token: CancellationToken,
 ) {
-    let mut readers: HashMap<String, ReaderHandle> = HashMap::new();
+    let mut discovery = Discovery {
+        readers: HashMap::new(),
+        known: HashMap::new(),
+        tx,
+        store,
+        parent_token: parent_token.clone(),
+    };
     let mut warned_no_sensors = false;
 
+    let listener = init_uevent_listener();
+    let mut event_stream = if listener.is_some() {
+        None
+    } else {
+        init_inotify()
+    };
+
+    // The first tick completes immediately and triggers the initial scan.
+    let mut poll = tokio::time::interval(FALLBACK_POLL);
+
+    loop {
+        tokio::select! {
+            _ = poll.tick() => discovery.reconcile().await,
+            _ = wait_for_device_event(&mut event_stream) => discovery.reconcile().await,
+            event = next_uevent(listener.as_ref()) => match event {
+                Ok(event) => discovery.handle_uevent(event).await,
+                Err(e) => {
+                    // ENOBUFS means events were dropped; resync with a full scan.
+                    log::warn!("uevent receive error: {}, rescanning", e);
+                    discovery.reconcile().await;
+                }
+            },
+            _ = parent_token.cancelled() => break,
+        }
+
+        if discovery.readers.is_empty() && !warned_no_sensors {
+            log::warn!("no sensors found");
+            warned_no_sensors = true;
+        } else if !discovery.readers.is_empty() {
+            warned_no_sensors = false;
+        }
+    }
+
+    // Cancel all remaining readers on shutdown.
+    for (port, handle) in discovery.readers {
+        log::info!("{}: shutting down reader", port);
+        handle.token.cancel();
+        let _ = handle.join.await;
+    }
+}
+
+/// Bind the udev netlink listener, or return None to fall back to inotify.
+fn init_uevent_listener() -> Option<UeventListener> {
+    if !uevent::udev_running() {
+        log::warn!("udev is not running, falling back to inotify");
+        return None;
+    }
+    match UeventListener::bind() {
+        Ok(l) => {
+            log::info!("listening for udev tty events");
+            Some(l)
+        }
+        Err(e) => {
+            log::warn!(
+                "failed to bind uevent socket: {}, falling back to inotify",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Watch /dev/ for device node creation/deletion, or return None to rely on polling.
+fn init_inotify() -> Option<DeviceEventStream> {
     let inotify = match Inotify::init() {
         Ok(i) => {
             match i
@@ -73,52 +190,30 @@ pub async fn run(
     // into_event_stream consumes the Inotify instance. If it fails, the fd is
     // dropped and we fall back to polling permanently. This is acceptable since
     // event stream creation failures indicate a systemic issue, not a transient one.
-    let mut event_stream =
-        inotify.and_then(|i| match i.into_event_stream([0u8; INOTIFY_BUF_SIZE]) {
-            Ok(s) => Some(s),
-            Err(e) => {
-                log::warn!(
-                    "failed to create event stream: {}, falling back to polling",
-                    e
-                );
-                None
-            }
-        });
-
-    loop {
-        if parent_token.is_cancelled() {
-            break;
-        }
-
-        reconcile(&mut readers, &tx, &store, &parent_token).await;
-
-        if readers.is_empty() && !warned_no_sensors {
-            log::warn!("no sensors found");
-            warned_no_sensors = true;
-        } else if !readers.is_empty() {
-            warned_no_sensors = false;
-        }
-
-        // Wait for a device event, fallback timeout, or shutdown.
-        tokio::select! {
-            _ = wait_for_device_event(&mut event_stream) => {}
-            _ = tokio::time::sleep(FALLBACK_POLL) => {}
-            _ = parent_token.cancelled() => break,
+    inotify.and_then(|i| match i.into_event_stream([0u8; INOTIFY_BUF_SIZE]) {
+        Ok(s) => Some(s),
+        Err(e) => {
+            log::warn!(
+                "failed to create event stream: {}, falling back to polling",
+                e
+            );
+            None
         }
-    }
+    })
+}
 
-    // Cancel all remaining readers on shutdown.
-    for (port, handle) in readers {
-        log::info!("{}: shutting down reader", port);
-        handle.token.cancel();
-        let _ = handle.join.await;
+/// Wait for the next uevent, or pend forever if the listener is unavailable.
+async fn next_uevent(listener: Option<&UeventListener>) -> std::io::Result<Uevent> {
+    match listener {
+        Some(l) => l.next().await,
+        None => std::future::pending().await,
     }
 }
 
 /// Wait for an inotify event on /dev/, or pend forever if inotify is unavailable.
 ///
 /// When inotify is not available, this future never resolves. The caller's
-/// select! uses a separate fallback sleep to handle the polling case.
+/// select! uses a separate fallback poll to handle the polling case.
 async fn wait_for_device_event(stream: &mut Option<DeviceEventStream>) {
     use futures_core::Stream;
     use std::pin::Pin;
@@ -143,76 +238,222 @@ async fn wait_for_device_event(stream: &mut Option<DeviceEventStream>) {
     }
 }
 
-/// Reconcile the set of active readers with the set of live ports.
-async fn reconcile(
-    readers: &mut HashMap<String, ReaderHandle>,
-    tx: &mpsc::Sender<MetricBatch>,
-    store: &MetricsStore,
-    parent_token: &CancellationToken,
-) {
-    // Remove readers whose tasks have finished (device disconnected, read error).
-    let dead: Vec<String> = readers
-        .iter()
-        .filter(|(_, h)| h.join.is_finished())
-        .map(|(p, _)| p.clone())
-        .collect();
+/// Check whether uevent properties describe an ESP32-C3 USB serial port.
+fn is_sensor_event(event: &Uevent) -> bool {
+    let id = |key: &str| {
+        event
+            .property(key)
+            .and_then(|v| u16::from_str_radix(v, 16).ok())
+    };
+    event.is_tty()
+        && id("ID_VENDOR_ID") == Some(ESPRESSIF_VID)
+        && id("ID_MODEL_ID") == Some(ESP32_C3_PID)
+}
 
-    for port in dead {
-        if let Some(handle) = readers.remove(&port) {
-            let _ = handle.join.await;
-            store.remove(&port).await;
-            log::info!("{}: reader exited, cleared metrics", port);
+impl Discovery {
+    /// Start or stop a single reader in response to a udev event.
+    async fn handle_uevent(&mut self, event: Uevent) {
+        if !event.is_tty() {
+            return;
+        }
+        let Some(port) = event.devnode() else {
+            return;
+        };
+
+        match event.action {
+            Action::Add if is_sensor_event(&event) => {
+                self.reap_finished().await;
+                if !self.readers.contains_key(&port) {
+                    let device = Device::from_properties(port, &event.into_properties());
+                    self.start_reader(device);
+                }
+            }
+            Action::Remove => {
+                if let Some(handle) = self.readers.remove(&port) {
+                    log::info!("{}: device removed, stopping reader", handle.device);
+                    self.stop_reader(&port, handle).await;
+                }
+            }
+            _ => {}
         }
     }
 
-    let live_ports = scan_ports();
+    /// Reconcile the set of active readers with the set of live ports.
+    async fn reconcile(&mut self) {
+        self.reap_finished().await;
+
+        let mut live = scan_ports();
+
+        // Tear down readers for ports that disappeared but whose tasks are still running.
+        let stale: Vec<String> = self
+            .readers
+            .keys()
+            .filter(|p| !live.contains_key(p.as_str()))
+            .cloned()
+            .collect();
+
+        for port in stale {
+            if let Some(handle) = self.readers.remove(&port) {
+                log::info!("{}: port disappeared, stopping reader", port);
+                self.stop_reader(&port, handle).await;
+            }
+        }
+
+        // Spawn readers for new ports.
+        live.retain(|port, _| !self.readers.contains_key(port));
+        for (_, device) in live {
+            self.start_reader(device);
+        }
+    }
 
-    // Tear down readers for ports that disappeared but whose tasks are still running.
-    let stale: Vec<String> = readers
-        .keys()
-        .filter(|p| !live_ports.contains(p.as_str()))
-        .cloned()
-        .collect();
+    /// Remove readers whose tasks have finished (device disconnected, read error).
+    async fn reap_finished(&mut self) {
+        let dead: Vec<String> = self
+            .readers
+            .iter()
+            .filter(|(_, h)| h.join.is_finished())
+            .map(|(p, _)| p.clone())
+            .collect();
 
-    for port in stale {
-        if let Some(handle) = readers.remove(&port) {
-            log::info!("{}: port disappeared, stopping reader", port);
-            handle.token.cancel();
-            let _ = handle.join.await;
-            store.remove(&port).await;
+        for port in dead {
+            if let Some(handle) = self.readers.remove(&port) {
+                let _ = handle.join.await;
+                self.store.remove(&port).await;
+                log::info!("{}: reader exited, cleared metrics", port);
+            }
         }
     }
 
-    // Spawn readers for new ports.
-    for port in &live_ports {
-        if !readers.contains_key(port) {
-            log::info!("{}: port discovered, starting reader", port);
-            let token = parent_token.child_token();
-            let join = serial::spawn_reader(port.clone(), tx.clone(), token.clone());
-            readers.insert(port.clone(), ReaderHandle { token, join });
+    fn start_reader(&mut self, device: Device) {
+        match device
+            .serial
+            .as_ref()
+            .and_then(|s| self.known.insert(s.clone(), device.port.clone()))
+        {
+            Some(previous) if previous == device.port => {
+                log::info!("{}: board reconnected, starting reader", device);
+            }
+            Some(previous) => {
+                log::info!(
+                    "{}: board reconnected (previously {}), starting reader",
+                    device,
+                    previous
+                );
+            }
+            None => log::info!("{}: port discovered, starting reader", device),
         }
+
+        let token = self.parent_token.child_token();
+        let join = serial::spawn_reader(device.clone(), self.tx.clone(), token.clone());
+        self.readers.insert(
+            device.port.clone(),
+            ReaderHandle {
+                device,
+                token,
+                join,
+            },
+        );
+    }
+
+    async fn stop_reader(&self, port: &str, handle: ReaderHandle) {
+        handle.token.cancel();
+        let _ = handle.join.await;
+        self.store.remove(port).await;
     }
 }
 
 /// Scan for serial ports matching the Espressif ESP32-C3 VID/PID.
-fn scan_ports() -> HashSet<String> {
+///
+/// Identity comes from the udev database when available, falling back to
+/// the USB serial number reported by the port enumeration.
+fn scan_ports() -> HashMap<String, Device> {
     let ports = match serialport::available_ports() {
         Ok(p) => p,
         Err(e) => {
             log::warn!("failed to enumerate serial ports: {}", e);
-            return HashSet::new();
+            return HashMap::new();
         }
     };
 
     ports
         .into_iter()
         .filter_map(|p| {
-            if let serialport::SerialPortType::UsbPort(usb) = &p.port_type {
-                if usb.vid == ESPRESSIF_VID && usb.pid == ESP32_C3_PID {
-                    return Some(p.port_name);
-                }
+            let serialport::SerialPortType::UsbPort(usb) = p.port_type else {
+                return None;
+            };
+            if usb.vid != ESPRESSIF_VID || usb.pid != ESP32_C3_PID {
+                return None;
             }
-            None
+            let props = uevent::udev_properties(&p.port_name);
+            let mut device = Device::from_properties(p.port_name.clone(), &props);
+            if device.serial.is_none() {
+                device.serial = usb.serial_number;
+            }
+            Some((p.port_name, device))
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(props: &[&str]) -> Uevent {
+        let mut msg = b"add@/devices/tty/ttyACM0\0".to_vec();
+        for p in props {
+            msg.extend_from_slice(p.as_bytes());
+            msg.push(0);
+        }
+        Uevent::parse(&msg).unwrap()
+    }
+
+    #[test]
+    fn sensor_event_matches_vid_pid() {
+        let ev = event(&[
+            "ACTION=add",
+            "SUBSYSTEM=tty",
+            "DEVNAME=/dev/ttyACM0",
+            "ID_VENDOR_ID=303a",
+            "ID_MODEL_ID=1001",
+        ]);
+        assert!(is_sensor_event(&ev));
+    }
+
+    #[test]
+    fn sensor_event_rejects_other_devices() {
+        let ev = event(&[
+            "ACTION=add",
+            "SUBSYSTEM=tty",
+            "DEVNAME=/dev/ttyUSB0",
+            "ID_VENDOR_ID=10c4",
+            "ID_MODEL_ID=ea60",
+        ]);
+        assert!(!is_sensor_event(&ev));
+
+        // The USB interface itself carries the same IDs but is not a tty.
+        let ev = event(&[
+            "ACTION=add",
+            "SUBSYSTEM=usb",
+            "ID_VENDOR_ID=303a",
+            "ID_MODEL_ID=1001",
+        ]);
+        assert!(!is_sensor_event(&ev));
+    }
+
+    #[test]
+    fn device_identity_from_properties() {
+        let props = HashMap::from([
+            ("ID_SERIAL".to_owned(), "Espressif_A0:76".to_owned()),
+            (
+                "ID_PATH".to_owned(),
+                "platform-xhci-usb-0:1.2:1.0".to_owned(),
+            ),
+        ]);
+        let device = Device::from_properties("/dev/ttyACM0".to_owned(), &props);
+        assert_eq!(device.serial.as_deref(), Some("Espressif_A0:76"));
+        assert_eq!(
+            device.to_string(),
+            "/dev/ttyACM0 serial=Espressif_A0:76 path=platform-xhci-usb-0:1.2:1.0"
+        );
+    }
+}
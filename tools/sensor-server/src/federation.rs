@@ -0,0 +1,201 @@
+//! Federation of other sensor-server instances.
+//!
+//! Each configured peer's `/metrics` is scraped on an interval and merged
+//! into the local store with an `instance` label, so a single Prometheus job
+//! covers every sensor host. Peer health is exposed as `sensor_peer_up`; a
+//! peer that cannot be scraped has its series dropped, like an unplugged
+//! board.
+
+use std::time::Duration;
+
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::client::HttpClient;
+use crate::config::{FederationConfig, PeerConfig};
+use crate::sample::Sample;
+use crate::store::{escape_label, is_valid_metric_line, MetricsStore};
+
+/// Scrape all peers until the token is cancelled.
+pub async fn run(config: FederationConfig, store: MetricsStore, token: CancellationToken) {
+    let client = HttpClient::new();
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+
+    let mut tasks = JoinSet::new();
+    for peer in config.peers {
+        tasks.spawn(scrape_peer(
+            peer,
+            interval,
+            client.clone(),
+            store.clone(),
+            token.clone(),
+        ));
+    }
+    while let Some(result) = tasks.join_next().await {
+        if let Err(e) = result {
+            log::error!("peer scrape task panicked: {}", e);
+        }
+    }
+}
+
+async fn scrape_peer(
+    peer: PeerConfig,
+    interval: Duration,
+    client: HttpClient,
+    store: MetricsStore,
+    token: CancellationToken,
+) {
+    let instance = peer.instance();
+    let url = peer.metrics_url();
+    log::info!("federating {} as instance={}", url, instance);
+
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut up = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = token.cancelled() => break,
+        }
+
+        match client.get(&url, &[]).await {
+            Ok(body) => {
+                let body = String::from_utf8_lossy(&body);
+                store
+                    .update_peer(&instance, relabel(&body, &instance))
+                    .await;
+                if up != Some(true) {
+                    log::info!("peer {} up", instance);
+                }
+                up = Some(true);
+            }
+            Err(e) => {
+                store.peer_down(&instance).await;
+                if up != Some(false) {
+                    log::warn!("peer {} down: {:#}", instance, e);
+                }
+                up = Some(false);
+            }
+        }
+    }
+}
+
+/// Keep the metric lines of a peer response and add the `instance` label.
+///
+/// Comments are dropped; the store declares the types of its own families.
+fn relabel(body: &str, instance: &str) -> Vec<String> {
+    body.lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && is_valid_metric_line(l))
+        .map(|l| with_instance(l, instance))
+        .collect()
+}
+
+/// Insert `instance="..."` as the first label of a metric line.
+///
+/// Lines that already carry an instance label, e.g. from a peer that itself
+/// federates other hosts, are kept unchanged.
+fn with_instance(line: &str, instance: &str) -> String {
+    let has_instance =
+        Sample::parse(line).is_some_and(|s| s.labels.iter().any(|(k, _)| k == "instance"));
+    if has_instance {
+        return line.to_owned();
+    }
+
+    let label = format!("instance=\"{}\"", escape_label(instance));
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    let (name, rest) = line.split_at(name_end);
+    match rest.strip_prefix('{') {
+        Some(labels) if labels.trim_start().starts_with('}') => {
+            format!("{name}{{{label}{}", labels.trim_start())
+        }
+        Some(labels) => format!("{name}{{{label},{labels}"),
+        None => format!("{name}{{{label}}}{rest}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_label_is_added() {
+        assert_eq!(with_instance("up 1 5", "pi"), "up{instance=\"pi\"} 1 5");
+        assert_eq!(
+            with_instance("temp{sensor=\"a\"} 23.5", "pi"),
+            "temp{instance=\"pi\",sensor=\"a\"} 23.5"
+        );
+        assert_eq!(with_instance("m{} 1", "pi"), "m{instance=\"pi\"} 1");
+    }
+
+    #[test]
+    fn existing_instance_label_is_kept() {
+        let line = "temp{instance=\"attic\",sensor=\"a\"} 1";
+        assert_eq!(with_instance(line, "pi"), line);
+    }
+
+    #[test]
+    fn relabel_drops_comments_and_garbage() {
+        let body = "# TYPE sensor_board_boots_total counter\nup 1\n\nnot a metric\n";
+        assert_eq!(relabel(body, "pi"), ["up{instance=\"pi\"} 1"]);
+    }
+
+    #[tokio::test]
+    async fn merges_peer_and_tracks_health() {
+        use crate::discovery::Device;
+
+        let peer_store = MetricsStore::new();
+        let device = Device {
+            port: "/dev/ttyACM0".to_owned(),
+            serial: None,
+            path: None,
+        };
+        peer_store
+            .update(&device, vec!["temp{sensor=\"a\"} 61.5 1000".to_owned()])
+            .await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = crate::http::router(peer_store);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // A closed port stands in for an unreachable peer.
+        let dead = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_addr = dead.local_addr().unwrap();
+        drop(dead);
+
+        let config = FederationConfig {
+            interval_secs: 1,
+            peers: vec![
+                PeerConfig {
+                    url: format!("http://{addr}"),
+                    instance: Some("boiler".to_owned()),
+                },
+                PeerConfig {
+                    url: format!("http://{dead_addr}"),
+                    instance: None,
+                },
+            ],
+        };
+        let store = MetricsStore::new();
+        let token = CancellationToken::new();
+        let task = tokio::spawn(run(config, store.clone(), token.clone()));
+
+        let dead_up = format!("sensor_peer_up{{instance=\"{dead_addr}\"}} 0\n");
+        let mut output = String::new();
+        for _ in 0..50 {
+            output = store.render().await;
+            if output.contains("sensor_peer_up{instance=\"boiler\"} 1") && output.contains(&dead_up)
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        token.cancel();
+        task.await.unwrap();
+
+        assert!(output.contains("temp{instance=\"boiler\",sensor=\"a\"} 61.5 1000\n"));
+        assert!(output.contains("sensor_peer_up{instance=\"boiler\"} 1\n"));
+        assert!(output.contains(&dead_up));
+    }
+}
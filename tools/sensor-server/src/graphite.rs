@@ -0,0 +1,216 @@
+//! Graphite plaintext protocol sink.
+//!
+//! Each sample becomes a `path value timestamp` line over a persistent TCP
+//! connection. The path is the prefix, the metric name, then each label as
+//! a `key.value` pair, e.g. `sensors.temperature_celsius.sensor.28FF`.
+
+use std::fmt::Write as _;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::config::{GraphiteConfig, SinkOptions};
+use crate::output::Sink;
+use crate::sample::Sample;
+
+/// Timeout for connecting to the Carbon listener.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Timeout for writing one chunk of lines.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct GraphiteSink {
+    name: String,
+    address: String,
+    options: SinkOptions,
+    stream: Option<TcpStream>,
+}
+
+impl GraphiteSink {
+    pub fn new(config: &GraphiteConfig) -> Self {
+        Self {
+            name: format!("graphite {}", config.address),
+            address: config.address.clone(),
+            options: config.options.clone(),
+            stream: None,
+        }
+    }
+
+    async fn connect(&mut self) -> anyhow::Result<&mut TcpStream> {
+        // Carbon closes idle connections, and a write to a half-closed
+        // socket still succeeds, losing the batch. Reconnect first instead.
+        if self.stream.as_ref().is_some_and(peer_closed) {
+            self.stream = None;
+        }
+        if self.stream.is_none() {
+            let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&self.address))
+                .await
+                .map_err(|_| anyhow::anyhow!("connect timeout: {}", self.address))??;
+            self.stream = Some(stream);
+        }
+        self.stream
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("not connected"))
+    }
+}
+
+impl Sink for GraphiteSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let mut payload = String::new();
+        for sample in samples {
+            if let Some(line) = format_line(sample, &self.options) {
+                payload.push_str(&line);
+                payload.push('\n');
+            }
+        }
+        if payload.is_empty() {
+            return Ok(());
+        }
+
+        let stream = self.connect().await?;
+        let result =
+            tokio::time::timeout(WRITE_TIMEOUT, stream.write_all(payload.as_bytes())).await;
+        match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => {
+                // Reset by the peer; reconnect on the next attempt.
+                self.stream = None;
+                Err(e.into())
+            }
+            Err(_) => {
+                self.stream = None;
+                anyhow::bail!("write timeout: {}", self.address)
+            }
+        }
+    }
+}
+
+/// Whether the peer has closed the connection or reset it.
+///
+/// Carbon never sends anything, so a readable socket means EOF or an error.
+/// This peeks with the syscall directly because the reactor may not have
+/// seen the FIN yet.
+fn peer_closed(stream: &TcpStream) -> bool {
+    let mut byte = 0u8;
+    // SAFETY: byte is valid for a write of one byte.
+    let n = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            std::ptr::addr_of_mut!(byte).cast::<libc::c_void>(),
+            1,
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        )
+    };
+    match n {
+        0 => true,
+        n if n > 0 => false,
+        _ => std::io::Error::last_os_error().kind() != std::io::ErrorKind::WouldBlock,
+    }
+}
+
+/// Format a sample as a Graphite plaintext line, or None for non-finite values.
+fn format_line(sample: &Sample, options: &SinkOptions) -> Option<String> {
+    if !sample.value.is_finite() {
+        return None;
+    }
+
+    let mut path = String::new();
+    if !options.prefix.is_empty() {
+        path.push_str(options.prefix.trim_end_matches('.'));
+        path.push('.');
+    }
+    path.push_str(&sanitize(options.metric_name(&sample.name)));
+    for (key, value) in &sample.labels {
+        let _ = write!(path, ".{}.{}", sanitize(key), sanitize(value));
+    }
+
+    let ts_secs = sample.timestamp_ms / 1000;
+    Some(format!("{path} {} {ts_secs}", sample.value))
+}
+
+/// Replace characters that would split or break a Graphite path component.
+fn sanitize(component: &str) -> String {
+    if component.is_empty() {
+        return "_".to_owned();
+    }
+    component
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | ':') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn dotted_path_from_labels() {
+        let sample = Sample::parse(
+            "temperature_celsius{sensor=\"28FF\",loc=\"inlet.pipe\"} 23.5 1708000000123",
+        )
+        .unwrap();
+        let options = SinkOptions {
+            prefix: "sensors.".to_owned(),
+            ..SinkOptions::default()
+        };
+        assert_eq!(
+            format_line(&sample, &options).unwrap(),
+            "sensors.temperature_celsius.sensor.28FF.loc.inlet_pipe 23.5 1708000000"
+        );
+    }
+
+    #[test]
+    fn no_prefix_and_empty_label() {
+        let sample = Sample::parse("m{a=\"\"} 1 2000").unwrap();
+        assert_eq!(
+            format_line(&sample, &SinkOptions::default()).unwrap(),
+            "m.a._ 1 2"
+        );
+        assert!(format_line(&Sample::parse("m +Inf").unwrap(), &SinkOptions::default()).is_none());
+    }
+
+    #[tokio::test]
+    async fn tcp_sink_writes_and_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = GraphiteConfig {
+            address: listener.local_addr().unwrap().to_string(),
+            options: SinkOptions::default(),
+        };
+        let mut sink = GraphiteSink::new(&config);
+
+        sink.write(&[Sample::parse("up 1 3000").unwrap()])
+            .await
+            .unwrap();
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut buf = [0u8; 64];
+        let n = conn.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"up 1 3\n");
+
+        // The server drops the connection; the very next batch must arrive
+        // over a new one.
+        drop(conn);
+        sink.write(&[Sample::parse("up 0 4000").unwrap()])
+            .await
+            .unwrap();
+        let (mut conn, _) = tokio::time::timeout(Duration::from_secs(1), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let n = conn.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"up 0 4\n");
+    }
+}
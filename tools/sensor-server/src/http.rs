@@ -1,11 +1,12 @@
 //! HTTP server for Prometheus metric scraping.
 //!
-//! Serves a single `GET /metrics` endpoint that returns all stored sensor
-//! metrics in Prometheus text exposition format.
+//! Serves `GET /metrics`, which returns all stored sensor metrics in
+//! Prometheus text exposition format, and `GET /api/devices/{port}/log` with
+//! the recent non-metric lines printed by one board.
 
-use axum::extract::State;
-use axum::http::header;
-use axum::response::IntoResponse;
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::Router;
 
@@ -14,13 +15,33 @@ use crate::store::MetricsStore;
 /// Prometheus text exposition content type.
 const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
 
+/// Plain text content type for board logs.
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
 async fn metrics(State(store): State<MetricsStore>) -> impl IntoResponse {
     let body = store.render().await;
     ([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], body)
 }
 
+/// Return recent board lines for a port.
+///
+/// The port is either a device name relative to /dev (`ttyACM0`) or a
+/// percent-encoded absolute path (`%2Fdev%2FttyACM0`).
+async fn device_log(State(store): State<MetricsStore>, Path(port): Path<String>) -> Response {
+    let port = if port.starts_with('/') {
+        port
+    } else {
+        format!("/dev/{port}")
+    };
+    match store.render_log(&port).await {
+        Some(body) => ([(header::CONTENT_TYPE, TEXT_CONTENT_TYPE)], body).into_response(),
+        None => (StatusCode::NOT_FOUND, format!("no log for {port}\n")).into_response(),
+    }
+}
+
 pub fn router(store: MetricsStore) -> Router {
     Router::new()
         .route("/metrics", get(metrics))
+        .route("/api/devices/{port}/log", get(device_log))
         .with_state(store)
 }
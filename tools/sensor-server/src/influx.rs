@@ -0,0 +1,327 @@
+//! InfluxDB line protocol sink.
+//!
+//! Each sample becomes one line: the metric name is the measurement,
+//! Prometheus labels become tags and the value is written to the `value`
+//! field. Timestamps are sent in nanoseconds, the default precision of both
+//! the v2 HTTP API and the UDP listener.
+
+use std::fmt::Write;
+
+use anyhow::Context;
+use tokio::net::UdpSocket;
+
+use crate::client::HttpClient;
+use crate::config::{InfluxConfig, InfluxTransport, SinkOptions};
+use crate::output::{escape, Sink};
+use crate::sample::Sample;
+
+/// Maximum UDP payload. Stays below a typical Ethernet MTU to avoid fragmentation.
+const UDP_MAX_PAYLOAD: usize = 1400;
+
+enum Transport {
+    Http {
+        client: HttpClient,
+        url: String,
+        auth: Option<String>,
+    },
+    Udp {
+        socket: Option<UdpSocket>,
+        target: std::net::SocketAddr,
+    },
+}
+
+pub struct InfluxSink {
+    name: String,
+    transport: Transport,
+    options: SinkOptions,
+}
+
+impl InfluxSink {
+    pub fn new(config: &InfluxConfig) -> Self {
+        let (name, transport) = match &config.transport {
+            InfluxTransport::Http {
+                url,
+                org,
+                bucket,
+                token,
+            } => {
+                let token = token.clone().or_else(|| std::env::var("INFLUX_TOKEN").ok());
+                let write_url = format!(
+                    "{}/api/v2/write?org={}&bucket={}",
+                    url.trim_end_matches('/'),
+                    encode_query(org),
+                    encode_query(bucket)
+                );
+                (
+                    format!("influx {url}"),
+                    Transport::Http {
+                        client: HttpClient::new(),
+                        url: write_url,
+                        auth: token.map(|t| format!("Token {t}")),
+                    },
+                )
+            }
+            InfluxTransport::Udp { udp } => (
+                format!("influx udp://{udp}"),
+                Transport::Udp {
+                    socket: None,
+                    target: *udp,
+                },
+            ),
+        };
+        Self {
+            name,
+            transport,
+            options: config.options.clone(),
+        }
+    }
+}
+
+impl Sink for InfluxSink {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+        let lines: Vec<String> = samples
+            .iter()
+            .filter_map(|s| format_line(s, &self.options))
+            .collect();
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        match &mut self.transport {
+            Transport::Http { client, url, auth } => {
+                let mut headers = vec![("Content-Type", "text/plain; charset=utf-8")];
+                if let Some(auth) = auth {
+                    headers.push(("Authorization", auth.as_str()));
+                }
+                client
+                    .post(url, &headers, lines.join("\n").into_bytes())
+                    .await?;
+            }
+            Transport::Udp { socket, target } => {
+                if socket.is_none() {
+                    let bind = if target.is_ipv4() {
+                        "0.0.0.0:0"
+                    } else {
+                        "[::]:0"
+                    };
+                    let s = UdpSocket::bind(bind).await.context("bind udp socket")?;
+                    s.connect(*target).await?;
+                    *socket = Some(s);
+                }
+                let Some(s) = socket.as_ref() else {
+                    unreachable!("socket initialized above");
+                };
+                for datagram in pack_datagrams(&lines, UDP_MAX_PAYLOAD) {
+                    if let Err(e) = s.send(datagram.as_bytes()).await {
+                        // Recreate the socket on the next attempt, e.g. after a route change.
+                        *socket = None;
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Format a sample as an Influx line, or None for values Influx cannot store.
+fn format_line(sample: &Sample, options: &SinkOptions) -> Option<String> {
+    if !sample.value.is_finite() {
+        return None;
+    }
+
+    let measurement = format!("{}{}", options.prefix, options.metric_name(&sample.name));
+    let mut line = escape(&measurement, &[',', ' ']);
+    for (key, value) in &sample.labels {
+        // Influx rejects empty tag values.
+        if value.is_empty() {
+            continue;
+        }
+        let _ = write!(
+            line,
+            ",{}={}",
+            escape(key, &[',', '=', ' ']),
+            escape(value, &[',', '=', ' '])
+        );
+    }
+    let ts_ns = u128::from(sample.timestamp_ms) * 1_000_000;
+    let _ = write!(line, " value={:?} {ts_ns}", sample.value);
+    Some(line)
+}
+
+/// Group lines into newline-separated datagrams no larger than `max` bytes.
+///
+/// A single line longer than `max` is sent on its own.
+fn pack_datagrams(lines: &[String], max: usize) -> Vec<String> {
+    let mut datagrams = Vec::new();
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > max {
+            datagrams.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+/// Percent-encode a query parameter value.
+fn encode_query(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "%{b:02X}");
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_with_tags() {
+        let sample = Sample::parse(
+            "temperature_celsius{sensor=\"28FF\",loc=\"inlet pipe\"} 23.5 1708000000000",
+        )
+        .unwrap();
+        let options = SinkOptions::default();
+        assert_eq!(
+            format_line(&sample, &options).unwrap(),
+            "temperature_celsius,sensor=28FF,loc=inlet\\ pipe value=23.5 1708000000000000000"
+        );
+    }
+
+    #[test]
+    fn line_with_prefix_and_rename() {
+        let sample = Sample::parse("temperature_celsius 21 1000").unwrap();
+        let mut options = SinkOptions {
+            prefix: "lab_".to_owned(),
+            ..SinkOptions::default()
+        };
+        options
+            .rename
+            .insert("temperature_celsius".to_owned(), "temperature".to_owned());
+        assert_eq!(
+            format_line(&sample, &options).unwrap(),
+            "lab_temperature value=21.0 1000000000"
+        );
+    }
+
+    #[test]
+    fn line_skips_non_finite_and_empty_tags() {
+        let options = SinkOptions::default();
+        assert!(format_line(&Sample::parse("m NaN 1").unwrap(), &options).is_none());
+        let sample = Sample::parse("m{a=\"\",b=\"x,y\"} 1 1").unwrap();
+        assert_eq!(
+            format_line(&sample, &options).unwrap(),
+            "m,b=x\\,y value=1.0 1000000"
+        );
+    }
+
+    #[test]
+    fn datagram_packing() {
+        let lines: Vec<String> = ["aaaa", "bbbb", "cccc", "dddddddddddd"]
+            .iter()
+            .map(|s| (*s).to_owned())
+            .collect();
+        assert_eq!(
+            pack_datagrams(&lines, 9),
+            ["aaaa\nbbbb", "cccc", "dddddddddddd"]
+        );
+    }
+
+    #[test]
+    fn query_encoding() {
+        assert_eq!(encode_query("my org/1"), "my%20org%2F1");
+    }
+
+    #[tokio::test]
+    async fn udp_sink_sends_lines() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let config = InfluxConfig {
+            transport: InfluxTransport::Udp {
+                udp: receiver.local_addr().unwrap(),
+            },
+            options: SinkOptions::default(),
+        };
+        let mut sink = InfluxSink::new(&config);
+        sink.write(&[Sample::parse("up{host=\"a\"} 1 5").unwrap()])
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 256];
+        let n = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"up,host=a value=1.0 5000000");
+    }
+
+    #[tokio::test]
+    async fn http_sink_posts_to_v2_write() {
+        use axum::extract::{RawQuery, State};
+        use axum::http::{HeaderMap, StatusCode};
+        use std::sync::{Arc, Mutex};
+
+        type Captured = Arc<Mutex<Vec<(Option<String>, Option<String>, String)>>>;
+
+        async fn write(
+            State(captured): State<Captured>,
+            RawQuery(query): RawQuery,
+            headers: HeaderMap,
+            body: String,
+        ) -> StatusCode {
+            let auth = headers
+                .get("authorization")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned);
+            captured.lock().unwrap().push((query, auth, body));
+            StatusCode::NO_CONTENT
+        }
+
+        let captured: Captured = Arc::default();
+        let app = axum::Router::new()
+            .route("/api/v2/write", axum::routing::post(write))
+            .with_state(captured.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = InfluxConfig {
+            transport: InfluxTransport::Http {
+                url: format!("http://{addr}/"),
+                org: "lab".to_owned(),
+                bucket: "sensors".to_owned(),
+                token: Some("secret".to_owned()),
+            },
+            options: SinkOptions::default(),
+        };
+        let mut sink = InfluxSink::new(&config);
+        sink.write(&[
+            Sample::parse("up 1 5").unwrap(),
+            Sample::parse("temp{sensor=\"a\"} 2.5 6").unwrap(),
+        ])
+        .await
+        .unwrap();
+
+        let captured = captured.lock().unwrap();
+        assert_eq!(captured.len(), 1);
+        let (query, auth, body) = &captured[0];
+        assert_eq!(query.as_deref(), Some("org=lab&bucket=sensors"));
+        assert_eq!(auth.as_deref(), Some("Token secret"));
+        assert_eq!(
+            body,
+            "up value=1.0 5000000\ntemp,sensor=a value=2.5 6000000"
+        );
+    }
+}
@@ -1,19 +1,32 @@
 //! Sensor server entry point.
 //!
-//! Parses CLI arguments, starts the HTTP server, discovery loop, and metric
-//! drain task, then waits for shutdown.
+//! Parses CLI arguments and the optional config file, starts the HTTP server,
+//! discovery loop, output sinks and metric drain task, then waits for shutdown.
 
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use clap::Parser;
 use tokio::net::TcpListener;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
+mod boardlog;
+mod client;
+mod config;
+mod derived;
 mod discovery;
+mod federation;
+mod graphite;
 mod http;
+mod influx;
+mod otlp;
+mod output;
+mod sample;
 mod serial;
 mod store;
+mod uevent;
 
 /// Channel buffer size for metric batches from serial readers.
 const BATCH_CHANNEL_SIZE: usize = 64;
@@ -24,6 +37,10 @@ struct Args {
     /// Address to listen on.
     #[arg(long, default_value = "0.0.0.0:8888")]
     listen: SocketAddr,
+
+    /// Path to TOML config file. Optional; enables extra outputs.
+    #[arg(long, default_value = "/etc/sensor-server/config.toml")]
+    config: PathBuf,
 }
 
 #[tokio::main]
@@ -31,15 +48,52 @@ async fn main() -> anyhow::Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
     let args = Args::parse();
+    let cfg = config::Config::load(&args.config)
+        .map_err(|e| anyhow::anyhow!("config file {}: {e}", args.config.display()))?;
+    let evaluator = derived::Evaluator::new(&cfg.derived)
+        .map_err(|e| anyhow::anyhow!("config file {}: {e:#}", args.config.display()))?;
     let store = store::MetricsStore::new();
     let token = CancellationToken::new();
 
     let (tx, rx) = mpsc::channel::<serial::MetricBatch>(BATCH_CHANNEL_SIZE);
 
-    // Drain metric batches from serial readers into the store.
+    let mut sinks = Vec::new();
+    if let Some(influx) = &cfg.influx {
+        sinks.push(output::spawn(
+            influx::InfluxSink::new(influx),
+            &influx.options,
+        ));
+    }
+    if let Some(graphite) = &cfg.graphite {
+        sinks.push(output::spawn(
+            graphite::GraphiteSink::new(graphite),
+            &graphite.options,
+        ));
+    }
+
+    // Drain metric batches from serial readers into the store and sinks.
     let drain_store = store.clone();
     let drain_handle = tokio::spawn(async move {
-        drain_batches(rx, drain_store).await;
+        drain_batches(rx, drain_store, &sinks, evaluator).await;
+        for sink in sinks {
+            sink.shutdown().await;
+        }
+    });
+
+    // Export the stored series to an OpenTelemetry collector.
+    let otlp_handle = cfg.otlp.map(|otlp| {
+        let otlp_store = store.clone();
+        let otlp_token = token.clone();
+        tokio::spawn(async move { otlp::run(otlp, otlp_store, otlp_token).await })
+    });
+
+    // Merge the series of other sensor-server instances.
+    let federation_handle = cfg.federation.map(|federation| {
+        let federation_store = store.clone();
+        let federation_token = token.clone();
+        tokio::spawn(async move {
+            federation::run(federation, federation_store, federation_token).await
+        })
     });
 
     // Discover serial ports and manage reader lifecycle. tx is moved here;
@@ -71,15 +125,60 @@ async fn main() -> anyhow::Result<()> {
     if let Err(e) = drain_handle.await {
         log::error!("drain task panicked: {}", e);
     }
+    if let Some(handle) = federation_handle {
+        if let Err(e) = handle.await {
+            log::error!("federation task panicked: {}", e);
+        }
+    }
+    if let Some(handle) = otlp_handle {
+        if let Err(e) = handle.await {
+            log::error!("OTLP export task panicked: {}", e);
+        }
+    }
 
     log::info!("shutting down");
     Ok(())
 }
 
-/// Receive metric batches from serial readers and update the store.
-async fn drain_batches(mut rx: mpsc::Receiver<serial::MetricBatch>, store: store::MetricsStore) {
+/// Receive metric batches from serial readers, update the store and feed the sinks.
+///
+/// Derived series are re-evaluated after every batch with metric lines.
+async fn drain_batches(
+    mut rx: mpsc::Receiver<serial::MetricBatch>,
+    store: store::MetricsStore,
+    sinks: &[output::SinkHandle],
+    mut evaluator: derived::Evaluator,
+) {
     while let Some(batch) = rx.recv().await {
-        store.update(&batch.port, batch.lines).await;
+        if !sinks.is_empty() && !batch.lines.is_empty() {
+            let samples: Arc<[sample::Sample]> = batch
+                .lines
+                .iter()
+                .filter_map(|l| sample::Sample::parse(l))
+                .collect();
+            for sink in sinks {
+                sink.send(samples.clone());
+            }
+        }
+
+        // A cycle with only board log lines must not blank the port's metrics.
+        if !batch.lines.is_empty() {
+            store.update(&batch.device, batch.lines).await;
+
+            if !evaluator.is_empty() {
+                let evaluation = evaluator.evaluate(&store.samples().await);
+                store.update_derived(&evaluation.current).await;
+                if !evaluation.updated.is_empty() {
+                    let samples: Arc<[sample::Sample]> = evaluation.updated.into();
+                    for sink in sinks {
+                        sink.send(samples.clone());
+                    }
+                }
+            }
+        }
+        if !batch.logs.is_empty() {
+            store.record_logs(&batch.device, batch.logs).await;
+        }
     }
 }
 
@@ -0,0 +1,426 @@
+//! OpenTelemetry OTLP/HTTP metrics exporter.
+//!
+//! Periodically converts every stored series into an OTLP gauge and posts an
+//! `ExportMetricsServiceRequest` as protobuf to the collector's `/v1/metrics`
+//! endpoint. Each board becomes one OTLP resource whose attributes carry the
+//! device identity, and each federated peer one carrying its instance. The
+//! server's own series (derived series, board event counters, peer health)
+//! share a resource without device attributes. Prometheus labels become data
+//! point attributes.
+//!
+//! The message types below are a hand-written subset of the OTLP protobuf
+//! schema (opentelemetry-proto, `metrics/v1`, `common/v1`, `resource/v1`).
+//! Deriving them with prost avoids protoc and generated code in the build,
+//! which keeps the static musl build self-contained. Fields that are `oneof`
+//! members in the schema are declared as optional fields with the same tag,
+//! which encodes identically on the wire.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use prost::Message;
+use tokio_util::sync::CancellationToken;
+
+use crate::client::HttpClient;
+use crate::config::OtlpConfig;
+use crate::sample::Sample;
+use crate::store::{MetricsStore, Source};
+
+/// OTLP/HTTP path for metrics, appended to the configured endpoint.
+const METRICS_PATH: &str = "/v1/metrics";
+
+/// Value of the `service.name` resource attribute.
+const SERVICE_NAME: &str = "sensor-server";
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ExportMetricsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ResourceMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    pub scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    pub attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ScopeMetrics {
+    #[prost(message, optional, tag = "1")]
+    pub scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub version: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Metric {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    /// Member of the `data` oneof.
+    #[prost(message, optional, tag = "5")]
+    pub gauge: Option<Gauge>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Gauge {
+    #[prost(message, repeated, tag = "1")]
+    pub data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct NumberDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    pub attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "3")]
+    pub time_unix_nano: u64,
+    /// Member of the `value` oneof. Optional so that 0.0 is still encoded.
+    #[prost(double, optional, tag = "4")]
+    pub as_double: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct KeyValue {
+    #[prost(string, tag = "1")]
+    pub key: String,
+    #[prost(message, optional, tag = "2")]
+    pub value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AnyValue {
+    /// Member of the `value` oneof.
+    #[prost(string, optional, tag = "1")]
+    pub string_value: Option<String>,
+}
+
+impl KeyValue {
+    fn string(key: &str, value: &str) -> Self {
+        Self {
+            key: key.to_owned(),
+            value: Some(AnyValue {
+                string_value: Some(value.to_owned()),
+            }),
+        }
+    }
+}
+
+/// Export stored series to the collector until the token is cancelled.
+pub async fn run(config: OtlpConfig, store: MetricsStore, token: CancellationToken) {
+    let client = HttpClient::new();
+    let url = format!("{}{METRICS_PATH}", config.endpoint.trim_end_matches('/'));
+    let mut headers: Vec<(&str, &str)> = vec![("Content-Type", "application/x-protobuf")];
+    headers.extend(config.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    let host = hostname();
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    log::info!(
+        "exporting OTLP metrics to {} every {}s",
+        url,
+        config.interval_secs
+    );
+
+    let mut failing = false;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = token.cancelled() => break,
+        }
+
+        let request = build_request(&store.snapshot().await, host.as_deref());
+        if request.resource_metrics.is_empty() {
+            continue;
+        }
+
+        match client.post(&url, &headers, request.encode_to_vec()).await {
+            Ok(_) if failing => {
+                log::info!("OTLP export recovered");
+                failing = false;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                // Gauges carry current values only, so a failed export is not
+                // retried; the next interval sends fresh data.
+                if !failing {
+                    log::warn!("OTLP export failed: {:#}", e);
+                }
+                failing = true;
+            }
+        }
+    }
+}
+
+/// Convert samples grouped by source into an OTLP export request.
+pub fn build_request(
+    snapshot: &[(Source, Vec<Sample>)],
+    host: Option<&str>,
+) -> ExportMetricsServiceRequest {
+    let scope = InstrumentationScope {
+        name: SERVICE_NAME.to_owned(),
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+    };
+
+    let resource_metrics = snapshot
+        .iter()
+        .filter(|(_, samples)| !samples.is_empty())
+        .map(|(source, samples)| ResourceMetrics {
+            resource: Some(Resource {
+                attributes: resource_attributes(source, host),
+            }),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(scope.clone()),
+                metrics: gauges(samples),
+            }],
+        })
+        .collect();
+
+    ExportMetricsServiceRequest { resource_metrics }
+}
+
+fn resource_attributes(source: &Source, host: Option<&str>) -> Vec<KeyValue> {
+    let mut attributes = vec![KeyValue::string("service.name", SERVICE_NAME)];
+    // Peer series were measured on the peer, not on this host.
+    if let Source::Peer(instance) = source {
+        attributes.push(KeyValue::string("sensor.instance", instance));
+        return attributes;
+    }
+    if let Some(host) = host {
+        attributes.push(KeyValue::string("host.name", host));
+    }
+    if let Source::Port(device) = source {
+        attributes.push(KeyValue::string("sensor.port", &device.port));
+        if let Some(serial) = &device.serial {
+            attributes.push(KeyValue::string("sensor.serial", serial));
+        }
+        if let Some(path) = &device.path {
+            attributes.push(KeyValue::string("sensor.usb_path", path));
+        }
+    }
+    attributes
+}
+
+/// Group samples by metric name into gauges, one data point per series.
+fn gauges(samples: &[Sample]) -> Vec<Metric> {
+    let mut by_name: BTreeMap<&str, Vec<NumberDataPoint>> = BTreeMap::new();
+    for sample in samples {
+        by_name
+            .entry(&sample.name)
+            .or_default()
+            .push(NumberDataPoint {
+                attributes: sample
+                    .labels
+                    .iter()
+                    .map(|(k, v)| KeyValue::string(k, v))
+                    .collect(),
+                time_unix_nano: sample.timestamp_ms.saturating_mul(1_000_000),
+                as_double: Some(sample.value),
+            });
+    }
+    by_name
+        .into_iter()
+        .map(|(name, data_points)| Metric {
+            name: name.to_owned(),
+            gauge: Some(Gauge { data_points }),
+        })
+        .collect()
+}
+
+/// Read the kernel host name for the `host.name` resource attribute.
+fn hostname() -> Option<String> {
+    let name = std::fs::read_to_string("/proc/sys/kernel/hostname").ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::Device;
+
+    fn device() -> Device {
+        Device {
+            port: "/dev/ttyACM0".to_owned(),
+            serial: Some("Espressif_A0:76".to_owned()),
+            path: Some("platform-xhci-usb-0:1.2:1.0".to_owned()),
+        }
+    }
+
+    fn attr<'a>(attributes: &'a [KeyValue], key: &str) -> Option<&'a str> {
+        attributes
+            .iter()
+            .find(|kv| kv.key == key)
+            .and_then(|kv| kv.value.as_ref())
+            .and_then(|v| v.string_value.as_deref())
+    }
+
+    #[test]
+    fn request_groups_series_into_gauges() {
+        let samples = vec![
+            Sample::parse("temperature_celsius{sensor=\"a\"} 23.5 1000").unwrap(),
+            Sample::parse("temperature_celsius{sensor=\"b\"} 0 1000").unwrap(),
+            Sample::parse("up 1 1000").unwrap(),
+        ];
+        let request = build_request(&[(Source::Port(device()), samples)], Some("pi-lab"));
+
+        assert_eq!(request.resource_metrics.len(), 1);
+        let rm = &request.resource_metrics[0];
+        let resource = rm.resource.as_ref().unwrap();
+        assert_eq!(attr(&resource.attributes, "host.name"), Some("pi-lab"));
+        assert_eq!(
+            attr(&resource.attributes, "sensor.serial"),
+            Some("Espressif_A0:76")
+        );
+        assert_eq!(
+            attr(&resource.attributes, "sensor.usb_path"),
+            Some("platform-xhci-usb-0:1.2:1.0")
+        );
+
+        let metrics = &rm.scope_metrics[0].metrics;
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].name, "temperature_celsius");
+        let points = &metrics[0].gauge.as_ref().unwrap().data_points;
+        assert_eq!(points.len(), 2);
+        assert_eq!(attr(&points[0].attributes, "sensor"), Some("a"));
+        assert_eq!(points[0].time_unix_nano, 1_000_000_000);
+        assert_eq!(points[1].as_double, Some(0.0));
+    }
+
+    #[test]
+    fn zero_value_is_encoded() {
+        let point = NumberDataPoint {
+            attributes: Vec::new(),
+            time_unix_nano: 0,
+            as_double: Some(0.0),
+        };
+        let decoded = NumberDataPoint::decode(point.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.as_double, Some(0.0));
+    }
+
+    #[test]
+    fn empty_ports_are_skipped() {
+        let request = build_request(&[(Source::Port(device()), Vec::new())], None);
+        assert!(request.resource_metrics.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_to_collector_stand_in() {
+        use axum::body::Bytes;
+        use axum::extract::State;
+        use axum::http::{HeaderMap, StatusCode};
+        use std::collections::HashMap;
+        use tokio::sync::mpsc;
+
+        async fn collect(
+            State(tx): State<mpsc::Sender<(HeaderMap, Bytes)>>,
+            headers: HeaderMap,
+            body: Bytes,
+        ) -> StatusCode {
+            let _ = tx.send((headers, body)).await;
+            StatusCode::OK
+        }
+
+        let (tx, mut rx) = mpsc::channel(4);
+        let app = axum::Router::new()
+            .route(METRICS_PATH, axum::routing::post(collect))
+            .with_state(tx);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let store = MetricsStore::new();
+        store
+            .update(&device(), vec!["temperature_celsius 21.5 1000".to_owned()])
+            .await;
+        store
+            .record_logs(
+                &device(),
+                vec![crate::boardlog::LogLine::new(
+                    "ESP-ROM:esp32c3-api1-20210207",
+                )],
+            )
+            .await;
+        store
+            .update_derived(&[Sample::parse("inlet_delta_celsius 2.5 1000").unwrap()])
+            .await;
+        store
+            .update_peer(
+                "pi-b:9100",
+                vec!["humidity_percent{instance=\"pi-b:9100\"} 40 1000".to_owned()],
+            )
+            .await;
+
+        let config = OtlpConfig {
+            endpoint: format!("http://{addr}"),
+            interval_secs: 1,
+            headers: HashMap::from([("x-api-key".to_owned(), "k".to_owned())]),
+        };
+        let token = CancellationToken::new();
+        let exporter = tokio::spawn(run(config, store, token.clone()));
+
+        let (headers, body) = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        token.cancel();
+        exporter.await.unwrap();
+
+        assert_eq!(headers["content-type"], "application/x-protobuf");
+        assert_eq!(headers["x-api-key"], "k");
+        let request = ExportMetricsServiceRequest::decode(body).unwrap();
+        assert_eq!(request.resource_metrics.len(), 3);
+        let resource = |key: &str| {
+            request
+                .resource_metrics
+                .iter()
+                .find(|rm| attr(&rm.resource.as_ref().unwrap().attributes, key).is_some())
+                .unwrap()
+        };
+        let value = |rm: &ResourceMetrics, name: &str| {
+            let metric = rm.scope_metrics[0]
+                .metrics
+                .iter()
+                .find(|m| m.name == name)
+                .unwrap();
+            metric.gauge.as_ref().unwrap().data_points[0].as_double
+        };
+
+        let port = resource("sensor.port");
+        assert_eq!(value(port, "temperature_celsius"), Some(21.5));
+
+        let local = request
+            .resource_metrics
+            .iter()
+            .find(|rm| {
+                let attributes = &rm.resource.as_ref().unwrap().attributes;
+                attr(attributes, "sensor.port").is_none()
+                    && attr(attributes, "sensor.instance").is_none()
+            })
+            .unwrap();
+        assert_eq!(value(local, "inlet_delta_celsius"), Some(2.5));
+        assert_eq!(value(local, "sensor_board_boots_total"), Some(1.0));
+        assert_eq!(value(local, "sensor_board_panics_total"), Some(0.0));
+        assert_eq!(value(local, "sensor_peer_up"), Some(1.0));
+
+        let peer = resource("sensor.instance");
+        let attributes = &peer.resource.as_ref().unwrap().attributes;
+        assert_eq!(attr(attributes, "sensor.instance"), Some("pi-b:9100"));
+        assert_eq!(attr(attributes, "host.name"), None);
+        assert_eq!(value(peer, "humidity_percent"), Some(40.0));
+    }
+}
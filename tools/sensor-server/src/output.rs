@@ -0,0 +1,304 @@
+//! Pluggable output sinks fed from the metric batch stream.
+//!
+//! Every batch accepted by the store is parsed into samples and handed to
+//! each configured sink. A sink runs in its own task with a bounded buffer:
+//! samples accumulate between flushes and stay buffered while the
+//! destination is down, with exponential backoff between retries. When the
+//! buffer is full, the oldest samples are dropped.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::config::SinkOptions;
+use crate::sample::Sample;
+
+/// Channel capacity between the batch drain and a sink task, in batches.
+const SINK_CHANNEL_SIZE: usize = 64;
+
+/// Maximum samples written in one request or connection burst.
+const MAX_CHUNK: usize = 5_000;
+
+/// Initial retry delay after a failed write.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A destination that accepts samples in some wire protocol.
+pub trait Sink: Send + 'static {
+    /// Short name for log messages.
+    fn name(&self) -> &str;
+
+    /// Write a chunk of samples. On error the chunk stays buffered and is retried.
+    fn write(&mut self, samples: &[Sample]) -> impl Future<Output = anyhow::Result<()>> + Send;
+}
+
+/// Handle to a running sink task.
+pub struct SinkHandle {
+    name: String,
+    tx: mpsc::Sender<Arc<[Sample]>>,
+    join: JoinHandle<()>,
+}
+
+impl SinkHandle {
+    /// Queue samples without waiting. A sink that falls behind loses the batch
+    /// rather than stalling the drain and with it the Prometheus endpoint.
+    pub fn send(&self, samples: Arc<[Sample]>) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(samples) {
+            log::warn!("{}: sink channel full, dropping batch", self.name);
+        }
+    }
+
+    /// Close the channel and wait for the final flush.
+    pub async fn shutdown(self) {
+        drop(self.tx);
+        if let Err(e) = self.join.await {
+            log::error!("{}: sink task panicked: {}", self.name, e);
+        }
+    }
+}
+
+/// Spawn a task that buffers samples and flushes them to the sink.
+pub fn spawn<S: Sink>(sink: S, options: &SinkOptions) -> SinkHandle {
+    let name = sink.name().to_owned();
+    let (tx, rx) = mpsc::channel(SINK_CHANNEL_SIZE);
+    let join = tokio::spawn(run(sink, rx, options.clone()));
+    log::info!("{}: output enabled", name);
+    SinkHandle { name, tx, join }
+}
+
+async fn run<S: Sink>(mut sink: S, mut rx: mpsc::Receiver<Arc<[Sample]>>, options: SinkOptions) {
+    let mut buffer = Buffer::new(options.buffer_size);
+    let mut backoff = Backoff::new(options.max_backoff());
+    let mut flush = tokio::time::interval(options.flush_interval());
+    flush.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            batch = rx.recv() => match batch {
+                Some(samples) => {
+                    let dropped = buffer.extend(&samples);
+                    if dropped > 0 {
+                        log::warn!("{}: buffer full, dropped {} oldest samples", sink.name(), dropped);
+                    }
+                }
+                None => break,
+            },
+            _ = flush.tick() => {
+                if backoff.ready() {
+                    flush_buffer(&mut sink, &mut buffer, &mut backoff).await;
+                }
+            }
+        }
+    }
+
+    // Best-effort final flush on shutdown, without retries.
+    if !buffer.is_empty() {
+        flush_buffer(&mut sink, &mut buffer, &mut backoff).await;
+        if !buffer.is_empty() {
+            log::warn!(
+                "{}: discarding {} unsent samples on shutdown",
+                sink.name(),
+                buffer.len()
+            );
+        }
+    }
+}
+
+/// Write buffered samples in chunks until the buffer is empty or a write fails.
+async fn flush_buffer<S: Sink>(sink: &mut S, buffer: &mut Buffer, backoff: &mut Backoff) {
+    while !buffer.is_empty() {
+        let chunk = buffer.front(MAX_CHUNK);
+        match sink.write(chunk).await {
+            Ok(()) => {
+                let n = chunk.len();
+                buffer.consume(n);
+                if backoff.reset() {
+                    log::info!("{}: write recovered", sink.name());
+                }
+            }
+            Err(e) => {
+                let delay = backoff.fail();
+                log::warn!(
+                    "{}: write failed: {:#}, {} samples buffered, retry in {:?}",
+                    sink.name(),
+                    e,
+                    buffer.len(),
+                    delay
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// Bounded FIFO of samples that drops the oldest entries on overflow.
+struct Buffer {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+}
+
+impl Buffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Append samples and return how many old samples were dropped.
+    fn extend(&mut self, samples: &[Sample]) -> usize {
+        self.samples.extend(samples.iter().cloned());
+        let excess = self.samples.len().saturating_sub(self.capacity);
+        self.samples.drain(..excess);
+        excess
+    }
+
+    /// Return up to `max` samples from the front as a contiguous slice.
+    fn front(&mut self, max: usize) -> &[Sample] {
+        let n = self.samples.len().min(max);
+        &self.samples.make_contiguous()[..n]
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.samples.drain(..n);
+    }
+
+    fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+/// Exponential retry backoff.
+struct Backoff {
+    delay: Option<Duration>,
+    retry_at: Instant,
+    max: Duration,
+}
+
+impl Backoff {
+    fn new(max: Duration) -> Self {
+        Self {
+            delay: None,
+            retry_at: Instant::now(),
+            max,
+        }
+    }
+
+    fn ready(&self) -> bool {
+        Instant::now() >= self.retry_at
+    }
+
+    /// Record a failure and return the delay until the next attempt.
+    fn fail(&mut self) -> Duration {
+        let delay = match self.delay {
+            Some(d) => (d * 2).min(self.max),
+            None => INITIAL_BACKOFF.min(self.max),
+        };
+        self.delay = Some(delay);
+        self.retry_at = Instant::now() + delay;
+        delay
+    }
+
+    /// Record a success. Returns true if the previous attempt had failed.
+    fn reset(&mut self) -> bool {
+        self.delay.take().is_some()
+    }
+}
+
+/// Escape characters with a backslash.
+pub fn escape(s: &str, special: &[char]) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &str) -> Sample {
+        Sample {
+            name: name.to_owned(),
+            labels: Vec::new(),
+            value: 1.0,
+            timestamp_ms: 0,
+        }
+    }
+
+    #[test]
+    fn buffer_drops_oldest() {
+        let mut buffer = Buffer::new(3);
+        assert_eq!(buffer.extend(&[sample("a"), sample("b")]), 0);
+        assert_eq!(buffer.extend(&[sample("c"), sample("d")]), 1);
+        let names: Vec<_> = buffer.front(10).iter().map(|s| s.name.clone()).collect();
+        assert_eq!(names, ["b", "c", "d"]);
+        buffer.consume(2);
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(5));
+        assert_eq!(backoff.fail(), Duration::from_secs(1));
+        assert_eq!(backoff.fail(), Duration::from_secs(2));
+        assert_eq!(backoff.fail(), Duration::from_secs(4));
+        assert_eq!(backoff.fail(), Duration::from_secs(5));
+        assert!(!backoff.ready());
+        assert!(backoff.reset());
+        assert!(!backoff.reset());
+    }
+
+    /// Sink that fails a configured number of times, then records writes.
+    struct FlakySink {
+        failures: usize,
+        written: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl Sink for FlakySink {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn write(&mut self, samples: &[Sample]) -> anyhow::Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                anyhow::bail!("unreachable");
+            }
+            let mut written = self.written.lock().unwrap();
+            written.extend(samples.iter().map(|s| s.name.clone()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sink_retries_until_write_succeeds() {
+        let written = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = FlakySink {
+            failures: 2,
+            written: written.clone(),
+        };
+        let options = SinkOptions {
+            flush_interval_secs: 1,
+            ..SinkOptions::default()
+        };
+        let handle = spawn(sink, &options);
+        handle.send(vec![sample("a"), sample("b")].into());
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(*written.lock().unwrap(), ["a", "b"]);
+        handle.shutdown().await;
+    }
+}
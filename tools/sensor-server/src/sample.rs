@@ -0,0 +1,195 @@
+//! Parsed Prometheus samples for output sinks.
+//!
+//! The store keeps metric lines as text. Sinks that speak other protocols
+//! need the name, labels, value and timestamp as separate fields.
+
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::store::escape_label;
+
+/// A single Prometheus sample with its labels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+    pub timestamp_ms: u64,
+}
+
+impl Sample {
+    /// Parse a metric line of the form `name[{labels}] value [timestamp]`.
+    ///
+    /// A missing timestamp is replaced with the current wall-clock time.
+    /// Returns None for comments and malformed lines.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let name_end = line
+            .find(|c: char| c == '{' || c.is_ascii_whitespace())
+            .unwrap_or(line.len());
+        let name = &line[..name_end];
+        if name.is_empty() {
+            return None;
+        }
+
+        let (labels, rest) = if line[name_end..].starts_with('{') {
+            parse_labels(&line[name_end + 1..])?
+        } else {
+            (Vec::new(), &line[name_end..])
+        };
+
+        let mut tokens = rest.split_whitespace();
+        let value = parse_value(tokens.next()?)?;
+        let timestamp_ms = match tokens.next() {
+            Some(ts) => ts.parse().ok()?,
+            None => now_ms(),
+        };
+
+        Some(Self {
+            name: name.to_owned(),
+            labels,
+            value,
+            timestamp_ms,
+        })
+    }
+
+    /// Look up a label value by key.
+    #[cfg(test)]
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Format as a Prometheus metric line with timestamp.
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if !self.labels.is_empty() {
+            f.write_str("{")?;
+            for (i, (key, value)) in self.labels.iter().enumerate() {
+                let sep = if i == 0 { "" } else { "," };
+                write!(f, "{sep}{key}=\"{}\"", escape_label(value))?;
+            }
+            f.write_str("}")?;
+        }
+        let value = match self.value {
+            v if v == f64::INFINITY => "+Inf".to_owned(),
+            v if v == f64::NEG_INFINITY => "-Inf".to_owned(),
+            v => v.to_string(),
+        };
+        write!(f, " {value} {}", self.timestamp_ms)
+    }
+}
+
+/// Parse a Prometheus sample value, including the special float spellings.
+fn parse_value(token: &str) -> Option<f64> {
+    match token {
+        "+Inf" | "Inf" | "inf" => Some(f64::INFINITY),
+        "-Inf" | "-inf" => Some(f64::NEG_INFINITY),
+        "NaN" => Some(f64::NAN),
+        _ => token.parse().ok(),
+    }
+}
+
+/// Parse `k="v",...}` and return the labels and the remainder after `}`.
+fn parse_labels(mut s: &str) -> Option<(Vec<(String, String)>, &str)> {
+    let mut labels = Vec::new();
+    loop {
+        s = s.trim_start_matches([',', ' ']);
+        if let Some(rest) = s.strip_prefix('}') {
+            return Some((labels, rest));
+        }
+
+        let eq = s.find('=')?;
+        let key = s[..eq].trim().to_owned();
+        s = s[eq + 1..].trim_start().strip_prefix('"')?;
+
+        let mut value = String::new();
+        let mut chars = s.char_indices();
+        let end = loop {
+            match chars.next()? {
+                (i, '"') => break i,
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    c => value.push(c),
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        s = &s[end + 1..];
+        labels.push((key, value));
+    }
+}
+
+/// Current wall-clock time in milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_with_labels_and_timestamp() {
+        let s =
+            Sample::parse("temperature_celsius{sensor=\"28FF\",loc=\"inlet\"} 23.5 1708000000000")
+                .unwrap();
+        assert_eq!(s.name, "temperature_celsius");
+        assert_eq!(s.label("sensor"), Some("28FF"));
+        assert_eq!(s.label("loc"), Some("inlet"));
+        assert_eq!(s.value, 23.5);
+        assert_eq!(s.timestamp_ms, 1_708_000_000_000);
+    }
+
+    #[test]
+    fn parse_without_labels_or_timestamp() {
+        let s = Sample::parse("up 1").unwrap();
+        assert_eq!(s.name, "up");
+        assert!(s.labels.is_empty());
+        assert_eq!(s.value, 1.0);
+        assert!(s.timestamp_ms > 1_700_000_000_000);
+    }
+
+    #[test]
+    fn parse_escaped_label_values() {
+        let s = Sample::parse(r#"m{a="x\"y",b="1,2}",c="p\\q\n"} -1.5 5"#).unwrap();
+        assert_eq!(s.label("a"), Some("x\"y"));
+        assert_eq!(s.label("b"), Some("1,2}"));
+        assert_eq!(s.label("c"), Some("p\\q\n"));
+        assert_eq!(s.value, -1.5);
+    }
+
+    #[test]
+    fn parse_special_values() {
+        assert!(Sample::parse("m NaN").unwrap().value.is_nan());
+        assert_eq!(Sample::parse("m +Inf").unwrap().value, f64::INFINITY);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let line = r#"m{a="x\"y",b="1"} -1.5 5"#;
+        assert_eq!(Sample::parse(line).unwrap().to_string(), line);
+        assert_eq!(Sample::parse("up 1 7").unwrap().to_string(), "up 1 7");
+        assert_eq!(Sample::parse("m -Inf 7").unwrap().to_string(), "m -Inf 7");
+    }
+
+    #[test]
+    fn parse_rejects_malformed() {
+        assert!(Sample::parse("# TYPE m gauge").is_none());
+        assert!(Sample::parse("").is_none());
+        assert!(Sample::parse("m{a=\"1\" 1").is_none());
+        assert!(Sample::parse("m").is_none());
+        assert!(Sample::parse("m abc").is_none());
+    }
+}
@@ -3,6 +3,7 @@
 //! Opens a serial port at 115200 baud and reads lines in a blocking thread.
 //! Valid Prometheus metric lines are timestamped and sent as batches through
 //! an mpsc channel. Batch boundaries are detected after 100ms of silence.
+//! Other lines are forwarded to the server log and carried in the same batch.
 
 use std::io::BufRead;
 use std::io::BufReader;
@@ -11,12 +12,16 @@ use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
+use crate::boardlog::LogLine;
+use crate::discovery::Device;
 use crate::store::{is_valid_metric_line, stamp_metric_line};
 
 /// A validated batch of Prometheus metric lines from a single serial read cycle.
 pub struct MetricBatch {
-    pub port: String,
+    pub device: Device,
     pub lines: Vec<String>,
+    /// Non-metric lines received in the same cycle.
+    pub logs: Vec<LogLine>,
 }
 
 /// Spawn a blocking serial reader task for a given port.
@@ -26,25 +31,26 @@ pub struct MetricBatch {
 /// A batch boundary is detected after 100ms of silence (no new lines),
 /// matching the firmware's periodic output cycle.
 pub fn spawn_reader(
-    port_name: String,
+    device: Device,
     tx: mpsc::Sender<MetricBatch>,
     token: CancellationToken,
 ) -> tokio::task::JoinHandle<()> {
     tokio::task::spawn_blocking(move || {
-        if let Err(e) = read_loop(&port_name, &tx, &token) {
+        if let Err(e) = read_loop(&device, &tx, &token) {
             if !token.is_cancelled() {
-                log::warn!("{}: reader error: {}", port_name, e);
+                log::warn!("{}: reader error: {}", device.port, e);
             }
         }
-        log::info!("{}: reader stopped", port_name);
+        log::info!("{}: reader stopped", device.port);
     })
 }
 
 fn read_loop(
-    port_name: &str,
+    device: &Device,
     tx: &mpsc::Sender<MetricBatch>,
     token: &CancellationToken,
 ) -> anyhow::Result<()> {
+    let port_name = device.port.as_str();
     let port = serialport::new(port_name, 115_200)
         .timeout(Duration::from_millis(100))
         .open()?;
@@ -52,6 +58,7 @@ fn read_loop(
     log::info!("{}: reader started", port_name);
     let mut reader = BufReader::new(port);
     let mut batch = Vec::new();
+    let mut logs = Vec::new();
     let mut line_buf = String::new();
 
     loop {
@@ -69,15 +76,19 @@ fn read_loop(
                 }
                 if is_valid_metric_line(trimmed) {
                     batch.push(stamp_metric_line(trimmed));
+                } else {
+                    let log_line = LogLine::new(trimmed);
+                    log_line.forward(device);
+                    logs.push(log_line);
                 }
             }
             Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
                 // 100ms silence: flush the accumulated batch.
-                if !batch.is_empty() {
-                    let lines = std::mem::take(&mut batch);
+                if !batch.is_empty() || !logs.is_empty() {
                     let msg = MetricBatch {
-                        port: port_name.to_owned(),
-                        lines,
+                        device: device.clone(),
+                        lines: std::mem::take(&mut batch),
+                        logs: std::mem::take(&mut logs),
                     };
                     if tx.blocking_send(msg).is_err() {
                         return Ok(()); // Receiver dropped, shutting down.
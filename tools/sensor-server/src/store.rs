@@ -1,61 +1,339 @@
 //! Per-port Prometheus metrics storage and line validation.
 //!
 //! Serial readers write validated metric batches per port. The HTTP handler
-//! reads all ports and concatenates them into a single response.
+//! reads all ports and concatenates them into a single response. Non-metric
+//! board lines are kept in a bounded per-port history, and boot and panic
+//! events are counted per board. Series scraped from federated peers are
+//! stored per peer instance alongside the local ports.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use tokio::sync::RwLock;
 
+use crate::boardlog::{BoardEvent, LogLine};
+use crate::discovery::Device;
+use crate::sample::Sample;
+
+/// Number of recent non-metric lines kept per port.
+const LOG_HISTORY: usize = 200;
+
 /// Validated Prometheus metric lines for a single serial port.
 struct PortMetrics {
+    device: Device,
+    lines: Vec<String>,
+}
+
+/// Series scraped from a federated peer, already labelled with `instance`.
+struct PeerMetrics {
+    up: bool,
     lines: Vec<String>,
 }
 
+/// Families rendered by the store under a `# TYPE` line.
+///
+/// A family may only be declared once per exposition, so series of these
+/// families scraped from peers are rendered under the local declaration.
+const TYPED_FAMILIES: [&str; 3] = [
+    "sensor_board_boots_total",
+    "sensor_board_panics_total",
+    "sensor_peer_up",
+];
+
+/// Boot and panic counters for a single board.
+struct EventCounters {
+    /// Label the board is identified by, `serial` or `port` if the serial
+    /// is unknown. Only one is set, so a board replugged onto another port
+    /// keeps its series.
+    label: &'static str,
+    boots: u64,
+    panics: u64,
+}
+
+impl EventCounters {
+    fn get(&self, event: BoardEvent) -> u64 {
+        match event {
+            BoardEvent::Boot => self.boots,
+            BoardEvent::Panic => self.panics,
+        }
+    }
+}
+
+/// Where a group of exported samples comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A locally attached board.
+    Port(Device),
+    /// Series the server itself produces: derived series, board event
+    /// counters and peer health.
+    Local,
+    /// A federated peer, by instance label.
+    Peer(String),
+}
+
+#[derive(Default)]
+struct StoreInner {
+    ports: HashMap<String, PortMetrics>,
+    /// Recent board lines per port. Kept after the port disappears, so a
+    /// panic report stays readable after the board resets or drops off USB.
+    logs: HashMap<String, VecDeque<LogLine>>,
+    /// Event counters keyed by board serial, or by port if the serial is unknown.
+    /// Kept across reconnects so counters survive the reset that follows a panic.
+    events: HashMap<String, EventCounters>,
+    /// Federated peers keyed by instance label.
+    peers: HashMap<String, PeerMetrics>,
+    /// Current values of the config-defined derived series.
+    derived: Vec<String>,
+}
+
 /// Thread-safe store of per-port Prometheus metrics.
 ///
 /// Serial reader tasks write metrics for their port. The HTTP handler reads
 /// all ports and concatenates them into a single response.
 #[derive(Clone)]
 pub struct MetricsStore {
-    inner: Arc<RwLock<HashMap<String, PortMetrics>>>,
+    inner: Arc<RwLock<StoreInner>>,
 }
 
 impl MetricsStore {
     pub fn new() -> Self {
         Self {
-            inner: Arc::new(RwLock::new(HashMap::new())),
+            inner: Arc::new(RwLock::new(StoreInner::default())),
         }
     }
 
     /// Replace all stored metrics for a port with new validated lines.
-    pub async fn update(&self, port: &str, lines: Vec<String>) {
+    pub async fn update(&self, device: &Device, lines: Vec<String>) {
         let mut store = self.inner.write().await;
-        store.insert(port.to_owned(), PortMetrics { lines });
+        store.ports.insert(
+            device.port.clone(),
+            PortMetrics {
+                device: device.clone(),
+                lines,
+            },
+        );
     }
 
     /// Remove metrics for a port that is no longer connected.
     pub async fn remove(&self, port: &str) {
         let mut store = self.inner.write().await;
-        store.remove(port);
+        store.ports.remove(port);
+    }
+
+    /// Replace the series of a federated peer after a successful scrape.
+    pub async fn update_peer(&self, instance: &str, lines: Vec<String>) {
+        let mut store = self.inner.write().await;
+        store
+            .peers
+            .insert(instance.to_owned(), PeerMetrics { up: true, lines });
+    }
+
+    /// Drop the series of a peer that could not be scraped and mark it down.
+    pub async fn peer_down(&self, instance: &str) {
+        let mut store = self.inner.write().await;
+        store.peers.insert(
+            instance.to_owned(),
+            PeerMetrics {
+                up: false,
+                lines: Vec::new(),
+            },
+        );
+    }
+
+    /// Append board lines to the port history and count boot and panic events.
+    pub async fn record_logs(&self, device: &Device, logs: Vec<LogLine>) {
+        let mut store = self.inner.write().await;
+
+        let (label, key) = match &device.serial {
+            Some(serial) => ("serial", serial),
+            None => ("port", &device.port),
+        };
+        let counters = store
+            .events
+            .entry(key.clone())
+            .or_insert_with(|| EventCounters {
+                label,
+                boots: 0,
+                panics: 0,
+            });
+        for line in &logs {
+            match line.event() {
+                Some(BoardEvent::Boot) => counters.boots += 1,
+                Some(BoardEvent::Panic) => counters.panics += 1,
+                None => {}
+            }
+        }
+
+        let history = store.logs.entry(device.port.clone()).or_default();
+        history.extend(logs);
+        let excess = history.len().saturating_sub(LOG_HISTORY);
+        history.drain(..excess);
+    }
+
+    /// Render the recent board lines for a port, oldest first.
+    ///
+    /// Each line is `<epoch_ms> <kind> <text>`. Returns None if nothing was
+    /// ever received from the port.
+    pub async fn render_log(&self, port: &str) -> Option<String> {
+        let store = self.inner.read().await;
+        let history = store.logs.get(port)?;
+        let mut output = String::new();
+        for line in history {
+            let _ = writeln!(output, "{} {} {}", line.timestamp_ms, line.kind, line.text);
+        }
+        Some(output)
+    }
+
+    /// Return every series that `render` exposes, grouped by source.
+    pub async fn snapshot(&self) -> Vec<(Source, Vec<Sample>)> {
+        let store = self.inner.read().await;
+        let parse = |lines: &[String]| -> Vec<Sample> {
+            lines.iter().filter_map(|l| Sample::parse(l)).collect()
+        };
+
+        let mut snapshot: Vec<(Source, Vec<Sample>)> = store
+            .ports
+            .values()
+            .map(|m| (Source::Port(m.device.clone()), parse(&m.lines)))
+            .collect();
+
+        let mut local = String::new();
+        render_events(&mut local, &store.events, &HashMap::new());
+        render_peers(&mut local, &store.peers, &HashMap::new());
+        let mut samples = parse(&store.derived);
+        samples.extend(local.lines().filter_map(Sample::parse));
+        snapshot.push((Source::Local, samples));
+
+        snapshot.extend(
+            store
+                .peers
+                .iter()
+                .map(|(instance, peer)| (Source::Peer(instance.clone()), parse(&peer.lines))),
+        );
+        snapshot
+    }
+
+    /// Return every local and federated sample, excluding derived series.
+    pub async fn samples(&self) -> Vec<Sample> {
+        let store = self.inner.read().await;
+        let ports = store.ports.values().flat_map(|m| &m.lines);
+        let peers = store.peers.values().flat_map(|p| &p.lines);
+        ports
+            .chain(peers)
+            .filter_map(|l| Sample::parse(l))
+            .collect()
+    }
+
+    /// Replace the derived series with their latest values.
+    pub async fn update_derived(&self, samples: &[Sample]) {
+        let mut store = self.inner.write().await;
+        store.derived = samples.iter().map(Sample::to_string).collect();
     }
 
     /// Render all stored metrics into a single Prometheus-compatible response.
     pub async fn render(&self) -> String {
         let store = self.inner.read().await;
         let mut output = String::new();
-        for metrics in store.values() {
+        for metrics in store.ports.values() {
             for line in &metrics.lines {
                 output.push_str(line);
                 output.push('\n');
             }
         }
+        for line in &store.derived {
+            output.push_str(line);
+            output.push('\n');
+        }
+
+        // Peer series of the store's own families are held back and
+        // rendered under the local `# TYPE` line.
+        let mut typed: HashMap<&str, Vec<&str>> = HashMap::new();
+        for peer in store.peers.values() {
+            for line in &peer.lines {
+                let name = line.split(['{', ' ']).next().unwrap_or(line);
+                match TYPED_FAMILIES.iter().find(|f| **f == name) {
+                    Some(family) => typed.entry(family).or_default().push(line),
+                    None => {
+                        output.push_str(line);
+                        output.push('\n');
+                    }
+                }
+            }
+        }
+
+        render_events(&mut output, &store.events, &typed);
+        render_peers(&mut output, &store.peers, &typed);
         output
     }
 }
 
+/// Write the federated lines of a typed family.
+fn render_federated(output: &mut String, typed: &HashMap<&str, Vec<&str>>, name: &str) {
+    for line in typed.get(name).into_iter().flatten() {
+        output.push_str(line);
+        output.push('\n');
+    }
+}
+
+/// Append boot and panic counters as Prometheus counter families.
+fn render_events(
+    output: &mut String,
+    events: &HashMap<String, EventCounters>,
+    typed: &HashMap<&str, Vec<&str>>,
+) {
+    for (name, event) in [
+        ("sensor_board_boots_total", BoardEvent::Boot),
+        ("sensor_board_panics_total", BoardEvent::Panic),
+    ] {
+        if events.is_empty() && !typed.contains_key(name) {
+            continue;
+        }
+        let _ = writeln!(output, "# TYPE {name} counter");
+        for (key, counters) in events {
+            let _ = writeln!(
+                output,
+                "{name}{{{}=\"{}\"}} {}",
+                counters.label,
+                escape_label(key),
+                counters.get(event)
+            );
+        }
+        render_federated(output, typed, name);
+    }
+}
+
+/// Append the health of federated peers as a gauge family.
+fn render_peers(
+    output: &mut String,
+    peers: &HashMap<String, PeerMetrics>,
+    typed: &HashMap<&str, Vec<&str>>,
+) {
+    let name = "sensor_peer_up";
+    if peers.is_empty() && !typed.contains_key(name) {
+        return;
+    }
+    let _ = writeln!(output, "# TYPE {name} gauge");
+    for (instance, peer) in peers {
+        let _ = writeln!(
+            output,
+            "{name}{{instance=\"{}\"}} {}",
+            escape_label(instance),
+            u8::from(peer.up)
+        );
+    }
+    render_federated(output, typed, name);
+}
+
+/// Escape a Prometheus label value.
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 /// Parsed components of a Prometheus metric line.
 struct MetricParts<'a> {
     /// Everything up to and including the space before the value.
@@ -209,9 +487,11 @@ mod tests {
     #[tokio::test]
     async fn store_update_and_render() {
         let store = MetricsStore::new();
-        store.update("/dev/ttyACM0", vec!["up 1".to_owned()]).await;
         store
-            .update("/dev/ttyACM1", vec!["temp 23.5".to_owned()])
+            .update(&device("/dev/ttyACM0", None), vec!["up 1".to_owned()])
+            .await;
+        store
+            .update(&device("/dev/ttyACM1", None), vec!["temp 23.5".to_owned()])
             .await;
 
         let output = store.render().await;
@@ -222,10 +502,135 @@ mod tests {
     #[tokio::test]
     async fn store_remove() {
         let store = MetricsStore::new();
-        store.update("/dev/ttyACM0", vec!["up 1".to_owned()]).await;
+        store
+            .update(&device("/dev/ttyACM0", None), vec!["up 1".to_owned()])
+            .await;
         store.remove("/dev/ttyACM0").await;
 
         let output = store.render().await;
         assert!(output.is_empty());
     }
+
+    #[tokio::test]
+    async fn store_derived_series() {
+        let store = MetricsStore::new();
+        store
+            .update(&device("/dev/ttyACM0", None), vec!["up 1 5".to_owned()])
+            .await;
+        let derived = Sample::parse("double_up{via=\"config\"} 2 5").unwrap();
+        store.update_derived(&[derived]).await;
+
+        assert!(store
+            .render()
+            .await
+            .contains("double_up{via=\"config\"} 2 5\n"));
+        // Derived series are not inputs to other rules.
+        let samples = store.samples().await;
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].name, "up");
+    }
+
+    #[tokio::test]
+    async fn store_renders_peers() {
+        let store = MetricsStore::new();
+        store
+            .record_logs(
+                &device("/dev/ttyACM0", None),
+                vec![LogLine::new("ESP-ROM:esp32c3-api1-20210207")],
+            )
+            .await;
+        store
+            .update_peer(
+                "boiler",
+                vec![
+                    "temp{instance=\"boiler\"} 61.5 1000".to_owned(),
+                    "sensor_board_boots_total{instance=\"boiler\",port=\"/dev/ttyACM0\"} 3"
+                        .to_owned(),
+                ],
+            )
+            .await;
+        store.peer_down("attic").await;
+
+        let output = store.render().await;
+        assert!(output.contains("temp{instance=\"boiler\"} 61.5 1000\n"));
+        assert!(output.contains("sensor_peer_up{instance=\"boiler\"} 1\n"));
+        assert!(output.contains("sensor_peer_up{instance=\"attic\"} 0\n"));
+
+        // The peer's counter follows the single local declaration.
+        assert_eq!(
+            output
+                .matches("# TYPE sensor_board_boots_total counter\n")
+                .count(),
+            1
+        );
+        let header = output.find("# TYPE sensor_board_boots_total").unwrap();
+        let peer_line = output.find("instance=\"boiler\",port=").unwrap();
+        assert!(peer_line > header);
+        assert!(output.find("# TYPE sensor_board_panics_total").unwrap() > peer_line);
+    }
+
+    fn device(port: &str, serial: Option<&str>) -> Device {
+        Device {
+            port: port.to_owned(),
+            serial: serial.map(str::to_owned),
+            path: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn store_counts_board_events() {
+        let store = MetricsStore::new();
+        let dev = device("/dev/ttyACM0", Some("Espressif_A0:76"));
+        store
+            .record_logs(
+                &dev,
+                vec![
+                    LogLine::new("ESP-ROM:esp32c3-api1-20210207"),
+                    LogLine::new("rst:0x15 (USB_UART_CHIP_RESET)"),
+                    LogLine::new("panicked at src/main.rs:42:5:"),
+                ],
+            )
+            .await;
+
+        // The same board reappears on another port after a reset.
+        let dev = device("/dev/ttyACM1", Some("Espressif_A0:76"));
+        store
+            .record_logs(&dev, vec![LogLine::new("ESP-ROM:esp32c3-api1-20210207")])
+            .await;
+
+        let output = store.render().await;
+        assert!(output.contains("# TYPE sensor_board_boots_total counter\n"));
+        assert!(output.contains("sensor_board_boots_total{serial=\"Espressif_A0:76\"} 2\n"));
+        assert!(output.contains("sensor_board_panics_total{serial=\"Espressif_A0:76\"} 1\n"));
+
+        // A board without a serial is counted by port.
+        store
+            .record_logs(
+                &device("/dev/ttyUSB0", None),
+                vec![LogLine::new("ESP-ROM:esp32c3-api1-20210207")],
+            )
+            .await;
+        let output = store.render().await;
+        assert!(output.contains("sensor_board_boots_total{port=\"/dev/ttyUSB0\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn store_log_history_is_bounded_and_survives_remove() {
+        let store = MetricsStore::new();
+        let dev = device("/dev/ttyACM0", None);
+        let lines = (0..LOG_HISTORY + 5)
+            .map(|i| LogLine::new(&format!("WARN - line {i}")))
+            .collect();
+        store.record_logs(&dev, lines).await;
+        store.remove("/dev/ttyACM0").await;
+
+        let log = store.render_log("/dev/ttyACM0").await.unwrap();
+        assert_eq!(log.lines().count(), LOG_HISTORY);
+        assert!(log
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with(" warning WARN - line 5"));
+        assert!(store.render_log("/dev/ttyACM1").await.is_none());
+    }
 }
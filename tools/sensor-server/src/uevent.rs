@@ -0,0 +1,553 @@
+//! Netlink kobject uevent listener and udev property lookup.
+//!
+//! Subscribes to the udev multicast group of a `NETLINK_KOBJECT_UEVENT`
+//! socket. Udev rebroadcasts each kernel uevent after its rules have run, so
+//! the device node exists and identity properties such as `ID_SERIAL` and
+//! `ID_PATH` are already attached when the event arrives.
+//!
+//! Any local process can unicast a datagram to the socket, so messages are
+//! only accepted as libudev accepts them: multicast, sent by root, and in
+//! kernel format only from the kernel itself.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::MetadataExt;
+
+use tokio::io::unix::AsyncFd;
+
+/// Netlink multicast group for uevents rebroadcast by udev.
+const UDEV_GROUP: u32 = 2;
+
+/// Prefix of a libudev monitor message, including the NUL terminator.
+const LIBUDEV_PREFIX: &[u8] = b"libudev\0";
+
+/// Magic number in a libudev monitor header, stored in network byte order.
+const LIBUDEV_MAGIC: u32 = 0xfeed_cafe;
+
+/// Receive buffer size. Uevents are bounded by the kernel at 2 KiB of
+/// environment; udev adds its properties on top.
+const RECV_BUF_SIZE: usize = 8192;
+
+/// Directory of the udev device database.
+const UDEV_DATA_DIR: &str = "/run/udev/data";
+
+/// Control socket created by a running udevd.
+const UDEV_CONTROL: &str = "/run/udev/control";
+
+/// Device action reported by a uevent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Add,
+    Remove,
+    Other,
+}
+
+/// A parsed uevent with its properties.
+#[derive(Debug)]
+pub struct Uevent {
+    pub action: Action,
+    properties: HashMap<String, String>,
+}
+
+impl Uevent {
+    /// Parse a raw netlink message from either the kernel or udev group.
+    ///
+    /// Kernel messages start with an `action@devpath` summary followed by
+    /// NUL-separated `KEY=VALUE` pairs. Udev messages start with a binary
+    /// libudev header that points at the same property block.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let body = if buf.starts_with(LIBUDEV_PREFIX) {
+            libudev_properties(buf)?
+        } else {
+            // Skip the "action@devpath" summary line.
+            let summary_end = buf.iter().position(|&b| b == 0)?;
+            if !buf[..summary_end].contains(&b'@') {
+                return None;
+            }
+            &buf[summary_end + 1..]
+        };
+
+        let properties: HashMap<String, String> = body
+            .split(|&b| b == 0)
+            .filter_map(|field| {
+                let field = std::str::from_utf8(field).ok()?;
+                let (key, value) = field.split_once('=')?;
+                Some((key.to_owned(), value.to_owned()))
+            })
+            .collect();
+
+        let action = match properties.get("ACTION").map(String::as_str) {
+            Some("add") => Action::Add,
+            Some("remove") => Action::Remove,
+            Some(_) => Action::Other,
+            None => return None,
+        };
+
+        Some(Self { action, properties })
+    }
+
+    /// Look up a single property by key.
+    pub fn property(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    /// Absolute device node path, e.g. `/dev/ttyACM0`.
+    ///
+    /// The kernel reports `DEVNAME` relative to `/dev`, udev reports it
+    /// absolute. Both forms are normalized here.
+    pub fn devnode(&self) -> Option<String> {
+        let name = self.property("DEVNAME")?;
+        if name.starts_with('/') {
+            Some(name.to_owned())
+        } else {
+            Some(format!("/dev/{name}"))
+        }
+    }
+
+    /// True if this event describes a tty device node.
+    pub fn is_tty(&self) -> bool {
+        self.property("SUBSYSTEM") == Some("tty") && self.property("DEVNAME").is_some()
+    }
+
+    /// Consume the event and return its properties.
+    pub fn into_properties(self) -> HashMap<String, String> {
+        self.properties
+    }
+}
+
+/// Return the property block of a libudev monitor message.
+///
+/// Header layout: 8-byte prefix, then `magic`, `header_size`,
+/// `properties_off` and `properties_len` as 32-bit integers. The magic is in
+/// network byte order, the other fields in host byte order.
+fn libudev_properties(buf: &[u8]) -> Option<&[u8]> {
+    let field = |n: usize| -> Option<[u8; 4]> {
+        let start = LIBUDEV_PREFIX.len() + n * 4;
+        buf.get(start..start + 4)?.try_into().ok()
+    };
+
+    if u32::from_be_bytes(field(0)?) != LIBUDEV_MAGIC {
+        return None;
+    }
+    let off = u32::from_ne_bytes(field(2)?) as usize;
+    let len = u32::from_ne_bytes(field(3)?) as usize;
+    buf.get(off..off.checked_add(len)?)
+}
+
+/// Sender of a netlink message, from its address and credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sender {
+    /// Multicast groups the message was sent to; 0 for unicast.
+    groups: u32,
+    /// Netlink port ID of the sender; 0 for the kernel.
+    pid: u32,
+    /// User ID from `SCM_CREDENTIALS`, if the message carried them.
+    uid: Option<libc::uid_t>,
+}
+
+impl Sender {
+    /// Whether `buf` from this sender may be trusted.
+    ///
+    /// Unprivileged processes cannot send to the uevent multicast groups,
+    /// but they can unicast to any netlink socket. Udevd rebroadcasts from
+    /// its own port, so only messages in kernel format must come from port 0.
+    fn trusted(&self, buf: &[u8]) -> bool {
+        self.groups != 0
+            && self.uid == Some(0)
+            && (self.pid == 0 || buf.starts_with(LIBUDEV_PREFIX))
+    }
+}
+
+/// Receive one datagram into `buf` with the address and credentials of
+/// its sender.
+fn recv_from(fd: &OwnedFd, buf: &mut [u8]) -> io::Result<(usize, Sender)> {
+    // SAFETY: sockaddr_nl and msghdr are plain C structs for which all-zero
+    // is valid.
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    // Room for one SCM_CREDENTIALS message, aligned for cmsghdr.
+    let mut control = [0u64; 8];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast::<libc::c_void>(),
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_name = std::ptr::addr_of_mut!(addr).cast::<libc::c_void>();
+    msg.msg_namelen = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast::<libc::c_void>();
+    msg.msg_controllen = std::mem::size_of_val(&control);
+
+    // SAFETY: msg points at buffers that are valid for writes of the sizes
+    // given and outlive the call.
+    let n = unsafe { libc::recvmsg(fd.as_raw_fd(), &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut uid = None;
+    // SAFETY: msg was filled in by recvmsg, so the control messages it
+    // points at lie within `control`. ucred may be unaligned in the data.
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_CREDENTIALS
+            {
+                let cred: libc::ucred =
+                    std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::ucred>());
+                uid = Some(cred.uid);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    let sender = Sender {
+        groups: addr.nl_groups,
+        pid: addr.nl_pid,
+        uid,
+    };
+    Ok((n as usize, sender))
+}
+
+/// Async listener on a netlink kobject uevent socket.
+pub struct UeventListener {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl UeventListener {
+    /// Subscribe to uevents rebroadcast by udev.
+    ///
+    /// Raw kernel uevents are not used: they arrive before udev has applied
+    /// permissions and identity properties, which is the race this avoids.
+    pub fn bind() -> io::Result<Self> {
+        // SAFETY: socket() has no memory safety preconditions. The returned
+        // descriptor is checked before use and owned by OwnedFd from here on.
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: raw is a freshly created, valid descriptor owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        // SAFETY: sockaddr_nl is a plain C struct for which all-zero is valid.
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = UDEV_GROUP;
+
+        // SAFETY: addr is a valid sockaddr_nl and the length matches its size.
+        let rc = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                std::ptr::addr_of!(addr).cast::<libc::sockaddr>(),
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Have the kernel attach the sender's credentials to every message.
+        let on: libc::c_int = 1;
+        // SAFETY: on is a valid c_int and the length matches its size.
+        let rc = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PASSCRED,
+                std::ptr::addr_of!(on).cast::<libc::c_void>(),
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Wait for the next parseable uevent.
+    ///
+    /// Messages from untrusted senders and messages that are not valid
+    /// uevents are skipped.
+    pub async fn next(&self) -> io::Result<Uevent> {
+        let mut buf = [0u8; RECV_BUF_SIZE];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let (n, sender) = match guard.try_io(|fd| recv_from(fd.get_ref(), &mut buf)) {
+                Ok(Ok(received)) => received,
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            };
+
+            if !sender.trusted(&buf[..n]) {
+                log::debug!("dropping uevent from untrusted sender {sender:?}");
+                continue;
+            }
+            if let Some(event) = Uevent::parse(&buf[..n]) {
+                return Ok(event);
+            }
+        }
+    }
+}
+
+/// Check whether a udev daemon is running on this host.
+///
+/// Without udevd nothing is rebroadcast on the udev group, so the netlink
+/// listener would stay silent forever.
+pub fn udev_running() -> bool {
+    std::path::Path::new(UDEV_CONTROL).exists()
+}
+
+/// Read udev properties for a device node from the udev database.
+///
+/// Udev stores properties of character devices in `/run/udev/data/c<major>:<minor>`
+/// as `E:KEY=VALUE` lines. Returns an empty map if the node or the database
+/// entry does not exist, e.g. on systems without udevd.
+pub fn udev_properties(devnode: &str) -> HashMap<String, String> {
+    let Ok(meta) = std::fs::metadata(devnode) else {
+        return HashMap::new();
+    };
+    let rdev = meta.rdev();
+    let path = format!(
+        "{UDEV_DATA_DIR}/c{}:{}",
+        libc::major(rdev),
+        libc::minor(rdev)
+    );
+    match std::fs::read_to_string(path) {
+        Ok(contents) => parse_udev_db(&contents),
+        Err(_) => HashMap::new(),
+    }
+}
+
+/// Parse the `E:` property lines of a udev database entry.
+fn parse_udev_db(contents: &str) -> HashMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| line.strip_prefix("E:"))
+        .filter_map(|prop| prop.split_once('='))
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACM_PROPS: &[&str] = &[
+        "ACTION=add",
+        "DEVPATH=/devices/platform/xhci-hcd.0/usb1/1-1/1-1.2/1-1.2:1.0/tty/ttyACM0",
+        "SUBSYSTEM=tty",
+        "DEVNAME=/dev/ttyACM0",
+        "SEQNUM=4242",
+        "ID_VENDOR_ID=303a",
+        "ID_MODEL_ID=1001",
+        "ID_SERIAL=Espressif_USB_JTAG_serial_debug_unit_A0:76:4E:5A:3B:10",
+        "ID_PATH=platform-xhci-hcd.0-usb-0:1.2:1.0",
+    ];
+
+    fn property_block(props: &[&str]) -> Vec<u8> {
+        let mut block = Vec::new();
+        for p in props {
+            block.extend_from_slice(p.as_bytes());
+            block.push(0);
+        }
+        block
+    }
+
+    /// Build a synthetic libudev monitor message with a 40-byte header.
+    fn libudev_message(props: &[&str]) -> Vec<u8> {
+        const HEADER_SIZE: u32 = 40;
+        let block = property_block(props);
+        let mut msg = Vec::new();
+        msg.extend_from_slice(LIBUDEV_PREFIX);
+        msg.extend_from_slice(&LIBUDEV_MAGIC.to_be_bytes());
+        msg.extend_from_slice(&HEADER_SIZE.to_ne_bytes());
+        msg.extend_from_slice(&HEADER_SIZE.to_ne_bytes());
+        msg.extend_from_slice(&(block.len() as u32).to_ne_bytes());
+        // Subsystem hash, devtype hash and tag bloom filter.
+        msg.extend_from_slice(&[0u8; 16]);
+        msg.extend_from_slice(&block);
+        msg
+    }
+
+    fn kernel_message(summary: &str, props: &[&str]) -> Vec<u8> {
+        let mut msg = summary.as_bytes().to_vec();
+        msg.push(0);
+        msg.extend_from_slice(&property_block(props));
+        msg
+    }
+
+    #[test]
+    fn parse_libudev_add() {
+        let event = Uevent::parse(&libudev_message(ACM_PROPS)).unwrap();
+        assert_eq!(event.action, Action::Add);
+        assert!(event.is_tty());
+        assert_eq!(event.devnode().as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(
+            event.property("ID_SERIAL"),
+            Some("Espressif_USB_JTAG_serial_debug_unit_A0:76:4E:5A:3B:10")
+        );
+        assert_eq!(
+            event.property("ID_PATH"),
+            Some("platform-xhci-hcd.0-usb-0:1.2:1.0")
+        );
+    }
+
+    #[test]
+    fn parse_kernel_remove() {
+        let msg = kernel_message(
+            "remove@/devices/platform/xhci-hcd.0/usb1/1-1/1-1.2/1-1.2:1.0/tty/ttyACM0",
+            &[
+                "ACTION=remove",
+                "SUBSYSTEM=tty",
+                "DEVNAME=ttyACM0",
+                "MAJOR=166",
+                "MINOR=0",
+            ],
+        );
+        let event = Uevent::parse(&msg).unwrap();
+        assert_eq!(event.action, Action::Remove);
+        assert!(event.is_tty());
+        assert_eq!(event.devnode().as_deref(), Some("/dev/ttyACM0"));
+        assert_eq!(event.property("ID_SERIAL"), None);
+    }
+
+    #[test]
+    fn parse_other_action() {
+        let mut props = ACM_PROPS.to_vec();
+        props[0] = "ACTION=change";
+        let event = Uevent::parse(&libudev_message(&props)).unwrap();
+        assert_eq!(event.action, Action::Other);
+    }
+
+    #[test]
+    fn non_tty_event() {
+        let msg = kernel_message(
+            "add@/devices/platform/xhci-hcd.0/usb1/1-1/1-1.2",
+            &["ACTION=add", "SUBSYSTEM=usb", "DEVNAME=bus/usb/001/005"],
+        );
+        let event = Uevent::parse(&msg).unwrap();
+        assert!(!event.is_tty());
+    }
+
+    #[test]
+    fn reject_bad_magic() {
+        let mut msg = libudev_message(ACM_PROPS);
+        msg[8] = 0;
+        assert!(Uevent::parse(&msg).is_none());
+    }
+
+    #[test]
+    fn reject_truncated_header() {
+        let msg = libudev_message(ACM_PROPS);
+        assert!(Uevent::parse(&msg[..20]).is_none());
+    }
+
+    #[test]
+    fn reject_properties_out_of_bounds() {
+        let mut msg = libudev_message(ACM_PROPS);
+        msg.truncate(msg.len() - 10);
+        assert!(Uevent::parse(&msg).is_none());
+    }
+
+    #[test]
+    fn reject_garbage() {
+        assert!(Uevent::parse(b"").is_none());
+        assert!(Uevent::parse(b"hello\0world").is_none());
+        assert!(Uevent::parse(b"add@/devices/x\0SUBSYSTEM=tty\0").is_none());
+    }
+
+    #[test]
+    fn udev_db_properties() {
+        let contents = "S:serial/by-id/usb-Espressif_USB_JTAG_serial_debug_unit-if00\n\
+                        I:1234567\n\
+                        E:ID_SERIAL=Espressif_USB_JTAG_serial_debug_unit_A0:76:4E:5A:3B:10\n\
+                        E:ID_PATH=platform-xhci-hcd.0-usb-0:1.2:1.0\n\
+                        G:systemd\n";
+        let props = parse_udev_db(contents);
+        assert_eq!(props.len(), 2);
+        assert_eq!(
+            props.get("ID_PATH").map(String::as_str),
+            Some("platform-xhci-hcd.0-usb-0:1.2:1.0")
+        );
+    }
+
+    #[test]
+    fn trust_only_multicast_from_root() {
+        let udevd = Sender {
+            groups: UDEV_GROUP,
+            pid: 4242,
+            uid: Some(0),
+        };
+        let libudev = libudev_message(ACM_PROPS);
+        let kernel = kernel_message("add@/devices/x", &["SUBSYSTEM=tty"]);
+        assert!(udevd.trusted(&libudev));
+        assert!(!udevd.trusted(&kernel));
+        assert!(Sender { pid: 0, ..udevd }.trusted(&kernel));
+        assert!(!Sender { groups: 0, ..udevd }.trusted(&libudev));
+        assert!(!Sender {
+            uid: Some(1000),
+            ..udevd
+        }
+        .trusted(&libudev));
+        assert!(!Sender { uid: None, ..udevd }.trusted(&libudev));
+    }
+
+    #[tokio::test]
+    async fn drop_unicast_messages() {
+        let listener = UeventListener::bind().unwrap();
+
+        // SAFETY: sockaddr_nl is a plain C struct; the lengths match it.
+        let port = unsafe {
+            let mut addr: libc::sockaddr_nl = std::mem::zeroed();
+            let mut len = std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+            let rc = libc::getsockname(
+                listener.fd.as_raw_fd(),
+                std::ptr::addr_of_mut!(addr).cast::<libc::sockaddr>(),
+                &mut len,
+            );
+            assert_eq!(rc, 0);
+            addr.nl_pid
+        };
+
+        // SAFETY: as in bind; the raw fd is owned from here on.
+        let sender = unsafe {
+            let fd = libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            );
+            assert!(fd >= 0);
+            OwnedFd::from_raw_fd(fd)
+        };
+        let msg = libudev_message(ACM_PROPS);
+        // SAFETY: sockaddr_nl is a plain C struct; msg and dest outlive the call.
+        let sent = unsafe {
+            let mut dest: libc::sockaddr_nl = std::mem::zeroed();
+            dest.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            dest.nl_pid = port;
+            libc::sendto(
+                sender.as_raw_fd(),
+                msg.as_ptr().cast::<libc::c_void>(),
+                msg.len(),
+                0,
+                std::ptr::addr_of!(dest).cast::<libc::sockaddr>(),
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(sent, msg.len() as isize);
+
+        let received =
+            tokio::time::timeout(std::time::Duration::from_millis(200), listener.next()).await;
+        assert!(received.is_err(), "unicast uevent was accepted");
+    }
+}